@@ -334,6 +334,7 @@ pub unsafe fn intr_init_core() {
         unsafe {
             // virtio_blk
             INTERRUPT_CONTROLLER.enable(Armv8::VIRTIO0_IRQ);
+            INTERRUPT_CONTROLLER.enable(Armv8::VIRTIO1_IRQ);
             // pl011 uart
             INTERRUPT_CONTROLLER.enable(Armv8::UART0_IRQ);
         }
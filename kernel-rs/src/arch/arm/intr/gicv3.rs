@@ -1214,6 +1214,7 @@ pub unsafe fn intr_init_core() {
         unsafe {
             // virtio_blk
             intr_controller.enable(Armv8::VIRTIO0_IRQ);
+            intr_controller.enable(Armv8::VIRTIO1_IRQ);
 
             // pl011 uart
             intr_controller.enable(Armv8::UART0_IRQ);
@@ -6,6 +6,7 @@
 //! 00000000 -- boot ROM, provided by qemu, space up to 0x8000000 is reserved.
 //! 08000000 -- GIC
 //! 09000000 -- uart0
+//! 09010000 -- PL031 RTC
 //! 0a000000 -- virtio disk
 //! 40010000 -- boot ROM jumps here in machine mode
 //!             -kernel loads the kernel here
@@ -32,6 +33,8 @@ impl MemLayout for Armv8 {
     /// virtio mmio interface
     const VIRTIO0: usize = 0x0a000000;
     const VIRTIO0_IRQ: usize = 48;
+    const VIRTIO1: usize = 0x0a000200;
+    const VIRTIO1_IRQ: usize = 49;
 }
 
 // TODO: Find counterpart of this in ARM, seems that it doesn't exist.
@@ -42,3 +45,7 @@ impl MemLayout for Armv8 {
 pub const GIC: usize = 0x08000000;
 
 pub const TIMER0_IRQ: usize = 27;
+
+/// PL031 real-time clock. Its data register holds the time in seconds.
+pub const RTC: usize = 0x09010000;
+pub const RTC_DR: usize = RTC;
@@ -144,10 +144,18 @@ impl const TrapFrameManager for TrapFrame {
         self.pc = val;
     }
 
+    fn get_sp(&self) -> usize {
+        self.sp
+    }
+
     fn set_sp(&mut self, val: usize) {
         self.sp = val;
     }
 
+    fn set_ret_addr(&mut self, val: usize) {
+        self.r30 = val;
+    }
+
     /// Set the value of return value register
     fn set_ret_val(&mut self, val: usize) {
         self.r0 = val;
@@ -185,6 +193,27 @@ impl const TrapFrameManager for TrapFrame {
         self.spsr = 0;
         self.fpsr = 0;
     }
+
+    fn user_regs(&self) -> Self {
+        Self {
+            kernel_satp: 0,
+            kernel_sp: 0,
+            kernel_trap: 0,
+            ..*self
+        }
+    }
+
+    fn set_user_regs(&mut self, regs: &Self) {
+        // The saved program status must not be taken from user space,
+        // since it decides the exception level that eret returns to.
+        *self = Self {
+            kernel_satp: self.kernel_satp,
+            kernel_sp: self.kernel_sp,
+            spsr: self.spsr,
+            kernel_trap: self.kernel_trap,
+            ..*regs
+        };
+    }
 }
 
 impl const ContextManager for Context {
@@ -1,7 +1,9 @@
+use core::ptr;
+
 use cortex_a::{asm::barrier, registers::*};
 use tock_registers::interfaces::{Readable, Writeable};
 
-use crate::arch::{interface::TimeManager, Armv8};
+use crate::arch::{interface::TimeManager, memlayout::RTC_DR, Armv8};
 
 const US_PER_S: u64 = 1_000_000;
 
@@ -18,6 +20,23 @@ impl TimeManager for Armv8 {
         Ok((read_cntpct() * US_PER_S / read_freq()) as usize)
     }
 
+    fn realtime_as_micro() -> Result<usize, ()> {
+        // SAFETY: the RTC is mapped in the kernel page table.
+        let secs = unsafe { ptr::read_volatile(RTC_DR as *const u32) } as u64;
+        Ok((secs * US_PER_S) as usize)
+    }
+
+    fn set_timer_deadline(deadline: usize) {
+        // Round up, so that the uptime has reached `deadline` at the interrupt.
+        // Assumes the virtual counter has no offset from the physical one.
+        let target = (deadline as u64 * read_freq() + US_PER_S - 1) / US_PER_S;
+        unsafe { barrier::isb(barrier::SY) };
+        if target < CNTV_CVAL_EL0.get() {
+            CNTV_CVAL_EL0.set(target);
+        }
+        unsafe { barrier::isb(barrier::SY) };
+    }
+
     fn r_cycle() -> usize {
         read_cntpct() as usize
     }
@@ -16,8 +16,8 @@ use crate::{
         timer::set_next_timer,
         Armv8,
     },
-    memlayout::{TRAMPOLINE, TRAPFRAME},
-    trap::{IrqNum, IrqTypes, TrapTypes},
+    memlayout::TRAMPOLINE,
+    trap::{IrqNum, IrqTypes, PageFaultTypes, TrapTypes},
 };
 
 /// In ARM.v8 architecture, interrupts are part
@@ -41,6 +41,9 @@ impl ExceptionTypes {
     }
 }
 
+/// Write not Read bit of the ISS of a data abort.
+const ISS_WNR: u64 = 1 << 6;
+
 extern "C" {
     // trampoline.S
     static mut trampoline: [u8; 0];
@@ -53,7 +56,8 @@ impl From<&IrqTypes> for IrqNum {
     fn from(item: &IrqTypes) -> Self {
         match item {
             IrqTypes::Uart => Armv8::UART0_IRQ,
-            IrqTypes::Virtio => Armv8::VIRTIO0_IRQ,
+            IrqTypes::Virtio(0) => Armv8::VIRTIO0_IRQ,
+            IrqTypes::Virtio(_) => Armv8::VIRTIO1_IRQ,
             IrqTypes::Unknown(i) => *i,
             IrqTypes::Others(i) => *i,
         }
@@ -84,11 +88,16 @@ impl TrapManager for Armv8 {
             ExceptionTypes::SyncException => {
                 if ESR_EL1.matches_all(ESR_EL1::EC::SVC64) {
                     TrapTypes::Syscall
-                } else if ESR_EL1
-                    .matches_any(ESR_EL1::EC::DataAbortLowerEL + ESR_EL1::EC::InstrAbortLowerEL)
-                {
-                    // TODO: Should handle these exceptions?
-                    TrapTypes::BadTrap
+                } else if ESR_EL1.matches_all(ESR_EL1::EC::InstrAbortLowerEL) {
+                    TrapTypes::PageFault(PageFaultTypes::Instruction, FAR_EL1.get() as usize)
+                } else if ESR_EL1.matches_all(ESR_EL1::EC::DataAbortLowerEL) {
+                    // ISS.WnR tells whether the abort was caused by a write.
+                    let typ = if ESR_EL1.read(ESR_EL1::ISS) & ISS_WNR != 0 {
+                        PageFaultTypes::Store
+                    } else {
+                        PageFaultTypes::Load
+                    };
+                    TrapTypes::PageFault(typ, FAR_EL1.get() as usize)
                 } else {
                     TrapTypes::BadTrap
                 }
@@ -103,7 +112,8 @@ impl TrapManager for Armv8 {
                                 return TrapTypes::TimerInterrupt;
                             }
                             Armv8::UART0_IRQ => IrqTypes::Uart,
-                            Armv8::VIRTIO0_IRQ => IrqTypes::Virtio,
+                            Armv8::VIRTIO0_IRQ => IrqTypes::Virtio(0),
+                            Armv8::VIRTIO1_IRQ => IrqTypes::Virtio(1),
                             _ => IrqTypes::Unknown(i),
                         }
                     }
@@ -131,17 +141,20 @@ impl TrapManager for Armv8 {
     }
 
     /// do things before the kernel handle the trap.
-    unsafe fn before_handling_trap(_trap: &TrapTypes, _trapframe: Option<&mut TrapFrame>) {}
+    unsafe fn before_handling_trap(trap: &TrapTypes, _trapframe: Option<&mut TrapFrame>) {
+        // Set the next timer before handling, so that the kernel may ask for
+        // an earlier one.
+        if let TrapTypes::TimerInterrupt = trap {
+            set_next_timer();
+        }
+    }
 
     /// do things after the kernel handle the trap.
     unsafe fn after_handling_trap(trap: &TrapTypes) {
         match trap {
-            TrapTypes::TimerInterrupt => {
-                set_next_timer();
-                unsafe {
-                    INTERRUPT_CONTROLLER.finish(TIMER0_IRQ);
-                }
-            }
+            TrapTypes::TimerInterrupt => unsafe {
+                INTERRUPT_CONTROLLER.finish(TIMER0_IRQ);
+            },
             TrapTypes::Irq(irq_type) => unsafe {
                 INTERRUPT_CONTROLLER.finish(irq_type.into());
             },
@@ -194,6 +207,7 @@ impl TrapManager for Armv8 {
     unsafe fn user_trap_ret(
         user_pagetable_addr: usize,
         trapframe: &mut TrapFrame,
+        trap_frame_va: usize,
         kernel_stack: usize,
         usertrap: usize,
     ) -> ! {
@@ -219,7 +233,7 @@ impl TrapManager for Armv8 {
         let fn_0: usize =
             TRAMPOLINE + unsafe { userret.as_ptr().offset_from(trampoline.as_ptr()) } as usize;
         let fn_0 = unsafe { mem::transmute::<_, unsafe extern "C" fn(usize, usize) -> !>(fn_0) };
-        unsafe { fn_0(TRAPFRAME, user_pagetable_addr) }
+        unsafe { fn_0(trap_frame_va, user_pagetable_addr) }
     }
 
     fn save_trap_regs(store: &mut [usize; 10]) {
@@ -3,13 +3,13 @@ use cortex_a::registers::*;
 use tock_registers::interfaces::ReadWriteable;
 
 use crate::{
-    addr::PAddr,
+    addr::{PAddr, PGSIZE},
     arch::Armv8,
     arch::{
         addr::{pa2pte, pte2pa, PLNUM},
-        asm::{isb, tlbi_vmalle1},
+        asm::{barrier, isb, tlbi_vmalle1},
         interface::{IPageTableEntry, MemLayout, PageTableManager},
-        memlayout::GIC,
+        memlayout::{GIC, RTC},
     },
     vm::{AccessFlags, RawPageTable},
 };
@@ -36,6 +36,8 @@ bitflags! {
         const UXN = 1 << 54;
         /// Privileged execute-never, stage 1 only
         const PXN = 1 << 53;
+        /// Copy-on-write, one of the bits reserved for software use
+        const COW = 1 << 55;
 
         // Could be used for set_entry function and MAIR_EL1 register
         const MEM_ATTR_IDX_0 = (0 << 2);
@@ -53,42 +55,27 @@ bitflags! {
 
 impl From<AccessFlags> for PteFlags {
     fn from(item: AccessFlags) -> Self {
-        Self::ACCESS_FLAG
-            | match item {
-                AccessFlags::R => {
-                    // Privileged Read-Only
-                    Self::RO_P | Self::UXN | Self::PXN
-                }
-                AccessFlags::RW => {
-                    // Privileged Read-Write
-                    Self::RW_P | Self::UXN | Self::PXN
-                }
-                AccessFlags::RU => {
-                    // User Read-Only
-                    Self::RO_U | Self::UXN | Self::PXN
-                }
-                AccessFlags::RWU => {
-                    // User Read-Write
-                    Self::RW_U | Self::UXN | Self::PXN
-                }
-                AccessFlags::RX => {
-                    // Privileged Read-Execute
-                    Self::RO_P | Self::UXN
-                }
-                AccessFlags::RWX => {
-                    // Privileged Read-Write-Execute
-                    Self::RW_P | Self::UXN
-                }
-                AccessFlags::RXU => {
-                    // User Read-Execute
-                    Self::RO_U | Self::PXN
-                }
-                AccessFlags::RWXU => {
-                    // User Read-Write-Execute
-                    Self::RW_U | Self::PXN
-                }
-                _ => panic!("invalid access flag!"),
-            }
+        assert!(item.contains(AccessFlags::R), "invalid access flag!");
+        let user = item.contains(AccessFlags::U);
+        let mut ret = Self::ACCESS_FLAG
+            | match (item.contains(AccessFlags::W), user) {
+                // Privileged Read-Write
+                (true, false) => Self::RW_P,
+                // Privileged Read-Only
+                (false, false) => Self::RO_P,
+                // User Read-Write
+                (true, true) => Self::RW_U,
+                // User Read-Only
+                (false, true) => Self::RO_U,
+            };
+        // Executable only at the level that owns the page.
+        if !(item.contains(AccessFlags::X) && user) {
+            ret |= Self::UXN;
+        }
+        if !(item.contains(AccessFlags::X) && !user) {
+            ret |= Self::PXN;
+        }
+        ret
     }
 }
 
@@ -164,6 +151,26 @@ impl IPageTableEntry for PageTableEntry {
         self.inner &= !(Self::EntryFlags::U.bits());
     }
 
+    fn is_writable(&self) -> bool {
+        // AP[2] (the upper bit of the AP flags) makes the page read-only.
+        self.is_data() && !self.flag_intersects(Self::EntryFlags::RO_P)
+    }
+
+    fn is_cow(&self) -> bool {
+        self.is_data() && self.flag_intersects(Self::EntryFlags::COW)
+    }
+
+    /// Make the entry read-only by setting AP[2], and set Self::EntryFlags::COW.
+    fn set_cow(&mut self) {
+        self.inner |= (Self::EntryFlags::RO_P | Self::EntryFlags::COW).bits();
+    }
+
+    /// Make the entry refer to `pa`, clear AP[2], and clear Self::EntryFlags::COW.
+    fn clear_cow(&mut self, pa: PAddr) {
+        let perm = self.get_flags() - Self::EntryFlags::RO_P - Self::EntryFlags::COW;
+        self.inner = pa2pte(pa) | perm.bits();
+    }
+
     /// Invalidate the entry by making every bit 0.
     fn invalidate(&mut self) {
         self.inner = 0;
@@ -172,8 +179,8 @@ impl IPageTableEntry for PageTableEntry {
 
 impl Armv8 {
     // TODO: put ARM's counterpart of SiFive Test Finisher here
-    // GIC
-    const DEV_MAPPING: [(usize, usize); 1] = [(GIC, Armv8::UART0 - GIC)];
+    // GIC, RTC
+    const DEV_MAPPING: [(usize, usize); 2] = [(GIC, Armv8::UART0 - GIC), (RTC, PGSIZE)];
 }
 
 impl PageTableManager for Armv8 {
@@ -205,4 +212,11 @@ impl PageTableManager for Armv8 {
         isb();
         tlbi_vmalle1();
     }
+
+    fn flush_tlb_of(_cpu_id: usize) -> bool {
+        // The invalidation is broadcast to every CPU in the inner shareable
+        // domain.
+        barrier();
+        true
+    }
 }
@@ -27,6 +27,8 @@ pub trait MemLayout {
 
     /// virtio mmio interface
     const VIRTIO0: usize;
+    /// virtio mmio interface of the second disk
+    const VIRTIO1: usize;
 
     /// the kernel expects there to be RAM
     /// for use by the kernel and user pages
@@ -35,6 +37,7 @@ pub trait MemLayout {
 
     const UART0_IRQ: usize;
     const VIRTIO0_IRQ: usize;
+    const VIRTIO1_IRQ: usize;
 }
 
 pub trait TimeManager {
@@ -44,6 +47,15 @@ pub trait TimeManager {
     /// This includes time consumed by firmware and bootloaders.
     fn uptime_as_micro() -> Result<usize, ()>;
 
+    /// The real time, in microseconds since the Unix epoch, as the real-time
+    /// clock of the device tells.
+    fn realtime_as_micro() -> Result<usize, ()>;
+
+    /// Requests a timer interrupt on this CPU once the uptime reaches
+    /// `deadline` microseconds, if none would come by then. The periodic
+    /// timer interrupts that follow may shift accordingly.
+    fn set_timer_deadline(deadline: usize);
+
     fn r_cycle() -> usize;
 }
 
@@ -126,6 +138,7 @@ pub trait TrapManager {
     unsafe fn switch_to_user_vec();
 
     /// Go back to the user space after handling user trap.
+    /// `trap_frame_va` is the address at which `trap` is mapped in the user page table.
     ///
     /// # Safety
     ///
@@ -133,6 +146,7 @@ pub trait TrapManager {
     unsafe fn user_trap_ret(
         user_pagetable_addr: usize,
         trap: &mut <TargetArch as ProcManager>::TrapFrame,
+        trap_frame_va: usize,
         kernel_stack: usize,
         usertrap: usize,
     ) -> !;
@@ -187,9 +201,15 @@ pub trait TrapFrameManager: Copy + Clone {
     /// Set user pc.
     fn set_pc(&mut self, val: usize);
 
+    /// Get the value of user stack pointer.
+    fn get_sp(&self) -> usize;
+
     /// Set the value of user stack pointer.
     fn set_sp(&mut self, val: usize);
 
+    /// Set the value of return address register (ra or lr).
+    fn set_ret_addr(&mut self, val: usize);
+
     /// Set the value of return value register.
     fn set_ret_val(&mut self, val: usize);
 
@@ -201,6 +221,14 @@ pub trait TrapFrameManager: Copy + Clone {
 
     /// Initialize arch-specific registers.
     fn init_reg(&mut self);
+
+    /// Returns a copy of this trap frame whose kernel-only fields are cleared,
+    /// so that it can be handed to user space.
+    fn user_regs(&self) -> Self;
+
+    /// Restore the user registers from `regs`, keeping the fields that only
+    /// the kernel may set.
+    fn set_user_regs(&mut self, regs: &Self);
 }
 
 pub trait ContextManager: Copy + Clone + Default {
@@ -226,6 +254,12 @@ pub trait PageTableManager {
     ///
     /// `page_table_base` must contain base address for a valid page table, containing mapping for current pc.
     unsafe fn switch_page_table_and_enable_mmu(page_table_base: usize);
+
+    /// Makes CPU `cpu_id` drop the user mappings cached in its TLB, after the
+    /// user page table it runs with has changed. Returns true if they have
+    /// been dropped already, or false if the CPU has only been interrupted,
+    /// and drops them when it returns to user space after the trap.
+    fn flush_tlb_of(cpu_id: usize) -> bool;
 }
 
 /// # Safety
@@ -261,6 +295,18 @@ pub trait IPageTableEntry: Default {
     /// Make the entry inaccessible by user processes by clearing PteFlags::U.
     fn clear_user(&mut self);
 
+    /// Return whether the entry refers to a writable data page.
+    fn is_writable(&self) -> bool;
+
+    /// Return whether the entry refers to a copy-on-write page.
+    fn is_cow(&self) -> bool;
+
+    /// Make the entry read-only and mark it as a copy-on-write page.
+    fn set_cow(&mut self);
+
+    /// Make the copy-on-write entry refer to `pa` and writable again.
+    fn clear_cow(&mut self, pa: PAddr);
+
     /// Invalidate the entry by making every bit 0.
     fn invalidate(&mut self);
 
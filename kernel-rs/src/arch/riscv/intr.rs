@@ -12,6 +12,7 @@ impl InterruptManager for RiscV {
         // set desired IRQ priorities non-zero (otherwise disabled).
         unsafe { *((PLIC.wrapping_add(RiscV::UART0_IRQ.wrapping_mul(4))) as *mut u32) = 1 };
         unsafe { *((PLIC + RiscV::VIRTIO0_IRQ * 4) as *mut u32) = 1 };
+        unsafe { *((PLIC + RiscV::VIRTIO1_IRQ * 4) as *mut u32) = 1 };
     }
 
     unsafe fn intr_init_core() {
@@ -20,7 +21,7 @@ impl InterruptManager for RiscV {
         // set uart's enable bit for this hart's S-mode.
         unsafe {
             *(plic_senable(hart) as *mut u32) =
-                (1 << RiscV::UART0_IRQ | 1 << RiscV::VIRTIO0_IRQ) as u32
+                (1 << RiscV::UART0_IRQ | 1 << RiscV::VIRTIO0_IRQ | 1 << RiscV::VIRTIO1_IRQ) as u32
         };
 
         // set this hart's S-mode priority threshold to 0.
@@ -4,6 +4,7 @@
 //! based on qemu's hw/riscv/virt.c:
 //!
 //! 00001000 -- boot ROM, provided by qemu
+//! 00101000 -- goldfish RTC
 //! 02000000 -- CLINT
 //! 0C000000 -- PLIC
 //! 10000000 -- uart0
@@ -33,11 +34,19 @@ impl MemLayout for RiscV {
     /// virtio mmio interface
     const VIRTIO0: usize = 0x10001000;
     const VIRTIO0_IRQ: usize = 1;
+    const VIRTIO1: usize = 0x10002000;
+    const VIRTIO1_IRQ: usize = 2;
 }
 
 /// SiFive Test Finisher. (virt device only)
 pub const FINISHER: usize = 0x100000;
 
+/// Goldfish real-time clock. (virt device only)
+/// Reading TIME_LOW latches TIME_HIGH, and the time is in nanoseconds.
+pub const RTC: usize = 0x101000;
+pub const RTC_TIME_LOW: usize = RTC;
+pub const RTC_TIME_HIGH: usize = RTC.wrapping_add(4);
+
 /// core local interruptor (CLINT), which contains the timer.
 pub const CLINT: usize = 0x2000000;
 pub const fn clint_mtimecmp(hartid: usize) -> usize {
@@ -46,6 +55,11 @@ pub const fn clint_mtimecmp(hartid: usize) -> usize {
         .wrapping_add(hartid.wrapping_mul(8))
 }
 
+/// Writing 1 raises a machine-mode software interrupt on the hart.
+pub const fn clint_msip(hartid: usize) -> usize {
+    CLINT.wrapping_add(hartid.wrapping_mul(4))
+}
+
 /// cycles since boot.
 pub const CLINT_MTIME: usize = CLINT.wrapping_add(0xbff8);
 
@@ -174,10 +174,18 @@ impl const TrapFrameManager for TrapFrame {
         self.epc = val;
     }
 
+    fn get_sp(&self) -> usize {
+        self.sp
+    }
+
     fn set_sp(&mut self, val: usize) {
         self.sp = val;
     }
 
+    fn set_ret_addr(&mut self, val: usize) {
+        self.ra = val;
+    }
+
     /// Set the value of return value register
     fn set_ret_val(&mut self, val: usize) {
         self.a0 = val;
@@ -214,6 +222,26 @@ impl const TrapFrameManager for TrapFrame {
     fn init_reg(&mut self) {
         // nothing to do
     }
+
+    fn user_regs(&self) -> Self {
+        Self {
+            kernel_satp: 0,
+            kernel_sp: 0,
+            kernel_trap: 0,
+            kernel_hartid: 0,
+            ..*self
+        }
+    }
+
+    fn set_user_regs(&mut self, regs: &Self) {
+        *self = Self {
+            kernel_satp: self.kernel_satp,
+            kernel_sp: self.kernel_sp,
+            kernel_trap: self.kernel_trap,
+            kernel_hartid: self.kernel_hartid,
+            ..*regs
+        };
+    }
 }
 
 impl const ContextManager for Context {
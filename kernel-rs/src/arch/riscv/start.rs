@@ -1,11 +1,15 @@
-use core::arch::asm;
+use core::{
+    arch::asm,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use crate::{
     arch::asm::{
-        r_mhartid, w_medeleg, w_mepc, w_mideleg, w_mscratch, w_mtvec, w_satp, w_tp, Mstatus, Pmp,
-        MIE, SIE,
+        cpu_id, r_mhartid, w_medeleg, w_mepc, w_mideleg, w_mscratch, w_mtvec, w_satp, w_tp,
+        Mstatus, Pmp, MIE, SIE,
     },
-    arch::memlayout::{clint_mtimecmp, CLINT_MTIME},
+    arch::memlayout::{clint_msip, clint_mtimecmp, CLINT_MTIME},
     kernel::main,
     param::NCPU,
 };
@@ -30,7 +34,31 @@ impl Stack {
 pub static mut stack0: Stack = Stack::new();
 
 /// A scratch area per CPU for machine-mode timer interrupts.
-static mut TIMER_SCRATCH: [[usize; NCPU]; 5] = [[0; NCPU]; 5];
+static mut TIMER_SCRATCH: [[usize; 8]; NCPU] = [[0; 8]; NCPU];
+
+/// Asks the machine-mode timervec to lower this CPU's mtimecmp to `mtimecmp`,
+/// by interrupting this CPU. Interrupts must be disabled.
+pub fn lower_mtimecmp(mtimecmp: usize) {
+    let id = cpu_id();
+    // SAFETY: only this CPU and its timervec, which runs in between its
+    // instructions, access its scratch area. The CLINT is mapped in the kernel
+    // page table.
+    unsafe {
+        ptr::write_volatile(&mut TIMER_SCRATCH[id][6], mtimecmp.max(1));
+        ptr::write_volatile(clint_msip(id) as *mut u32, 1);
+    }
+}
+
+/// Returns true if the machine-mode timer of this CPU has fired since the last
+/// call, as opposed to only the other CPUs interrupting it. Interrupts must be
+/// disabled.
+pub fn take_timer_fired() -> bool {
+    let id = cpu_id();
+    // SAFETY: only this CPU and its timervec access its scratch area, and the
+    // swap is a single instruction, so timervec cannot run in the middle of it.
+    let fired = unsafe { &*(&raw mut TIMER_SCRATCH[id][7] as *const AtomicUsize) };
+    fired.swap(0, Ordering::SeqCst) != 0
+}
 
 /// Configures the Pmp registers so that we can trivally boot.
 /// See section 3.7.1 "Physical Memory Protection CSRs" in the RISC-V privileged specification for details.
@@ -86,6 +114,7 @@ pub unsafe fn start() {
 /// set up to receive timer interrupts in machine mode,
 /// which arrive at timervec in kernelvec.S,
 /// which turns them into software interrupts for devintr() in trap.c.
+/// The software interrupts that the other harts send arrive there too.
 unsafe fn timerinit() {
     // each CPU has a separate source of timer interrupts.
     let id = r_mhartid();
@@ -98,9 +127,13 @@ unsafe fn timerinit() {
     // scratch[0..2] : space for timervec to save registers.
     // scratch[3] : address of CLINT MTIMECMP register.
     // scratch[4] : desired interval (in cycles) between timer interrupts.
+    // scratch[5] : address of CLINT MSIP register.
+    // scratch[6] : an mtimecmp value that supervisor mode asks for, or 0.
+    // scratch[7] : 1 if the timer has fired since supervisor mode looked.
     let scratch = unsafe { &mut TIMER_SCRATCH[id][..] };
     *unsafe { scratch.get_unchecked_mut(3) } = clint_mtimecmp(id);
     *unsafe { scratch.get_unchecked_mut(4) } = interval;
+    *unsafe { scratch.get_unchecked_mut(5) } = clint_msip(id);
     unsafe { w_mscratch(&scratch[0] as *const _ as usize) };
 
     // set the machine-mode trap handler.
@@ -111,8 +144,9 @@ unsafe fn timerinit() {
     x.insert(Mstatus::MIE);
     unsafe { x.write() };
 
-    // enable machine-mode timer interrupts.
+    // enable machine-mode timer and software interrupts.
     let mut y = MIE::read();
     y.insert(MIE::MTIE);
+    y.insert(MIE::MSIE);
     unsafe { y.write() };
 }
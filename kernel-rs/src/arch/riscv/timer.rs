@@ -1,7 +1,16 @@
 use core::arch::asm;
+use core::ptr;
 
-use super::RiscV;
-use crate::arch::interface::TimeManager;
+use super::{start::lower_mtimecmp, RiscV};
+use crate::arch::{
+    interface::TimeManager,
+    memlayout::{CLINT_MTIME, RTC_TIME_HIGH, RTC_TIME_LOW},
+};
+
+/// The CLINT's mtime counts at 10MHz in qemu.
+const MTIME_PER_MICRO: usize = 10;
+
+const NANOS_PER_MICRO: u64 = 1_000;
 
 impl TimeManager for RiscV {
     fn timer_init() {
@@ -9,9 +18,28 @@ impl TimeManager for RiscV {
     }
 
     /// The uptime since power-on of the device, in microseconds.
-    /// This function is only supporeted on ARM now.
     fn uptime_as_micro() -> Result<usize, ()> {
-        todo!()
+        // SAFETY: the CLINT is mapped in the kernel page table.
+        let mtime = unsafe { ptr::read_volatile(CLINT_MTIME as *const usize) };
+        Ok(mtime / MTIME_PER_MICRO)
+    }
+
+    fn realtime_as_micro() -> Result<usize, ()> {
+        // SAFETY: the RTC is mapped in the kernel page table. Reading TIME_LOW
+        // first latches TIME_HIGH, so the two halves are of the same time.
+        let nanos = unsafe {
+            let low = ptr::read_volatile(RTC_TIME_LOW as *const u32);
+            let high = ptr::read_volatile(RTC_TIME_HIGH as *const u32);
+            (high as u64) << 32 | low as u64
+        };
+        Ok((nanos / NANOS_PER_MICRO) as usize)
+    }
+
+    fn set_timer_deadline(deadline: usize) {
+        // The machine-mode timervec adds the interval to mtimecmp on each
+        // timer interrupt, so the periodic interrupts go on after this one.
+        // It may do so at any moment, so it also lowers mtimecmp for us.
+        lower_mtimecmp(deadline.saturating_mul(MTIME_PER_MICRO));
     }
 
     fn r_cycle() -> usize {
@@ -11,9 +11,10 @@ use crate::{
     arch::interface::{MemLayout, TrapManager},
     arch::intr::{plic_claim, plic_complete},
     arch::proc::TrapFrame,
+    arch::start::take_timer_fired,
     arch::RiscV,
-    memlayout::{TRAMPOLINE, TRAPFRAME},
-    trap::{IrqNum, IrqTypes, TrapTypes},
+    memlayout::TRAMPOLINE,
+    trap::{IrqNum, IrqTypes, PageFaultTypes, TrapTypes},
 };
 
 extern "C" {
@@ -32,7 +33,8 @@ impl From<&IrqTypes> for IrqNum {
     fn from(item: &IrqTypes) -> Self {
         match item {
             IrqTypes::Uart => RiscV::UART0_IRQ,
-            IrqTypes::Virtio => RiscV::VIRTIO0_IRQ,
+            IrqTypes::Virtio(0) => RiscV::VIRTIO0_IRQ,
+            IrqTypes::Virtio(_) => RiscV::VIRTIO1_IRQ,
             IrqTypes::Unknown(i) => *i,
             IrqTypes::Others(_) => 0,
         }
@@ -70,7 +72,8 @@ impl TrapManager for RiscV {
 
             match irq {
                 RiscV::UART0_IRQ => TrapTypes::Irq(IrqTypes::Uart),
-                RiscV::VIRTIO0_IRQ => TrapTypes::Irq(IrqTypes::Virtio),
+                RiscV::VIRTIO0_IRQ => TrapTypes::Irq(IrqTypes::Virtio(0)),
+                RiscV::VIRTIO1_IRQ => TrapTypes::Irq(IrqTypes::Virtio(1)),
                 0 => {
                     // TODO: should we handle this?
                     TrapTypes::Irq(IrqTypes::Others(0))
@@ -78,10 +81,19 @@ impl TrapManager for RiscV {
                 _ => TrapTypes::Irq(IrqTypes::Unknown(irq)),
             }
         } else if scause == 0x8000000000000001 {
-            // Software interrupt from a machine-mode timer interrupt,
-            // forwarded by timervec in selfvec.S.
-
-            TrapTypes::TimerInterrupt
+            // Software interrupt from a machine-mode timer interrupt, or from
+            // another hart, forwarded by timervec in kernelvec.S.
+            if take_timer_fired() {
+                TrapTypes::TimerInterrupt
+            } else {
+                TrapTypes::TlbShootdown
+            }
+        } else if scause == 12 {
+            TrapTypes::PageFault(PageFaultTypes::Instruction, r_stval())
+        } else if scause == 13 {
+            TrapTypes::PageFault(PageFaultTypes::Load, r_stval())
+        } else if scause == 15 {
+            TrapTypes::PageFault(PageFaultTypes::Store, r_stval())
         } else {
             TrapTypes::BadTrap
         }
@@ -123,7 +135,7 @@ impl TrapManager for RiscV {
                     }
                 }
             }
-            TrapTypes::TimerInterrupt => {
+            TrapTypes::TimerInterrupt | TrapTypes::TlbShootdown => {
                 // Acknowledge the software interrupt by clearing
                 // the SSIP bit in sip.
                 unsafe { w_sip(r_sip() & !2) };
@@ -176,6 +188,7 @@ impl TrapManager for RiscV {
     unsafe fn user_trap_ret(
         user_pagetable_addr: usize,
         trapframe: &mut TrapFrame,
+        trap_frame_va: usize,
         kernel_stack: usize,
         usertrap: usize,
     ) -> ! {
@@ -232,7 +245,7 @@ impl TrapManager for RiscV {
         let fn_0: usize =
             TRAMPOLINE + unsafe { userret.as_ptr().offset_from(trampoline.as_ptr()) } as usize;
         let fn_0 = unsafe { mem::transmute::<_, unsafe extern "C" fn(usize, usize) -> !>(fn_0) };
-        unsafe { fn_0(TRAPFRAME, satp) }
+        unsafe { fn_0(trap_frame_va, satp) }
     }
 
     /// Save trap registers in `store`.
@@ -1,10 +1,12 @@
+use core::ptr;
+
 use bitflags::bitflags;
 
 use super::RiscV;
 use crate::{
     addr::{PAddr, PGSIZE},
     arch::interface::{IPageTableEntry, PageTableManager},
-    arch::memlayout::{FINISHER, PLIC},
+    arch::memlayout::{clint_msip, CLINT, FINISHER, PLIC, RTC},
     arch::{
         addr::{pa2pte, pte2pa, PLNUM},
         asm::{make_satp, sfence_vma, w_satp},
@@ -24,6 +26,8 @@ bitflags! {
         const X = 1 << 3;
         /// user-accessible
         const U = 1 << 4;
+        /// copy-on-write (one of the RSW bits reserved for software)
+        const COW = 1 << 8;
     }
 }
 
@@ -112,6 +116,25 @@ impl IPageTableEntry for PageTableEntry {
         self.inner &= !(Self::EntryFlags::U.bits());
     }
 
+    fn is_writable(&self) -> bool {
+        self.is_data() && self.flag_intersects(Self::EntryFlags::W)
+    }
+
+    fn is_cow(&self) -> bool {
+        self.is_data() && self.flag_intersects(Self::EntryFlags::COW)
+    }
+
+    /// Make the entry read-only by clearing PteFlags::W, and set PteFlags::COW.
+    fn set_cow(&mut self) {
+        self.inner = (self.inner & !Self::EntryFlags::W.bits()) | Self::EntryFlags::COW.bits();
+    }
+
+    /// Make the entry refer to `pa`, set PteFlags::W, and clear PteFlags::COW.
+    fn clear_cow(&mut self, pa: PAddr) {
+        let perm = (self.get_flags() - Self::EntryFlags::COW) | Self::EntryFlags::W;
+        self.inner = pa2pte(pa) | perm.bits();
+    }
+
     /// Invalidate the entry by making every bit 0.
     fn invalidate(&mut self) {
         self.inner = 0;
@@ -120,8 +143,13 @@ impl IPageTableEntry for PageTableEntry {
 
 impl RiscV {
     // Device mappings in memory.
-    // SiFive Test Finisher MMIO, PLIC.
-    const DEV_MAPPING: [(usize, usize); 2] = [(FINISHER, PGSIZE), (PLIC, 0x400000)];
+    // SiFive Test Finisher MMIO, RTC, CLINT, PLIC.
+    const DEV_MAPPING: [(usize, usize); 4] = [
+        (FINISHER, PGSIZE),
+        (RTC, PGSIZE),
+        (CLINT, 0x10000),
+        (PLIC, 0x400000),
+    ];
 }
 
 impl PageTableManager for RiscV {
@@ -130,7 +158,7 @@ impl PageTableManager for RiscV {
     const PLNUM: usize = PLNUM;
 
     fn kernel_page_dev_mappings() -> &'static [(usize, usize)] {
-        &Self::DEV_MAPPING[0..2]
+        &Self::DEV_MAPPING
     }
 
     /// Switch the page table to `page_table_base` and enable paging.
@@ -145,4 +173,13 @@ impl PageTableManager for RiscV {
             sfence_vma();
         }
     }
+
+    fn flush_tlb_of(cpu_id: usize) -> bool {
+        // The machine-mode timervec turns the software interrupt into a
+        // supervisor software interrupt, and trampoline.S flushes the TLB
+        // when the hart returns to user space.
+        // SAFETY: the CLINT is mapped in the kernel page table.
+        unsafe { ptr::write_volatile(clint_msip(cpu_id) as *mut u32, 1) };
+        false
+    }
 }
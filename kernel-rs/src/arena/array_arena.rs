@@ -53,6 +53,13 @@ impl<T, const CAPACITY: usize> ArrayArena<T, CAPACITY> {
     ) -> StrongPin<'s, SpinLock<ArrayArenaInner<T, CAPACITY>>> {
         unsafe { StrongPin::new_unchecked(&(*self.ptr()).inner) }
     }
+
+    /// Returns the number of `Rc`s that refer to the entries.
+    pub fn borrows(self: StrongPin<'_, Self>) -> usize {
+        let mut guard = self.inner().strong_pinned_lock();
+        let this = guard.get_strong_pinned_mut();
+        this.entries().iter_mut().map(|entry| entry.borrows()).sum()
+    }
 }
 
 impl<T, const CAPACITY: usize> ArrayArenaInner<T, CAPACITY> {
@@ -7,8 +7,9 @@
 
 use core::mem::ManuallyDrop;
 use core::ops::Deref;
+use core::ptr::NonNull;
 
-use crate::util::static_arc::Ref;
+use crate::util::static_arc::{Ref, StaticArc};
 use crate::util::strong_pin::StrongPin;
 
 mod array_arena;
@@ -120,6 +121,26 @@ impl<A: Arena> ArenaRc<A> {
     pub fn free(self, ctx: <A::Data as ArenaObject>::Ctx<'_, '_>) {
         A::dealloc(self, ctx);
     }
+
+    /// Consumes the `Rc` and returns a pointer to its entry, keeping the reference.
+    /// Use `ArenaRc::from_raw` to get the `Rc` back.
+    pub fn into_raw(self) -> NonNull<StaticArc<A::Data>> {
+        let mut this = ManuallyDrop::new(self);
+        // SAFETY: `this` is not used anymore.
+        let inner = unsafe { ManuallyDrop::take(&mut this.inner) };
+        inner.into_raw()
+    }
+
+    /// Makes an `Rc` out of a pointer returned by `ArenaRc::into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` is returned by `ArenaRc::into_raw` of an `Rc` allocated from `arena`,
+    /// and no other `Rc` has been made out of it.
+    pub unsafe fn from_raw(arena: StrongPin<'_, A>, ptr: NonNull<StaticArc<A::Data>>) -> Self {
+        // SAFETY: safety condition of this method.
+        unsafe { Self::new(arena, Ref::from_raw(ptr)) }
+    }
 }
 
 impl<A: Arena> Drop for ArenaRc<A> {
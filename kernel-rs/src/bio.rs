@@ -10,9 +10,14 @@
 //! * When done with the buffer, call release.
 //! * Do not use the buffer after calling release.
 //! * Only one process at a time can use a buffer, so do not keep them longer than necessary.
+//!
+//! A file system may change a buffer without writing it at once. It marks the buffer dirty
+//! until the disk write, and keeps it in the cache by holding a reference to it. The flusher
+//! (see `writeback`) makes the file systems write the buffers that stay dirty too long.
 
 use core::mem::{self, ManuallyDrop};
 use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use derive_more::{Deref, DerefMut};
 
@@ -27,7 +32,7 @@ use crate::{
 };
 
 pub struct BufEntry {
-    dev: u32,
+    pub dev: u32,
     pub blockno: u32,
 
     /// WaitChannel saying virtio_disk request is done.
@@ -69,6 +74,9 @@ pub struct BufInner {
     /// Does disk "own" buf?
     disk: bool,
 
+    /// Has data been changed since it was last written to disk?
+    dirty: bool,
+
     data: BufData,
 }
 
@@ -93,6 +101,7 @@ impl BufInner {
         Self {
             valid: false,
             disk: false,
+            dirty: false,
             data: BufData { inner: [0; BSIZE] },
         }
     }
@@ -100,6 +109,14 @@ impl BufInner {
 
 pub type Bcache = MruArena<BufEntry, NBUF>;
 
+/// Counts how often reads of disk blocks find their data in the buffer cache,
+/// and how many buffers are dirty.
+pub struct BcacheStats {
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    dirty: AtomicUsize,
+}
+
 /// A reference counted smart pointer to a `BufEntry`.
 /// Use `BufUnlocked::lock` to access the buffer's inner data.
 #[derive(Clone)]
@@ -179,6 +196,21 @@ impl Buf {
         self.deref_inner_mut().valid = true;
     }
 
+    /// Marks the data as changed. The buffer must stay in the cache until the
+    /// data is written to disk, which marks it clean.
+    pub fn mark_dirty(&mut self, ctx: &KernelCtx<'_, '_>) {
+        if !mem::replace(&mut self.deref_inner_mut().dirty, true) {
+            ctx.kernel().bcache_stats().add_dirty();
+        }
+    }
+
+    /// Marks the data as written to disk.
+    pub fn mark_clean(&mut self, ctx: &KernelCtx<'_, '_>) {
+        if mem::replace(&mut self.deref_inner_mut().dirty, false) {
+            ctx.kernel().bcache_stats().remove_dirty();
+        }
+    }
+
     /// Returns a mutable reference to the `BufInner`'s `disk` field,
     /// which marks whether the buffer is owned by the disk or not.
     /// Usually you should use this only inside driver related code.
@@ -224,6 +256,46 @@ impl Drop for Buf {
     }
 }
 
+impl BcacheStats {
+    pub const fn new() -> Self {
+        Self {
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+            dirty: AtomicUsize::new(0),
+        }
+    }
+
+    /// Records a read that found its data in the cache if `hit` is `true`, and
+    /// one that had to read the disk otherwise.
+    pub fn record(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        let _ = counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the numbers of hits and misses.
+    pub fn get(&self) -> (usize, usize) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Records that a buffer has become dirty.
+    fn add_dirty(&self) {
+        let _ = self.dirty.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that a dirty buffer has been written to disk.
+    fn remove_dirty(&self) {
+        let _ = self.dirty.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of dirty buffers.
+    pub fn dirty(&self) -> usize {
+        self.dirty.load(Ordering::Relaxed)
+    }
+}
+
 impl Bcache {
     /// # Safety
     ///
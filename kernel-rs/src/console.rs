@@ -13,10 +13,12 @@ use crate::{
     addr::UVAddr,
     arch::interface::{Arch, UartManager, UartManagerConst},
     arch::TargetArch,
+    errno::Errno,
     hal::hal,
     kernel::{Kernel, KernelRef},
     lock::{SleepableLock, SleepableLockGuard, SpinLock, SpinLockGuard},
     proc::KernelCtx,
+    signal::SIGINT,
     util::spin_loop,
 };
 
@@ -162,7 +164,7 @@ impl Console {
         }
     }
 
-    fn write(&self, src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    fn write(&self, src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, Errno> {
         for i in 0..n {
             let mut c = [0u8];
             if ctx
@@ -171,14 +173,19 @@ impl Console {
                 .copy_in_bytes(&mut c, src + i as usize)
                 .is_err()
             {
-                return i;
+                return Ok(i as usize);
             }
             self.putc_sleep(c[0], ctx);
         }
-        n
+        Ok(n as usize)
     }
 
-    fn read(&self, mut dst: UVAddr, mut n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    fn read(
+        &self,
+        mut dst: UVAddr,
+        mut n: i32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
         let mut guard = self.input_buffer.lock();
         let target = n;
         while n > 0 {
@@ -186,7 +193,7 @@ impl Console {
             // input into CONS.buffer.
             while guard.r == guard.w {
                 if ctx.proc().killed() {
-                    return -1;
+                    return Err(Errno::EINTR);
                 }
                 guard.sleep(ctx);
             }
@@ -221,7 +228,7 @@ impl Console {
                 }
             }
         }
-        target - n
+        Ok((target - n) as usize)
     }
 
     /// Handle a uart interrupt, raised because input has arrived, or the uart is ready for more
@@ -241,6 +248,11 @@ impl Console {
                     unsafe { kernel.dump() };
                 }
 
+                // Interrupt the running programs.
+                m if m == ctrl('C') => {
+                    kernel.procs().kill_all(SIGINT);
+                }
+
                 // Kill line.
                 m if m == ctrl('U') => {
                     while guard.e != guard.w
@@ -330,13 +342,23 @@ const fn ctrl(x: char) -> i32 {
 }
 
 /// User write()s to the console go here.
-pub fn console_write(src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+pub fn console_write(
+    _minor: u16,
+    src: UVAddr,
+    n: i32,
+    ctx: &mut KernelCtx<'_, '_>,
+) -> Result<usize, Errno> {
     hal().console().write(src, n, ctx)
 }
 
 /// User read()s from the console go here.
 /// Copy (up to) a whole input line to dst.
 /// User_dist indicates whether dst is a user or kernel address.
-pub fn console_read(dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+pub fn console_read(
+    _minor: u16,
+    dst: UVAddr,
+    n: i32,
+    ctx: &mut KernelCtx<'_, '_>,
+) -> Result<usize, Errno> {
     hal().console().read(dst, n, ctx)
 }
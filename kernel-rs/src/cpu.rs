@@ -1,13 +1,15 @@
 use core::{
     cell::{Cell, UnsafeCell},
+    hint::spin_loop,
     marker::PhantomData,
     ptr::{self, NonNull},
+    sync::atomic::{fence, AtomicUsize, Ordering},
 };
 
 use array_macro::array;
 
 use crate::{
-    arch::interface::{ContextManager, ProcManager, TrapManager},
+    arch::interface::{ContextManager, PageTableManager, ProcManager, TrapManager},
     arch::TargetArch,
     param::NCPU,
     proc::Proc,
@@ -16,7 +18,26 @@ use crate::{
 // The `Cpu` struct of the current cpu can be mutated. To do so, we need to
 // obtain mutable pointers to `Cpu`s from a shared reference of a `Cpus`.
 // It requires interior mutability, so we use `UnsafeCell`.
-pub struct Cpus([UnsafeCell<Cpu>; NCPU]);
+pub struct Cpus {
+    cpus: [UnsafeCell<Cpu>; NCPU],
+
+    /// The user page table that each CPU runs with, which the other CPUs read
+    /// to flush its TLB.
+    user_tables: [UserTable; NCPU],
+}
+
+/// The user page table that a CPU runs with.
+///
+/// A CPU flushes its TLB each time it returns to user space, so it caches no
+/// stale user mapping unless it has stayed in user space since the page table
+/// changed.
+struct UserTable {
+    /// The address of the page table, or 0 while the CPU is in the kernel.
+    addr: AtomicUsize,
+
+    /// The number of times that the CPU has returned to user space.
+    epoch: AtomicUsize,
+}
 
 /// # Safety
 ///
@@ -37,7 +58,10 @@ unsafe impl Sync for Cpus {}
 
 impl Cpus {
     pub const fn new() -> Self {
-        Self(array![_ => UnsafeCell::new(Cpu::new()); NCPU])
+        Self {
+            cpus: array![_ => UnsafeCell::new(Cpu::new()); NCPU],
+            user_tables: array![_ => UserTable::new(); NCPU],
+        }
     }
 }
 
@@ -48,7 +72,49 @@ impl Cpus {
     /// current CPU since the scheduler can move the process to another CPU on time interrupt.
     pub fn current_raw(&self) -> *mut Cpu {
         let id: usize = cpuid();
-        self.0[id].get()
+        self.cpus[id].get()
+    }
+
+    /// Records that this CPU returns to user space with the page table at
+    /// `page_table`. Interrupts must be disabled until it does.
+    pub fn enter_user(&self, page_table: usize) {
+        assert!(!TargetArch::intr_get(), "enter_user: interruptible");
+        let table = &self.user_tables[cpuid()];
+        let _ = table.epoch.fetch_add(1, Ordering::SeqCst);
+        table.addr.store(page_table, Ordering::SeqCst);
+    }
+
+    /// Records that this CPU has trapped from user space into the kernel.
+    pub fn leave_user(&self) {
+        assert!(!TargetArch::intr_get(), "leave_user: interruptible");
+        self.user_tables[cpuid()].addr.store(0, Ordering::SeqCst);
+    }
+
+    /// Makes the other CPUs that run user code with the page table at
+    /// `page_table` drop the mappings cached in their TLBs. Must be called
+    /// after the page table has changed, and before the pages that it no
+    /// longer maps are freed.
+    pub fn flush_tlbs(&self, page_table: usize) {
+        // Make the changes to the page table visible before reading which
+        // CPUs run with it.
+        fence(Ordering::SeqCst);
+        for (id, table) in self.user_tables.iter().enumerate() {
+            // A CPU in the kernel flushes its TLB on its way back to user space.
+            if table.addr.load(Ordering::SeqCst) != page_table {
+                continue;
+            }
+            let epoch = table.epoch.load(Ordering::SeqCst);
+            if TargetArch::flush_tlb_of(id) {
+                continue;
+            }
+            // A CPU in user space takes the interrupt at once, so this does
+            // not wait for any lock that the caller may hold.
+            while table.addr.load(Ordering::SeqCst) == page_table
+                && table.epoch.load(Ordering::SeqCst) == epoch
+            {
+                spin_loop();
+            }
+        }
     }
 
     /// Returns a `CpuMut` to the current CPU.
@@ -115,6 +181,9 @@ pub struct Cpu {
 
     /// Were interrupts enabled before push_off()?
     interrupt_enabled: bool,
+
+    /// The clock ticks since boot that this cpu has counted.
+    ticks: usize,
 }
 
 impl Cpu {
@@ -124,6 +193,7 @@ impl Cpu {
             context: <TargetArch as ProcManager>::Context::new(),
             noff: 0,
             interrupt_enabled: false,
+            ticks: 0,
         }
     }
 }
@@ -138,6 +208,15 @@ pub struct CpuMut<'s> {
     _marker: PhantomData<&'s Cell<Cpu>>,
 }
 
+impl UserTable {
+    const fn new() -> Self {
+        Self {
+            addr: AtomicUsize::new(0),
+            epoch: AtomicUsize::new(0),
+        }
+    }
+}
+
 impl CpuMut<'_> {
     /// # Safety
     ///
@@ -195,6 +274,18 @@ impl CpuMut<'_> {
         }
     }
 
+    /// Records that `ticks` clock ticks have passed since boot. Returns true
+    /// if this cpu has not counted them yet.
+    pub fn count_ticks(&self, ticks: usize) -> bool {
+        // SAFETY: invariant of `CpuMut`
+        unsafe {
+            let cpu = &mut *self.ptr();
+            let new = ticks > cpu.ticks;
+            cpu.ticks = cpu.ticks.max(ticks);
+            new
+        }
+    }
+
     fn push_off(&self, old: bool) {
         let noff = self.get_noff();
         if noff == 0 {
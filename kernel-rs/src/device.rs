@@ -0,0 +1,198 @@
+//! The registry of devices.
+//!
+//! A driver registers the functions that read and write the devices of its
+//! major device number, and then registers each of its devices with a name and
+//! a minor device number. The devfs shows the registered devices in `/dev`, so
+//! that no device node has to be made by hand.
+//!
+//! The kernel registers the console and the memory devices below at boot:
+//! * `null`: reads nothing, and discards writes.
+//! * `zero`: reads zeros, and discards writes.
+//! * `random`: reads pseudo-random bytes, and discards writes.
+
+use core::cmp;
+
+use arrayvec::ArrayVec;
+
+use crate::{
+    addr::UVAddr,
+    arch::{interface::TimeManager, TargetArch},
+    errno::Errno,
+    param::{NDEV, NDEVICE},
+    proc::KernelCtx,
+};
+
+/// Major device number of the console.
+pub const CONSOLE: u16 = 1;
+
+/// Major device number of the memory devices.
+pub const MEM: u16 = 2;
+
+/// Minor device numbers of the memory devices.
+const MEM_NULL: u16 = 0;
+const MEM_ZERO: u16 = 1;
+const MEM_RANDOM: u16 = 2;
+
+/// Reads or writes `n` bytes at a user address from or to the device of the
+/// given minor number. Returns the number of bytes read or written.
+pub type DevFn = fn(u16, UVAddr, i32, &mut KernelCtx<'_, '_>) -> Result<usize, Errno>;
+
+/// map major device number to device functions.
+#[derive(Copy, Clone)]
+pub struct Devsw {
+    pub read: Option<DevFn>,
+    pub write: Option<DevFn>,
+}
+
+/// A registered device.
+#[derive(Copy, Clone)]
+pub struct Device {
+    /// The name of the device in `/dev`.
+    pub name: &'static str,
+    pub major: u16,
+    pub minor: u16,
+}
+
+/// The registered drivers and devices.
+pub struct Devices {
+    /// The functions of the drivers, indexed by major device numbers.
+    devsw: [Option<Devsw>; NDEV],
+
+    /// The devices, in the order of registration. A device is never removed,
+    /// so its index identifies it.
+    devices: ArrayVec<Device, NDEVICE>,
+
+    /// State of the random device.
+    seed: u64,
+}
+
+impl Devices {
+    pub const fn new() -> Self {
+        Self {
+            devsw: [None; NDEV],
+            devices: ArrayVec::new_const(),
+            seed: 0,
+        }
+    }
+
+    /// Registers the functions of the driver of the major device number `major`.
+    /// Returns Err(EINVAL) if `major` is too large, or Err(EBUSY) if another
+    /// driver has registered it.
+    pub fn register_driver(&mut self, major: u16, devsw: Devsw) -> Result<(), Errno> {
+        let slot = self.devsw.get_mut(major as usize).ok_or(Errno::EINVAL)?;
+        if slot.is_some() {
+            return Err(Errno::EBUSY);
+        }
+        *slot = Some(devsw);
+        Ok(())
+    }
+
+    /// Registers the device `name` of the device numbers `major` and `minor`.
+    /// Returns Err(ENODEV) if no driver has registered `major`, Err(EEXIST) if
+    /// another device has the same name, or Err(ENOMEM) if the registry is full.
+    pub fn register(&mut self, name: &'static str, major: u16, minor: u16) -> Result<(), Errno> {
+        if self.devsw(major).is_none() {
+            return Err(Errno::ENODEV);
+        }
+        if self.find(name.as_bytes()).is_some() {
+            return Err(Errno::EEXIST);
+        }
+        self.devices
+            .try_push(Device { name, major, minor })
+            .map_err(|_| Errno::ENOMEM)
+    }
+
+    /// Returns the functions of the driver of the major device number `major`.
+    pub fn devsw(&self, major: u16) -> Option<Devsw> {
+        *self.devsw.get(major as usize)?
+    }
+
+    /// Returns the registered devices.
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+
+    /// Returns the index of the device named `name`, and the device.
+    pub fn find(&self, name: &[u8]) -> Option<(usize, Device)> {
+        self.devices
+            .iter()
+            .enumerate()
+            .find(|(_, dev)| dev.name.as_bytes() == name)
+            .map(|(i, dev)| (i, *dev))
+    }
+
+    /// Registers the memory devices.
+    pub fn register_mem(&mut self) -> Result<(), Errno> {
+        self.register_driver(
+            MEM,
+            Devsw {
+                read: Some(mem_read),
+                write: Some(mem_write),
+            },
+        )?;
+        self.register("null", MEM, MEM_NULL)?;
+        self.register("zero", MEM, MEM_ZERO)?;
+        self.register("random", MEM, MEM_RANDOM)
+    }
+
+    /// Returns the next pseudo-random number, by xorshift64*. The uptime is
+    /// mixed into the state, so the numbers are hard to guess but not suitable
+    /// for cryptography.
+    fn random(&mut self) -> u64 {
+        let mut x = self.seed ^ TargetArch::uptime_as_micro().unwrap_or(0) as u64;
+        if x == 0 {
+            x = 0x9e37_79b9_7f4a_7c15;
+        }
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.seed = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+/// User read()s from the memory devices go here.
+fn mem_read(minor: u16, dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, Errno> {
+    if n < 0 {
+        return Err(Errno::EINVAL);
+    }
+    let n = n as usize;
+    let mut buf = [0u8; 64];
+    match minor {
+        MEM_NULL => return Ok(0),
+        MEM_ZERO | MEM_RANDOM => {}
+        _ => return Err(Errno::ENODEV),
+    }
+    let mut tot = 0;
+    while tot < n {
+        let m = cmp::min(n - tot, buf.len());
+        if minor == MEM_RANDOM {
+            let mut devices = ctx.kernel().devices().lock();
+            for chunk in buf[..m].chunks_mut(8) {
+                let bytes = devices.random().to_ne_bytes();
+                chunk.copy_from_slice(&bytes[..chunk.len()]);
+            }
+        }
+        ctx.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(dst + tot, &buf[..m])?;
+        tot += m;
+    }
+    Ok(tot)
+}
+
+/// User write()s to the memory devices go here. They discard the data.
+fn mem_write(
+    minor: u16,
+    _src: UVAddr,
+    n: i32,
+    _ctx: &mut KernelCtx<'_, '_>,
+) -> Result<usize, Errno> {
+    if n < 0 {
+        return Err(Errno::EINVAL);
+    }
+    match minor {
+        MEM_NULL | MEM_ZERO | MEM_RANDOM => Ok(n as usize),
+        _ => Err(Errno::ENODEV),
+    }
+}
@@ -7,14 +7,16 @@ use itertools::*;
 use zerocopy::{AsBytes, FromBytes};
 
 use crate::{
-    addr::{pgroundup, PAddr, PGSIZE},
+    addr::{PAddr, PGSIZE},
     arch::interface::TrapFrameManager,
-    fs::{FileSystem, FileSystemExt, Path},
+    errno::Errno,
+    fs::{Access, Attr, InodeType, Path},
     hal::hal,
+    memlayout::USTACKTOP,
     page::Page,
     param::MAXARG,
     proc::{KernelCtx, RegNum},
-    vm::UserMemory,
+    vm::{AccessFlags, UserMemory},
 };
 
 /// "\x7FELF" in little endian
@@ -94,32 +96,53 @@ impl ProgHdr {
     pub fn is_prog_load(&self) -> bool {
         self.typ == ELF_PROG_LOAD
     }
+
+    /// Permissions of the segment in user memory.
+    /// Every segment is readable, and none is both writable and executable.
+    pub fn perm(&self) -> Result<AccessFlags, ()> {
+        let mut perm = AccessFlags::RU;
+        if self.flags.contains(ProgFlags::WRITE) {
+            perm |= AccessFlags::W;
+        }
+        if self.flags.contains(ProgFlags::EXEC) {
+            perm |= AccessFlags::X;
+        }
+        if perm.contains(AccessFlags::W | AccessFlags::X) {
+            return Err(());
+        }
+        Ok(perm)
+    }
 }
 
 impl KernelCtx<'_, '_> {
-    pub fn exec(&mut self, path: &Path, args: &[Page]) -> Result<usize, ()> {
+    /// Returns Ok(argc) on success. Returns Err(EACCES) if the file is not a
+    /// regular file that the current process may execute, Err(ENOEXEC) if it is
+    /// not a valid executable, or Err(E2BIG) if the arguments do not fit in the stack.
+    pub fn exec(&mut self, path: &Path, args: &[Page]) -> Result<usize, Errno> {
         if args.len() > MAXARG {
-            return Err(());
+            return Err(Errno::E2BIG);
         }
 
         let allocator = hal().kmem();
 
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let tx = scopeguard::guard(tx, |t| t.end(self));
-        let ptr = self.kernel().fs().namei(path, &tx, self)?;
-        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((&tx, self)));
-        let ip = ptr.lock(self);
-        let mut ip = scopeguard::guard(ip, |ip| ip.free(self));
+        let ip = self.namei(path)?;
+        let ip = scopeguard::guard(ip, |ip| ip.free(self));
+        if ip.typ(self) != InodeType::File {
+            return Err(Errno::EACCES);
+        }
+        ip.access(Access::EXEC, self)?;
+        let attr = ip.attr(self);
 
         // Check ELF header
         let mut elf: ElfHdr = Default::default();
-        ip.read_kernel(&mut elf, 0, self)?;
+        ip.read_kernel(&mut elf, 0, self)
+            .map_err(|_| Errno::ENOEXEC)?;
         if !elf.is_valid() {
-            return Err(());
+            return Err(Errno::ENOEXEC);
         }
 
         let trap_frame: PAddr = (self.proc().trap_frame() as *const _ as usize).into();
-        let mem = UserMemory::new(trap_frame, None, allocator).ok_or(())?;
+        let mem = UserMemory::new(trap_frame, None, allocator).ok_or(Errno::ENOMEM)?;
         let mut mem = scopeguard::guard(mem, |mem| mem.free(allocator));
 
         // Load program into memory.
@@ -127,25 +150,26 @@ impl KernelCtx<'_, '_> {
             let off = elf.phoff + i * mem::size_of::<ProgHdr>();
 
             let mut ph: ProgHdr = Default::default();
-            ip.read_kernel(&mut ph, off as _, self)?;
+            ip.read_kernel(&mut ph, off as _, self)
+                .map_err(|_| Errno::ENOEXEC)?;
             if ph.is_prog_load() {
                 if ph.memsz < ph.filesz || ph.vaddr % PGSIZE != 0 {
-                    return Err(());
+                    return Err(Errno::ENOEXEC);
                 }
-                let _ = mem.alloc(ph.vaddr.checked_add(ph.memsz).ok_or(())?, allocator)?;
-                mem.load_file(ph.vaddr.into(), &mut ip, ph.off as _, ph.filesz as _, self)?;
+                let newsz = ph.vaddr.checked_add(ph.memsz).ok_or(Errno::ENOEXEC)?;
+                let perm = ph.perm().map_err(|_| Errno::ENOEXEC)?;
+                let _ = mem
+                    .alloc(newsz, perm, allocator)
+                    .map_err(|_| Errno::ENOMEM)?;
+                mem.load_file(ph.vaddr.into(), &ip, ph.off as _, ph.filesz as _, self)
+                    .map_err(|_| Errno::ENOEXEC)?;
             }
         }
         drop(ip);
-        drop(ptr);
-        drop(tx);
-
-        // Allocate two pages at the next page boundary.
-        // Use the second as the user stack.
-        let mut sz = pgroundup(mem.size());
-        sz = mem.alloc(sz + 2 * PGSIZE, allocator)?;
-        mem.clear((sz - 2 * PGSIZE).into());
-        let mut sp: usize = sz;
+
+        // The user stack grows down from USTACKTOP, and its pages are
+        // allocated on demand. Arguments must fit in its first page.
+        let mut sp: usize = USTACKTOP;
         let stackbase: usize = sp - PGSIZE;
 
         // Push argument strings, prepare rest of stack in ustack.
@@ -161,7 +185,7 @@ impl KernelCtx<'_, '_> {
             // riscv sp must be 16-byte aligned
             sp &= !0xf;
             if sp < stackbase {
-                return Err(());
+                return Err(Errno::E2BIG);
             }
 
             mem.copy_out_bytes(sp.into(), bytes)?;
@@ -175,11 +199,16 @@ impl KernelCtx<'_, '_> {
         sp -= argv_size;
         sp &= !0xf;
         if sp < stackbase {
-            return Err(());
+            return Err(Errno::E2BIG);
         }
         // SAFETY: any byte can be considered as a valid u8.
         let (_, ustack, _) = unsafe { ustack.align_to::<u8>() };
         mem.copy_out_bytes(sp.into(), &ustack[..argv_size])?;
+        let mem = self
+            .kernel()
+            .address_spaces()
+            .alloc_space(scopeguard::ScopeGuard::into_inner(mem))
+            .map_err(|_| Errno::ENOMEM)?;
 
         // Save program name for debugging.
         let path_str = path.as_bytes();
@@ -188,19 +217,31 @@ impl KernelCtx<'_, '_> {
             .rposition(|c| *c == b'/')
             .map(|i| &path_str[(i + 1)..])
             .unwrap_or(path_str);
-        let proc_name = &mut self.proc_mut().deref_mut_data().name;
-        let len = cmp::min(proc_name.len(), name.len());
-        proc_name[..len].copy_from_slice(&name[..len]);
-        if len < proc_name.len() {
-            proc_name[len] = 0;
+        let mut info = self.proc().info.lock();
+        let len = cmp::min(info.name.len(), name.len());
+        info.name[..len].copy_from_slice(&name[..len]);
+        if len < info.name.len() {
+            info.name[len] = 0;
         }
+        drop(info);
 
-        // Commit to the user image.
-        mem::replace(
-            self.proc_mut().memory_mut(),
-            scopeguard::ScopeGuard::into_inner(mem),
-        )
-        .free(allocator);
+        // Commit to the user image. The other threads of the process, which
+        // share the old image, are killed, and this thread becomes a process.
+        self.proc_mut().replace_memory(mem);
+        let tgid = self.proc_mut().become_process();
+        self.kernel().procs().kill_threads(tgid, self);
+
+        // The handlers of the old image are gone, and a set-user-id or a
+        // set-group-id program runs with the id of its owner or its group.
+        let mut info = self.proc().info.lock();
+        info.signals.reset_handlers();
+        if attr.mode & Attr::SETUID != 0 {
+            info.cred.euid = attr.uid;
+        }
+        if attr.mode & Attr::SETGID != 0 {
+            info.cred.egid = attr.gid;
+        }
+        drop(info);
 
         // arguments to user main(argc, argv)
         // argc is returned via the system call return
@@ -1,23 +1,21 @@
 //! Support functions for system calls that involve file descriptors.
 
-use core::{
-    cell::UnsafeCell,
-    cmp,
-    mem::{self, ManuallyDrop},
-    ops::Deref,
-    ops::DerefMut,
-};
+use core::{cmp, mem};
 
-use cfg_if::cfg_if;
+use array_macro::array;
+use static_assertions::const_assert;
 
 use crate::{
-    addr::UVAddr,
+    addr::{UVAddr, PGSIZE},
     arena::{Arena, ArenaObject, ArenaRc, ArrayArena},
-    fs::{DefaultFs, FileSystem, FileSystemExt, InodeGuard, RcInode},
+    errno::Errno,
+    fs::{Dirent64, RcVnode},
     hal::hal,
-    param::{BSIZE, MAXOPBLOCKS, NFILE},
+    lock::{SleepLock, SpinLock},
+    param::{NFILE, NOFILE, NPROC},
     pipe::AllocatedPipe,
     proc::KernelCtx,
+    some_or,
     util::strong_pin::StrongPin,
 };
 
@@ -25,26 +23,15 @@ pub enum FileType {
     None,
     Pipe { pipe: AllocatedPipe },
     Inode { inner: InodeFileType },
-    Device { ip: RcInode<DefaultFs>, major: u16 },
+    Device { ip: RcVnode, major: u16, minor: u16 },
 }
 
 /// It has an inode and an offset.
-///
-/// # Safety
-///
-/// The offset should be accessed only when the inode is locked.
 pub struct InodeFileType {
-    pub ip: RcInode<DefaultFs>,
-    // It should be accessed only when `ip` is locked.
-    pub off: UnsafeCell<u32>,
-}
-
-/// It can be acquired when the inode of `InodeFileType` is locked. `ip` is the guard of the locked
-/// inode. `off` is a mutable reference to the offset. Accessing `off` is guaranteed to be safe
-/// since the inode is locked.
-struct InodeFileTypeGuard<'a, FS: FileSystem> {
-    ip: ManuallyDrop<InodeGuard<'a, FS>>,
-    off: &'a mut u32,
+    pub ip: RcVnode,
+    /// Locked while reading or writing the file, so that concurrent
+    /// accesses through the same open file do not overlap.
+    pub off: SleepLock<u32>,
 }
 
 pub struct File {
@@ -55,16 +42,26 @@ pub struct File {
 
 pub type FileTable = ArrayArena<File, NFILE>;
 
-/// map major device number to device functions.
-#[derive(Copy, Clone)]
-pub struct Devsw {
-    pub read: Option<fn(UVAddr, i32, &mut KernelCtx<'_, '_>) -> i32>,
-    pub write: Option<fn(UVAddr, i32, &mut KernelCtx<'_, '_>) -> i32>,
+/// The open files and the current directory of a process, shared by its threads.
+pub struct ProcFiles {
+    /// Open files, indexed by file descriptors.
+    open_files: SpinLock<[Option<RcFile>; NOFILE]>,
+
+    /// Current directory. It is `Some` while an `RcProcFiles` refers to this.
+    cwd: SpinLock<Option<RcVnode>>,
 }
 
+pub type ProcFilesTable = ArrayArena<ProcFiles, NPROC>;
+
+// `ProcFiles::open_fds` returns the open descriptors as a bitmask.
+const_assert!(NOFILE <= u64::BITS as usize);
+
 /// A reference counted smart pointer to a `File`.
 pub type RcFile = ArenaRc<FileTable>;
 
+/// A reference counted smart pointer to a `ProcFiles`.
+pub type RcProcFiles = ArenaRc<ProcFilesTable>;
+
 // Events for `select`
 #[derive(Copy, Clone)]
 pub enum SelectEvent {
@@ -85,48 +82,6 @@ impl Default for FileType {
     }
 }
 
-impl InodeFileType {
-    fn lock(&self, ctx: &KernelCtx<'_, '_>) -> InodeFileTypeGuard<'_, DefaultFs> {
-        let ip = self.ip.lock(ctx);
-        // SAFETY: `ip` is locked and `off` can be exclusively accessed.
-        let off = unsafe { &mut *self.off.get() };
-        InodeFileTypeGuard {
-            ip: ManuallyDrop::new(ip),
-            off,
-        }
-    }
-}
-
-impl<FS: FileSystem> InodeFileTypeGuard<'_, FS> {
-    fn free(mut self, ctx: &KernelCtx<'_, '_>) {
-        let ip = unsafe { ManuallyDrop::take(&mut self.ip) };
-        ip.free(ctx);
-        core::mem::forget(self);
-    }
-}
-
-impl<'a, FS: FileSystem> Deref for InodeFileTypeGuard<'a, FS> {
-    type Target = InodeGuard<'a, FS>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.ip
-    }
-}
-
-impl<'a, FS: FileSystem> DerefMut for InodeFileTypeGuard<'a, FS> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.ip
-    }
-}
-
-impl<FS: FileSystem> Drop for InodeFileTypeGuard<'_, FS> {
-    fn drop(&mut self) {
-        // HACK(@efenniht): we really need linear type here:
-        // https://github.com/rust-lang/rfcs/issues/814
-        panic!("InodeFileTypeGuard must never drop.");
-    }
-}
-
 impl File {
     pub const fn new(typ: FileType, readable: bool, writable: bool) -> Self {
         Self {
@@ -138,7 +93,7 @@ impl File {
 
     /// Get metadata about file self.
     /// addr is a user virtual address, pointing to a struct stat.
-    pub fn stat(&self, addr: UVAddr, ctx: &mut KernelCtx<'_, '_>) -> Result<(), ()> {
+    pub fn stat(&self, addr: UVAddr, ctx: &mut KernelCtx<'_, '_>) -> Result<(), Errno> {
         match &self.typ {
             FileType::Inode {
                 inner: InodeFileType { ip, .. },
@@ -147,109 +102,158 @@ impl File {
                 let st = ip.stat(ctx);
                 ctx.proc_mut().memory_mut().copy_out(addr, &st)
             }
-            _ => Err(()),
+            _ => Err(Errno::EINVAL),
         }
     }
 
     /// Read from file self.
     /// addr is a user virtual address.
-    pub fn read(&self, addr: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+    pub fn read(&self, addr: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, Errno> {
         if !self.readable {
-            return Err(());
+            return Err(Errno::EBADF);
         }
 
         match &self.typ {
             FileType::Pipe { pipe } => pipe.read(addr, n as usize, ctx),
             FileType::Inode { inner } => {
-                let mut ip = inner.lock(ctx);
-                let curr_off = *ip.off;
-                let ret = ip.read_user(addr, curr_off, n as u32, ctx);
+                let mut off = inner.off.lock(ctx);
+                let ret = inner.ip.read_user(addr, *off, n as u32, ctx);
                 if let Ok(v) = ret {
-                    *ip.off += v as u32;
+                    *off += v as u32;
                 }
-                ip.free(ctx);
+                off.free(ctx);
                 ret
             }
-            FileType::Device { major, .. } => {
-                let major = ctx.kernel().devsw().get(*major as usize).ok_or(())?;
-                let read = major.read.ok_or(())?;
-                Ok(read(addr, n, ctx) as usize)
+            FileType::Device { major, minor, .. } => {
+                let devsw = ctx
+                    .kernel()
+                    .devices()
+                    .lock()
+                    .devsw(*major)
+                    .ok_or(Errno::ENODEV)?;
+                let read = devsw.read.ok_or(Errno::EINVAL)?;
+                read(*minor, addr, n, ctx)
             }
             FileType::None => panic!("File::read"),
         }
     }
 
+    /// Read the entries of the directory self from its offset into n bytes at
+    /// addr, a user virtual address, as `Dirent64` records, and move the offset
+    /// past them. Returns the number of bytes, which is 0 at the end of the
+    /// directory, or Err(EINVAL) if the next entry does not fit in n bytes.
+    pub fn getdents(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
+        if !self.readable {
+            return Err(Errno::EBADF);
+        }
+        let inner = match &self.typ {
+            FileType::Inode { inner } => inner,
+            _ => return Err(Errno::ENOTDIR),
+        };
+
+        let mut off = inner.off.lock(ctx);
+        let mut len = 0;
+        let mut full = false;
+        let mut copied = Ok(());
+        let res = inner.ip.readdir(
+            *off,
+            &mut |next, inum, typ, name| {
+                let header = mem::size_of::<Dirent64>();
+                // The name ends with a NUL, and the record is padded to 4 bytes.
+                let reclen = (header + name.len() + 1 + 3) & !3;
+                if len + reclen > n {
+                    full = true;
+                    return false;
+                }
+                let de = Dirent64 {
+                    ino: inum,
+                    off: next,
+                    reclen: reclen as u16,
+                    typ: typ.stat_type() as u8,
+                    namelen: name.len() as u8,
+                };
+                let mut memory = ctx.proc().memory();
+                copied = memory
+                    .copy_out(addr + len, &de)
+                    .and_then(|_| memory.copy_out_bytes(addr + len + header, name))
+                    .and_then(|_| {
+                        let pad = reclen - header - name.len();
+                        memory.copy_out_bytes(addr + len + header + name.len(), &[0; 4][..pad])
+                    });
+                if copied.is_err() {
+                    return false;
+                }
+                len += reclen;
+                *off = next;
+                true
+            },
+            ctx,
+        );
+        off.free(ctx);
+        res?;
+        copied?;
+        if len == 0 && full {
+            return Err(Errno::EINVAL);
+        }
+        Ok(len)
+    }
+
     /// Write to file self.
     /// addr is a user virtual address.
-    pub fn write(&self, addr: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+    pub fn write(&self, addr: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, Errno> {
         if !self.writable {
-            return Err(());
+            return Err(Errno::EBADF);
         }
 
         match &self.typ {
             FileType::Pipe { pipe } => pipe.write(addr, n as usize, ctx),
             FileType::Inode { inner } => {
                 let n = n as usize;
-
-                // Use a different `max` value depending on the file system.
-                // TODO: We may want to use a different way when we use
-                // multiple file systems at the same time.
-                cfg_if! {
-                    if #[cfg(feature = "lfs")] {
-                        // need to subtract one more compared to `ufs` because of the imap.
-                        let max = (MAXOPBLOCKS - 1 - 1 - 2 - 1) / 2 * BSIZE;
-                    } else {
-                        // write a few blocks at a time to avoid exceeding
-                        // the maximum log transaction size, including
-                        // i-node, indirect block, allocation blocks,
-                        // and 2 blocks of slop for non-aligned writes.
-                        // this really belongs lower down, since write()
-                        // might be writing a device like the console.
-                        let max = (MAXOPBLOCKS - 1 - 1 - 2) / 2 * BSIZE;
-                    }
+                let mut off = inner.off.lock(ctx);
+                let r = inner.ip.write_user(addr, *off, n, ctx);
+                if let Ok(r) = r {
+                    *off += r as u32;
                 }
-
-                let mut bytes_written: usize = 0;
-                while bytes_written < n {
-                    let bytes_to_write = cmp::min(n - bytes_written, max);
-                    let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
-                    let mut ip = inner.lock(ctx);
-                    let curr_off = *ip.off;
-                    let r = ip.write_user(
-                        addr + bytes_written,
-                        curr_off,
-                        bytes_to_write as u32,
-                        ctx,
-                        &tx,
-                    );
-                    if let Ok(r) = r {
-                        *ip.off += r as u32;
-                    }
-                    // Drop the `InodeFileTypeGuard` before completing the transacton
-                    // to prevent deadlocks (e.g. during the lfs segment cleaner).
-                    ip.free(ctx);
-                    tx.end(ctx);
-                    let r = r?;
-                    if r != bytes_to_write {
-                        // error from write_user
-                        break;
-                    }
-                    bytes_written += r;
-                }
-                if bytes_written != n {
-                    return Err(());
+                off.free(ctx);
+                if r? != n {
+                    return Err(Errno::EFAULT);
                 }
                 Ok(n)
             }
-            FileType::Device { major, .. } => {
-                let major = ctx.kernel().devsw().get(*major as usize).ok_or(())?;
-                let write = major.write.ok_or(())?;
-                Ok(write(addr, n, ctx) as usize)
+            FileType::Device { major, minor, .. } => {
+                let devsw = ctx
+                    .kernel()
+                    .devices()
+                    .lock()
+                    .devsw(*major)
+                    .ok_or(Errno::ENODEV)?;
+                let write = devsw.write.ok_or(Errno::EINVAL)?;
+                write(*minor, addr, n, ctx)
             }
             FileType::None => panic!("File::read"),
         }
     }
 
+    /// Writes the changes of the file to the disk, with those of the other files
+    /// of its file system. Returns Err(EINVAL) for a pipe.
+    pub fn sync(&self, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        match &self.typ {
+            FileType::Inode {
+                inner: InodeFileType { ip, .. },
+            }
+            | FileType::Device { ip, .. } => {
+                ip.sync(ctx);
+                Ok(())
+            }
+            _ => Err(Errno::EINVAL),
+        }
+    }
+
     /// Repositions the file offset of the open file description
     /// associated with the file descriptor fd to the `n` according
     /// to the directive `option`.
@@ -258,37 +262,80 @@ impl File {
         n: i32,
         option: SeekWhence,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         if !self.readable {
-            return Err(());
+            return Err(Errno::EBADF);
         }
 
         if let FileType::Inode { inner } = &self.typ {
-            let ip = inner.lock(ctx);
+            let mut guard = inner.off.lock(ctx);
             let off = match option {
                 SeekWhence::Set => n as u32,
-                SeekWhence::Cur => *ip.off + n as u32,
-                SeekWhence::End => {
-                    let ip_inner = ip.deref_inner();
-                    ip_inner.size + n as u32
-                }
+                SeekWhence::Cur => *guard + n as u32,
+                SeekWhence::End => inner.ip.stat(ctx).size as u32 + n as u32,
             };
-            *ip.off = off;
-            ip.free(ctx);
+            *guard = off;
+            guard.free(ctx);
             Ok(off as usize)
         } else {
-            Err(())
+            Err(Errno::ESPIPE)
+        }
+    }
+
+    /// Fill the mapping at va of the current process with len bytes of file
+    /// self from offset off. va must be the start of a mapping created by
+    /// `UserMemory::map`. The contents are read when mapped, and writes to the
+    /// mapping are private to the process and never written back to the file.
+    /// Returns Ok(()) on success, Err(EACCES) if self is not readable, or
+    /// Err(ENODEV) if it is not an inode.
+    pub fn mmap(
+        &self,
+        va: UVAddr,
+        len: usize,
+        off: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        if !self.readable {
+            return Err(Errno::EACCES);
+        }
+
+        if let FileType::Inode { inner } = &self.typ {
+            let allocator = hal().kmem();
+            let mut result = Ok(());
+            for i in num_iter::range_step(0, len, PGSIZE) {
+                let mut page = some_or!(allocator.alloc(Some(0)), {
+                    result = Err(Errno::ENOMEM);
+                    break;
+                });
+                let n = cmp::min(len - i, PGSIZE);
+                // Bytes past the end of the file are left zero.
+                let _ = inner
+                    .ip
+                    .read_bytes_kernel(&mut page[..n], off + i as u32, ctx);
+                if let Err(page) = ctx
+                    .proc_mut()
+                    .memory_mut()
+                    .map_page(va + i, page, allocator)
+                {
+                    allocator.free(page);
+                    result = Err(Errno::ENOMEM);
+                    break;
+                }
+            }
+            result
+        } else {
+            Err(Errno::ENODEV)
         }
     }
 
     /// Check file is ready for specified select event.
     /// It only supports pipe now.
     /// TODO: support other type of files
-    pub fn is_ready(&self, event: SelectEvent) -> Result<bool, ()> {
+    pub fn is_ready(&self, event: SelectEvent) -> Result<bool, Errno> {
         match event {
             SelectEvent::Read => {
                 if !self.readable {
-                    return Err(());
+                    return Err(Errno::EBADF);
                 }
 
                 match &self.typ {
@@ -333,11 +380,7 @@ impl ArenaObject for File {
             FileType::Inode {
                 inner: InodeFileType { ip, .. },
             }
-            | FileType::Device { ip, .. } => {
-                let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
-                ip.free((&tx, ctx));
-                tx.end(ctx);
-            }
+            | FileType::Device { ip, .. } => ip.free(ctx),
             _ => (),
         }
     }
@@ -349,28 +392,122 @@ impl FileTable {
     }
 
     /// Allocate a file structure.
+    /// Returns Err(ENFILE) if the file table is full.
     pub fn alloc_file(
         self: StrongPin<'_, Self>,
         typ: FileType,
         readable: bool,
         writable: bool,
-    ) -> Result<RcFile, ()> {
-        self.alloc(|| File::new(typ, readable, writable)).ok_or(())
+    ) -> Result<RcFile, Errno> {
+        self.alloc(|| File::new(typ, readable, writable))
+            .ok_or(Errno::ENFILE)
     }
 }
 
 impl RcFile {
     /// Allocate a file descriptor for the given file.
     /// Takes over file reference from caller on success.
-    pub fn fdalloc(self, ctx: &mut KernelCtx<'_, '_>) -> Result<i32, ()> {
-        let proc_data = ctx.proc_mut().deref_mut_data();
-        for (fd, f) in proc_data.open_files.iter_mut().enumerate() {
-            if f.is_none() {
-                *f = Some(self);
-                return Ok(fd as i32);
-            }
+    /// Returns Err(EMFILE) if every file descriptor is in use.
+    pub fn fdalloc(self, ctx: &mut KernelCtx<'_, '_>) -> Result<i32, Errno> {
+        let mut open_files = ctx.proc().files().open_files.lock();
+        if let Some(fd) = open_files.iter().position(|f| f.is_none()) {
+            open_files[fd] = Some(self);
+            return Ok(fd as i32);
         }
+        drop(open_files);
         self.free(ctx);
-        Err(())
+        Err(Errno::EMFILE)
+    }
+}
+
+impl const Default for ProcFiles {
+    fn default() -> Self {
+        Self {
+            open_files: SpinLock::new("open_files", array![_ => None; NOFILE]),
+            cwd: SpinLock::new("cwd", None),
+        }
+    }
+}
+
+impl ArenaObject for ProcFiles {
+    type Ctx<'a, 'id: 'a> = &'a KernelCtx<'id, 'a>;
+
+    fn finalize<'a, 'id: 'a>(&mut self, ctx: Self::Ctx<'a, 'id>) {
+        for f in self.open_files.get_mut().iter_mut() {
+            if let Some(f) = f.take() {
+                f.free(ctx);
+            }
+        }
+        if let Some(cwd) = self.cwd.get_mut().take() {
+            cwd.free(ctx);
+        }
+    }
+}
+
+impl ProcFilesTable {
+    pub const fn new_table() -> Self {
+        ArrayArena::<ProcFiles, NPROC>::new("PFTABLE")
+    }
+
+    /// Allocate a `ProcFiles` with the current directory `cwd` and no open files.
+    /// Returns Err(cwd) on failure.
+    pub fn alloc_files(self: StrongPin<'_, Self>, cwd: RcVnode) -> Result<RcProcFiles, RcVnode> {
+        let mut cwd = Some(cwd);
+        let files = self.alloc(|| {
+            ProcFiles {
+                open_files: SpinLock::new("open_files", array![_ => None; NOFILE]),
+                cwd: SpinLock::new("cwd", cwd.take()),
+            }
+        });
+        match cwd {
+            Some(cwd) => Err(cwd),
+            None => Ok(files.expect("alloc_files")),
+        }
+    }
+
+    /// Allocate a `ProcFiles` that refers to the same open files and current
+    /// directory as `src`, for a child process.
+    pub fn dup_files(
+        self: StrongPin<'_, Self>,
+        src: &ProcFiles,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcProcFiles, ()> {
+        self.alloc(|| {
+            ProcFiles {
+                open_files: SpinLock::new("open_files", src.open_files.lock().clone()),
+                cwd: SpinLock::new("cwd", Some(src.cwd(ctx))),
+            }
+        })
+        .ok_or(())
+    }
+}
+
+impl ProcFiles {
+    /// Returns a new reference to the file of descriptor `fd`, if it is open.
+    pub fn get(&self, fd: i32) -> Option<RcFile> {
+        self.open_files.lock().get(fd as usize)?.clone()
+    }
+
+    /// Closes the descriptor `fd` and returns its file, if it is open.
+    pub fn take(&self, fd: i32) -> Option<RcFile> {
+        self.open_files.lock().get_mut(fd as usize)?.take()
+    }
+
+    /// Returns the open file descriptors as a bitmask.
+    pub fn open_fds(&self) -> u64 {
+        let open_files = self.open_files.lock();
+        (0..NOFILE)
+            .filter(|&fd| open_files[fd].is_some())
+            .fold(0, |fds, fd| fds | 1 << fd)
+    }
+
+    /// Returns a new reference to the current directory.
+    pub fn cwd(&self, ctx: &KernelCtx<'_, '_>) -> RcVnode {
+        self.cwd.lock().as_ref().expect("cwd").dup(ctx)
+    }
+
+    /// Changes the current directory to `cwd`, and returns the old one.
+    pub fn replace_cwd(&self, cwd: RcVnode) -> RcVnode {
+        self.cwd.lock().replace(cwd).expect("cwd")
     }
 }
@@ -0,0 +1,427 @@
+//! A file system that shows the registered devices.
+//!
+//! The root directory holds a device file for each device in the registry of
+//! devices (see `device.rs`), named by the name that its driver has registered.
+//! Opening a device file opens the device, as a device node made by `mknod`
+//! does. The directory is generated from the registry on each read, so the
+//! devices registered after mounting show up as well. The file system is
+//! read-only.
+
+use core::mem;
+use core::ops::Deref;
+
+use array_macro::array;
+use arrayvec::ArrayVec;
+use zerocopy::AsBytes;
+
+use super::{
+    Attr, FileName, FileSystem, FsType, Inode, InodeGuard, InodeType, Itable, RcInode, Stat, Tx,
+};
+use crate::{
+    arena::{Arena, ArrayArena},
+    errno::Errno,
+    lock::SleepLock,
+    param::{DEVDEV, MAXPATH, NDEVICE, NINODE},
+    proc::KernelCtx,
+    util::strong_pin::StrongPin,
+};
+
+/// root i-number
+const ROOTINO: u32 = 1;
+
+/// Directory is a file containing a sequence of dirent structures.
+const DIRSIZ: usize = 14;
+
+const DIRENT_SIZE: usize = mem::size_of::<Dirent>();
+
+/// Maximum size of the root directory, which holds ".", "..", and the devices.
+const ROOTSIZE: usize = (NDEVICE + 2) * DIRENT_SIZE;
+
+#[repr(C)]
+#[derive(Default, AsBytes)]
+pub struct Dirent {
+    pub inum: u16,
+    name: [u8; DIRSIZ],
+}
+
+/// The devfs keeps only the type of an inode, which it looks up from the
+/// registry when the inode is locked.
+pub struct InodeInner {
+    typ: InodeType,
+}
+
+pub struct Devfs {
+    itable: Itable<Self>,
+}
+
+impl Dirent {
+    fn new(name: &[u8], inum: u32) -> Self {
+        let mut de = Self {
+            inum: inum as u16,
+            ..Default::default()
+        };
+        let len = name.len().min(DIRSIZ);
+        de.name[..len].copy_from_slice(&name[..len]);
+        de
+    }
+}
+
+/// Returns the inode number of the device at `index` in the registry.
+fn inum_of(index: usize) -> u32 {
+    ROOTINO + 1 + index as u32
+}
+
+/// Returns the index of the device of the inode `inum` in the registry.
+fn index_of(inum: u32) -> usize {
+    (inum - ROOTINO - 1) as usize
+}
+
+impl const Default for Inode<Devfs> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Inode<Devfs> {
+    pub const fn new() -> Self {
+        Self {
+            dev: 0,
+            inum: 0,
+            inner: SleepLock::new(
+                "inode",
+                InodeInner {
+                    typ: InodeType::None,
+                },
+            ),
+        }
+    }
+
+    /// Looks up the type of the inode, which tells the device numbers of a
+    /// device file.
+    fn lookup_type(&self, ctx: &KernelCtx<'_, '_>) -> InodeType {
+        if self.inum == ROOTINO {
+            return InodeType::Dir;
+        }
+        let devices = ctx.kernel().devices().lock();
+        let dev = devices.devices()[index_of(self.inum)];
+        InodeType::Device {
+            major: dev.major,
+            minor: dev.minor,
+        }
+    }
+}
+
+impl Itable<Devfs> {
+    pub const fn new_itable() -> Self {
+        ArrayArena::<Inode<Devfs>, NINODE>::new("DEVITABLE")
+    }
+
+    /// Find the inode with number inum on device dev
+    /// and return the in-memory copy. Does not lock the inode.
+    pub fn get_inode(self: StrongPin<'_, Self>, dev: u32, inum: u32) -> RcInode<Devfs> {
+        self.find_or_alloc(
+            |inode| inode.dev == dev && inode.inum == inum,
+            |inode| {
+                inode.dev = dev;
+                inode.inum = inum;
+            },
+        )
+        .expect("[Itable::get_inode] no inodes")
+    }
+}
+
+impl Devfs {
+    pub const fn new() -> Self {
+        Self {
+            itable: Itable::<Self>::new_itable(),
+        }
+    }
+}
+
+impl FileSystem for Devfs {
+    type Dirent = Dirent;
+    type InodeInner = InodeInner;
+
+    // Nothing is ever written.
+    const MAX_WRITE: usize = ROOTSIZE;
+    const TYPE: FsType = FsType::Devfs;
+
+    fn init(&self, dev: u32, _ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        if dev == DEVDEV {
+            Ok(())
+        } else {
+            Err(Errno::EINVAL)
+        }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().itable) }
+    }
+
+    fn root(self: StrongPin<'_, Self>, dev: u32) -> RcInode<Self> {
+        self.itable().get_inode(dev, ROOTINO)
+    }
+
+    fn lookup(
+        self: StrongPin<'_, Self>,
+        dir: &mut InodeGuard<'_, Self>,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self>, Errno> {
+        if dir.inum != ROOTINO {
+            return Err(Errno::ENOTDIR);
+        }
+        let name = name.as_bytes();
+        let inum = if name == b"." || name == b".." {
+            ROOTINO
+        } else {
+            let devices = ctx.kernel().devices().lock();
+            let (index, _) = devices.find(name).ok_or(Errno::ENOENT)?;
+            inum_of(index)
+        };
+        Ok(self.itable().get_inode(dir.dev, inum))
+    }
+
+    fn readdir<F>(
+        self: StrongPin<'_, Self>,
+        dir: &mut InodeGuard<'_, Self>,
+        off: u32,
+        mut f: F,
+        ctx: &KernelCtx<'_, '_>,
+    ) where
+        F: FnMut(u32, u32, InodeType, &[u8]) -> bool,
+    {
+        if dir.inum != ROOTINO {
+            return;
+        }
+        // Copy the registry, so that it is not locked while `f` runs.
+        let registry = ctx.kernel().devices().lock();
+        let devices: ArrayVec<_, NDEVICE> = registry.devices().iter().copied().collect();
+        drop(registry);
+        let dots = [&b"."[..], b".."].map(|name| (name, ROOTINO, InodeType::Dir));
+        let devs = devices.iter().enumerate().map(|(i, dev)| {
+            let typ = InodeType::Device {
+                major: dev.major,
+                minor: dev.minor,
+            };
+            (dev.name.as_bytes(), inum_of(i), typ)
+        });
+        let entries = dots.into_iter().chain(devs);
+        // The offset after an entry is the number of entries up to it.
+        for (next, (name, inum, typ)) in (1..).zip(entries).skip(off as usize) {
+            if !f(next, inum, typ, name) {
+                break;
+            }
+        }
+    }
+
+    fn link(
+        self: StrongPin<'_, Self>,
+        _inode: &RcInode<Self>,
+        _dir: &RcInode<Self>,
+        _name: &FileName<{ MAXPATH }>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn unlink(
+        self: StrongPin<'_, Self>,
+        _dir: &RcInode<Self>,
+        _name: &FileName<{ MAXPATH }>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn dirlookup(
+        _dir: &mut InodeGuard<'_, Self>,
+        _name: &FileName<{ MAXPATH }>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<Self>, u32), Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn dirlink(
+        _dir: &mut InodeGuard<'_, Self>,
+        _name: &FileName<{ MAXPATH }>,
+        _inum: u32,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn dirunlink(
+        _dir: &mut InodeGuard<'_, Self>,
+        _off: u32,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) {
+    }
+
+    fn dirrelink(
+        _dir: &mut InodeGuard<'_, Self>,
+        _off: u32,
+        _inum: u32,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) {
+    }
+
+    fn is_dir_empty(_dir: &mut InodeGuard<'_, Self>, _ctx: &KernelCtx<'_, '_>) -> bool {
+        false
+    }
+
+    fn create<F, T>(
+        self: StrongPin<'_, Self>,
+        _dir: &RcInode<Self>,
+        _name: &FileName<{ MAXPATH }>,
+        _typ: InodeType,
+        _attr: Attr,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+        _f: F,
+    ) -> Result<(RcInode<Self>, T), Errno>
+    where
+        F: FnOnce(&mut InodeGuard<'_, Self>) -> T,
+    {
+        Err(Errno::EROFS)
+    }
+
+    fn tx_begin(&self, _ctx: &KernelCtx<'_, '_>) {}
+
+    unsafe fn tx_end(&self, _tx: &mut Tx<'_, Self>, _ctx: &KernelCtx<'_, '_>) {}
+
+    fn sync(&self, _ctx: &KernelCtx<'_, '_>) {}
+
+    fn dirty_since(&self, _ctx: &KernelCtx<'_, '_>) -> Option<u32> {
+        None
+    }
+
+    fn inode_read<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), Errno>,
+    >(
+        guard: &mut InodeGuard<'_, Self>,
+        off: u32,
+        n: u32,
+        mut f: F,
+        mut k: K,
+    ) -> Result<usize, Errno> {
+        // The content of a device file is read from the device instead.
+        if guard.inum != ROOTINO {
+            return Ok(0);
+        }
+        let mut dirents = array![_ => Dirent::default(); NDEVICE + 2];
+        dirents[0] = Dirent::new(b".", ROOTINO);
+        dirents[1] = Dirent::new(b"..", ROOTINO);
+        let devices = k.kernel().devices().lock();
+        for (i, dev) in devices.devices().iter().enumerate() {
+            dirents[i + 2] = Dirent::new(dev.name.as_bytes(), inum_of(i));
+        }
+        let size = (devices.devices().len() + 2) * DIRENT_SIZE;
+        drop(devices);
+
+        let begin = (off as usize).min(size);
+        let end = (begin + n as usize).min(size);
+        f(0, &dirents.as_bytes()[begin..end], &mut k)?;
+        Ok(end - begin)
+    }
+
+    fn inode_write<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), Errno>,
+    >(
+        _guard: &mut InodeGuard<'_, Self>,
+        _off: u32,
+        _n: u32,
+        _f: F,
+        _tx: &Tx<'_, Self>,
+        _k: K,
+    ) -> Result<usize, Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn inode_trunc(
+        _guard: &mut InodeGuard<'_, Self>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) {
+    }
+
+    fn inode_lock<'a>(inode: &'a Inode<Self>, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'a, Self> {
+        let mut guard = inode.inner.lock(ctx);
+        guard.typ = inode.lookup_type(ctx);
+        mem::forget(guard);
+        InodeGuard { inode }
+    }
+
+    fn inode_finalize<'a, 'id: 'a>(
+        _inode: &mut Inode<Self>,
+        _tx: &'a Tx<'a, Self>,
+        _ctx: &'a KernelCtx<'id, 'a>,
+    ) {
+    }
+
+    fn inode_stat(inode: &Inode<Self>, ctx: &KernelCtx<'_, '_>) -> Stat {
+        let ip = inode.lock(ctx);
+        let attr = Self::inode_attr(&ip);
+        let st = Stat {
+            dev: inode.dev as i32,
+            ino: inode.inum,
+            typ: match ip.deref_inner().typ {
+                InodeType::Dir => 1,
+                _ => 3,
+            },
+            nlink: 1,
+            _padding: 0,
+            size: 0,
+            mode: attr.mode as u32,
+            uid: attr.uid,
+            gid: attr.gid,
+            atime: attr.atime,
+            mtime: attr.mtime,
+            ctime: attr.ctime,
+        };
+        ip.free(ctx);
+        st
+    }
+
+    fn inode_type(guard: &InodeGuard<'_, Self>) -> InodeType {
+        guard.deref_inner().typ
+    }
+
+    fn inode_nlink(_guard: &InodeGuard<'_, Self>) -> i16 {
+        1
+    }
+
+    fn inode_set_nlink(
+        _guard: &mut InodeGuard<'_, Self>,
+        _nlink: i16,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) {
+    }
+
+    /// The root and the devices belong to root, and have the default modes.
+    fn inode_attr(guard: &InodeGuard<'_, Self>) -> Attr {
+        Attr::new(guard.deref_inner().typ, 0, 0)
+    }
+
+    fn inode_set_attr(
+        _guard: &mut InodeGuard<'_, Self>,
+        _attr: Attr,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+}
@@ -125,7 +125,7 @@ impl Lfs {
     ) -> Option<(DSegSum, usize)> {
         // 1. read the segment summary block
         let superblock = self.superblock();
-        let buf = hal().disk().read(
+        let buf = hal().disks().read(
             dev,
             superblock.seg_to_disk_block_no(seg_no, seg_block_no),
             ctx,
@@ -61,7 +61,7 @@ impl Imap {
 
     /// Returns the `block_no`th block of the imap.
     fn get_imap_block(&self, block_no: usize, ctx: &KernelCtx<'_, '_>) -> Buf {
-        hal().disk().read(self.dev_no, self.addr[block_no], ctx)
+        hal().disks().read(self.dev_no, self.addr[block_no], ctx)
     }
 
     /// Returns the disk block number of the imap's `n`th block.
@@ -106,7 +106,7 @@ impl Imap {
     /// For the inode with inode number `inum`, returns the disk_block_no of it.
     pub fn get(&self, inum: u32, ctx: &KernelCtx<'_, '_>) -> u32 {
         assert!(
-            0 < inum && inum < ctx.kernel().fs().superblock().ninodes(),
+            0 < inum && inum < ctx.kernel().lfs(self.dev_no).superblock().ninodes(),
             "invalid inum"
         );
         let (block_no, offset) = self.get_imap_block_no(inum);
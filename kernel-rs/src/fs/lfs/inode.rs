@@ -3,14 +3,15 @@ use core::{iter::StepBy, mem, ops::Range};
 use static_assertions::const_assert;
 use zerocopy::{AsBytes, FromBytes};
 
-use super::{FileName, Lfs, Path, SegManager, NDIRECT, NINDIRECT, ROOTINO};
+use super::{FileName, Lfs, SegManager, NDIRECT, NINDIRECT};
 use crate::{
     arena::{Arena, ArrayArena},
     bio::{Buf, BufData},
-    fs::{DInodeType, Inode, InodeGuard, InodeType, Itable, RcInode, Tx},
+    errno::Errno,
+    fs::{Attr, DInodeType, Inode, InodeGuard, InodeType, Itable, RcInode, Tx},
     hal::hal,
     lock::SleepLock,
-    param::{NINODE, ROOTDEV},
+    param::NINODE,
     proc::KernelCtx,
     util::{memset, strong_pin::StrongPin},
 };
@@ -34,6 +35,8 @@ pub struct InodeInner {
     pub addr_direct: [u32; NDIRECT],
     /// indirect address
     pub addr_indirect: u32,
+    /// permission bits, owner, and times
+    pub attr: Attr,
 }
 
 /// On-disk inode structure
@@ -64,6 +67,42 @@ pub struct Dinode {
 
     /// Indirect data block address
     pub addr_indirect: u32,
+
+    /// Permission bits
+    pub mode: u16,
+
+    _padding: u16,
+
+    /// Owner and group
+    pub uid: u32,
+    pub gid: u32,
+
+    /// Times of last access, modification, and status change, in seconds
+    /// since the Unix epoch
+    pub atime: u32,
+    pub mtime: u32,
+    pub ctime: u32,
+
+    /// Unused, so that the size of an inode divides `BSIZE`.
+    _reserved: [u32; 10],
+}
+
+impl Dinode {
+    /// Returns the type of the inode, with its device numbers if it is a device.
+    pub fn inode_type(&self) -> InodeType {
+        match self.typ {
+            DInodeType::None => InodeType::None,
+            DInodeType::Dir => InodeType::Dir,
+            DInodeType::File => InodeType::File,
+            DInodeType::Symlink => InodeType::Symlink,
+            DInodeType::Device => {
+                InodeType::Device {
+                    major: self.major,
+                    minor: self.minor,
+                }
+            }
+        }
+    }
 }
 
 impl<'s> TryFrom<&'s BufData> for &'s Dinode {
@@ -110,7 +149,11 @@ pub struct Dirent {
 }
 
 impl Dirent {
-    fn new(ip: &mut InodeGuard<'_, Lfs>, off: u32, ctx: &KernelCtx<'_, '_>) -> Result<Dirent, ()> {
+    fn new(
+        ip: &mut InodeGuard<'_, Lfs>,
+        off: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Dirent, Errno> {
         let mut dirent = Dirent::default();
         ip.read_kernel(&mut dirent, off, ctx)?;
         Ok(dirent)
@@ -162,7 +205,17 @@ impl Iterator for DirentIter<'_, '_, '_> {
 
 impl<'t> InodeGuard<'t, Lfs> {
     fn iter_dirents<'id, 's>(&'s mut self, ctx: &'s KernelCtx<'id, 's>) -> DirentIter<'id, 's, 't> {
-        let iter = (0..self.deref_inner().size).step_by(DIRENT_SIZE);
+        self.iter_dirents_from(0, ctx)
+    }
+
+    /// Iterates the entries from the offset `off`, which must be a multiple of
+    /// `DIRENT_SIZE`.
+    fn iter_dirents_from<'id, 's>(
+        &'s mut self,
+        off: u32,
+        ctx: &'s KernelCtx<'id, 's>,
+    ) -> DirentIter<'id, 's, 't> {
+        let iter = (off..self.deref_inner().size).step_by(DIRENT_SIZE);
         DirentIter {
             guard: self,
             iter,
@@ -176,17 +229,18 @@ impl<'t> InodeGuard<'t, Lfs> {
 /// Handling directories
 impl InodeGuard<'_, Lfs> {
     /// Write a new directory entry (name, inum) into the directory dp.
+    /// Returns Err(EEXIST) if name is already present.
     pub fn dirlink(
         &mut self,
         name: &FileName<DIRSIZ>,
         inum: u32,
         tx: &Tx<'_, Lfs>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), Errno> {
         // Check that name is not present.
         if let Ok((ip, _)) = self.dirlookup(name, ctx) {
             ip.free((tx, ctx));
-            return Err(());
+            return Err(Errno::EEXIST);
         };
 
         // Look for an empty Dirent.
@@ -200,13 +254,40 @@ impl InodeGuard<'_, Lfs> {
         Ok(())
     }
 
+    /// Make the directory entry at offset `off` refer to the inode `inum`.
+    pub fn dirrelink(&mut self, off: u32, inum: u32, tx: &Tx<'_, Lfs>, ctx: &KernelCtx<'_, '_>) {
+        // The entry begins with the inode number.
+        self.write_kernel(&(inum as u16), off, tx, ctx)
+            .expect("dirrelink");
+    }
+
+    /// Calls `f(next, inum, name)` for each used entry from the offset `off`,
+    /// until `f` returns false. `next` is the offset after the entry.
+    pub fn dirread<F>(&mut self, off: u32, mut f: F, ctx: &KernelCtx<'_, '_>)
+    where
+        F: FnMut(u32, u32, &[u8]) -> bool,
+    {
+        // Start from the entry after the one that `off` is in the middle of.
+        let off = (off + DIRENT_SIZE as u32 - 1) / DIRENT_SIZE as u32 * DIRENT_SIZE as u32;
+        let dirents = self.iter_dirents_from(off, ctx);
+        for (de, off) in dirents.filter(|(de, _)| de.inum != 0) {
+            if !f(
+                off + DIRENT_SIZE as u32,
+                de.inum as u32,
+                de.get_name().as_bytes(),
+            ) {
+                break;
+            }
+        }
+    }
+
     /// Look for a directory entry in a directory.
-    /// If found, return the entry and byte offset of entry.
+    /// If found, return the entry and byte offset of entry. Otherwise, return Err(ENOENT).
     pub fn dirlookup(
         &mut self,
         name: &FileName<DIRSIZ>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<Lfs>, u32), ()> {
+    ) -> Result<(RcInode<Lfs>, u32), Errno> {
         assert_eq!(self.deref_inner().typ, InodeType::Dir, "dirlookup not DIR");
 
         self.iter_dirents(ctx)
@@ -214,13 +295,13 @@ impl InodeGuard<'_, Lfs> {
             .map(|(de, off)| {
                 (
                     ctx.kernel()
-                        .fs()
+                        .lfs(self.dev)
                         .itable()
                         .get_inode(self.dev, de.inum as u32),
                     off,
                 )
             })
-            .ok_or(())
+            .ok_or(Errno::ENOENT)
     }
 }
 
@@ -263,6 +344,11 @@ impl InodeGuard<'_, Lfs> {
                 dip.major = 0;
                 dip.minor = 0;
             }
+            InodeType::Symlink => {
+                dip.typ = DInodeType::Symlink;
+                dip.major = 0;
+                dip.minor = 0;
+            }
         }
 
         (*dip).nlink = inner.nlink;
@@ -271,6 +357,15 @@ impl InodeGuard<'_, Lfs> {
             *d = *s;
         }
         (*dip).addr_indirect = inner.addr_indirect;
+        // An old image does not keep the attributes.
+        if tx.fs.superblock().attrs() {
+            (*dip).mode = inner.attr.mode;
+            (*dip).uid = inner.attr.uid;
+            (*dip).gid = inner.attr.gid;
+            (*dip).atime = inner.attr.atime;
+            (*dip).mtime = inner.attr.mtime;
+            (*dip).ctime = inner.attr.ctime;
+        }
 
         bp.free(ctx);
         if seg.is_full() {
@@ -301,7 +396,7 @@ impl InodeGuard<'_, Lfs> {
             None
         } else {
             // Read the indirect block.
-            let bp = hal().disk().read(self.dev, inner.addr_indirect, ctx);
+            let bp = hal().disks().read(self.dev, inner.addr_indirect, ctx);
             // Get the address.
             let data: &[u32; NINDIRECT] = bp.data().into();
             let addr = data[bn - NDIRECT];
@@ -322,7 +417,7 @@ impl InodeGuard<'_, Lfs> {
     /// Any write operations to a inode's data block should be done using `InodeGuard::bmap_write`.
     pub fn readable_data_block(&self, bn: usize, ctx: &KernelCtx<'_, '_>) -> Buf {
         let addr = self.read_addr(bn, ctx).expect("bmap: out of range");
-        hal().disk().read(self.dev, addr, ctx)
+        hal().disks().read(self.dev, addr, ctx)
     }
 
     /// Copies the inode's `bn`th data block content into an empty block on the segment,
@@ -402,7 +497,7 @@ impl InodeGuard<'_, Lfs> {
                 .unwrap();
             if new_addr != addr {
                 // Copy from old block to new block.
-                let old_buf = hal().disk().read(self.dev, addr, ctx);
+                let old_buf = hal().disks().read(self.dev, addr, ctx);
                 buf.data_mut().copy_from(old_buf.data());
                 old_buf.free(ctx);
             }
@@ -433,7 +528,7 @@ impl InodeGuard<'_, Lfs> {
                 .unwrap();
             if indirect != new_indirect {
                 // Copy from old block to new block.
-                let old_bp = hal().disk().read(self.dev, indirect, ctx);
+                let old_bp = hal().disks().read(self.dev, indirect, ctx);
                 bp.data_mut().copy_from(old_bp.data());
                 old_bp.free(ctx);
                 self.deref_inner_mut().addr_indirect = new_indirect;
@@ -477,6 +572,7 @@ impl Inode<Lfs> {
                     size: 0,
                     addr_direct: [0; NDIRECT],
                     addr_indirect: 0,
+                    attr: Attr::default(),
                 },
             ),
         }
@@ -527,6 +623,7 @@ impl Itable<Lfs> {
             InodeType::None => dip.typ = DInodeType::None,
             InodeType::Dir => dip.typ = DInodeType::Dir,
             InodeType::File => dip.typ = DInodeType::File,
+            InodeType::Symlink => dip.typ = DInodeType::Symlink,
             InodeType::Device { major, minor } => {
                 dip.typ = DInodeType::Device;
                 dip.major = major;
@@ -548,67 +645,4 @@ impl Itable<Lfs> {
 
         self.get_inode(dev, inum)
     }
-
-    pub fn root(self: StrongPin<'_, Self>) -> RcInode<Lfs> {
-        self.get_inode(ROOTDEV, ROOTINO)
-    }
-
-    pub fn namei(
-        self: StrongPin<'_, Self>,
-        path: &Path,
-        tx: &Tx<'_, Lfs>,
-        proc: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Lfs>, ()> {
-        Ok(self.namex(path, false, tx, proc)?.0)
-    }
-
-    pub fn nameiparent<'s>(
-        self: StrongPin<'_, Self>,
-        path: &'s Path,
-        tx: &Tx<'_, Lfs>,
-        ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<Lfs>, &'s FileName<{ DIRSIZ }>), ()> {
-        let (ip, name_in_path) = self.namex(path, true, tx, ctx)?;
-        let name_in_path = name_in_path.ok_or(())?;
-        Ok((ip, name_in_path))
-    }
-
-    fn namex<'s>(
-        self: StrongPin<'_, Self>,
-        mut path: &'s Path,
-        parent: bool,
-        tx: &Tx<'_, Lfs>,
-        ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<Lfs>, Option<&'s FileName<{ DIRSIZ }>>), ()> {
-        let mut ptr = if path.is_absolute() {
-            self.root()
-        } else {
-            ctx.proc().cwd().clone()
-        };
-
-        while let Some((new_path, name)) = path.skipelem() {
-            path = new_path;
-
-            let mut ip = ptr.lock(ctx);
-            if ip.deref_inner().typ != InodeType::Dir {
-                ip.free(ctx);
-                ptr.free((tx, ctx));
-                return Err(());
-            }
-            if parent && path.is_empty_string() {
-                // Stop one level early.
-                ip.free(ctx);
-                return Ok((ptr, Some(name)));
-            }
-            let next = ip.dirlookup(name, ctx);
-            ip.free(ctx);
-            ptr.free((tx, ctx));
-            ptr = next?.0
-        }
-        if parent {
-            ptr.free((tx, ctx));
-            return Err(());
-        }
-        Ok((ptr, None))
-    }
 }
@@ -11,6 +11,7 @@ use static_assertions::const_assert;
 use super::{Imap, Itable, SegManager, SegTable, Superblock, Tx, TxManager};
 use crate::{
     bio::BufData,
+    errno::Errno,
     hal::hal,
     lock::{SleepLock, SleepLockGuard, SleepableLock},
     param::IMAPSIZE,
@@ -20,8 +21,10 @@ use crate::{
 
 #[pin_project]
 pub struct Lfs {
+    /// The disk that the file system is loaded from. An `Lfs` is loaded from one disk only.
+    dev: Once<u32>,
+
     /// Initializing superblock should run only once because forkret() calls FileSystem::init().
-    /// There should be one superblock per disk device, but we run with only one device.
     superblock: Once<Superblock>,
 
     /// In-memory inodes.
@@ -108,6 +111,7 @@ impl Tx<'_, Lfs> {
 impl Lfs {
     pub const fn new() -> Self {
         Self {
+            dev: Once::new(),
             superblock: Once::new(),
             itable: Itable::<Self>::new_itable(),
             segmanager: Once::new(),
@@ -188,20 +192,32 @@ impl Lfs {
         self.tx_manager.get().expect("tx_manager")
     }
 
-    /// Initializes `self`.
-    /// Does nothing if already initialized.
-    pub fn initialize(&self, dev: u32, ctx: &KernelCtx<'_, '_>) {
+    /// Returns `true` if `self` has been initialized.
+    pub fn is_initialized(&self) -> bool {
+        self.tx_manager.is_completed()
+    }
+
+    /// Initializes `self` from the disk `dev`.
+    /// Does nothing if already initialized from `dev`.
+    /// Returns `Err(EINVAL)` if `dev` does not hold an lfs, or `Err(EBUSY)` if
+    /// `self` is initialized from another disk.
+    pub fn initialize(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
         if !self.superblock.is_completed() {
             // Load the superblock.
-            let buf = hal().disk().read(dev, 1, ctx);
-            let superblock = self.superblock.call_once(|| Superblock::new(&buf));
+            let buf = hal().disks().read(dev, 1, ctx);
+            let superblock = Superblock::new(&buf);
             buf.free(ctx);
+            let superblock = superblock?;
+            if *self.dev.call_once(|| dev) != dev {
+                return Err(Errno::EBUSY);
+            }
+            let superblock = self.superblock.call_once(|| superblock);
 
             // Load the checkpoint.
             let (bno1, bno2) = superblock.get_chkpt_block_no();
-            let buf1 = hal().disk().read(dev, bno1, ctx);
+            let buf1 = hal().disks().read(dev, bno1, ctx);
             let chkpt1: &Checkpoint = buf1.data().into();
-            let buf2 = hal().disk().read(dev, bno2, ctx);
+            let buf2 = hal().disks().read(dev, bno2, ctx);
             let chkpt2: &Checkpoint = buf2.data().into();
 
             let (chkpt, timestamp, stored_at_first) = if chkpt1.timestamp > chkpt2.timestamp {
@@ -233,6 +249,11 @@ impl Lfs {
                 )
             });
         }
+        if self.dev.get() == Some(&dev) {
+            Ok(())
+        } else {
+            Err(Errno::EBUSY)
+        }
     }
 
     /// Commits the checkpoint at the checkpoint region.
@@ -254,7 +275,7 @@ impl Lfs {
         chkpt.segtable = seg.dsegtable();
         chkpt.imap = imap.dimap();
         chkpt.timestamp = timestamp;
-        hal().disk().write(&mut buf, ctx);
+        hal().disks().write(&mut buf, ctx);
         buf.free(ctx);
     }
 }
@@ -1,16 +1,15 @@
-use core::cell::UnsafeCell;
 use core::mem;
 use core::ops::Deref;
 
 use super::{
-    DInodeType, FcntlFlags, FileName, FileSystem, Inode, InodeGuard, InodeType, Itable, Path,
-    RcInode, Stat, Tx,
+    Attr, FileName, FileSystem, FsType, Inode, InodeGuard, InodeType, Itable, RcInode, Stat, Tx,
 };
 use crate::{
-    file::{FileType, InodeFileType},
+    errno::Errno,
     hal::hal,
-    param::BSIZE,
+    param::{BSIZE, MAXOPBLOCKS, MAXPATH},
     proc::KernelCtx,
+    timer::realtime,
     util::strong_pin::StrongPin,
 };
 
@@ -23,7 +22,7 @@ mod superblock;
 mod tx;
 
 use imap::Imap;
-use inode::{Dinode, Dirent, InodeInner};
+use inode::{Dinode, Dirent, InodeInner, DIRSIZ};
 pub use lfs::Lfs;
 use segment::{SegManager, SegTable};
 use superblock::Superblock;
@@ -40,73 +39,117 @@ impl FileSystem for Lfs {
     type Dirent = Dirent;
     type InodeInner = InodeInner;
 
-    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) {
-        self.initialize(dev, ctx);
+    // Write a few blocks at a time to avoid exceeding the maximum log
+    // transaction size, including i-node, indirect block, allocation blocks,
+    // and 2 blocks of slop for non-aligned writes.
+    // Need to subtract one more because of the imap.
+    const MAX_WRITE: usize = (MAXOPBLOCKS - 1 - 1 - 2 - 1) / 2 * BSIZE;
+    const TYPE: FsType = FsType::Lfs;
+
+    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        self.initialize(dev, ctx)
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
+        Lfs::itable(self)
     }
 
-    fn root(self: StrongPin<'_, Self>) -> RcInode<Self> {
-        self.itable().root()
+    fn root(self: StrongPin<'_, Self>, dev: u32) -> RcInode<Self> {
+        self.itable().get_inode(dev, ROOTINO)
     }
 
-    fn namei(
+    fn lookup(
         self: StrongPin<'_, Self>,
-        path: &Path,
-        tx: &Tx<'_, Self>,
+        dir: &mut InodeGuard<'_, Self>,
+        name: &FileName<{ MAXPATH }>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Self>, ()> {
-        // name-to-inode translation
-        self.itable().namei(path, tx, ctx)
+    ) -> Result<RcInode<Self>, Errno> {
+        Ok(dir.dirlookup(name.truncate(), ctx)?.0)
+    }
+
+    fn readdir<F>(
+        self: StrongPin<'_, Self>,
+        dir: &mut InodeGuard<'_, Self>,
+        off: u32,
+        mut f: F,
+        ctx: &KernelCtx<'_, '_>,
+    ) where
+        F: FnMut(u32, u32, InodeType, &[u8]) -> bool,
+    {
+        // An entry stores only the inode number, so the type is read from the
+        // inode itself. The in-memory inode is not locked, since it may be
+        // `dir` or its parent.
+        let dev = dir.dev;
+        dir.dirread(
+            off,
+            |next, inum, name| {
+                let imap = self.imap(ctx);
+                let bp = hal().disks().read(dev, imap.get(inum, ctx), ctx);
+                imap.free(ctx);
+                let dip: &Dinode = bp.data().try_into().unwrap();
+                let typ = dip.inode_type();
+                bp.free(ctx);
+                f(next, inum, typ, name)
+            },
+            ctx,
+        );
     }
 
     fn link(
         self: StrongPin<'_, Self>,
-        inode: RcInode<Self>,
-        path: &Path,
+        inode: &RcInode<Self>,
+        dir: &RcInode<Self>,
+        name: &FileName<{ MAXPATH }>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
-        // Create another name `path` by linking to inode
-        let inode = scopeguard::guard(inode, |ptr| ptr.free((tx, ctx)));
+    ) -> Result<(), Errno> {
+        // Create another name `name` in `dir` by linking to inode
         let ip = inode.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         if ip.deref_inner().typ == InodeType::Dir {
-            return Err(());
+            return Err(Errno::EPERM);
         }
         ip.deref_inner_mut().nlink += 1;
+        ip.deref_inner_mut().attr.ctime = realtime();
         ip.update(tx, ctx);
         drop(ip);
 
-        if let Ok((ptr2, name)) = self.itable().nameiparent(path, tx, ctx) {
-            let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
-            let dp = ptr2.lock(ctx);
+        let res = {
+            let dp = dir.lock(ctx);
             let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
-            if dp.dev == inode.dev && dp.dirlink(name, inode.inum, tx, ctx).is_ok() {
-                return Ok(());
+            if dp.dev != inode.dev {
+                Err(Errno::EXDEV)
+            } else {
+                dp.dirlink(name.truncate(), inode.inum, tx, ctx)
             }
+        };
+        if res.is_ok() {
+            return Ok(());
         }
 
         let ip = inode.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         ip.deref_inner_mut().nlink -= 1;
         ip.update(tx, ctx);
-        Err(())
+        res
     }
 
     fn unlink(
         self: StrongPin<'_, Self>,
-        path: &Path,
+        dir: &RcInode<Self>,
+        name: &FileName<{ MAXPATH }>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
-        // remove a file with `path`
-        let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
-        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-        let dp = ptr.lock(ctx);
+    ) -> Result<(), Errno> {
+        // remove the file `name` in `dir`
+        let name = name.truncate::<DIRSIZ>();
+        let dp = dir.lock(ctx);
         let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
 
         // Cannot unlink "." or "..".
         if name.as_bytes() == b"." || name.as_bytes() == b".." {
-            return Err(());
+            return Err(Errno::EINVAL);
         }
 
         let (ptr2, off) = dp.dirlookup(name, ctx)?;
@@ -116,7 +159,7 @@ impl FileSystem for Lfs {
         assert!(ip.deref_inner().nlink >= 1, "unlink: nlink < 1");
 
         if ip.deref_inner().typ == InodeType::Dir && !ip.is_dir_empty(ctx) {
-            return Err(());
+            return Err(Errno::ENOTEMPTY);
         }
 
         dp.write_kernel(&Dirent::default(), off, tx, ctx)
@@ -126,37 +169,80 @@ impl FileSystem for Lfs {
             dp.update(tx, ctx);
         }
         drop(dp);
-        drop(ptr);
         ip.deref_inner_mut().nlink -= 1;
+        ip.deref_inner_mut().attr.ctime = realtime();
         ip.update(tx, ctx);
         Ok(())
     }
 
+    fn dirlookup(
+        dir: &mut InodeGuard<'_, Self>,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<Self>, u32), Errno> {
+        dir.dirlookup(name.truncate::<DIRSIZ>(), ctx)
+    }
+
+    fn dirlink(
+        dir: &mut InodeGuard<'_, Self>,
+        name: &FileName<{ MAXPATH }>,
+        inum: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        dir.dirlink(name.truncate::<DIRSIZ>(), inum, tx, ctx)
+    }
+
+    fn dirunlink(
+        dir: &mut InodeGuard<'_, Self>,
+        off: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        dir.write_kernel(&Dirent::default(), off, tx, ctx)
+            .expect("dirunlink");
+    }
+
+    fn dirrelink(
+        dir: &mut InodeGuard<'_, Self>,
+        off: u32,
+        inum: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        dir.dirrelink(off, inum, tx, ctx);
+    }
+
+    fn is_dir_empty(dir: &mut InodeGuard<'_, Self>, ctx: &KernelCtx<'_, '_>) -> bool {
+        dir.is_dir_empty(ctx)
+    }
+
     fn create<F, T>(
         self: StrongPin<'_, Self>,
-        path: &Path,
+        dir: &RcInode<Self>,
+        name: &FileName<{ MAXPATH }>,
         typ: InodeType,
+        attr: Attr,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
         f: F,
-    ) -> Result<(RcInode<Self>, T), ()>
+    ) -> Result<(RcInode<Self>, T), Errno>
     where
         F: FnOnce(&mut InodeGuard<'_, Self>) -> T,
     {
-        let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
-        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-        let dp = ptr.lock(ctx);
+        let name = name.truncate::<DIRSIZ>();
+        let dp = dir.lock(ctx);
         let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
         if let Ok((ptr2, _)) = dp.dirlookup(name, ctx) {
             let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
             drop(dp);
             if typ != InodeType::File {
-                return Err(());
+                return Err(Errno::EEXIST);
             }
             let ip = ptr2.lock(ctx);
             let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
             if let InodeType::None | InodeType::Dir = ip.deref_inner().typ {
-                return Err(());
+                return Err(Errno::EISDIR);
             }
             let ret = f(&mut ip);
             drop(ip);
@@ -166,6 +252,7 @@ impl FileSystem for Lfs {
         let ip = ptr2.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         ip.deref_inner_mut().nlink = 1;
+        ip.deref_inner_mut().attr = attr;
         ip.update(tx, ctx);
 
         // Create . and .. entries.
@@ -188,84 +275,6 @@ impl FileSystem for Lfs {
         Ok((ptr2, ret))
     }
 
-    fn open(
-        self: StrongPin<'_, Self>,
-        path: &Path,
-        omode: FcntlFlags,
-        tx: &Tx<'_, Self>,
-        ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
-        let (ip, typ) = if omode.contains(FcntlFlags::O_CREATE) {
-            self.create(path, InodeType::File, tx, ctx, |ip| ip.deref_inner().typ)?
-        } else {
-            let ptr = self.itable().namei(path, tx, ctx)?;
-            let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-            let ip = ptr.lock(ctx);
-            let ip = scopeguard::guard(ip, |ip| ip.free(ctx));
-            let typ = ip.deref_inner().typ;
-
-            if typ == InodeType::Dir && omode != FcntlFlags::O_RDONLY {
-                return Err(());
-            }
-            drop(ip);
-            (scopeguard::ScopeGuard::into_inner(ptr), typ)
-        };
-
-        let filetype = match typ {
-            InodeType::Device { major, .. } => FileType::Device { ip, major },
-            _ => {
-                FileType::Inode {
-                    inner: InodeFileType {
-                        ip,
-                        off: UnsafeCell::new(0),
-                    },
-                }
-            }
-        };
-
-        let f = ctx.kernel().ftable().alloc_file(
-            filetype,
-            !omode.intersects(FcntlFlags::O_WRONLY),
-            omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR),
-        )?;
-
-        if omode.contains(FcntlFlags::O_TRUNC) && typ == InodeType::File {
-            match &f.typ {
-                // It is safe to call itrunc because ip.lock() is held
-                FileType::Device { ip, .. }
-                | FileType::Inode {
-                    inner: InodeFileType { ip, .. },
-                } => {
-                    let mut ip = ip.lock(ctx);
-                    ip.trunc(tx, ctx);
-                    ip.free(ctx);
-                }
-                _ => panic!("sys_open : Not reach"),
-            };
-        }
-        let fd = f.fdalloc(ctx)?;
-        Ok(fd as usize)
-    }
-
-    fn chdir(
-        self: StrongPin<'_, Self>,
-        inode: RcInode<Self>,
-        tx: &Tx<'_, Self>,
-        ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
-        // change the current directory
-        let ip = inode.lock(ctx);
-        let typ = ip.deref_inner().typ;
-        ip.free(ctx);
-        if typ != InodeType::Dir {
-            inode.free((tx, ctx));
-            return Err(());
-        }
-
-        mem::replace(ctx.proc_mut().cwd_mut(), inode).free((tx, ctx));
-        Ok(())
-    }
-
     fn tx_begin(&self, ctx: &KernelCtx<'_, '_>) {
         self.tx_manager().begin_op(self, ctx);
     }
@@ -275,19 +284,31 @@ impl FileSystem for Lfs {
         self.tx_manager().end_op(self, tx, ctx);
     }
 
+    fn sync(&self, ctx: &KernelCtx<'_, '_>) {
+        if self.is_initialized() {
+            self.tx_manager().sync(self, ctx);
+        }
+    }
+
+    /// The segment is written when it fills up, and the checkpoint once in the
+    /// expire time (see `writeback`), so the time is not kept.
+    fn dirty_since(&self, _ctx: &KernelCtx<'_, '_>) -> Option<u32> {
+        None
+    }
+
     #[inline]
     fn inode_read<
         'id,
         's,
         K: Deref<Target = KernelCtx<'id, 's>>,
-        F: FnMut(u32, &[u8], &mut K) -> Result<(), ()>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), Errno>,
     >(
         guard: &mut InodeGuard<'_, Self>,
         mut off: u32,
         mut n: u32,
         mut f: F,
         mut k: K,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         // read inode
         let inner = guard.deref_inner();
         if off > inner.size || off.wrapping_add(n) < off {
@@ -296,6 +317,9 @@ impl FileSystem for Lfs {
         if off + n > inner.size {
             n = inner.size - off;
         }
+        // Reading does not write the disk, so the access time reaches the
+        // disk with the next update of the inode.
+        guard.deref_inner_mut().attr.atime = realtime();
         let mut tot: u32 = 0;
         while tot < n {
             let bp = guard.readable_data_block(off as usize / BSIZE, &k);
@@ -315,7 +339,7 @@ impl FileSystem for Lfs {
         'id,
         's,
         K: Deref<Target = KernelCtx<'id, 's>>,
-        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), ()>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), Errno>,
     >(
         guard: &mut InodeGuard<'_, Self>,
         mut off: u32,
@@ -323,13 +347,13 @@ impl FileSystem for Lfs {
         mut f: F,
         tx: &Tx<'_, Lfs>,
         mut k: K,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         // write the inode
         if off > guard.deref_inner().size {
-            return Err(());
+            return Err(Errno::EINVAL);
         }
-        if off.checked_add(n).ok_or(())? as usize > MAXFILE * BSIZE {
-            return Err(());
+        if off.checked_add(n).ok_or(Errno::EFBIG)? as usize > MAXFILE * BSIZE {
+            return Err(Errno::EFBIG);
         }
         let mut tot: u32 = 0;
         while tot < n {
@@ -354,6 +378,11 @@ impl FileSystem for Lfs {
         if off > guard.deref_inner().size {
             guard.deref_inner_mut().size = off;
         }
+        if tot > 0 {
+            let attr = &mut guard.deref_inner_mut().attr;
+            attr.mtime = realtime();
+            attr.ctime = attr.mtime;
+        }
 
         // Write the i-node back to disk even if the size didn't change
         // because the loop above might have called bmap() and added a new
@@ -366,35 +395,49 @@ impl FileSystem for Lfs {
         guard.deref_inner_mut().addr_direct = [0; NDIRECT];
         guard.deref_inner_mut().addr_indirect = 0;
         guard.deref_inner_mut().size = 0;
+        let attr = &mut guard.deref_inner_mut().attr;
+        attr.mtime = realtime();
+        attr.ctime = attr.mtime;
         guard.update(tx, ctx);
     }
 
     fn inode_lock<'a>(inode: &'a Inode<Self>, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'a, Self> {
         let mut guard = inode.inner.lock(ctx);
         if !guard.valid {
-            let fs = ctx.kernel().fs();
+            let fs = ctx.kernel().lfs(inode.dev);
             let imap = fs.imap(ctx);
-            let bp = hal().disk().read(inode.dev, imap.get(inode.inum, ctx), ctx);
+            let bp = hal()
+                .disks()
+                .read(inode.dev, imap.get(inode.inum, ctx), ctx);
             imap.free(ctx);
 
             let dip: &Dinode = bp.data().try_into().unwrap();
-            match dip.typ {
-                DInodeType::None => guard.typ = InodeType::None,
-                DInodeType::Dir => guard.typ = InodeType::Dir,
-                DInodeType::File => guard.typ = InodeType::File,
-                DInodeType::Device => {
-                    guard.typ = InodeType::Device {
-                        major: dip.major,
-                        minor: dip.minor,
-                    }
-                }
-            }
+            guard.typ = dip.inode_type();
             guard.nlink = dip.nlink;
             guard.size = dip.size;
             for (d, s) in guard.addr_direct.iter_mut().zip(&dip.addr_direct) {
                 *d = *s;
             }
             guard.addr_indirect = dip.addr_indirect;
+            guard.attr = if fs.superblock().attrs() {
+                Attr {
+                    mode: dip.mode,
+                    uid: dip.uid,
+                    gid: dip.gid,
+                    atime: dip.atime,
+                    mtime: dip.mtime,
+                    ctime: dip.ctime,
+                }
+            } else {
+                // An inode of an old image belongs to root, and has the
+                // default mode and no times.
+                Attr {
+                    atime: 0,
+                    mtime: 0,
+                    ctime: 0,
+                    ..Attr::new(guard.typ, 0, 0)
+                }
+            };
             bp.free(ctx);
             guard.valid = true;
             assert_ne!(guard.typ, InodeType::None, "Inode::lock: no type");
@@ -439,12 +482,53 @@ impl FileSystem for Lfs {
                 InodeType::Dir => 1,
                 InodeType::File => 2,
                 InodeType::Device { .. } => 3,
+                InodeType::Symlink => 4,
             },
             nlink: inner.nlink,
             _padding: 0,
             size: inner.size as usize,
+            mode: inner.attr.mode as u32,
+            uid: inner.attr.uid,
+            gid: inner.attr.gid,
+            atime: inner.attr.atime,
+            mtime: inner.attr.mtime,
+            ctime: inner.attr.ctime,
         };
         inner.free(ctx);
         st
     }
+
+    fn inode_type(guard: &InodeGuard<'_, Self>) -> InodeType {
+        guard.deref_inner().typ
+    }
+
+    fn inode_nlink(guard: &InodeGuard<'_, Self>) -> i16 {
+        guard.deref_inner().nlink
+    }
+
+    fn inode_set_nlink(
+        guard: &mut InodeGuard<'_, Self>,
+        nlink: i16,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        guard.deref_inner_mut().nlink = nlink;
+        guard.deref_inner_mut().attr.ctime = realtime();
+        guard.update(tx, ctx);
+    }
+
+    fn inode_attr(guard: &InodeGuard<'_, Self>) -> Attr {
+        guard.deref_inner().attr
+    }
+
+    fn inode_set_attr(
+        guard: &mut InodeGuard<'_, Self>,
+        attr: Attr,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        guard.deref_inner_mut().attr = attr;
+        guard.update(tx, ctx);
+        Ok(())
+    }
 }
@@ -236,7 +236,7 @@ impl SegManager {
     /// `seg_block_no` starts from 0 to `SEGSIZE - 1`.
     fn get_disk_block_no(&self, seg_block_no: usize, ctx: &KernelCtx<'_, '_>) -> u32 {
         ctx.kernel()
-            .fs()
+            .lfs(self.dev_no)
             .superblock()
             .seg_to_disk_block_no(self.segment_no, seg_block_no as u32)
     }
@@ -339,7 +339,9 @@ impl SegManager {
         } else {
             // Append segment with a new zeroed block.
             let block_no = self.start + 1 + self.segment.len();
-            let buf = self.get_segment_block(block_no, ctx);
+            let mut buf = self.get_segment_block(block_no, ctx);
+            // It stays dirty until the segment is committed.
+            buf.mark_dirty(ctx);
             self.segment.push((entry, buf.create_unlocked()));
             self.blocks_written += 1;
             Some((buf, self.get_disk_block_no(block_no, ctx)))
@@ -491,7 +493,7 @@ impl SegManager {
             for (_, buf) in self.segment.drain(..) {
                 self.locked_bufs.push(buf.lock(ctx));
             }
-            hal().disk().write_sequential(&mut self.locked_bufs, ctx);
+            hal().disks().write_sequential(&mut self.locked_bufs, ctx);
             for buf in self.locked_bufs.drain(..) {
                 buf.free(ctx);
             }
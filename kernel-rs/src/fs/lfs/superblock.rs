@@ -4,10 +4,16 @@ use static_assertions::const_assert;
 
 use crate::{
     bio::{Buf, BufData},
+    errno::Errno,
     param::SEGSIZE,
 };
 
-const FSMAGIC: u32 = 0x10203040;
+/// Differs from the magic of ufs, so that `mount` can tell the two apart.
+const FSMAGIC: u32 = 0x10203041;
+
+/// Version of the disk format. Version 1 added the permission bits, owners,
+/// and times to inodes. An older image has version 0, which is still read.
+const FSVERSION: u32 = 1;
 
 // Disk layout:
 // [ boot block | super block | checkpoint1  | checkpoint2 |
@@ -41,6 +47,9 @@ pub struct Superblock {
 
     // Block number of first segment
     segstart: u32,
+
+    /// At most FSVERSION
+    version: u32,
 }
 
 impl<'s> TryFrom<&'s BufData> for &'s Superblock {
@@ -66,9 +75,19 @@ impl<'s> TryFrom<&'s BufData> for &'s Superblock {
 
 impl Superblock {
     /// Read the super block.
-    pub fn new(buf: &Buf) -> Self {
-        let sb: &Superblock = buf.data().try_into().unwrap();
-        sb.clone()
+    /// Returns `Err(EINVAL)` if the disk does not hold an lfs, or the lfs has
+    /// a newer version.
+    pub fn new(buf: &Buf) -> Result<Self, Errno> {
+        let sb: &Superblock = buf.data().try_into().map_err(|_| Errno::EINVAL)?;
+        if sb.version > FSVERSION {
+            return Err(Errno::EINVAL);
+        }
+        Ok(sb.clone())
+    }
+
+    /// Returns `true` if inodes have permission bits, owners, and times.
+    pub fn attrs(&self) -> bool {
+        self.version >= 1
     }
 
     pub fn ninodes(&self) -> u32 {
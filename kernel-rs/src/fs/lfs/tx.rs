@@ -5,6 +5,7 @@
 //! * After all FS sys calls are done, commits the checkpoint.
 //! * After all FS sys calls are done, runs the segment cleaner if the number of remaining segments
 //!   are lower than threshold.
+//! * `sync` commits the segment and the checkpoint at once.
 
 use super::{cleaner::MIN_REQUIRED_BLOCKS, Lfs, Tx};
 use crate::{
@@ -36,6 +37,9 @@ pub struct TxManager {
     /// In commit(), please wait.
     committing: bool,
 
+    /// How many syncs wait for the FS sys calls to be done? Please wait.
+    syncs: i32,
+
     /// Stores whether the latest checkpoint is stored at the first checkpoint region or the second.
     stored_at_first: bool,
 
@@ -61,6 +65,7 @@ impl TxManager {
             dev,
             outstanding: 0,
             committing: false,
+            syncs: 0,
             stored_at_first,
             timestamp,
             last_blocks_written: 0,
@@ -79,7 +84,7 @@ impl SleepableLock<TxManager> {
             let nfree = seg.nfree();
             seg.free(ctx);
 
-            if guard.committing ||
+            if guard.committing || guard.syncs > 0 ||
             // This op might exhaust the `Bcache`; wait for the outstanding sys calls to be done.
             (guard.outstanding + 1) * MAXOPBLOCKS as i32 > NBUF as i32 ||
             // This op might exhaust segments; wait for cleaner.
@@ -149,4 +154,44 @@ impl SleepableLock<TxManager> {
         // the amount of reserved space.
         guard.wakeup(ctx.kernel());
     }
+
+    /// Commits the segment and the checkpoint if blocks have been written to the
+    /// segment since the last checkpoint commit, so that the FS sys calls that
+    /// are done survive a crash.
+    pub fn sync(&self, fs: &Lfs, ctx: &KernelCtx<'_, '_>) {
+        let mut guard = self.lock();
+        // Wait for the outstanding sys calls, and keep new ones from starting meanwhile.
+        guard.syncs += 1;
+        while guard.committing || guard.outstanding > 0 {
+            guard.sleep(ctx);
+        }
+        guard.syncs -= 1;
+
+        // SAFETY: there is no another transaction, so `SegManager` is not mutated.
+        let seg = unsafe { &mut *fs.segmanager_raw() };
+        if seg.blocks_written() > guard.last_blocks_written {
+            guard.committing = true;
+
+            // Update info about the latest checkpoint.
+            guard.stored_at_first = !guard.stored_at_first;
+            guard.timestamp += 1;
+
+            let dev = guard.dev;
+            let stored_at_first = guard.stored_at_first;
+            let timestamp = guard.timestamp;
+            guard.reacquire_after(|| {
+                seg.commit(false, ctx);
+
+                // SAFETY: there is no another transaction, so `Imap` is not mutated.
+                let imap = unsafe { &*fs.imap_raw() };
+                fs.commit_checkpoint(stored_at_first, timestamp, seg, imap, dev, ctx);
+            });
+
+            guard.last_blocks_written = seg.blocks_written();
+            guard.committing = false;
+        }
+
+        // begin_op() may be waiting for the sync.
+        guard.wakeup(ctx.kernel());
+    }
 }
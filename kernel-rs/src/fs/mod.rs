@@ -8,28 +8,38 @@ use zerocopy::{AsBytes, FromBytes};
 use crate::{
     addr::UVAddr,
     arena::{ArenaObject, ArenaRc, ArrayArena},
+    errno::Errno,
     lock::SleepLock,
-    param::NINODE,
+    param::{MAXPATH, NINODE},
     proc::KernelCtx,
+    timer::realtime,
     util::strong_pin::StrongPin,
 };
 
+mod devfs;
+mod lfs;
 mod path;
+mod procfs;
 mod stat;
+mod tmpfs;
+mod ufs;
+mod vfs;
 
+pub use devfs::Devfs;
+pub use lfs::Lfs;
 pub use path::{FileName, Path};
-pub use stat::Stat;
+pub use procfs::Procfs;
+pub use stat::{Dirent64, Stat, Utimbuf};
+pub use tmpfs::Tmpfs;
+pub use ufs::Ufs;
+pub use vfs::{FsType, MountTable, RcVnode, Vfs};
 
-// The default file system. Ufs or Lfs
+// The file system of the root disk. Ufs or Lfs
 cfg_if! {
     if #[cfg(feature = "lfs")] {
-        pub type DefaultFs = Lfs;
-        mod lfs;
-        pub use lfs::Lfs;
+        pub const ROOTFS: FsType = FsType::Lfs;
     } else {
-        pub type DefaultFs = Ufs;
-        mod ufs;
-        pub use ufs::Ufs;
+        pub const ROOTFS: FsType = FsType::Ufs;
     }
 }
 
@@ -40,6 +50,7 @@ bitflags! {
         const O_RDWR = 0x2;
         const O_CREATE = 0x200;
         const O_TRUNC = 0x400;
+        const O_NOFOLLOW = 0x800;
     }
 }
 
@@ -50,6 +61,49 @@ pub enum InodeType {
     Dir,
     File,
     Device { major: u16, minor: u16 },
+    Symlink,
+}
+
+impl InodeType {
+    /// Returns the type as `Stat` and `Dirent64` tell it.
+    pub fn stat_type(self) -> u16 {
+        match self {
+            InodeType::None => 0,
+            InodeType::Dir => 1,
+            InodeType::File => 2,
+            InodeType::Device { .. } => 3,
+            InodeType::Symlink => 4,
+        }
+    }
+}
+
+bitflags! {
+    /// The kinds of access that permission bits grant.
+    pub struct Access: u16 {
+        const READ = 0o4;
+        const WRITE = 0o2;
+        const EXEC = 0o1;
+    }
+}
+
+/// The permission bits, owner, and times of an inode.
+#[derive(Copy, Clone)]
+pub struct Attr {
+    /// Permission bits for the owner, the group, and the others, and the
+    /// set-user-id and set-group-id bits, as in `chmod`.
+    pub mode: u16,
+
+    /// Owner.
+    pub uid: u32,
+
+    /// Group.
+    pub gid: u32,
+
+    /// Times of the last access, the last modification of the content, and the
+    /// last change of the inode, in seconds since the Unix epoch.
+    pub atime: u32,
+    pub mtime: u32,
+    pub ctime: u32,
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -59,6 +113,74 @@ pub enum DInodeType {
     Dir,
     File,
     Device,
+    Symlink,
+}
+
+impl const Default for Attr {
+    fn default() -> Self {
+        Self {
+            mode: 0,
+            uid: 0,
+            gid: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+        }
+    }
+}
+
+impl Attr {
+    /// The bits of `mode` that `chmod` sets.
+    pub const MODE_MASK: u16 = Self::SETUID | Self::SETGID | 0o777;
+    /// The bit of `mode` that runs a program with the group id of its group.
+    pub const SETGID: u16 = 0o2000;
+    /// The bit of `mode` that runs a program with the user id of its owner.
+    pub const SETUID: u16 = 0o4000;
+
+    /// Returns the attributes of a new inode of type `typ`, owned by `uid` and
+    /// `gid`.
+    pub fn new(typ: InodeType, uid: u32, gid: u32) -> Self {
+        let now = realtime();
+        Self {
+            mode: match typ {
+                InodeType::Dir => 0o755,
+                InodeType::Device { .. } => 0o666,
+                InodeType::Symlink => 0o777,
+                InodeType::None | InodeType::File => 0o644,
+            },
+            uid,
+            gid,
+            atime: now,
+            mtime: now,
+            ctime: now,
+        }
+    }
+
+    /// Checks that the user `uid` in the group `gid` may access an inode of
+    /// type `typ` with these attributes as `want` says.
+    /// Returns Ok(()) if it may, or Err(EACCES) otherwise.
+    pub fn check(&self, typ: InodeType, want: Access, uid: u32, gid: u32) -> Result<(), Errno> {
+        if uid == 0 {
+            // The superuser may do anything, but executes only the files that
+            // someone may execute.
+            if want.contains(Access::EXEC) && typ != InodeType::Dir && self.mode & 0o111 == 0 {
+                return Err(Errno::EACCES);
+            }
+            return Ok(());
+        }
+        let bits = if uid == self.uid {
+            self.mode >> 6
+        } else if gid == self.gid {
+            self.mode >> 3
+        } else {
+            self.mode
+        };
+        if Access::from_bits_truncate(bits).contains(want) {
+            Ok(())
+        } else {
+            Err(Errno::EACCES)
+        }
+    }
 }
 
 /// InodeGuard implies that `SleepLock<InodeInner>` is held by current thread.
@@ -103,18 +225,18 @@ impl<FS: FileSystem> InodeGuard<'_, FS> {
     }
 
     /// Copy data into `dst` from the content of inode at offset `off`.
-    /// Return Ok(()) on success, Err(()) on failure.
+    /// Return Ok(()) on success, Err(EIO) if the inode ends before `dst` is filled.
     pub fn read_kernel<T: AsBytes + FromBytes>(
         &mut self,
         dst: &mut T,
         off: u32,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), Errno> {
         let bytes = self.read_bytes_kernel(dst.as_bytes_mut(), off, ctx);
         if bytes == mem::size_of::<T>() {
             Ok(())
         } else {
-            Err(())
+            Err(Errno::EIO)
         }
     }
 
@@ -141,7 +263,7 @@ impl<FS: FileSystem> InodeGuard<'_, FS> {
 
     /// Copy data into virtual address `dst` of the current process by `n` bytes
     /// from the content of inode at offset `off`.
-    /// Returns Ok(number of bytes copied) on success, Err(()) on failure due to
+    /// Returns Ok(number of bytes copied) on success, Err(EFAULT) on failure due to
     /// accessing an invalid virtual address.
     pub fn read_user(
         &mut self,
@@ -149,7 +271,7 @@ impl<FS: FileSystem> InodeGuard<'_, FS> {
         off: u32,
         n: u32,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         FS::inode_read(
             self,
             off,
@@ -164,31 +286,31 @@ impl<FS: FileSystem> InodeGuard<'_, FS> {
     }
 
     /// Copy data from `src` into the inode at offset `off`.
-    /// Return Ok(()) on success, Err(()) on failure.
+    /// Return Ok(()) on success, Err(errno) on failure.
     pub fn write_kernel<T: AsBytes>(
         &mut self,
         src: &T,
         off: u32,
         tx: &Tx<'_, FS>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), Errno> {
         let bytes = self.write_bytes_kernel(src.as_bytes(), off, tx, ctx)?;
         if bytes == mem::size_of::<T>() {
             Ok(())
         } else {
-            Err(())
+            Err(Errno::EIO)
         }
     }
 
     /// Copy data from `src` into the inode at offset `off`.
-    /// Returns Ok(number of bytes copied) on success, Err(()) on failure.
+    /// Returns Ok(number of bytes copied) on success, Err(errno) on failure.
     pub fn write_bytes_kernel(
         &mut self,
         src: &[u8],
         off: u32,
         tx: &Tx<'_, FS>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         FS::inode_write(
             self,
             off,
@@ -204,7 +326,7 @@ impl<FS: FileSystem> InodeGuard<'_, FS> {
 
     /// Copy data from virtual address `src` of the current process by `n` bytes
     /// into the inode at offset `off`.
-    /// Returns Ok(number of bytes copied) on success, Err(()) on failure.
+    /// Returns Ok(number of bytes copied) on success, Err(errno) on failure.
     pub fn write_user(
         &mut self,
         src: UVAddr,
@@ -212,7 +334,7 @@ impl<FS: FileSystem> InodeGuard<'_, FS> {
         n: u32,
         ctx: &mut KernelCtx<'_, '_>,
         tx: &Tx<'_, FS>,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         FS::inode_write(
             self,
             off,
@@ -309,75 +431,128 @@ impl<FS: FileSystem> Tx<'_, FS> {
     }
 }
 
-pub trait FileSystem: 'static + Sized {
+pub trait FileSystem: 'static + Sized + Sync {
     type Dirent;
     type InodeInner: 'static + Unpin + Send + Sized;
 
-    /// Initializes the file system (loading from the disk).
-    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>);
+    /// The type of the file system, which tells `Vfs` which instance an inode belongs to.
+    const TYPE: FsType;
+
+    /// The largest number of bytes that a transaction may write to a file.
+    const MAX_WRITE: usize;
+
+    /// Initializes the file system (loading from the disk `dev`).
+    /// Does nothing if it has already been loaded from `dev`.
+    /// Returns Err(EINVAL) if `dev` does not hold this file system, or Err(EBUSY)
+    /// if it has been loaded from another disk.
+    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno>;
 
-    /// Finds the root inode.
-    fn root(self: StrongPin<'_, Self>) -> RcInode<Self>;
+    /// Returns the table of in-memory inodes.
+    fn itable(self: StrongPin<'_, Self>) -> StrongPin<'_, Itable<Self>>;
 
-    /// Finds inode from the given path.
-    fn namei(
+    /// Finds the root inode of the disk `dev`.
+    fn root(self: StrongPin<'_, Self>, dev: u32) -> RcInode<Self>;
+
+    /// Looks for `name` in the directory `dir`.
+    /// Returns Ok(inode) on success, Err(ENOENT) if there is no such entry.
+    fn lookup(
         self: StrongPin<'_, Self>,
-        path: &Path,
-        tx: &Tx<'_, Self>,
+        dir: &mut InodeGuard<'_, Self>,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self>, Errno>;
+
+    /// Calls `f(next, inum, typ, name)` for each entry of the directory `dir`
+    /// from the offset `off`, until `f` returns false. `next` is the offset
+    /// after the entry, where a later call may resume. An offset in the middle
+    /// of an entry resumes from the entry after it.
+    fn readdir<F>(
+        self: StrongPin<'_, Self>,
+        dir: &mut InodeGuard<'_, Self>,
+        off: u32,
+        f: F,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Self>, ()>;
+    ) where
+        F: FnMut(u32, u32, InodeType, &[u8]) -> bool;
 
-    /// Create another name(newname) for the file oldname.
-    /// Returns Ok(()) on success, Err(()) on error.
+    /// Create another name `name` in the directory `dir` for the file `inode`.
+    /// Returns Ok(()) on success, Err(errno) on error.
     fn link(
         self: StrongPin<'_, Self>,
-        inode: RcInode<Self>,
-        path: &Path,
+        inode: &RcInode<Self>,
+        dir: &RcInode<Self>,
+        name: &FileName<{ MAXPATH }>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()>;
+    ) -> Result<(), Errno>;
 
-    /// Remove a file(filename).
-    /// Returns Ok(()) on success, Err(()) on error.
+    /// Remove the file `name` from the directory `dir`.
+    /// Returns Ok(()) on success, Err(errno) on error.
     fn unlink(
         self: StrongPin<'_, Self>,
-        path: &Path,
+        dir: &RcInode<Self>,
+        name: &FileName<{ MAXPATH }>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno>;
+
+    /// Looks for `name` in the locked directory `dir`, to change the entry.
+    /// Returns Ok((inode, offset of the entry)) on success, Err(ENOENT) if there
+    /// is no such entry, or Err(EROFS) if the file system is read-only.
+    fn dirlookup(
+        dir: &mut InodeGuard<'_, Self>,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<Self>, u32), Errno>;
+
+    /// Adds the entry (`name`, `inum`) to the locked directory `dir`.
+    /// Returns Ok(()) on success, Err(errno) on error.
+    fn dirlink(
+        dir: &mut InodeGuard<'_, Self>,
+        name: &FileName<{ MAXPATH }>,
+        inum: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno>;
+
+    /// Removes the entry at the offset `off` from the locked directory `dir`.
+    fn dirunlink(
+        dir: &mut InodeGuard<'_, Self>,
+        off: u32,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()>;
+    );
 
-    /// Create an inode with given type.
-    /// Returns Ok(created inode, result of given function f) on success, Err(()) on error.
+    /// Makes the entry at the offset `off` of the locked directory `dir` refer
+    /// to the inode `inum`.
+    fn dirrelink(
+        dir: &mut InodeGuard<'_, Self>,
+        off: u32,
+        inum: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    );
+
+    /// Returns true if the locked directory `dir` has no entries but "." and "..".
+    fn is_dir_empty(dir: &mut InodeGuard<'_, Self>, ctx: &KernelCtx<'_, '_>) -> bool;
+
+    /// Create an inode with given type and attributes, named `name` in the
+    /// directory `dir`. If a file named `name` exists, `f` is called with it
+    /// instead, and its attributes are kept.
+    /// Returns Ok(created inode, result of given function f) on success, Err(errno) on error.
     fn create<F, T>(
         self: StrongPin<'_, Self>,
-        path: &Path,
+        dir: &RcInode<Self>,
+        name: &FileName<{ MAXPATH }>,
         typ: InodeType,
+        attr: Attr,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
         f: F,
-    ) -> Result<(RcInode<Self>, T), ()>
+    ) -> Result<(RcInode<Self>, T), Errno>
     where
         F: FnOnce(&mut InodeGuard<'_, Self>) -> T;
 
-    /// Open a file; omode indicate read/write.
-    /// Returns Ok(file descriptor) on success, Err(()) on error.
-    fn open(
-        self: StrongPin<'_, Self>,
-        path: &Path,
-        omode: FcntlFlags,
-        tx: &Tx<'_, Self>,
-        ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()>;
-
-    /// Change the current directory.
-    /// Returns Ok(()) on success, Err(()) on error.
-    fn chdir(
-        self: StrongPin<'_, Self>,
-        inode: RcInode<Self>,
-        tx: &Tx<'_, Self>,
-        ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<(), ()>;
-
     /// Begins a transaction.
     ///
     /// Called for each FS system call that may cause a disk write.
@@ -393,6 +568,15 @@ pub trait FileSystem: 'static + Sized {
     /// that may cause a disk write should be called inside a transaction.
     unsafe fn tx_end(&self, tx: &mut Tx<'_, Self>, ctx: &KernelCtx<'_, '_>);
 
+    /// Writes the transactions that have ended to the disk, if not yet, so that
+    /// they survive a crash. Does nothing if the file system has not been loaded.
+    fn sync(&self, ctx: &KernelCtx<'_, '_>);
+
+    /// Returns the ticks when the oldest change that `sync` would write was
+    /// made, or None if there is no such change or the file system does not
+    /// keep the time.
+    fn dirty_since(&self, ctx: &KernelCtx<'_, '_>) -> Option<u32>;
+
     /// Read data from inode.
     ///
     /// `f` takes an offset and a slice as arguments. `f(off, src, ctx)` should copy
@@ -408,14 +592,14 @@ pub trait FileSystem: 'static + Sized {
         'id,
         's,
         K: Deref<Target = KernelCtx<'id, 's>>,
-        F: FnMut(u32, &[u8], &mut K) -> Result<(), ()>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), Errno>,
     >(
         guard: &mut InodeGuard<'_, Self>,
         off: u32,
         n: u32,
         f: F,
         k: K,
-    ) -> Result<usize, ()>;
+    ) -> Result<usize, Errno>;
 
     /// Write data to inode. Returns the number of bytes successfully written.
     /// If the return value is less than the requested n, there was an error of
@@ -447,7 +631,7 @@ pub trait FileSystem: 'static + Sized {
         'id,
         's,
         K: Deref<Target = KernelCtx<'id, 's>>,
-        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), ()>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), Errno>,
     >(
         guard: &mut InodeGuard<'_, Self>,
         off: u32,
@@ -455,7 +639,7 @@ pub trait FileSystem: 'static + Sized {
         f: F,
         tx: &Tx<'_, Self>,
         k: K,
-    ) -> Result<usize, ()>;
+    ) -> Result<usize, Errno>;
 
     /// Truncate inode (discard contents).
     /// This function is called with Inode's lock is held.
@@ -480,6 +664,32 @@ pub trait FileSystem: 'static + Sized {
 
     /// Copy stat information from inode.
     fn inode_stat(inode: &Inode<Self>, ctx: &KernelCtx<'_, '_>) -> Stat;
+
+    /// Returns the type of the locked inode.
+    fn inode_type(guard: &InodeGuard<'_, Self>) -> InodeType;
+
+    /// Returns the number of links to the locked inode.
+    fn inode_nlink(guard: &InodeGuard<'_, Self>) -> i16;
+
+    /// Sets the number of links to the locked inode.
+    fn inode_set_nlink(
+        guard: &mut InodeGuard<'_, Self>,
+        nlink: i16,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    );
+
+    /// Returns the permission bits, owner, and times of the locked inode.
+    fn inode_attr(guard: &InodeGuard<'_, Self>) -> Attr;
+
+    /// Sets the permission bits, owner, and times of the locked inode.
+    /// Returns Err(EROFS) if the file system is read-only.
+    fn inode_set_attr(
+        guard: &mut InodeGuard<'_, Self>,
+        attr: Attr,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno>;
 }
 
 pub trait FileSystemExt: FileSystem {
@@ -7,12 +7,12 @@ use cstr_core::CStr;
 pub struct FileName<const MAXSIZE: usize> {
     // Invariant:
     // - The slice contains no NUL characters.
-    // - The slice is not longer than DIRSIZ.
+    // - The slice is not longer than MAXSIZE.
     inner: [u8],
 }
 
 impl<const MAXSIZE: usize> FileName<{ MAXSIZE }> {
-    /// Truncate bytes followed by the first DIRSIZ bytes.
+    /// Truncate bytes followed by the first MAXSIZE bytes.
     ///
     /// # Safety
     ///
@@ -22,13 +22,19 @@ impl<const MAXSIZE: usize> FileName<{ MAXSIZE }> {
         // SAFETY: `&FileName` is layout-compatible with `[u8]` because of its
         // attribute `#[repr(transparent)]`. Also, the slice satisfies the
         // invariant of FileName because of the safety condition of this method
-        // and the fact that its length is at most DIRSIZ.
+        // and the fact that its length is at most MAXSIZE.
         unsafe { &*(&bytes[..cmp::min(MAXSIZE, bytes.len())] as *const [u8] as *const Self) }
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         &self.inner
     }
+
+    /// Returns the first `N` bytes of the name.
+    pub fn truncate<const N: usize>(&self) -> &FileName<{ N }> {
+        // SAFETY: `self.inner` contains no NUL characters.
+        unsafe { FileName::from_bytes(&self.inner) }
+    }
 }
 
 #[repr(transparent)]
@@ -114,6 +120,27 @@ impl Path {
         Some((path, name))
     }
 
+    /// Returns the last path element, or `None` if there is none.
+    ///
+    /// # Examples
+    /// ```
+    /// # unsafe {
+    /// assert_eq!(Path::from_bytes(b"a/bb//").file_name(), Some(b"bb"));
+    /// assert_eq!(Path::from_bytes(b"////").file_name(), None);
+    /// # }
+    /// ```
+    pub fn file_name<const MAXSIZE: usize>(&self) -> Option<&FileName<{ MAXSIZE }>> {
+        let end = self.inner.iter().rposition(|ch| *ch != b'/')? + 1;
+        let bytes = &self.inner[..end];
+        let start = bytes
+            .iter()
+            .rposition(|ch| *ch == b'/')
+            .map_or(0, |i| i + 1);
+
+        // SAFETY: `bytes` is a subslice of `self.inner`, which contains no NUL characters.
+        Some(unsafe { FileName::from_bytes(&bytes[start..]) })
+    }
+
     /// Returns `true` if `Path` begins with `'/'`.
     pub fn is_absolute(&self) -> bool {
         !self.inner.is_empty() && self.inner[0] == b'/'
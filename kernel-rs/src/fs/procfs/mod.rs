@@ -0,0 +1,652 @@
+//! A file system that shows the processes and the state of the kernel as files.
+//!
+//! The root directory holds a directory for each process, named by its pid,
+//! and the files below, which describe the whole kernel:
+//!
+//! * `meminfo`: the numbers of free pages and all pages of `Kmem`.
+//! * `bcache`: the numbers of hits and misses of the buffer cache, the number of
+//!   dirty buffers, and the commit latency and the expire time of dirty buffers
+//!   in ticks.
+//! * `uptime`: the ticks, and the microseconds since boot.
+//! * `cpus`: the pid of the process that each online CPU runs, or `-` if idle.
+//!
+//! The directory of a process holds `state`, `name`, `parent` (the pid of the
+//! parent), `size` (the size of the user memory), `fds` (the open file
+//! descriptors), and `cwd` (the device and inode numbers of the current
+//! directory). `size`, `fds`, and `cwd` show `-` while the process runs on
+//! another CPU, or after it has started exiting.
+//!
+//! Nothing is stored: each read generates the content of the file anew, and
+//! each file shows the size 0. The file system is read-only.
+
+use core::fmt::{self, Write};
+use core::ops::Deref;
+use core::{cmp, mem, str};
+
+use zerocopy::AsBytes;
+
+use super::{
+    Attr, FileName, FileSystem, FsType, Inode, InodeGuard, InodeType, Itable, RcInode, Stat, Tx,
+};
+use crate::{
+    arch::{interface::TimeManager, TargetArch},
+    arena::{Arena, ArrayArena},
+    errno::Errno,
+    hal::hal,
+    lock::SleepLock,
+    page::PGSIZE,
+    param::{MAXPATH, NCPU, NINODE, PROCDEV},
+    proc::{KernelCtx, ProcStat},
+    util::strong_pin::StrongPin,
+};
+
+/// root i-number
+const ROOTINO: u32 = 1;
+
+/// Directory is a file containing a sequence of dirent structures.
+const DIRSIZ: usize = 14;
+
+type Pid = i32;
+
+/// A file or directory of the procfs.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Entry {
+    Root,
+    Meminfo,
+    Bcache,
+    Uptime,
+    Cpus,
+    Proc(Pid, ProcFile),
+}
+
+/// A file or directory that each process has.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ProcFile {
+    Dir,
+    State,
+    Name,
+    Parent,
+    Size,
+    Fds,
+    Cwd,
+}
+
+/// The files in the root directory besides the directories of processes.
+const GLOBAL_FILES: [(&str, Entry); 4] = [
+    ("meminfo", Entry::Meminfo),
+    ("bcache", Entry::Bcache),
+    ("uptime", Entry::Uptime),
+    ("cpus", Entry::Cpus),
+];
+
+/// The files in the directory of a process.
+const PROC_FILES: [(&str, ProcFile); 6] = [
+    ("state", ProcFile::State),
+    ("name", ProcFile::Name),
+    ("parent", ProcFile::Parent),
+    ("size", ProcFile::Size),
+    ("fds", ProcFile::Fds),
+    ("cwd", ProcFile::Cwd),
+];
+
+#[repr(C)]
+#[derive(Default, AsBytes)]
+pub struct Dirent {
+    inum: u16,
+    name: [u8; DIRSIZ],
+}
+
+/// The procfs does not keep anything in its inodes.
+pub struct InodeInner;
+
+pub struct Procfs {
+    itable: Itable<Self>,
+}
+
+/// Writes the content of a file into a buffer, dropping what does not fit.
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Entry {
+    /// Returns the inode number of the entry. The inode number of a file of a
+    /// process holds the pid in its upper bits, and the kind of the file in
+    /// its lowest 3 bits, which are never 0.
+    fn inum(self) -> u32 {
+        match self {
+            Entry::Root => ROOTINO,
+            Entry::Meminfo => 2,
+            Entry::Bcache => 3,
+            Entry::Uptime => 4,
+            Entry::Cpus => 5,
+            Entry::Proc(pid, file) => (pid as u32) << 3 | (file as u32 + 1),
+        }
+    }
+
+    fn from_inum(inum: u32) -> Self {
+        match inum {
+            ROOTINO => Entry::Root,
+            2 => Entry::Meminfo,
+            3 => Entry::Bcache,
+            4 => Entry::Uptime,
+            5 => Entry::Cpus,
+            _ => {
+                let file = match inum & 7 {
+                    1 => ProcFile::Dir,
+                    2 => ProcFile::State,
+                    3 => ProcFile::Name,
+                    4 => ProcFile::Parent,
+                    5 => ProcFile::Size,
+                    6 => ProcFile::Fds,
+                    7 => ProcFile::Cwd,
+                    _ => panic!("procfs: bad inum"),
+                };
+                Entry::Proc((inum >> 3) as Pid, file)
+            }
+        }
+    }
+
+    fn typ(self) -> InodeType {
+        match self {
+            Entry::Root | Entry::Proc(_, ProcFile::Dir) => InodeType::Dir,
+            _ => InodeType::File,
+        }
+    }
+
+    /// Returns the attributes of an entry of type `typ`. Every entry belongs
+    /// to root and is read-only.
+    fn attr(typ: InodeType) -> Attr {
+        let mut attr = Attr::new(typ, 0, 0);
+        attr.mode &= !0o222;
+        attr
+    }
+
+    /// Calls `f(name, entry)` for each entry of the directory, until `f`
+    /// returns false.
+    fn for_each_dirent<F>(self, ctx: &KernelCtx<'_, '_>, mut f: F)
+    where
+        F: FnMut(&[u8], Entry) -> bool,
+    {
+        if self.typ() != InodeType::Dir || !f(b".", self) || !f(b"..", Entry::Root) {
+            return;
+        }
+        match self {
+            Entry::Root => {
+                for (name, entry) in GLOBAL_FILES {
+                    if !f(name.as_bytes(), entry) {
+                        return;
+                    }
+                }
+                for pid in ctx.kernel().procs().pids() {
+                    let mut name = [0; DIRSIZ];
+                    let mut nw = Writer::new(&mut name);
+                    let _ = write!(nw, "{}", pid);
+                    let len = nw.len;
+                    if !f(&name[..len], Entry::Proc(pid, ProcFile::Dir)) {
+                        return;
+                    }
+                }
+            }
+            Entry::Proc(pid, _) => {
+                for (name, file) in PROC_FILES {
+                    if !f(name.as_bytes(), Entry::Proc(pid, file)) {
+                        return;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Writes the content of the entry into `w`.
+    fn generate(self, w: &mut Writer<'_>, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        let kernel = ctx.kernel();
+        match self {
+            Entry::Root | Entry::Proc(_, ProcFile::Dir) => {
+                self.for_each_dirent(ctx, |name, entry| {
+                    w.dirent(name, entry.inum());
+                    true
+                });
+            }
+            Entry::Meminfo => {
+                let (nfree, ntotal) = hal().kmem().usage();
+                let _ = writeln!(w, "free {}\ntotal {}", nfree, ntotal);
+            }
+            Entry::Bcache => {
+                let (hits, misses) = kernel.bcache_stats().get();
+                let dirty = kernel.bcache_stats().dirty();
+                let writeback = kernel.writeback();
+                let _ = writeln!(
+                    w,
+                    "hits {}\nmisses {}\ndirty {}\ncommit_ticks {}\nexpire_ticks {}",
+                    hits,
+                    misses,
+                    dirty,
+                    writeback.commit_ticks(),
+                    writeback.expire_ticks()
+                );
+            }
+            Entry::Uptime => {
+                let ticks = *kernel.ticks().lock();
+                let _ = writeln!(w, "ticks {}", ticks);
+                if let Ok(now) = TargetArch::uptime_as_micro() {
+                    let _ = writeln!(w, "usec {}", now - kernel.boot_time());
+                }
+            }
+            Entry::Cpus => {
+                let procs = kernel.procs();
+                let online = procs.online();
+                for cpu in (0..NCPU).filter(|cpu| online & 1 << cpu != 0) {
+                    let _ = match procs.running(cpu) {
+                        Some(pid) => writeln!(w, "cpu{} {}", cpu, pid),
+                        None => writeln!(w, "cpu{} -", cpu),
+                    };
+                }
+            }
+            Entry::Proc(pid, file) => {
+                let st = kernel.procs().stat(pid, ctx)?;
+                generate_proc_file(&st, file, w);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the content of the file `file` of a process into `w`.
+fn generate_proc_file(st: &ProcStat, file: ProcFile, w: &mut Writer<'_>) {
+    let _ = match file {
+        ProcFile::Dir => unreachable!("generate_proc_file: directory"),
+        ProcFile::State => writeln!(w, "{}", st.state.as_str().trim_end()),
+        ProcFile::Name => {
+            let len = st
+                .name
+                .iter()
+                .position(|c| *c == 0)
+                .unwrap_or(st.name.len());
+            w.write_bytes(&st.name[..len]);
+            writeln!(w)
+        }
+        ProcFile::Parent => writeln!(w, "{}", st.ppid),
+        ProcFile::Size => {
+            match st.size {
+                Some(size) => writeln!(w, "{}", size),
+                None => writeln!(w, "-"),
+            }
+        }
+        ProcFile::Fds => {
+            match st.fds {
+                Some(fds) => {
+                    let mut sep = "";
+                    for fd in (0..u64::BITS).filter(|fd| fds & 1 << fd != 0) {
+                        let _ = write!(w, "{}{}", sep, fd);
+                        sep = " ";
+                    }
+                    writeln!(w)
+                }
+                None => writeln!(w, "-"),
+            }
+        }
+        ProcFile::Cwd => {
+            match st.cwd {
+                Some(cwd) => writeln!(w, "{} {}", cwd.dev, cwd.ino),
+                None => writeln!(w, "-"),
+            }
+        }
+    };
+}
+
+/// Parses a directory name of a process. Returns `None` unless `name` is a
+/// pid in decimal without leading zeros.
+fn parse_pid(name: &[u8]) -> Option<Pid> {
+    if name.first() == Some(&b'0') || !name.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    str::from_utf8(name).ok()?.parse().ok()
+}
+
+impl<'a> Writer<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        let n = cmp::min(bytes.len(), self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += n;
+    }
+
+    /// Writes a directory entry. Only the lower 16 bits of `inum` fit in a
+    /// `Dirent`, but they are never 0, which would mark an unused entry.
+    fn dirent(&mut self, name: &[u8], inum: u32) {
+        let mut de = Dirent {
+            inum: inum as u16,
+            ..Default::default()
+        };
+        de.name[..name.len()].copy_from_slice(name);
+        self.write_bytes(de.as_bytes());
+    }
+}
+
+impl fmt::Write for Writer<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl const Default for Inode<Procfs> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Inode<Procfs> {
+    pub const fn new() -> Self {
+        Self {
+            dev: 0,
+            inum: 0,
+            inner: SleepLock::new("inode", InodeInner),
+        }
+    }
+}
+
+impl Itable<Procfs> {
+    pub const fn new_itable() -> Self {
+        ArrayArena::<Inode<Procfs>, NINODE>::new("PROCITABLE")
+    }
+
+    /// Find the inode with number inum on device dev
+    /// and return the in-memory copy. Does not lock the inode.
+    pub fn get_inode(self: StrongPin<'_, Self>, dev: u32, inum: u32) -> RcInode<Procfs> {
+        self.find_or_alloc(
+            |inode| inode.dev == dev && inode.inum == inum,
+            |inode| {
+                inode.dev = dev;
+                inode.inum = inum;
+            },
+        )
+        .expect("[Itable::get_inode] no inodes")
+    }
+}
+
+impl Procfs {
+    pub const fn new() -> Self {
+        Self {
+            itable: Itable::<Self>::new_itable(),
+        }
+    }
+}
+
+impl FileSystem for Procfs {
+    type Dirent = Dirent;
+    type InodeInner = InodeInner;
+
+    // Nothing is ever written.
+    const MAX_WRITE: usize = PGSIZE;
+    const TYPE: FsType = FsType::Procfs;
+
+    fn init(&self, dev: u32, _ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        if dev == PROCDEV {
+            Ok(())
+        } else {
+            Err(Errno::EINVAL)
+        }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().itable) }
+    }
+
+    fn root(self: StrongPin<'_, Self>, dev: u32) -> RcInode<Self> {
+        self.itable().get_inode(dev, ROOTINO)
+    }
+
+    fn lookup(
+        self: StrongPin<'_, Self>,
+        dir: &mut InodeGuard<'_, Self>,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self>, Errno> {
+        let entry = Entry::from_inum(dir.inum);
+        let name = name.as_bytes();
+        let found = match entry {
+            _ if entry.typ() != InodeType::Dir => return Err(Errno::ENOTDIR),
+            _ if name == b"." => Some(entry),
+            _ if name == b".." => Some(Entry::Root),
+            Entry::Root => {
+                GLOBAL_FILES
+                    .iter()
+                    .find(|(n, _)| n.as_bytes() == name)
+                    .map(|(_, entry)| *entry)
+                    .or_else(|| {
+                        let pid = parse_pid(name)?;
+                        let exists = ctx.kernel().procs().pids().contains(&pid);
+                        exists.then(|| Entry::Proc(pid, ProcFile::Dir))
+                    })
+            }
+            Entry::Proc(pid, _) => {
+                PROC_FILES
+                    .iter()
+                    .find(|(n, _)| n.as_bytes() == name)
+                    .map(|(_, file)| Entry::Proc(pid, *file))
+            }
+            _ => None,
+        };
+        let found = found.ok_or(Errno::ENOENT)?;
+        Ok(self.itable().get_inode(dir.dev, found.inum()))
+    }
+
+    fn readdir<F>(
+        self: StrongPin<'_, Self>,
+        dir: &mut InodeGuard<'_, Self>,
+        off: u32,
+        mut f: F,
+        ctx: &KernelCtx<'_, '_>,
+    ) where
+        F: FnMut(u32, u32, InodeType, &[u8]) -> bool,
+    {
+        // The offset after an entry is the number of entries up to it.
+        let mut next = 0;
+        Entry::from_inum(dir.inum).for_each_dirent(ctx, |name, entry| {
+            next += 1;
+            next <= off || f(next, entry.inum(), entry.typ(), name)
+        });
+    }
+
+    fn link(
+        self: StrongPin<'_, Self>,
+        _inode: &RcInode<Self>,
+        _dir: &RcInode<Self>,
+        _name: &FileName<{ MAXPATH }>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn unlink(
+        self: StrongPin<'_, Self>,
+        _dir: &RcInode<Self>,
+        _name: &FileName<{ MAXPATH }>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn dirlookup(
+        _dir: &mut InodeGuard<'_, Self>,
+        _name: &FileName<{ MAXPATH }>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<Self>, u32), Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn dirlink(
+        _dir: &mut InodeGuard<'_, Self>,
+        _name: &FileName<{ MAXPATH }>,
+        _inum: u32,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn dirunlink(
+        _dir: &mut InodeGuard<'_, Self>,
+        _off: u32,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) {
+    }
+
+    fn dirrelink(
+        _dir: &mut InodeGuard<'_, Self>,
+        _off: u32,
+        _inum: u32,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) {
+    }
+
+    fn is_dir_empty(_dir: &mut InodeGuard<'_, Self>, _ctx: &KernelCtx<'_, '_>) -> bool {
+        false
+    }
+
+    fn create<F, T>(
+        self: StrongPin<'_, Self>,
+        _dir: &RcInode<Self>,
+        _name: &FileName<{ MAXPATH }>,
+        _typ: InodeType,
+        _attr: Attr,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+        _f: F,
+    ) -> Result<(RcInode<Self>, T), Errno>
+    where
+        F: FnOnce(&mut InodeGuard<'_, Self>) -> T,
+    {
+        Err(Errno::EROFS)
+    }
+
+    fn tx_begin(&self, _ctx: &KernelCtx<'_, '_>) {}
+
+    unsafe fn tx_end(&self, _tx: &mut Tx<'_, Self>, _ctx: &KernelCtx<'_, '_>) {}
+
+    fn sync(&self, _ctx: &KernelCtx<'_, '_>) {}
+
+    fn dirty_since(&self, _ctx: &KernelCtx<'_, '_>) -> Option<u32> {
+        None
+    }
+
+    fn inode_read<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), Errno>,
+    >(
+        guard: &mut InodeGuard<'_, Self>,
+        off: u32,
+        n: u32,
+        mut f: F,
+        mut k: K,
+    ) -> Result<usize, Errno> {
+        let mut page = hal().kmem().alloc(None).ok_or(Errno::ENOMEM)?;
+        let mut w = Writer::new(&mut page[..]);
+        let res = Entry::from_inum(guard.inum)
+            .generate(&mut w, &k)
+            .and_then(|()| {
+                let begin = cmp::min(off as usize, w.len);
+                let end = cmp::min(begin + n as usize, w.len);
+                f(0, &w.buf[begin..end], &mut k)?;
+                Ok(end - begin)
+            });
+        hal().kmem().free(page);
+        res
+    }
+
+    fn inode_write<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), Errno>,
+    >(
+        _guard: &mut InodeGuard<'_, Self>,
+        _off: u32,
+        _n: u32,
+        _f: F,
+        _tx: &Tx<'_, Self>,
+        _k: K,
+    ) -> Result<usize, Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn inode_trunc(
+        _guard: &mut InodeGuard<'_, Self>,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) {
+    }
+
+    fn inode_lock<'a>(inode: &'a Inode<Self>, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'a, Self> {
+        mem::forget(inode.inner.lock(ctx));
+        InodeGuard { inode }
+    }
+
+    fn inode_finalize<'a, 'id: 'a>(
+        _inode: &mut Inode<Self>,
+        _tx: &'a Tx<'a, Self>,
+        _ctx: &'a KernelCtx<'id, 'a>,
+    ) {
+    }
+
+    fn inode_stat(inode: &Inode<Self>, _ctx: &KernelCtx<'_, '_>) -> Stat {
+        let typ = Entry::from_inum(inode.inum).typ();
+        let attr = Entry::attr(typ);
+        Stat {
+            dev: inode.dev as i32,
+            ino: inode.inum,
+            typ: if typ == InodeType::Dir { 1 } else { 2 },
+            nlink: 1,
+            _padding: 0,
+            size: 0,
+            mode: attr.mode as u32,
+            uid: attr.uid,
+            gid: attr.gid,
+            atime: attr.atime,
+            mtime: attr.mtime,
+            ctime: attr.ctime,
+        }
+    }
+
+    fn inode_type(guard: &InodeGuard<'_, Self>) -> InodeType {
+        Entry::from_inum(guard.inum).typ()
+    }
+
+    fn inode_nlink(_guard: &InodeGuard<'_, Self>) -> i16 {
+        1
+    }
+
+    fn inode_set_nlink(
+        _guard: &mut InodeGuard<'_, Self>,
+        _nlink: i16,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) {
+    }
+
+    fn inode_attr(guard: &InodeGuard<'_, Self>) -> Attr {
+        Entry::attr(Entry::from_inum(guard.inum).typ())
+    }
+
+    fn inode_set_attr(
+        _guard: &mut InodeGuard<'_, Self>,
+        _attr: Attr,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+}
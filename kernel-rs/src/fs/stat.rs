@@ -1,4 +1,4 @@
-use zerocopy::AsBytes;
+use zerocopy::{AsBytes, FromBytes};
 
 #[derive(Copy, Clone, AsBytes)]
 #[repr(C)]
@@ -20,4 +20,50 @@ pub struct Stat {
 
     /// Size of file in bytes
     pub size: usize,
+
+    /// Permission bits
+    pub mode: u32,
+
+    /// Owner and group of file
+    pub uid: u32,
+    pub gid: u32,
+
+    /// Times of last access, modification, and status change, in seconds
+    /// since the Unix epoch
+    pub atime: u32,
+    pub mtime: u32,
+    pub ctime: u32,
+}
+
+/// `struct utimbuf` of the user library, the argument of `utime`.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Utimbuf {
+    /// Access time
+    pub actime: u32,
+
+    /// Modification time
+    pub modtime: u32,
+}
+
+/// The header of `struct dirent64` of the user library, the record that
+/// `getdents` returns for each entry of a directory. The name follows the
+/// header with a NUL terminator, padded so that `reclen` is a multiple of 4.
+#[derive(Copy, Clone, AsBytes)]
+#[repr(C)]
+pub struct Dirent64 {
+    /// Inode number
+    pub ino: u32,
+
+    /// Offset of the next entry in the directory, for `lseek`
+    pub off: u32,
+
+    /// Length of the record, including the name and the padding
+    pub reclen: u16,
+
+    /// Type of file, as in `Stat`
+    pub typ: u8,
+
+    /// Length of the name, without the NUL terminator
+    pub namelen: u8,
 }
@@ -0,0 +1,390 @@
+//! Inodes of the tmpfs.
+//!
+//! Each inode lives in a page allocated from `Kmem`, which holds its type,
+//! link count, size, permission bits, owner, times, and the pages holding its
+//! content. The `Tmpfs` keeps the pages of the allocated inodes, indexed by
+//! inode numbers, until their links and references have all gone.
+//!
+//! As for the other file systems, the `Itable` keeps the inodes in use, and an
+//! inode must be locked to examine or modify it. Since the `Itable` holds at most
+//! one in-memory inode for each inode number, the lock of the in-memory inode
+//! also protects the page of the inode.
+
+use core::{iter::StepBy, mem, ops::Range, ptr::NonNull};
+
+use static_assertions::const_assert;
+use zerocopy::{AsBytes, FromBytes};
+
+use super::{Tmpfs, ROOTINO};
+use crate::{
+    arena::{Arena, ArrayArena},
+    errno::Errno,
+    fs::{Attr, FileName, Inode, InodeGuard, InodeType, Itable, RcInode, Tx},
+    hal::hal,
+    lock::SleepLock,
+    page::{Page, PGSIZE},
+    param::{NINODE, NTMPINODE},
+    proc::KernelCtx,
+    some_or,
+    util::strong_pin::StrongPin,
+};
+
+/// Directory is a file containing a sequence of Dirent structures.
+pub const DIRSIZ: usize = 14;
+
+/// dirent size
+pub const DIRENT_SIZE: usize = mem::size_of::<Dirent>();
+
+/// The number of pages that a file may have.
+pub const NPAGE: usize = (PGSIZE - 2 * mem::size_of::<usize>() - mem::size_of::<Attr>())
+    / mem::size_of::<Option<Page>>();
+
+/// An inode, which lives in a page of its own.
+// An all-zero page is a valid `Dinode`: its type is `InodeType::None`, and it
+// has no pages.
+#[repr(C)]
+pub struct Dinode {
+    pub typ: InodeType,
+
+    /// Number of links to inode in file system
+    pub nlink: i16,
+
+    /// Size of file (bytes)
+    pub size: u32,
+
+    /// Permission bits, owner, and times
+    pub attr: Attr,
+
+    /// The pages holding the content. `pages[i]` holds the bytes from
+    /// `i * PGSIZE` to `(i + 1) * PGSIZE`.
+    pub pages: [Option<Page>; NPAGE],
+}
+
+const_assert!(mem::size_of::<Dinode>() <= PGSIZE);
+
+pub struct InodeInner {
+    /// The inode, or `None` if it has not been looked up since the in-memory
+    /// inode was allocated.
+    dinode: Option<NonNull<Dinode>>,
+}
+
+// SAFETY: `dinode` is accessed only while the in-memory inode is locked.
+unsafe impl Send for InodeInner {}
+
+#[repr(C)]
+#[derive(Default, AsBytes, FromBytes)]
+pub struct Dirent {
+    pub inum: u16,
+    name: [u8; DIRSIZ],
+}
+
+impl Dinode {
+    /// Allocates a page and makes an inode of type `typ` with no links in it.
+    /// Returns `None` if the memory is exhausted.
+    pub fn alloc(typ: InodeType) -> Option<Page> {
+        let mut page = hal().kmem().alloc(Some(0))?;
+        // SAFETY: the page is zero-filled, and an all-zero page is a valid `Dinode`.
+        let dinode = unsafe { page.as_uninit_mut::<Dinode>().assume_init_mut() };
+        dinode.typ = typ;
+        Some(page)
+    }
+
+    /// Allocates the root directory, which has "." and ".." only.
+    /// Returns `None` if the memory is exhausted.
+    pub fn alloc_root() -> Option<Page> {
+        let mut page = Self::alloc(InodeType::Dir)?;
+        let mut data = some_or!(hal().kmem().alloc(Some(0)), {
+            hal().kmem().free(page);
+            return None;
+        });
+        for (i, name) in [&b"."[..], &b".."[..]].iter().enumerate() {
+            data[i * DIRENT_SIZE..(i + 1) * DIRENT_SIZE]
+                .copy_from_slice(Dirent::with_name(ROOTINO, name).as_bytes());
+        }
+        // SAFETY: `page` holds a `Dinode`.
+        let dinode = unsafe { Self::from_page(&mut page).as_mut() };
+        dinode.nlink = 1;
+        dinode.size = 2 * DIRENT_SIZE as u32;
+        dinode.attr = Attr::new(InodeType::Dir, 0, 0);
+        dinode.pages[0] = Some(data);
+        Some(page)
+    }
+
+    /// Frees the page `page` of an inode, and its content.
+    ///
+    /// # Safety
+    ///
+    /// `page` must hold a `Dinode`.
+    pub unsafe fn free(mut page: Page) {
+        // SAFETY: `page` holds a `Dinode` by the safety condition.
+        unsafe { Self::from_page(&mut page).as_mut() }.trunc();
+        hal().kmem().free(page);
+    }
+
+    /// Returns a pointer to the inode in `page`.
+    ///
+    /// # Safety
+    ///
+    /// `page` must hold a `Dinode`.
+    pub unsafe fn from_page(page: &mut Page) -> NonNull<Self> {
+        // SAFETY: `page` holds a `Dinode` by the safety condition.
+        NonNull::from(unsafe { page.as_uninit_mut::<Self>().assume_init_mut() })
+    }
+
+    /// Frees the content of the inode.
+    pub fn trunc(&mut self) {
+        for page in self.pages.iter_mut().filter_map(Option::take) {
+            hal().kmem().free(page);
+        }
+        self.size = 0;
+    }
+}
+
+impl Dirent {
+    fn new(
+        ip: &mut InodeGuard<'_, Tmpfs>,
+        off: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Dirent, Errno> {
+        let mut dirent = Dirent::default();
+        ip.read_kernel(&mut dirent, off, ctx)?;
+        Ok(dirent)
+    }
+
+    /// Makes an entry for `name`, which must be at most `DIRSIZ` bytes long.
+    pub fn with_name(inum: u32, name: &[u8]) -> Self {
+        let mut dirent = Self {
+            inum: inum as _,
+            ..Default::default()
+        };
+        dirent.name[..name.len()].copy_from_slice(name);
+        dirent
+    }
+
+    /// Returns slice which exactly contains `name`.
+    ///
+    /// It contains no NUL characters.
+    fn get_name(&self) -> &FileName<{ DIRSIZ }> {
+        let len = self.name.iter().position(|ch| *ch == 0).unwrap_or(DIRSIZ);
+        // SAFETY: self.name[..len] doesn't contain '\0', and len must be <= DIRSIZ.
+        unsafe { FileName::from_bytes(&self.name[..len]) }
+    }
+}
+
+struct DirentIter<'id, 's, 't> {
+    guard: &'s mut InodeGuard<'t, Tmpfs>,
+    iter: StepBy<Range<u32>>,
+    ctx: &'s KernelCtx<'id, 's>,
+}
+
+impl Iterator for DirentIter<'_, '_, '_> {
+    type Item = (Dirent, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let off = self.iter.next()?;
+        let dirent = Dirent::new(self.guard, off, self.ctx).expect("DirentIter");
+        Some((dirent, off))
+    }
+}
+
+impl<'t> InodeGuard<'t, Tmpfs> {
+    fn iter_dirents<'id, 's>(&'s mut self, ctx: &'s KernelCtx<'id, 's>) -> DirentIter<'id, 's, 't> {
+        self.iter_dirents_from(0, ctx)
+    }
+
+    /// Iterates the entries from the offset `off`, which must be a multiple of
+    /// `DIRENT_SIZE`.
+    fn iter_dirents_from<'id, 's>(
+        &'s mut self,
+        off: u32,
+        ctx: &'s KernelCtx<'id, 's>,
+    ) -> DirentIter<'id, 's, 't> {
+        let iter = (off..self.dinode().size).step_by(DIRENT_SIZE);
+        DirentIter {
+            guard: self,
+            iter,
+            ctx,
+        }
+    }
+}
+
+impl InodeGuard<'_, Tmpfs> {
+    pub fn dinode(&self) -> &Dinode {
+        let dinode = self.deref_inner().dinode.expect("dinode");
+        // SAFETY: the inode is locked, and its page is freed only after its
+        // last reference has gone.
+        unsafe { dinode.as_ref() }
+    }
+
+    pub fn dinode_mut(&mut self) -> &mut Dinode {
+        let mut dinode = self.deref_inner().dinode.expect("dinode");
+        // SAFETY: the inode is locked, `&mut self` is exclusive, and its page
+        // is freed only after its last reference has gone.
+        unsafe { dinode.as_mut() }
+    }
+}
+
+// Directories
+impl InodeGuard<'_, Tmpfs> {
+    /// Write a new directory entry (name, inum) into the directory dp.
+    /// Returns Err(EEXIST) if name is already present.
+    pub fn dirlink(
+        &mut self,
+        name: &FileName<DIRSIZ>,
+        inum: u32,
+        tx: &Tx<'_, Tmpfs>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        // Check that name is not present.
+        if let Ok((ip, _)) = self.dirlookup(name, ctx) {
+            ip.free((tx, ctx));
+            return Err(Errno::EEXIST);
+        };
+
+        // Look for an empty Dirent.
+        let off = self
+            .iter_dirents(ctx)
+            .find(|(de, _)| de.inum == 0)
+            .map_or(self.dinode().size, |(_, off)| off);
+        self.write_kernel(&Dirent::with_name(inum, name.as_bytes()), off, tx, ctx)
+    }
+
+    /// Make the directory entry at offset `off` refer to the inode `inum`.
+    pub fn dirrelink(&mut self, off: u32, inum: u32, tx: &Tx<'_, Tmpfs>, ctx: &KernelCtx<'_, '_>) {
+        // The entry begins with the inode number.
+        self.write_kernel(&(inum as u16), off, tx, ctx)
+            .expect("dirrelink");
+    }
+
+    /// Calls `f(next, inum, name)` for each used entry from the offset `off`,
+    /// until `f` returns false. `next` is the offset after the entry.
+    pub fn dirread<F>(&mut self, off: u32, mut f: F, ctx: &KernelCtx<'_, '_>)
+    where
+        F: FnMut(u32, u32, &[u8]) -> bool,
+    {
+        // Start from the entry after the one that `off` is in the middle of.
+        let off = (off + DIRENT_SIZE as u32 - 1) / DIRENT_SIZE as u32 * DIRENT_SIZE as u32;
+        let dirents = self.iter_dirents_from(off, ctx);
+        for (de, off) in dirents.filter(|(de, _)| de.inum != 0) {
+            if !f(
+                off + DIRENT_SIZE as u32,
+                de.inum as u32,
+                de.get_name().as_bytes(),
+            ) {
+                break;
+            }
+        }
+    }
+
+    /// Look for a directory entry in a directory.
+    /// If found, return the entry and byte offset of entry. Otherwise, return Err(ENOENT).
+    pub fn dirlookup(
+        &mut self,
+        name: &FileName<DIRSIZ>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<Tmpfs>, u32), Errno> {
+        assert_eq!(self.dinode().typ, InodeType::Dir, "dirlookup not DIR");
+
+        self.iter_dirents(ctx)
+            .find(|(de, _)| de.inum != 0 && de.get_name() == name)
+            .map(|(de, off)| {
+                (
+                    ctx.kernel()
+                        .tmpfs()
+                        .itable()
+                        .get_inode(self.dev, de.inum as u32),
+                    off,
+                )
+            })
+            .ok_or(Errno::ENOENT)
+    }
+
+    /// Is the directory dp empty except for "." and ".." ?
+    #[allow(clippy::wrong_self_convention)] // for invariant reads
+    pub fn is_dir_empty(&mut self, ctx: &KernelCtx<'_, '_>) -> bool {
+        let mut de: Dirent = Default::default();
+        for off in (2 * DIRENT_SIZE as u32..self.dinode().size).step_by(DIRENT_SIZE) {
+            self.read_kernel(&mut de, off, ctx)
+                .expect("is_dir_empty: read_kernel");
+            if de.inum != 0 {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl InodeInner {
+    /// Looks up the page of the inode, if it has not been.
+    pub fn load(&mut self, inum: u32, tmpfs: &Tmpfs) {
+        if self.dinode.is_none() {
+            self.dinode = Some(tmpfs.dinode(inum));
+        }
+    }
+
+    /// Forgets the page of the inode.
+    pub fn unload(&mut self) {
+        self.dinode = None;
+    }
+
+    /// Returns `true` if the inode has been looked up and has no links.
+    pub fn is_unlinked(&mut self) -> bool {
+        // SAFETY: `&mut self` is exclusive, so no one else accesses the inode.
+        self.dinode
+            .map_or(false, |dinode| unsafe { dinode.as_ref() }.nlink == 0)
+    }
+}
+
+impl const Default for Inode<Tmpfs> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Inode<Tmpfs> {
+    pub const fn new() -> Self {
+        Self {
+            dev: 0,
+            inum: 0,
+            inner: SleepLock::new("inode", InodeInner { dinode: None }),
+        }
+    }
+}
+
+impl Itable<Tmpfs> {
+    pub const fn new_itable() -> Self {
+        ArrayArena::<Inode<Tmpfs>, NINODE>::new("TMPITABLE")
+    }
+
+    /// Find the inode with number inum on device dev
+    /// and return the in-memory copy. Does not lock the inode.
+    pub fn get_inode(self: StrongPin<'_, Self>, dev: u32, inum: u32) -> RcInode<Tmpfs> {
+        self.find_or_alloc(
+            |inode| inode.dev == dev && inode.inum == inum,
+            |inode| {
+                inode.dev = dev;
+                inode.inum = inum;
+                inode.inner.get_mut().unload();
+            },
+        )
+        .expect("[Itable::get_inode] no inodes")
+    }
+
+    /// Allocate an inode on device dev.
+    /// Returns an unlocked but allocated and referenced inode, or Err(ENOMEM) if
+    /// the inodes or the memory are exhausted.
+    pub fn alloc_inode(
+        self: StrongPin<'_, Self>,
+        dev: u32,
+        typ: InodeType,
+        tx: &Tx<'_, Tmpfs>,
+    ) -> Result<RcInode<Tmpfs>, Errno> {
+        let mut inodes = tx.fs.inodes.lock();
+        let inum = (ROOTINO as usize + 1..NTMPINODE)
+            .find(|inum| inodes[*inum].is_none())
+            .ok_or(Errno::ENOMEM)?;
+        inodes[inum] = Some(Dinode::alloc(typ).ok_or(Errno::ENOMEM)?);
+        drop(inodes);
+        Ok(self.get_inode(dev, inum as u32))
+    }
+}
@@ -0,0 +1,508 @@
+//! An in-memory file system.
+//!
+//! The inodes and the content of files live in pages allocated from `Kmem`
+//! instead of a disk, so no operation sleeps for the disk. There is no log:
+//! each operation modifies the inodes in place while holding their locks.
+//! The files stay across unmounts, but are lost when the machine restarts.
+//!
+//! A tmpfs does not have a disk, but it is identified by the device number
+//! `TMPDEV` like the file systems on disks.
+
+use core::ops::Deref;
+use core::{cmp, mem, ptr::NonNull};
+
+use array_macro::array;
+use derive_more::{Deref, DerefMut};
+use pin_project::pin_project;
+use spin::Once;
+
+use super::{
+    Attr, FileName, FileSystem, FsType, Inode, InodeGuard, InodeType, Itable, RcInode, Stat, Tx,
+};
+use crate::{
+    errno::Errno,
+    hal::hal,
+    lock::SpinLock,
+    page::{Page, PGSIZE},
+    param::{MAXPATH, NTMPINODE},
+    proc::KernelCtx,
+    some_or,
+    timer::realtime,
+    util::strong_pin::StrongPin,
+};
+
+mod inode;
+
+use inode::{Dinode, DIRSIZ, NPAGE};
+pub use inode::{Dirent, InodeInner};
+
+/// root i-number
+const ROOTINO: u32 = 1;
+
+/// The maximum size of a file in bytes.
+const MAXFILE: usize = NPAGE * PGSIZE;
+
+/// The pages of the allocated inodes, indexed by inode numbers.
+#[derive(Deref, DerefMut)]
+struct Inodes([Option<Page>; NTMPINODE]);
+
+// `Inodes` is `Send` because the pages do not point to thread-local data, and
+// they are accessed only after acquiring a lock.
+unsafe impl Send for Inodes {}
+
+#[pin_project]
+pub struct Tmpfs {
+    /// The device number of the file system. A `Tmpfs` has one instance only.
+    dev: Once<u32>,
+    inodes: SpinLock<Inodes>,
+    #[pin]
+    itable: Itable<Self>,
+}
+
+impl Tmpfs {
+    pub const fn new() -> Self {
+        Self {
+            dev: Once::new(),
+            inodes: SpinLock::new("TMPINODES", Inodes(array![_ => None; NTMPINODE])),
+            itable: Itable::<Self>::new_itable(),
+        }
+    }
+
+    /// Returns a pointer to the inode `inum`, which must have been allocated.
+    fn dinode(&self, inum: u32) -> NonNull<Dinode> {
+        let mut inodes = self.inodes.lock();
+        let page = inodes[inum as usize].as_mut().expect("dinode: no inode");
+        // SAFETY: the pages in `inodes` hold `Dinode`s.
+        unsafe { Dinode::from_page(page) }
+    }
+}
+
+impl FileSystem for Tmpfs {
+    type Dirent = Dirent;
+    type InodeInner = InodeInner;
+
+    // Transactions do not limit the size of writes.
+    const MAX_WRITE: usize = MAXFILE;
+    const TYPE: FsType = FsType::Tmpfs;
+
+    fn init(&self, dev: u32, _ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        if !self.dev.is_completed() {
+            let root = Dinode::alloc_root().ok_or(Errno::ENOMEM)?;
+            let mut inodes = self.inodes.lock();
+            if *self.dev.call_once(|| dev) == dev && inodes[ROOTINO as usize].is_none() {
+                inodes[ROOTINO as usize] = Some(root);
+            } else {
+                // SAFETY: `root` holds a `Dinode`.
+                unsafe { Dinode::free(root) };
+            }
+        }
+        if self.dev.get() == Some(&dev) {
+            Ok(())
+        } else {
+            Err(Errno::EBUSY)
+        }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().itable) }
+    }
+
+    fn root(self: StrongPin<'_, Self>, dev: u32) -> RcInode<Self> {
+        self.itable().get_inode(dev, ROOTINO)
+    }
+
+    fn lookup(
+        self: StrongPin<'_, Self>,
+        dir: &mut InodeGuard<'_, Self>,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self>, Errno> {
+        Ok(dir.dirlookup(name.truncate(), ctx)?.0)
+    }
+
+    fn readdir<F>(
+        self: StrongPin<'_, Self>,
+        dir: &mut InodeGuard<'_, Self>,
+        off: u32,
+        mut f: F,
+        ctx: &KernelCtx<'_, '_>,
+    ) where
+        F: FnMut(u32, u32, InodeType, &[u8]) -> bool,
+    {
+        dir.dirread(
+            off,
+            |next, inum, name| {
+                // SAFETY: the inode is linked from `dir`, which is locked, so it
+                // is neither allocated nor freed meanwhile, and only these change
+                // its type.
+                let typ = unsafe { self.dinode(inum).as_ref() }.typ;
+                f(next, inum, typ, name)
+            },
+            ctx,
+        );
+    }
+
+    fn link(
+        self: StrongPin<'_, Self>,
+        inode: &RcInode<Self>,
+        dir: &RcInode<Self>,
+        name: &FileName<{ MAXPATH }>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let ip = inode.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        if ip.dinode().typ == InodeType::Dir {
+            return Err(Errno::EPERM);
+        }
+        ip.dinode_mut().nlink += 1;
+        ip.dinode_mut().attr.ctime = realtime();
+        drop(ip);
+
+        let res = {
+            let dp = dir.lock(ctx);
+            let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+            if dp.dev != inode.dev {
+                Err(Errno::EXDEV)
+            } else {
+                dp.dirlink(name.truncate(), inode.inum, tx, ctx)
+            }
+        };
+        if res.is_ok() {
+            return Ok(());
+        }
+
+        let ip = inode.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        ip.dinode_mut().nlink -= 1;
+        res
+    }
+
+    fn unlink(
+        self: StrongPin<'_, Self>,
+        dir: &RcInode<Self>,
+        name: &FileName<{ MAXPATH }>,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let name = name.truncate::<DIRSIZ>();
+        let dp = dir.lock(ctx);
+        let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+
+        // Cannot unlink "." or "..".
+        if name.as_bytes() == b"." || name.as_bytes() == b".." {
+            return Err(Errno::EINVAL);
+        }
+
+        let (ptr2, off) = dp.dirlookup(name, ctx)?;
+        let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+        let ip = ptr2.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        assert!(ip.dinode().nlink >= 1, "unlink: nlink < 1");
+
+        if ip.dinode().typ == InodeType::Dir && !ip.is_dir_empty(ctx) {
+            return Err(Errno::ENOTEMPTY);
+        }
+
+        dp.write_kernel(&Dirent::default(), off, tx, ctx)
+            .expect("unlink: writei");
+        if ip.dinode().typ == InodeType::Dir {
+            dp.dinode_mut().nlink -= 1;
+        }
+        drop(dp);
+        ip.dinode_mut().nlink -= 1;
+        ip.dinode_mut().attr.ctime = realtime();
+        Ok(())
+    }
+
+    fn dirlookup(
+        dir: &mut InodeGuard<'_, Self>,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<Self>, u32), Errno> {
+        dir.dirlookup(name.truncate::<DIRSIZ>(), ctx)
+    }
+
+    fn dirlink(
+        dir: &mut InodeGuard<'_, Self>,
+        name: &FileName<{ MAXPATH }>,
+        inum: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        dir.dirlink(name.truncate::<DIRSIZ>(), inum, tx, ctx)
+    }
+
+    fn dirunlink(
+        dir: &mut InodeGuard<'_, Self>,
+        off: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        dir.write_kernel(&Dirent::default(), off, tx, ctx)
+            .expect("dirunlink");
+    }
+
+    fn dirrelink(
+        dir: &mut InodeGuard<'_, Self>,
+        off: u32,
+        inum: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        dir.dirrelink(off, inum, tx, ctx);
+    }
+
+    fn is_dir_empty(dir: &mut InodeGuard<'_, Self>, ctx: &KernelCtx<'_, '_>) -> bool {
+        dir.is_dir_empty(ctx)
+    }
+
+    fn create<F, T>(
+        self: StrongPin<'_, Self>,
+        dir: &RcInode<Self>,
+        name: &FileName<{ MAXPATH }>,
+        typ: InodeType,
+        attr: Attr,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+        f: F,
+    ) -> Result<(RcInode<Self>, T), Errno>
+    where
+        F: FnOnce(&mut InodeGuard<'_, Self>) -> T,
+    {
+        let name = name.truncate::<DIRSIZ>();
+        let dp = dir.lock(ctx);
+        let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+        if let Ok((ptr2, _)) = dp.dirlookup(name, ctx) {
+            let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+            drop(dp);
+            if typ != InodeType::File {
+                return Err(Errno::EEXIST);
+            }
+            let ip = ptr2.lock(ctx);
+            let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+            if let InodeType::None | InodeType::Dir = ip.dinode().typ {
+                return Err(Errno::EISDIR);
+            }
+            let ret = f(&mut ip);
+            drop(ip);
+            return Ok((scopeguard::ScopeGuard::into_inner(ptr2), ret));
+        }
+        let ptr2 = self.itable().alloc_inode(dp.dev, typ, tx)?;
+        let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+        let ip = ptr2.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+
+        // Link the inode only after its entries are written, so that it is
+        // freed when it cannot be linked for lack of memory.
+        if typ == InodeType::Dir {
+            let inum = ip.inum;
+            // SAFETY: b"." and b".." do not contain any NUL characters.
+            let (dot, dotdot) =
+                unsafe { (FileName::from_bytes(b"."), FileName::from_bytes(b"..")) };
+            // No ip->nlink++ for ".": avoid cyclic ref count.
+            ip.dirlink(dot, inum, tx, ctx)?;
+            ip.dirlink(dotdot, dp.inum, tx, ctx)?;
+        }
+        dp.dirlink(name, ip.inum, tx, ctx)?;
+        ip.dinode_mut().nlink = 1;
+        ip.dinode_mut().attr = attr;
+        if typ == InodeType::Dir {
+            // for ".."
+            dp.dinode_mut().nlink += 1;
+        }
+        let ret = f(&mut ip);
+        drop(ip);
+        Ok((scopeguard::ScopeGuard::into_inner(ptr2), ret))
+    }
+
+    fn tx_begin(&self, _ctx: &KernelCtx<'_, '_>) {}
+
+    unsafe fn tx_end(&self, _tx: &mut Tx<'_, Self>, _ctx: &KernelCtx<'_, '_>) {}
+
+    fn sync(&self, _ctx: &KernelCtx<'_, '_>) {}
+
+    fn dirty_since(&self, _ctx: &KernelCtx<'_, '_>) -> Option<u32> {
+        None
+    }
+
+    #[inline]
+    fn inode_read<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), Errno>,
+    >(
+        guard: &mut InodeGuard<'_, Self>,
+        mut off: u32,
+        mut n: u32,
+        mut f: F,
+        mut k: K,
+    ) -> Result<usize, Errno> {
+        let size = guard.dinode().size;
+        if off > size || off.wrapping_add(n) < off {
+            return Ok(0);
+        }
+        if off + n > size {
+            n = size - off;
+        }
+        guard.dinode_mut().attr.atime = realtime();
+        let mut tot: u32 = 0;
+        while tot < n {
+            let page = guard.dinode().pages[off as usize / PGSIZE]
+                .as_ref()
+                .expect("inode_read: no page");
+            let m = cmp::min(n - tot, PGSIZE as u32 - off % PGSIZE as u32);
+            let begin = off as usize % PGSIZE;
+            let end = begin + m as usize;
+            f(tot, &page[begin..end], &mut k)?;
+            tot += m;
+            off += m;
+        }
+        Ok(tot as usize)
+    }
+
+    #[inline]
+    fn inode_write<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), Errno>,
+    >(
+        guard: &mut InodeGuard<'_, Self>,
+        mut off: u32,
+        n: u32,
+        mut f: F,
+        _tx: &Tx<'_, Self>,
+        mut k: K,
+    ) -> Result<usize, Errno> {
+        if off > guard.dinode().size {
+            return Err(Errno::EINVAL);
+        }
+        if off.checked_add(n).ok_or(Errno::EFBIG)? as usize > MAXFILE {
+            return Err(Errno::EFBIG);
+        }
+        let mut tot: u32 = 0;
+        while tot < n {
+            let page = &mut guard.dinode_mut().pages[off as usize / PGSIZE];
+            if page.is_none() {
+                *page = hal().kmem().alloc(Some(0));
+            }
+            let page = some_or!(page.as_mut(), {
+                if tot == 0 {
+                    return Err(Errno::ENOMEM);
+                }
+                break;
+            });
+            let m = cmp::min(n - tot, PGSIZE as u32 - off % PGSIZE as u32);
+            let begin = off as usize % PGSIZE;
+            let end = begin + m as usize;
+            if f(tot, &mut page[begin..end], &mut k).is_err() {
+                break;
+            }
+            tot += m;
+            off += m;
+        }
+
+        if off > guard.dinode().size {
+            guard.dinode_mut().size = off;
+        }
+        if tot > 0 {
+            let attr = &mut guard.dinode_mut().attr;
+            attr.mtime = realtime();
+            attr.ctime = attr.mtime;
+        }
+        Ok(tot as usize)
+    }
+
+    fn inode_trunc(guard: &mut InodeGuard<'_, Self>, _tx: &Tx<'_, Self>, _ctx: &KernelCtx<'_, '_>) {
+        let dinode = guard.dinode_mut();
+        dinode.trunc();
+        dinode.attr.mtime = realtime();
+        dinode.attr.ctime = dinode.attr.mtime;
+    }
+
+    fn inode_lock<'a>(inode: &'a Inode<Self>, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'a, Self> {
+        let mut guard = inode.inner.lock(ctx);
+        guard.load(inode.inum, &ctx.kernel().tmpfs());
+        mem::forget(guard);
+        InodeGuard { inode }
+    }
+
+    fn inode_finalize<'a, 'id: 'a>(
+        inode: &mut Inode<Self>,
+        _tx: &'a Tx<'a, Self>,
+        ctx: &'a KernelCtx<'id, 'a>,
+    ) {
+        if inode.inner.get_mut().is_unlinked() {
+            // inode has no links and no other references: free the inode and
+            // its content.
+            let page = ctx.kernel().tmpfs().inodes.lock()[inode.inum as usize]
+                .take()
+                .expect("inode_finalize: no inode");
+            // SAFETY: the pages in `inodes` hold `Dinode`s.
+            unsafe { Dinode::free(page) };
+            inode.inner.get_mut().unload();
+        }
+    }
+
+    fn inode_stat(inode: &Inode<Self>, ctx: &KernelCtx<'_, '_>) -> Stat {
+        let ip = inode.lock(ctx);
+        let dinode = ip.dinode();
+        let st = Stat {
+            dev: inode.dev as i32,
+            ino: inode.inum,
+            typ: match dinode.typ {
+                InodeType::None => 0,
+                InodeType::Dir => 1,
+                InodeType::File => 2,
+                InodeType::Device { .. } => 3,
+                InodeType::Symlink => 4,
+            },
+            nlink: dinode.nlink,
+            _padding: 0,
+            size: dinode.size as usize,
+            mode: dinode.attr.mode as u32,
+            uid: dinode.attr.uid,
+            gid: dinode.attr.gid,
+            atime: dinode.attr.atime,
+            mtime: dinode.attr.mtime,
+            ctime: dinode.attr.ctime,
+        };
+        ip.free(ctx);
+        st
+    }
+
+    fn inode_type(guard: &InodeGuard<'_, Self>) -> InodeType {
+        guard.dinode().typ
+    }
+
+    fn inode_nlink(guard: &InodeGuard<'_, Self>) -> i16 {
+        guard.dinode().nlink
+    }
+
+    fn inode_set_nlink(
+        guard: &mut InodeGuard<'_, Self>,
+        nlink: i16,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) {
+        guard.dinode_mut().nlink = nlink;
+        guard.dinode_mut().attr.ctime = realtime();
+    }
+
+    fn inode_attr(guard: &InodeGuard<'_, Self>) -> Attr {
+        guard.dinode().attr
+    }
+
+    fn inode_set_attr(
+        guard: &mut InodeGuard<'_, Self>,
+        attr: Attr,
+        _tx: &Tx<'_, Self>,
+        _ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        guard.dinode_mut().attr = attr;
+        Ok(())
+    }
+}
@@ -0,0 +1,269 @@
+//! Extent trees.
+//!
+//! With the extents feature, the block addresses of an inode are the root of
+//! an extent tree, which maps the blocks of the file by extents instead of one
+//! by one. An extent (start, len) says that the next `len` blocks of the file
+//! are the consecutive disk blocks from `start`, so a file whose blocks are
+//! allocated contiguously needs only a few extents, and a run of its blocks
+//! can be read through a single disk request.
+//!
+//! A node of the tree is a header, which holds the number of entries and the
+//! depth of the node, followed by (start, len) entries. The root is in the
+//! block addresses of the inode, and the other nodes are in blocks. In a leaf,
+//! whose depth is 0, the entries are extents. In a node of depth d > 0, an
+//! entry says that the node of depth d - 1 in the block `start` maps the next
+//! `len` blocks of the file.
+//!
+//! Files have no holes, so a block is always added at the end of a file. It
+//! extends the last extent if it follows the extent on the disk, and otherwise
+//! becomes a new extent in the last leaf, which may need new nodes under the
+//! last nodes of the tree. If the root is full, its entries move to a new
+//! block under it, which makes the tree one level deeper.
+
+use super::{Ufs, NADDR};
+use crate::{
+    bio::Buf,
+    fs::{InodeGuard, Tx},
+    hal::hal,
+    proc::KernelCtx,
+    some_or,
+};
+
+/// An entry of a node of an extent tree.
+#[derive(Clone, Copy)]
+struct Extent {
+    start: u32,
+    len: u32,
+}
+
+/// A node of an extent tree, in the block addresses of an inode or in a block.
+struct Node<T>(T);
+
+impl<T: AsRef<[u32]>> Node<T> {
+    /// Returns the number of entries.
+    fn len(&self) -> usize {
+        (self.0.as_ref()[0] & 0xffff) as usize
+    }
+
+    /// Returns the depth, which is 0 for a leaf.
+    fn depth(&self) -> u32 {
+        self.0.as_ref()[0] >> 16
+    }
+
+    fn is_full(&self) -> bool {
+        self.len() == (self.0.as_ref().len() - 1) / 2
+    }
+
+    fn get(&self, i: usize) -> Extent {
+        let words = self.0.as_ref();
+        Extent {
+            start: words[1 + 2 * i],
+            len: words[2 + 2 * i],
+        }
+    }
+
+    fn last(&self) -> Option<Extent> {
+        Some(self.get(self.len().checked_sub(1)?))
+    }
+
+    fn entries(&self) -> impl Iterator<Item = Extent> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    /// Returns the entry that maps the `bn`th block of the subtree, and the
+    /// index of the block among the blocks of the entry.
+    fn find(&self, mut bn: usize) -> Option<(Extent, usize)> {
+        for e in self.entries() {
+            if bn < e.len as usize {
+                return Some((e, bn));
+            }
+            bn -= e.len as usize;
+        }
+        None
+    }
+}
+
+impl<T: AsRef<[u32]> + AsMut<[u32]>> Node<T> {
+    fn set_header(&mut self, len: usize, depth: u32) {
+        self.0.as_mut()[0] = len as u32 | depth << 16;
+    }
+
+    fn set(&mut self, i: usize, e: Extent) {
+        let words = self.0.as_mut();
+        words[1 + 2 * i] = e.start;
+        words[2 + 2 * i] = e.len;
+    }
+
+    /// Adds `e` after the entries. Returns `false` if the node is full.
+    fn push(&mut self, e: Extent) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        let len = self.len();
+        self.set(len, e);
+        self.set_header(len + 1, self.depth());
+        true
+    }
+}
+
+/// Returns the node in the block of `bp`.
+fn block_node(bp: &Buf) -> Node<&[u32]> {
+    // SAFETY: u32 does not have internal structure.
+    let (prefix, data, _) = unsafe { bp.data().align_to::<u32>() };
+    debug_assert_eq!(prefix.len(), 0, "block_node: Buf data unaligned");
+    Node(data)
+}
+
+/// Returns the node in the block of `bp`.
+fn block_node_mut(bp: &mut Buf) -> Node<&mut [u32]> {
+    // SAFETY: u32 does not have internal structure.
+    let (prefix, data, _) = unsafe { bp.data_mut().align_to_mut::<u32>() };
+    debug_assert_eq!(prefix.len(), 0, "block_node_mut: Buf data unaligned");
+    Node(data)
+}
+
+/// Returns the disk block address after the last block of the subtree of
+/// `node`, or 0 if the subtree is empty.
+fn end_of<T: AsRef<[u32]>>(dev: u32, node: &Node<T>, ctx: &KernelCtx<'_, '_>) -> u32 {
+    let e = some_or!(node.last(), return 0);
+    if node.depth() == 0 {
+        return e.start + e.len;
+    }
+    let bp = hal().disks().read(dev, e.start, ctx);
+    let end = end_of(dev, &block_node(&bp), ctx);
+    bp.free(ctx);
+    end
+}
+
+impl Tx<'_, Ufs> {
+    /// Adds the block `addr` at the end of the subtree of `node`. Returns
+    /// `false`, leaving the subtree unchanged, if the subtree is full.
+    fn extent_push<T: AsRef<[u32]> + AsMut<[u32]>>(
+        &self,
+        dev: u32,
+        node: &mut Node<T>,
+        addr: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> bool {
+        let last = node.len().checked_sub(1);
+        if node.depth() == 0 {
+            if let Some(i) = last {
+                let mut e = node.get(i);
+                if e.start + e.len == addr {
+                    e.len += 1;
+                    node.set(i, e);
+                    return true;
+                }
+            }
+            return node.push(Extent {
+                start: addr,
+                len: 1,
+            });
+        }
+
+        if let Some(i) = last {
+            let mut e = node.get(i);
+            let mut bp = hal().disks().read(dev, e.start, ctx);
+            if self.extent_push(dev, &mut block_node_mut(&mut bp), addr, ctx) {
+                self.write(bp, ctx);
+                e.len += 1;
+                node.set(i, e);
+                return true;
+            }
+            bp.free(ctx);
+        }
+        if node.is_full() {
+            return false;
+        }
+        let child = self.extent_branch(dev, node.depth() - 1, addr, ctx);
+        node.push(Extent {
+            start: child,
+            len: 1,
+        })
+    }
+
+    /// Allocates the nodes of a new subtree of depth `depth` that maps only
+    /// the block `addr`, and returns the block of its root.
+    fn extent_branch(&self, dev: u32, depth: u32, addr: u32, ctx: &KernelCtx<'_, '_>) -> u32 {
+        let start = if depth == 0 {
+            addr
+        } else {
+            self.extent_branch(dev, depth - 1, addr, ctx)
+        };
+        let b = self.balloc(dev, ctx);
+        let mut bp = hal().disks().read(dev, b, ctx);
+        let mut node = block_node_mut(&mut bp);
+        node.set_header(0, depth);
+        let _ = node.push(Extent { start, len: 1 });
+        self.write(bp, ctx);
+        b
+    }
+
+    /// Free the blocks that the subtree of `node` maps, and the blocks of the
+    /// nodes under `node`.
+    fn extent_free<T: AsRef<[u32]>>(&self, dev: u32, node: &Node<T>, ctx: &KernelCtx<'_, '_>) {
+        for e in node.entries() {
+            if node.depth() == 0 {
+                for b in e.start..e.start + e.len {
+                    self.bfree(dev, b, ctx);
+                }
+            } else {
+                let bp = hal().disks().read(dev, e.start, ctx);
+                self.extent_free(dev, &block_node(&bp), ctx);
+                bp.free(ctx);
+                self.bfree(dev, e.start, ctx);
+            }
+        }
+    }
+}
+
+impl InodeGuard<'_, Ufs> {
+    /// Returns the disk block address of the `bn`th block of the file, and the
+    /// number of the blocks of the file that are consecutive on the disk from
+    /// it. Returns `None` if the file does not have the block.
+    pub fn extent_map(&self, bn: usize, ctx: &KernelCtx<'_, '_>) -> Option<(u32, usize)> {
+        let root = Node(self.deref_inner().addrs);
+        let (mut e, mut bn) = root.find(bn)?;
+        for _ in 0..root.depth() {
+            let bp = hal().disks().read(self.dev, e.start, ctx);
+            let found = block_node(&bp).find(bn);
+            bp.free(ctx);
+            (e, bn) = found?;
+        }
+        Some((e.start + bn as u32, e.len as usize - bn))
+    }
+
+    /// Adds a block at the end of the file, and returns its disk block
+    /// address. The block is allocated after the last block of the file if
+    /// possible, so that the last extent grows instead of adding an extent.
+    pub fn extent_append(&mut self, tx: &Tx<'_, Ufs>, ctx: &KernelCtx<'_, '_>) -> u32 {
+        let mut root = Node(self.deref_inner().addrs);
+        let addr = tx.balloc_near(self.dev, end_of(self.dev, &root, ctx), ctx);
+        if !tx.extent_push(self.dev, &mut root, addr, ctx) {
+            // The root is full. Move its entries to a new block under it.
+            let b = tx.balloc(self.dev, ctx);
+            let mut bp = hal().disks().read(self.dev, b, ctx);
+            block_node_mut(&mut bp).0[..NADDR].copy_from_slice(&root.0);
+            tx.write(bp, ctx);
+
+            let len = root.entries().map(|e| e.len).sum();
+            let depth = root.depth() + 1;
+            root.0 = [0; NADDR];
+            root.set_header(0, depth);
+            let _ = root.push(Extent { start: b, len });
+            assert!(
+                tx.extent_push(self.dev, &mut root, addr, ctx),
+                "extent_append"
+            );
+        }
+        self.deref_inner_mut().addrs = root.0;
+        addr
+    }
+
+    /// Frees the blocks of the file and the nodes of its extent tree, leaving
+    /// an empty tree.
+    pub fn extent_trunc(&mut self, tx: &Tx<'_, Ufs>, ctx: &KernelCtx<'_, '_>) {
+        tx.extent_free(self.dev, &Node(self.deref_inner().addrs), ctx);
+        self.deref_inner_mut().addrs = [0; NADDR];
+    }
+}
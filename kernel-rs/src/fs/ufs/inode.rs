@@ -6,7 +6,7 @@
 //! list of blocks holding the file's content.
 //!
 //! The inodes are laid out sequentially on disk at
-//! kernel.fs().superblock.startinode. Each inode has a number, indicating its
+//! superblock.inodestart. Each inode has a number, indicating its
 //! position on the disk.
 //!
 //! The kernel keeps a table of in-use inodes in memory
@@ -67,20 +67,20 @@
 //! dev, and inum.  One must hold ip->lock in order to
 //! read or write that inode's ip->valid, ip->size, ip->type, &c.
 
-use core::{iter::StepBy, mem, ops::Range};
+use core::mem;
 
 use static_assertions::const_assert;
 use zerocopy::{AsBytes, FromBytes};
 
-use super::{FileName, Path, Ufs, IPB, NDIRECT, NINDIRECT, ROOTINO};
+use super::{FileName, Superblock, Ufs, NADDR, NINDIRECT};
 use crate::{
     arena::{Arena, ArrayArena},
     bio::BufData,
-    fs::{DInodeType, Inode, InodeGuard, InodeType, Itable, RcInode, Tx},
+    errno::Errno,
+    fs::{Attr, DInodeType, Inode, InodeGuard, InodeType, Itable, RcInode, Tx},
     hal::hal,
     lock::SleepLock,
-    param::NINODE,
-    param::ROOTDEV,
+    param::{BSIZE, NINODE},
     proc::KernelCtx,
     util::{memset, strong_pin::StrongPin},
 };
@@ -91,6 +91,12 @@ pub const DIRSIZ: usize = 14;
 /// dirent size
 pub const DIRENT_SIZE: usize = mem::size_of::<Dirent>();
 
+/// Maximum length of a name in a directory with long names.
+pub const LDIRSIZ: usize = 255;
+
+/// Size of the header of an entry of a directory with long names.
+const LDIRENT_SIZE: usize = mem::size_of::<Ldirent>();
+
 pub struct InodeInner {
     /// inode has been read from disk?
     pub valid: bool,
@@ -98,8 +104,8 @@ pub struct InodeInner {
     pub typ: InodeType,
     pub nlink: i16,
     pub size: u32,
-    pub addr_direct: [u32; NDIRECT],
-    pub addr_indirect: u32,
+    pub addrs: [u32; NADDR],
+    pub attr: Attr,
 }
 
 /// On-disk inode structure
@@ -124,11 +130,108 @@ pub struct Dinode {
     /// Size of file (bytes)
     pub size: u32,
 
-    /// Direct data block addresses
-    pub addr_direct: [u32; NDIRECT],
+    /// Data block addresses: direct blocks followed by indirect blocks, as
+    /// the `BlockMap` of the file system tells.
+    pub addrs: [u32; NADDR],
+}
+
+/// On-disk permission bits, owner, and times of an inode, which follow its
+/// `Dinode` from version 1 of the disk format.
+#[repr(C)]
+pub struct DinodeAttr {
+    /// Permission bits
+    pub mode: u16,
+
+    _padding: u16,
+
+    /// Owner and group
+    pub uid: u32,
+    pub gid: u32,
+
+    /// Times of last access, modification, and status change, in seconds
+    /// since the Unix epoch
+    pub atime: u32,
+    pub mtime: u32,
+    pub ctime: u32,
+
+    /// Unused, so that the size of an inode divides `BSIZE`.
+    _reserved: [u32; 10],
+}
+
+impl DinodeAttr {
+    /// Returns the attributes in memory.
+    pub fn attr(&self) -> Attr {
+        Attr {
+            mode: self.mode,
+            uid: self.uid,
+            gid: self.gid,
+            atime: self.atime,
+            mtime: self.mtime,
+            ctime: self.ctime,
+        }
+    }
+}
+
+const_assert!(BSIZE % mem::size_of::<Dinode>() == 0);
+const_assert!(BSIZE % (mem::size_of::<Dinode>() + mem::size_of::<DinodeAttr>()) == 0);
+const_assert!(mem::align_of::<BufData>() % mem::align_of::<Dinode>() == 0);
+const_assert!(mem::size_of::<Dinode>() % mem::align_of::<DinodeAttr>() == 0);
+
+/// Returns the inode `inum` in `data`, the block of inodes of the file system
+/// `sb` that holds it, and its attributes if the file system has them.
+pub fn dinode_mut(
+    data: &mut BufData,
+    inum: u32,
+    sb: Superblock,
+) -> (&mut Dinode, Option<&mut DinodeAttr>) {
+    let off = sb.ioffset(inum);
+    // SAFETY: the inode is inside `data`, as `ioffset` says.
+    let dip = unsafe { data.as_mut_ptr().add(off) };
+    // SAFETY: i16 does not have internal structure.
+    let t = unsafe { *(dip as *const i16) };
+    // If t >= #(variants of DInodeType), UB will happen when we read dip.typ.
+    assert!(t < mem::variant_count::<DInodeType>() as i16);
+    // SAFETY:
+    // * The inode and its attributes are inside `data`, and do not overlap.
+    // * They are aligned properly, and t < #(variants of DInodeType).
+    unsafe {
+        let attr = dip.add(mem::size_of::<Dinode>()) as *mut DinodeAttr;
+        (&mut *(dip as *mut Dinode), sb.attrs().then(|| &mut *attr))
+    }
+}
+
+/// Returns the inode `inum` in `data`, as `dinode_mut` does.
+pub fn dinode(data: &BufData, inum: u32, sb: Superblock) -> (&Dinode, Option<&DinodeAttr>) {
+    let off = sb.ioffset(inum);
+    // SAFETY: the inode is inside `data`, as `ioffset` says.
+    let dip = unsafe { data.as_ptr().add(off) };
+    // SAFETY: i16 does not have internal structure.
+    let t = unsafe { *(dip as *const i16) };
+    // If t >= #(variants of DInodeType), UB will happen when we read dip.typ.
+    assert!(t < mem::variant_count::<DInodeType>() as i16);
+    // SAFETY: as in `dinode_mut`.
+    unsafe {
+        let attr = dip.add(mem::size_of::<Dinode>()) as *const DinodeAttr;
+        (&*(dip as *const Dinode), sb.attrs().then(|| &*attr))
+    }
+}
 
-    /// Indirect data block address
-    pub addr_indirect: u32,
+impl Dinode {
+    /// Returns the type of the inode, with its device numbers if it is a device.
+    pub fn inode_type(&self) -> InodeType {
+        match self.typ {
+            DInodeType::None => InodeType::None,
+            DInodeType::Dir => InodeType::Dir,
+            DInodeType::File => InodeType::File,
+            DInodeType::Symlink => InodeType::Symlink,
+            DInodeType::Device => {
+                InodeType::Device {
+                    major: self.major,
+                    minor: self.minor,
+                }
+            }
+        }
+    }
 }
 
 #[repr(C)]
@@ -138,8 +241,74 @@ pub struct Dirent {
     name: [u8; DIRSIZ],
 }
 
+/// Header of an entry of a directory with long names, which a ufs with the
+/// long names feature has instead of `Dirent`s.
+///
+/// The name follows the header without a NUL terminator, padded to 4 bytes.
+/// Entries do not cross blocks, and the entries of a block cover the whole
+/// block: an entry may have free space after its name, and a free entry has
+/// inum 0.
+#[repr(C)]
+#[derive(Default, AsBytes, FromBytes)]
+struct Ldirent {
+    inum: u32,
+    /// Length of the entry, including the free space after it.
+    reclen: u16,
+    namelen: u16,
+}
+
+impl Ldirent {
+    /// Returns the length of an entry whose name has `namelen` bytes.
+    const fn entry_len(namelen: usize) -> usize {
+        (LDIRENT_SIZE + namelen + 3) & !3
+    }
+}
+
+/// An entry of a directory, in either format.
+struct DirEntry {
+    inum: u32,
+    /// Offset of the entry in the directory.
+    off: u32,
+    /// Length of the entry, including the free space after it.
+    reclen: u32,
+    namelen: usize,
+    name: [u8; LDIRSIZ],
+}
+
+impl DirEntry {
+    fn new(inum: u32, off: u32, reclen: u32, name: &[u8]) -> Self {
+        let mut entry = Self {
+            inum,
+            off,
+            reclen,
+            namelen: name.len(),
+            name: [0; LDIRSIZ],
+        };
+        entry.name[..name.len()].copy_from_slice(name);
+        entry
+    }
+
+    fn name(&self) -> &[u8] {
+        &self.name[..self.namelen]
+    }
+
+    /// Returns the length that the entry needs in a directory with long names,
+    /// which is 0 if the entry is free.
+    fn used(&self) -> u32 {
+        if self.inum == 0 {
+            0
+        } else {
+            Ldirent::entry_len(self.namelen) as u32
+        }
+    }
+}
+
 impl Dirent {
-    fn new(ip: &mut InodeGuard<'_, Ufs>, off: u32, ctx: &KernelCtx<'_, '_>) -> Result<Dirent, ()> {
+    fn new(
+        ip: &mut InodeGuard<'_, Ufs>,
+        off: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Dirent, Errno> {
         let mut dirent = Dirent::default();
         ip.read_kernel(&mut dirent, off, ctx)?;
         Ok(dirent)
@@ -171,26 +340,50 @@ impl Dirent {
 
 struct DirentIter<'id, 's, 't> {
     guard: &'s mut InodeGuard<'t, Ufs>,
-    iter: StepBy<Range<u32>>,
+    off: u32,
+    long_names: bool,
     ctx: &'s KernelCtx<'id, 's>,
 }
 
 impl Iterator for DirentIter<'_, '_, '_> {
-    type Item = (Dirent, u32);
+    type Item = DirEntry;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let off = self.iter.next()?;
-        let dirent = Dirent::new(self.guard, off, self.ctx).expect("DirentIter");
-        Some((dirent, off))
+        if self.off >= self.guard.deref_inner().size {
+            return None;
+        }
+        let entry = if self.long_names {
+            self.guard.read_ldirent(self.off, self.ctx)
+        } else {
+            let dirent = Dirent::new(self.guard, self.off, self.ctx).expect("DirentIter");
+            DirEntry::new(
+                dirent.inum as u32,
+                self.off,
+                DIRENT_SIZE as u32,
+                dirent.get_name().as_bytes(),
+            )
+        };
+        self.off += entry.reclen;
+        Some(entry)
     }
 }
 
 impl<'t> InodeGuard<'t, Ufs> {
     fn iter_dirents<'id, 's>(&'s mut self, ctx: &'s KernelCtx<'id, 's>) -> DirentIter<'id, 's, 't> {
-        let iter = (0..self.deref_inner().size).step_by(DIRENT_SIZE);
+        self.iter_dirents_from(0, ctx)
+    }
+
+    /// Iterates the entries from the offset `off`, which must be where an
+    /// entry begins.
+    fn iter_dirents_from<'id, 's>(
+        &'s mut self,
+        off: u32,
+        ctx: &'s KernelCtx<'id, 's>,
+    ) -> DirentIter<'id, 's, 't> {
         DirentIter {
             guard: self,
-            iter,
+            off,
+            long_names: ctx.kernel().ufs(self.dev).superblock().long_names(),
             ctx,
         }
     }
@@ -198,52 +391,185 @@ impl<'t> InodeGuard<'t, Ufs> {
 
 // Directories
 impl InodeGuard<'_, Ufs> {
+    /// Read the entry at offset `off` of a directory with long names.
+    fn read_ldirent(&mut self, off: u32, ctx: &KernelCtx<'_, '_>) -> DirEntry {
+        let mut de = Ldirent::default();
+        self.read_kernel(&mut de, off, ctx).expect("read_ldirent");
+        let namelen = de.namelen as usize;
+        assert!(
+            namelen <= LDIRSIZ
+                && Ldirent::entry_len(namelen) <= de.reclen as usize
+                && off as usize % BSIZE + de.reclen as usize <= BSIZE,
+            "read_ldirent: bad entry"
+        );
+        let mut entry = DirEntry::new(de.inum, off, de.reclen as u32, &[]);
+        entry.namelen = namelen;
+        let n = self.read_bytes_kernel(&mut entry.name[..namelen], off + LDIRENT_SIZE as u32, ctx);
+        assert_eq!(n, namelen, "read_ldirent: short name");
+        entry
+    }
+
+    /// Write an entry at offset `off` of a directory with long names.
+    fn write_ldirent(
+        &mut self,
+        off: u32,
+        inum: u32,
+        reclen: u32,
+        name: &[u8],
+        tx: &Tx<'_, Ufs>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let de = Ldirent {
+            inum,
+            reclen: reclen as u16,
+            namelen: name.len() as u16,
+        };
+        self.write_kernel(&de, off, tx, ctx)?;
+        if self.write_bytes_kernel(name, off + LDIRENT_SIZE as u32, tx, ctx)? != name.len() {
+            return Err(Errno::EIO);
+        }
+        Ok(())
+    }
+
     /// Write a new directory entry (name, inum) into the directory dp.
+    /// Returns Err(EEXIST) if name is already present.
     pub fn dirlink(
         &mut self,
-        name: &FileName<DIRSIZ>,
+        name: &FileName<LDIRSIZ>,
         inum: u32,
         tx: &Tx<'_, Ufs>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), Errno> {
         // Check that name is not present.
         if let Ok((ip, _)) = self.dirlookup(name, ctx) {
             ip.free((tx, ctx));
-            return Err(());
+            return Err(Errno::EEXIST);
         };
 
-        // Look for an empty Dirent.
-        let (mut de, off) = self
+        if !tx.fs.superblock().long_names() {
+            // Look for an empty Dirent.
+            let off = self
+                .iter_dirents(ctx)
+                .find(|de| de.inum == 0)
+                .map_or(self.deref_inner().size, |de| de.off);
+            let mut de = Dirent {
+                inum: inum as _,
+                ..Default::default()
+            };
+            de.set_name(name.truncate());
+            self.write_kernel(&de, off, tx, ctx).expect("dirlink");
+            return Ok(());
+        }
+
+        // Look for an entry with enough free space after it, and split it.
+        let name = name.as_bytes();
+        let len = Ldirent::entry_len(name.len()) as u32;
+        if let Some(de) = self
             .iter_dirents(ctx)
-            .find(|(de, _)| de.inum == 0)
-            .unwrap_or((Default::default(), self.deref_inner().size));
-        de.inum = inum as _;
-        de.set_name(name);
-        self.write_kernel(&de, off, tx, ctx).expect("dirlink");
+            .find(|de| de.reclen - de.used() >= len)
+        {
+            let used = de.used();
+            if used > 0 {
+                self.write_ldirent(de.off, de.inum, used, de.name(), tx, ctx)
+                    .expect("dirlink");
+            }
+            self.write_ldirent(de.off + used, inum, de.reclen - used, name, tx, ctx)
+                .expect("dirlink");
+        } else {
+            // Add a block with a single entry.
+            let off = self.deref_inner().size;
+            self.write_ldirent(off, inum, BSIZE as u32, name, tx, ctx)
+                .expect("dirlink");
+            self.deref_inner_mut().size = off + BSIZE as u32;
+            self.update(tx, ctx);
+        }
         Ok(())
     }
 
+    /// Remove the directory entry at offset `off` from the directory dp.
+    pub fn dirunlink(&mut self, off: u32, tx: &Tx<'_, Ufs>, ctx: &KernelCtx<'_, '_>) {
+        if !tx.fs.superblock().long_names() {
+            self.write_kernel(&Dirent::default(), off, tx, ctx)
+                .expect("dirunlink");
+            return;
+        }
+
+        // Merge the entry into the previous entry of its block, or free the
+        // entry if it is the first one.
+        let mut prev = None;
+        let mut de = self.read_ldirent(off - off % BSIZE as u32, ctx);
+        while de.off < off {
+            let next = self.read_ldirent(de.off + de.reclen, ctx);
+            prev = Some(de);
+            de = next;
+        }
+        assert_eq!(de.off, off, "dirunlink: no entry");
+        let (off, inum, reclen, name) = match &prev {
+            Some(prev) => (prev.off, prev.inum, prev.reclen + de.reclen, prev.name()),
+            None => (off, 0, de.reclen, &[][..]),
+        };
+        self.write_ldirent(off, inum, reclen, name, tx, ctx)
+            .expect("dirunlink");
+    }
+
+    /// Make the directory entry at offset `off` refer to the inode `inum`.
+    pub fn dirrelink(&mut self, off: u32, inum: u32, tx: &Tx<'_, Ufs>, ctx: &KernelCtx<'_, '_>) {
+        // Both formats of entries begin with the inode number.
+        let res = if tx.fs.superblock().long_names() {
+            self.write_kernel(&inum, off, tx, ctx)
+        } else {
+            self.write_kernel(&(inum as u16), off, tx, ctx)
+        };
+        res.expect("dirrelink");
+    }
+
+    /// Calls `f(next, inum, name)` for each used entry from the offset `off`,
+    /// until `f` returns false. `next` is the offset after the entry.
+    pub fn dirread<F>(&mut self, off: u32, mut f: F, ctx: &KernelCtx<'_, '_>)
+    where
+        F: FnMut(u32, u32, &[u8]) -> bool,
+    {
+        // Start from the entry that `off` is in. An entry of a directory with
+        // long names may be anywhere in its block, but one begins each block.
+        let unit = if ctx.kernel().ufs(self.dev).superblock().long_names() {
+            BSIZE as u32
+        } else {
+            DIRENT_SIZE as u32
+        };
+        let dirents = self.iter_dirents_from(off - off % unit, ctx);
+        for de in dirents.filter(|de| de.off >= off && de.inum != 0) {
+            if !f(de.off + de.reclen, de.inum, de.name()) {
+                break;
+            }
+        }
+    }
+
     /// Look for a directory entry in a directory.
-    /// If found, return the entry and byte offset of entry.
+    /// If found, return the entry and byte offset of entry. Otherwise, return Err(ENOENT).
     pub fn dirlookup(
         &mut self,
-        name: &FileName<DIRSIZ>,
+        name: &FileName<LDIRSIZ>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<Ufs>, u32), ()> {
+    ) -> Result<(RcInode<Ufs>, u32), Errno> {
         assert_eq!(self.deref_inner().typ, InodeType::Dir, "dirlookup not DIR");
 
+        let name = if ctx.kernel().ufs(self.dev).superblock().long_names() {
+            name.as_bytes()
+        } else {
+            name.truncate::<DIRSIZ>().as_bytes()
+        };
         self.iter_dirents(ctx)
-            .find(|(de, _)| de.inum != 0 && de.get_name() == name)
-            .map(|(de, off)| {
+            .find(|de| de.inum != 0 && de.name() == name)
+            .map(|de| {
                 (
                     ctx.kernel()
-                        .fs()
+                        .ufs(self.dev)
                         .itable()
-                        .get_inode(self.dev, de.inum as u32),
-                    off,
+                        .get_inode(self.dev, de.inum),
+                    de.off,
                 )
             })
-            .ok_or(())
+            .ok_or(Errno::ENOENT)
     }
 }
 
@@ -252,19 +578,9 @@ impl InodeGuard<'_, Ufs> {
     /// Must be called after every change to an ip->xxx field
     /// that lives on disk.
     pub fn update(&self, tx: &Tx<'_, Ufs>, ctx: &KernelCtx<'_, '_>) {
-        let mut bp = hal()
-            .disk()
-            .read(self.dev, tx.fs.superblock().iblock(self.inum), ctx);
-
-        const_assert!(IPB <= mem::size_of::<BufData>() / mem::size_of::<Dinode>());
-        const_assert!(mem::align_of::<BufData>() % mem::align_of::<Dinode>() == 0);
-        // SAFETY:
-        // * dip is aligned properly.
-        // * dip is inside bp.data.
-        // * dip will not be read.
-        let dip = unsafe {
-            &mut *(bp.data_mut().as_mut_ptr() as *mut Dinode).add(self.inum as usize % IPB)
-        };
+        let sb = *tx.fs.superblock();
+        let mut bp = hal().disks().read(self.dev, sb.iblock(self.inum), ctx);
+        let (dip, dattr) = dinode_mut(bp.data_mut(), self.inum, sb);
 
         let inner = self.deref_inner();
         match inner.typ {
@@ -288,23 +604,40 @@ impl InodeGuard<'_, Ufs> {
                 dip.major = 0;
                 dip.minor = 0;
             }
+            InodeType::Symlink => {
+                dip.typ = DInodeType::Symlink;
+                dip.major = 0;
+                dip.minor = 0;
+            }
         }
 
         (*dip).nlink = inner.nlink;
         (*dip).size = inner.size;
-        for (d, s) in (*dip).addr_direct.iter_mut().zip(&inner.addr_direct) {
-            *d = *s;
+        (*dip).addrs = inner.addrs;
+        // An old image does not keep the attributes.
+        if let Some(dattr) = dattr {
+            dattr.mode = inner.attr.mode;
+            dattr.uid = inner.attr.uid;
+            dattr.gid = inner.attr.gid;
+            dattr.atime = inner.attr.atime;
+            dattr.mtime = inner.attr.mtime;
+            dattr.ctime = inner.attr.ctime;
         }
-        (*dip).addr_indirect = inner.addr_indirect;
         tx.write(bp, ctx);
     }
 
     /// Inode content
     ///
     /// The content (data) associated with each inode is stored
-    /// in blocks on the disk. The first NDIRECT block numbers
-    /// are listed in self->addrs[].  The next NINDIRECT blocks are
-    /// listed in block self->addr_indirect.
+    /// in blocks on the disk. The block numbers are listed in
+    /// self->addrs[] and the indirect blocks under them, as the
+    /// `BlockMap` of the file system tells. For example, with
+    /// `BlockMap::BIG`, the first 10 block numbers are listed in
+    /// self->addrs[], the next NINDIRECT blocks in the indirect
+    /// block self->addrs[10], the next NINDIRECT^2 blocks under the
+    /// doubly-indirect block self->addrs[11], and so on. With the
+    /// extents feature, self->addrs[] is the root of an extent tree
+    /// instead (see `extent.rs`).
     /// Return the disk block address of the nth block in inode self.
     /// If there is no such block, bmap allocates one.
     pub fn bmap_or_alloc(&mut self, bn: usize, tx: &Tx<'_, Ufs>, ctx: &KernelCtx<'_, '_>) -> u32 {
@@ -315,59 +648,69 @@ impl InodeGuard<'_, Ufs> {
         self.bmap_internal(bn, None, ctx)
     }
 
+    /// Return the disk block address of the nth block in inode self, and
+    /// the number of the blocks of the file that are consecutive on the
+    /// disk from it, which is 1 without extent trees.
+    pub fn bmap_run(&mut self, bn: usize, ctx: &KernelCtx<'_, '_>) -> (u32, usize) {
+        if ctx.kernel().ufs(self.dev).superblock().extents() {
+            self.extent_map(bn, ctx).expect("bmap: out of range")
+        } else {
+            (self.bmap(bn, ctx), 1)
+        }
+    }
+
     fn bmap_internal(
         &mut self,
         bn: usize,
         tx_opt: Option<&Tx<'_, Ufs>>,
         ctx: &KernelCtx<'_, '_>,
     ) -> u32 {
-        let inner = self.deref_inner();
+        if ctx.kernel().ufs(self.dev).superblock().extents() {
+            // Files have no holes, so a block that the file does not have
+            // is the next block at its end.
+            return match self.extent_map(bn, ctx) {
+                Some((addr, _)) => addr,
+                None => self.extent_append(tx_opt.expect("bmap: out of range"), ctx),
+            };
+        }
 
-        if bn < NDIRECT {
-            let mut addr = inner.addr_direct[bn];
-            if addr == 0 {
-                addr = tx_opt.expect("bmap: out of range").balloc(self.dev, ctx);
-                self.deref_inner_mut().addr_direct[bn] = addr;
-            }
-            addr
-        } else {
-            let bn = bn - NDIRECT;
-            assert!(bn < NINDIRECT, "bmap: out of range");
+        let map = ctx.kernel().ufs(self.dev).superblock().block_map();
+        let (i, level, mut bn) = map.locate(bn).expect("bmap: out of range");
 
-            let mut indirect = inner.addr_indirect;
-            if indirect == 0 {
-                indirect = tx_opt.expect("bmap: out of range").balloc(self.dev, ctx);
-                self.deref_inner_mut().addr_indirect = indirect;
-            }
+        let mut addr = self.deref_inner().addrs[i];
+        if addr == 0 {
+            addr = tx_opt.expect("bmap: out of range").balloc(self.dev, ctx);
+            self.deref_inner_mut().addrs[i] = addr;
+        }
+
+        // Walk down the indirect blocks.
+        for level in (0..level).rev() {
+            let span = NINDIRECT.pow(level);
+            let index = bn / span;
+            bn %= span;
 
-            let mut bp = hal().disk().read(self.dev, indirect, ctx);
+            let mut bp = hal().disks().read(self.dev, addr, ctx);
+            // SAFETY: u32 does not have internal structure.
             let (prefix, data, _) = unsafe { bp.data_mut().align_to_mut::<u32>() };
             debug_assert_eq!(prefix.len(), 0, "bmap: Buf data unaligned");
-            let mut addr = data[bn];
+            addr = data[index];
             if addr == 0 {
                 let tx = tx_opt.expect("bmap: out of range");
                 addr = tx.balloc(self.dev, ctx);
-                data[bn] = addr;
+                data[index] = addr;
                 tx.write(bp, ctx);
             } else {
                 bp.free(ctx);
             }
-            addr
         }
+        addr
     }
 
     /// Is the directory dp empty except for "." and ".." ?
     #[allow(clippy::wrong_self_convention)] // for invariant reads
     pub fn is_dir_empty(&mut self, ctx: &KernelCtx<'_, '_>) -> bool {
-        let mut de: Dirent = Default::default();
-        for off in (2 * DIRENT_SIZE as u32..self.deref_inner().size).step_by(DIRENT_SIZE) {
-            self.read_kernel(&mut de, off, ctx)
-                .expect("is_dir_empty: read_kernel");
-            if de.inum != 0 {
-                return false;
-            }
-        }
-        true
+        self.iter_dirents(ctx)
+            .all(|de| de.inum == 0 || de.name() == b"." || de.name() == b"..")
     }
 }
 
@@ -389,8 +732,8 @@ impl Inode<Ufs> {
                     typ: InodeType::None,
                     nlink: 0,
                     size: 0,
-                    addr_direct: [0; NDIRECT],
-                    addr_indirect: 0,
+                    addrs: [0; NADDR],
+                    attr: Attr::default(),
                 },
             ),
         }
@@ -428,28 +771,23 @@ impl Itable<Ufs> {
         ctx: &KernelCtx<'_, '_>,
     ) -> RcInode<Ufs> {
         for inum in 1..tx.fs.superblock().ninodes {
-            let mut bp = hal().disk().read(dev, tx.fs.superblock().iblock(inum), ctx);
-
-            const_assert!(IPB <= mem::size_of::<BufData>() / mem::size_of::<Dinode>());
-            const_assert!(mem::align_of::<BufData>() % mem::align_of::<Dinode>() == 0);
-            // SAFETY: dip is inside bp.data.
-            let dip =
-                unsafe { (bp.data_mut().as_mut_ptr() as *mut Dinode).add(inum as usize % IPB) };
-            // SAFETY: i16 does not have internal structure.
-            let t = unsafe { *(dip as *const i16) };
-            // If t >= #(variants of DInodeType), UB will happen when we read dip.typ.
-            assert!(t < core::mem::variant_count::<DInodeType>() as i16);
-            // SAFETY: dip is aligned properly and t < #(variants of DInodeType).
-            let dip = unsafe { &mut *dip };
+            let sb = *tx.fs.superblock();
+            let mut bp = hal().disks().read(dev, sb.iblock(inum), ctx);
+            let (dip, dattr) = dinode_mut(bp.data_mut(), inum, sb);
 
             // a free inode
             if dip.typ == DInodeType::None {
                 // SAFETY: DInode does not have any invariant.
                 unsafe { memset(dip, 0u32) };
+                if let Some(dattr) = dattr {
+                    // SAFETY: DinodeAttr does not have any invariant.
+                    unsafe { memset(dattr, 0u32) };
+                }
                 match typ {
                     InodeType::None => dip.typ = DInodeType::None,
                     InodeType::Dir => dip.typ = DInodeType::Dir,
                     InodeType::File => dip.typ = DInodeType::File,
+                    InodeType::Symlink => dip.typ = DInodeType::Symlink,
                     InodeType::Device { major, minor } => {
                         dip.typ = DInodeType::Device;
                         dip.major = major;
@@ -466,67 +804,4 @@ impl Itable<Ufs> {
         }
         panic!("[Itable::alloc_inode] no inodes");
     }
-
-    pub fn root(self: StrongPin<'_, Self>) -> RcInode<Ufs> {
-        self.get_inode(ROOTDEV, ROOTINO)
-    }
-
-    pub fn namei(
-        self: StrongPin<'_, Self>,
-        path: &Path,
-        tx: &Tx<'_, Ufs>,
-        proc: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Ufs>, ()> {
-        Ok(self.namex(path, false, tx, proc)?.0)
-    }
-
-    pub fn nameiparent<'s>(
-        self: StrongPin<'_, Self>,
-        path: &'s Path,
-        tx: &Tx<'_, Ufs>,
-        ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<Ufs>, &'s FileName<{ DIRSIZ }>), ()> {
-        let (ip, name_in_path) = self.namex(path, true, tx, ctx)?;
-        let name_in_path = name_in_path.ok_or(())?;
-        Ok((ip, name_in_path))
-    }
-
-    fn namex<'s>(
-        self: StrongPin<'_, Self>,
-        mut path: &'s Path,
-        parent: bool,
-        tx: &Tx<'_, Ufs>,
-        ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<Ufs>, Option<&'s FileName<{ DIRSIZ }>>), ()> {
-        let mut ptr = if path.is_absolute() {
-            self.root()
-        } else {
-            ctx.proc().cwd().clone()
-        };
-
-        while let Some((new_path, name)) = path.skipelem() {
-            path = new_path;
-
-            let mut ip = ptr.lock(ctx);
-            if ip.deref_inner().typ != InodeType::Dir {
-                ip.free(ctx);
-                ptr.free((tx, ctx));
-                return Err(());
-            }
-            if parent && path.is_empty_string() {
-                // Stop one level early.
-                ip.free(ctx);
-                return Ok((ptr, Some(name)));
-            }
-            let next = ip.dirlookup(name, ctx);
-            ip.free(ctx);
-            ptr.free((tx, ctx));
-            ptr = next?.0
-        }
-        if parent {
-            ptr.free((tx, ctx));
-            return Err(());
-        }
-        Ok((ptr, None))
-    }
 }
@@ -12,6 +12,13 @@
 //! But if it thinks the LOG is close to running out, it
 //! sleeps until the last outstanding end_op() commits.
 //!
+//! The last outstanding end_op() commits only if the transaction
+//! has been open for the commit latency (`Writeback::commit_ticks`),
+//! if the next system call might not fit in the LOG, or if sync()
+//! waits for the commit. Otherwise the updated blocks stay dirty in
+//! the buffer cache, and later system calls join the transaction.
+//! The flusher calls sync() for a transaction that stays open too long.
+//!
 //! The LOG is a physical re-do LOG containing disk blocks.
 //! The on-disk LOG format:
 //!   header block, containing block #s for block A, B, C, ...
@@ -29,7 +36,7 @@ use static_assertions::const_assert;
 use crate::{
     bio::{Buf, BufData, BufUnlocked},
     hal::hal,
-    lock::SleepableLock,
+    lock::{SleepableLock, SleepableLockGuard},
     param::{BSIZE, LOGSIZE, MAXOPBLOCKS},
     proc::KernelCtx,
 };
@@ -45,6 +52,15 @@ pub struct Log {
     /// In commit(), please wait.
     committing: bool,
 
+    /// sync() waits for the last outstanding FS sys call to commit, please wait.
+    syncing: bool,
+
+    /// How many commits have finished? sync() waits for it to change.
+    commits: u32,
+
+    /// The ticks when the transaction wrote its first block.
+    since: u32,
+
     /// Contents of the header block, used to keep track in memory of logged block# before commit.
     bufs: ArrayVec<BufUnlocked, LOGSIZE>,
 }
@@ -63,6 +79,9 @@ impl Log {
             size,
             outstanding: 0,
             committing: false,
+            syncing: false,
+            commits: 0,
+            since: 0,
             bufs: ArrayVec::new(),
         };
         log.recover_from_log(ctx);
@@ -77,7 +96,7 @@ impl Log {
         for (tail, dbuf) in self.bufs.drain(..).enumerate() {
             // Read log block.
             let lbuf = hal()
-                .disk()
+                .disks()
                 .read(dev, (start + tail as i32 + 1) as u32, ctx);
 
             // Read dst.
@@ -87,7 +106,7 @@ impl Log {
             dbuf.data_mut().copy_from(lbuf.data());
 
             // Write dst to disk.
-            hal().disk().write(&mut dbuf, ctx);
+            hal().disks().write(&mut dbuf, ctx);
 
             lbuf.free(ctx);
             dbuf.free(ctx);
@@ -96,7 +115,7 @@ impl Log {
 
     /// Read the log header from disk into the in-memory log header.
     fn read_head(&mut self, ctx: &KernelCtx<'_, '_>) {
-        let mut buf = hal().disk().read(self.dev, self.start as u32, ctx);
+        let mut buf = hal().disks().read(self.dev, self.start as u32, ctx);
 
         const_assert!(mem::size_of::<LogHeader>() <= BSIZE);
         const_assert!(mem::align_of::<BufData>() % mem::align_of::<LogHeader>() == 0);
@@ -109,7 +128,7 @@ impl Log {
         buf.free(ctx);
 
         for b in &lh.block[0..lh.n as usize] {
-            let buf = hal().disk().read(self.dev, *b, ctx).unlock(ctx);
+            let buf = hal().disks().read(self.dev, *b, ctx).unlock(ctx);
             self.bufs.push(buf);
         }
     }
@@ -118,7 +137,7 @@ impl Log {
     /// This is the true point at which the
     /// current transaction commits.
     fn write_head(&mut self, ctx: &KernelCtx<'_, '_>) {
-        let mut buf = hal().disk().read(self.dev, self.start as u32, ctx);
+        let mut buf = hal().disks().read(self.dev, self.start as u32, ctx);
 
         const_assert!(mem::size_of::<LogHeader>() <= BSIZE);
         const_assert!(mem::align_of::<BufData>() % mem::align_of::<LogHeader>() == 0);
@@ -133,7 +152,7 @@ impl Log {
         for (db, b) in izip!(&mut lh.block, &self.bufs) {
             *db = b.blockno;
         }
-        hal().disk().write(&mut buf, ctx);
+        hal().disks().write(&mut buf, ctx);
         buf.free(ctx);
     }
 
@@ -152,16 +171,16 @@ impl Log {
         for (tail, from) in self.bufs.iter().enumerate() {
             // Log block.
             let mut to = hal()
-                .disk()
+                .disks()
                 .read(self.dev, (self.start + tail as i32 + 1) as u32, ctx);
 
             // Cache block.
-            let from = hal().disk().read(self.dev, from.blockno, ctx);
+            let from = hal().disks().read(self.dev, from.blockno, ctx);
 
             to.data_mut().copy_from(from.data());
 
             // Write the log.
-            hal().disk().write(&mut to, ctx);
+            hal().disks().write(&mut to, ctx);
 
             to.free(ctx);
             from.free(ctx);
@@ -184,22 +203,42 @@ impl Log {
         };
     }
 
+    /// Returns true if the finished transactions should be committed now.
+    fn should_commit(&self, ctx: &KernelCtx<'_, '_>) -> bool {
+        let now = *ctx.kernel().ticks().lock();
+        !self.bufs.is_empty()
+            && (self.syncing
+                // The next op might exhaust log space.
+                || self.bufs.len() + MAXOPBLOCKS > LOGSIZE
+                || now.wrapping_sub(self.since) >= ctx.kernel().writeback().commit_ticks())
+    }
+
+    /// Returns the ticks when the transaction wrote its first block, or None if
+    /// it has written none.
+    pub fn since(&self) -> Option<u32> {
+        (!self.bufs.is_empty()).then(|| self.since)
+    }
+
     /// Caller has modified b->data and is done with the buffer.
-    /// Record the block number and pin in the cache by increasing refcnt.
+    /// Record the block number, mark it dirty, and pin in the cache by increasing refcnt.
     /// commit()/write_log() will do the disk write.
     ///
     /// write() replaces write(); a typical use is:
     ///   bp = Disk::read(...)
     ///   modify bp->data[]
     ///   write(bp)
-    pub fn write(&mut self, b: Buf, ctx: &KernelCtx<'_, '_>) {
+    pub fn write(&mut self, mut b: Buf, ctx: &KernelCtx<'_, '_>) {
         assert!(
             !(self.bufs.len() >= LOGSIZE || self.bufs.len() as i32 >= self.size - 1),
             "too big a transaction"
         );
         assert!(self.outstanding >= 1, "write outside of trans");
 
+        b.mark_dirty(ctx);
         if self.bufs.iter().all(|buf| buf.blockno != b.blockno) {
+            if self.bufs.is_empty() {
+                self.since = *ctx.kernel().ticks().lock();
+            }
             // Add new block to log
             self.bufs.push(b.unlock(ctx));
         } else {
@@ -213,7 +252,7 @@ impl SleepableLock<Log> {
     pub fn begin_op(&self, ctx: &KernelCtx<'_, '_>) {
         let mut guard = self.lock();
         loop {
-            if guard.committing ||
+            if guard.committing || guard.syncing ||
             // This op might exhaust log space; wait for commit.
             guard.bufs.len() as i32 + (guard.outstanding + 1) * MAXOPBLOCKS as i32 > LOGSIZE as i32
             {
@@ -226,28 +265,61 @@ impl SleepableLock<Log> {
     }
 
     /// Called at the end of each FS system call.
-    /// Commits if this was the last outstanding operation.
+    /// Commits if this was the last outstanding operation, and the
+    /// transaction should not wait for more.
     pub fn end_op(&self, ctx: &KernelCtx<'_, '_>) {
         let mut guard = self.lock();
         guard.outstanding -= 1;
         assert!(!guard.committing, "guard.committing");
 
-        if guard.outstanding == 0 {
-            // Since outstanding is 0, no ongoing transaction exists.
-            // The lock is still held, so new transactions cannot start.
-            guard.committing = true;
-            // Committing is true, so new transactions cannot start even after releasing the lock.
-
-            // Call commit w/o holding locks, since not allowed to sleep with locks.
-            guard.reacquire_after(||
-                // SAFETY: there is no another transaction, so `inner` cannot be read or written.
-                unsafe { &mut *self.get_mut_raw() }.commit(ctx));
-
-            guard.committing = false;
+        if guard.outstanding == 0 && guard.should_commit(ctx) {
+            self.commit(&mut guard, ctx);
         }
 
         // begin_op() may be waiting for LOG space, and decrementing log.outstanding has decreased
-        // the amount of reserved space.
+        // the amount of reserved space. sync() may be waiting for the commit.
         guard.wakeup(ctx.kernel());
     }
+
+    /// Commits the transactions that have finished, and waits until
+    /// they are on the disk.
+    pub fn sync(&self, ctx: &KernelCtx<'_, '_>) {
+        let mut guard = self.lock();
+        if guard.committing {
+            // The running commit has all the finished transactions.
+            let commits = guard.commits;
+            while guard.commits == commits {
+                guard.sleep(ctx);
+            }
+        } else if guard.outstanding > 0 && !guard.bufs.is_empty() {
+            // Make the last outstanding end_op() commit, and keep new
+            // transactions from starting meanwhile.
+            guard.syncing = true;
+            let commits = guard.commits;
+            while guard.commits == commits {
+                guard.sleep(ctx);
+            }
+        } else if !guard.bufs.is_empty() {
+            self.commit(&mut guard, ctx);
+            // begin_op() may be waiting for the commit.
+            guard.wakeup(ctx.kernel());
+        }
+    }
+
+    /// Commits the transaction. Must be called with no outstanding operation.
+    fn commit(&self, guard: &mut SleepableLockGuard<'_, Log>, ctx: &KernelCtx<'_, '_>) {
+        // Since outstanding is 0, no ongoing transaction exists.
+        // The lock is still held, so new transactions cannot start.
+        guard.committing = true;
+        // Committing is true, so new transactions cannot start even after releasing the lock.
+
+        // Call commit w/o holding locks, since not allowed to sleep with locks.
+        guard.reacquire_after(||
+            // SAFETY: there is no another transaction, so `inner` cannot be read or written.
+            unsafe { &mut *self.get_mut_raw() }.commit(ctx));
+
+        guard.committing = false;
+        guard.syncing = false;
+        guard.commits = guard.commits.wrapping_add(1);
+    }
 }
@@ -11,7 +11,6 @@
 //!
 //! On-disk file system format used for both kernel and user programs are also included here.
 
-use core::cell::UnsafeCell;
 use core::ops::Deref;
 use core::{cmp, mem};
 
@@ -20,37 +19,98 @@ use spin::Once;
 
 use self::log::Log;
 use super::{
-    FcntlFlags, FileName, FileSystem, Inode, InodeGuard, InodeType, Itable, Path, RcInode, Stat, Tx,
+    Attr, FileName, FileSystem, FsType, Inode, InodeGuard, InodeType, Itable, RcInode, Stat, Tx,
 };
-use crate::fs::DInodeType;
 use crate::util::strong_pin::StrongPin;
 use crate::{
     bio::Buf,
-    file::{FileType, InodeFileType},
+    errno::Errno,
     hal::hal,
     lock::SleepableLock,
-    param::BSIZE,
+    param::{BSIZE, MAXOPBLOCKS, MAXPATH, MAXREADBLOCKS},
     proc::KernelCtx,
+    timer::realtime,
 };
 
+mod extent;
 mod inode;
 mod log;
 mod superblock;
 
-pub use inode::{Dinode, Dirent, InodeInner, DIRENT_SIZE, DIRSIZ};
-pub use superblock::{Superblock, BPB, IPB};
+pub use inode::{Dinode, DinodeAttr, Dirent, InodeInner, DIRENT_SIZE, DIRSIZ, LDIRSIZ};
+pub use superblock::{Superblock, BPB};
 
 /// root i-number
 const ROOTINO: u32 = 1;
 
-const NDIRECT: usize = 12;
+/// Number of block addresses in an inode.
+const NADDR: usize = 13;
 const NINDIRECT: usize = BSIZE.wrapping_div(mem::size_of::<u32>());
-const MAXFILE: usize = NDIRECT.wrapping_add(NINDIRECT);
+
+/// How the block addresses of an inode map the blocks of its file.
+#[derive(Clone, Copy)]
+pub struct BlockMap {
+    /// The first `ndirect` addresses are of data blocks.
+    ndirect: usize,
+    /// The other addresses are of indirect blocks, whose levels of indirection
+    /// are these: 1 for an indirect block, 2 for a doubly-indirect block, and
+    /// so on.
+    levels: &'static [u32],
+}
+
+impl BlockMap {
+    /// 10 direct blocks, and an indirect, a doubly-indirect, and a
+    /// triply-indirect block.
+    const BIG: Self = Self {
+        ndirect: 10,
+        levels: &[1, 2, 3],
+    };
+    /// 12 direct blocks and an indirect block, as in an old image.
+    const SMALL: Self = Self {
+        ndirect: 12,
+        levels: &[1],
+    };
+
+    /// Returns the level of indirection of the `i`th address of an inode.
+    fn level(self, i: usize) -> u32 {
+        i.checked_sub(self.ndirect).map_or(0, |i| self.levels[i])
+    }
+
+    /// Returns `Some((i, level, bn))` if the `i`th address of an inode leads
+    /// to the `bn`th block of a file, where `level` is the level of
+    /// indirection of the address and `bn` is the index of the block under the
+    /// address. Returns `None` if the file cannot have the block.
+    fn locate(self, mut bn: usize) -> Option<(usize, u32, usize)> {
+        if bn < self.ndirect {
+            return Some((bn, 0, 0));
+        }
+        bn -= self.ndirect;
+        for (i, &level) in self.levels.iter().enumerate() {
+            let span = NINDIRECT.pow(level);
+            if bn < span {
+                return Some((self.ndirect + i, level, bn));
+            }
+            bn -= span;
+        }
+        None
+    }
+
+    /// Returns the maximum number of blocks of a file.
+    fn max_blocks(self) -> usize {
+        self.ndirect
+            + self
+                .levels
+                .iter()
+                .map(|&level| NINDIRECT.pow(level))
+                .sum::<usize>()
+    }
+}
 
 #[pin_project]
 pub struct Ufs {
+    /// The disk that the file system is loaded from. A `Ufs` is loaded from one disk only.
+    dev: Once<u32>,
     /// Initializing superblock should run only once because forkret() calls FileSystem::init().
-    /// There should be one superblock per disk device, but we run with only one device.
     superblock: Once<Superblock>,
     log: Once<SleepableLock<Log>>,
     #[pin]
@@ -60,6 +120,7 @@ pub struct Ufs {
 impl Ufs {
     pub const fn new() -> Self {
         Self {
+            dev: Once::new(),
             superblock: Once::new(),
             log: Once::new(),
             itable: Itable::<Self>::new_itable(),
@@ -74,9 +135,14 @@ impl Ufs {
         self.superblock.get().expect("superblock")
     }
 
-    #[allow(clippy::needless_lifetimes)]
-    fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
-        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().itable) }
+    /// Reads the type of the inode `inum` from the disk `dev`, without the
+    /// in-memory inode, which may be locked.
+    fn read_type(&self, dev: u32, inum: u32, ctx: &KernelCtx<'_, '_>) -> InodeType {
+        let sb = *self.superblock();
+        let bp = hal().disks().read(dev, sb.iblock(inum), ctx);
+        let typ = inode::dinode(bp.data(), inum, sb).0.inode_type();
+        bp.free(ctx);
+        typ
     }
 }
 
@@ -86,7 +152,7 @@ impl Tx<'_, Ufs> {
     /// commit()/write_log() will do the disk write.
     ///
     /// write() replaces write(); a typical use is:
-    ///   bp = kernel.fs().disk.read(...)
+    ///   bp = hal().disks().read(...)
     ///   modify bp->data[]
     ///   write(bp)
     fn write(&self, b: Buf, ctx: &KernelCtx<'_, '_>) {
@@ -102,27 +168,72 @@ impl Tx<'_, Ufs> {
     /// Blocks.
     /// Allocate a zeroed disk block.
     fn balloc(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> u32 {
-        for b in num_iter::range_step(0, self.fs.superblock().size, BPB as u32) {
-            let mut bp = hal().disk().read(dev, self.fs.superblock().bblock(b), ctx);
-            for bi in 0..cmp::min(BPB as u32, self.fs.superblock().size - b) {
+        self.balloc_near(dev, 0, ctx)
+    }
+
+    /// Allocate a zeroed disk block, trying the block `goal` first and then
+    /// the blocks after it. Allocating the block after the last block of a
+    /// file keeps the file contiguous on the disk.
+    fn balloc_near(&self, dev: u32, goal: u32, ctx: &KernelCtx<'_, '_>) -> u32 {
+        let size = self.fs.superblock().size;
+        let goal = if goal < size { goal } else { 0 };
+        self.balloc_between(dev, goal, size, ctx)
+            .or_else(|| self.balloc_between(dev, 0, goal, ctx))
+            .expect("balloc: out of blocks")
+    }
+
+    /// Allocate a zeroed disk block among the blocks from `start` to `end`
+    /// (exclusive). Returns `None` if all of them are in use.
+    fn balloc_between(
+        &self,
+        dev: u32,
+        start: u32,
+        end: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Option<u32> {
+        let mut b = start;
+        while b < end {
+            let mut bp = hal().disks().read(dev, self.fs.superblock().bblock(b), ctx);
+            // The last block whose bit is in bp, plus one.
+            let last = cmp::min(end, (b / BPB as u32 + 1) * BPB as u32);
+            for b in b..last {
+                let bi = b as usize % BPB;
                 let m = 1 << (bi % 8);
-                if bp.data_mut()[(bi / 8) as usize] & m == 0 {
+                if bp.data_mut()[bi / 8] & m == 0 {
                     // Is block free?
-                    bp.data_mut()[(bi / 8) as usize] |= m; // Mark block in use.
+                    bp.data_mut()[bi / 8] |= m; // Mark block in use.
                     self.write(bp, ctx);
-                    self.bzero(dev, b + bi, ctx);
-                    return b + bi;
+                    self.bzero(dev, b, ctx);
+                    return Some(b);
                 }
             }
             bp.free(ctx);
+            b = last;
         }
+        None
+    }
 
-        panic!("balloc: out of blocks");
+    /// Free the disk block `b` and, if `b` is an indirect block of the given
+    /// level, the blocks under it.
+    fn bfree_tree(&self, dev: u32, b: u32, level: u32, ctx: &KernelCtx<'_, '_>) {
+        if level > 0 {
+            let mut bp = hal().disks().read(dev, b, ctx);
+            // SAFETY: u32 does not have internal structure.
+            let (prefix, data, _) = unsafe { bp.data_mut().align_to_mut::<u32>() };
+            debug_assert_eq!(prefix.len(), 0, "bfree_tree: Buf data unaligned");
+            for a in data {
+                if *a != 0 {
+                    self.bfree_tree(dev, *a, level - 1, ctx);
+                }
+            }
+            bp.free(ctx);
+        }
+        self.bfree(dev, b, ctx);
     }
 
     /// Free a disk block.
     fn bfree(&self, dev: u32, b: u32, ctx: &KernelCtx<'_, '_>) {
-        let mut bp = hal().disk().read(dev, self.fs.superblock().bblock(b), ctx);
+        let mut bp = hal().disks().read(dev, self.fs.superblock().bblock(b), ctx);
         let bi = b as usize % BPB;
         let m = 1u8 << (bi % 8);
         assert_ne!(bp.data_mut()[bi / 8] & m, 0, "freeing free block");
@@ -131,84 +242,140 @@ impl Tx<'_, Ufs> {
     }
 }
 
+/// Returns `name` as the name of a directory entry, or Err(ENAMETOOLONG) if it
+/// is longer than `LDIRSIZ` bytes.
+fn entry_name(name: &FileName<{ MAXPATH }>) -> Result<&FileName<LDIRSIZ>, Errno> {
+    if name.as_bytes().len() > LDIRSIZ {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    Ok(name.truncate())
+}
+
 impl FileSystem for Ufs {
     type Dirent = Dirent;
     type InodeInner = InodeInner;
 
-    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) {
+    // Write a few blocks at a time to avoid exceeding the maximum log
+    // transaction size, including i-node, up to 3 indirect blocks or extent
+    // tree nodes, allocation blocks, and 2 blocks of slop for non-aligned
+    // writes.
+    const MAX_WRITE: usize = (MAXOPBLOCKS - 1 - 3 - 2) / 2 * BSIZE;
+    const TYPE: FsType = FsType::Ufs;
+
+    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
         if !self.superblock.is_completed() {
-            let buf = hal().disk().read(dev, 1, ctx);
-            let superblock = self.superblock.call_once(|| Superblock::new(&buf));
+            let buf = hal().disks().read(dev, 1, ctx);
+            let superblock = Superblock::new(&buf);
             buf.free(ctx);
-            let _ = self.log.call_once(|| {
-                SleepableLock::new(
-                    "LOG",
-                    Log::new(dev, superblock.logstart as i32, superblock.nlog as i32, ctx),
-                )
-            });
+            let superblock = superblock?;
+            if *self.dev.call_once(|| dev) == dev {
+                let superblock = self.superblock.call_once(|| superblock);
+                let _ = self.log.call_once(|| {
+                    SleepableLock::new(
+                        "LOG",
+                        Log::new(dev, superblock.logstart as i32, superblock.nlog as i32, ctx),
+                    )
+                });
+            }
+        }
+        if self.dev.get() == Some(&dev) {
+            Ok(())
+        } else {
+            Err(Errno::EBUSY)
         }
     }
 
-    fn root(self: StrongPin<'_, Self>) -> RcInode<Self> {
-        self.itable().root()
+    #[allow(clippy::needless_lifetimes)]
+    fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<Self>> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().itable) }
     }
 
-    fn namei(
+    fn root(self: StrongPin<'_, Self>, dev: u32) -> RcInode<Self> {
+        self.itable().get_inode(dev, ROOTINO)
+    }
+
+    fn lookup(
         self: StrongPin<'_, Self>,
-        path: &Path,
-        tx: &Tx<'_, Self>,
+        dir: &mut InodeGuard<'_, Self>,
+        name: &FileName<{ MAXPATH }>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Self>, ()> {
-        self.itable().namei(path, tx, ctx)
+    ) -> Result<RcInode<Self>, Errno> {
+        Ok(dir.dirlookup(entry_name(name)?, ctx)?.0)
+    }
+
+    fn readdir<F>(
+        self: StrongPin<'_, Self>,
+        dir: &mut InodeGuard<'_, Self>,
+        off: u32,
+        mut f: F,
+        ctx: &KernelCtx<'_, '_>,
+    ) where
+        F: FnMut(u32, u32, InodeType, &[u8]) -> bool,
+    {
+        // An entry stores only the inode number, so the type is read from the
+        // inode itself. The in-memory inode is not locked, since it may be
+        // `dir` or its parent.
+        let dev = dir.dev;
+        dir.dirread(
+            off,
+            |next, inum, name| f(next, inum, self.read_type(dev, inum, ctx), name),
+            ctx,
+        );
     }
 
     fn link(
         self: StrongPin<'_, Self>,
-        inode: RcInode<Self>,
-        path: &Path,
+        inode: &RcInode<Self>,
+        dir: &RcInode<Self>,
+        name: &FileName<{ MAXPATH }>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
-        let inode = scopeguard::guard(inode, |ptr| ptr.free((tx, ctx)));
+    ) -> Result<(), Errno> {
+        let name = entry_name(name)?;
         let ip = inode.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         if ip.deref_inner().typ == InodeType::Dir {
-            return Err(());
+            return Err(Errno::EPERM);
         }
         ip.deref_inner_mut().nlink += 1;
+        ip.deref_inner_mut().attr.ctime = realtime();
         ip.update(tx, ctx);
         drop(ip);
 
-        if let Ok((ptr2, name)) = self.itable().nameiparent(path, tx, ctx) {
-            let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
-            let dp = ptr2.lock(ctx);
+        let res = {
+            let dp = dir.lock(ctx);
             let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
-            if dp.dev == inode.dev && dp.dirlink(name, inode.inum, tx, ctx).is_ok() {
-                return Ok(());
+            if dp.dev != inode.dev {
+                Err(Errno::EXDEV)
+            } else {
+                dp.dirlink(name, inode.inum, tx, ctx)
             }
+        };
+        if res.is_ok() {
+            return Ok(());
         }
 
         let ip = inode.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         ip.deref_inner_mut().nlink -= 1;
         ip.update(tx, ctx);
-        Err(())
+        res
     }
 
     fn unlink(
         self: StrongPin<'_, Self>,
-        path: &Path,
+        dir: &RcInode<Self>,
+        name: &FileName<{ MAXPATH }>,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
-        let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
-        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-        let dp = ptr.lock(ctx);
+    ) -> Result<(), Errno> {
+        let name = entry_name(name)?;
+        let dp = dir.lock(ctx);
         let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
 
         // Cannot unlink "." or "..".
         if name.as_bytes() == b"." || name.as_bytes() == b".." {
-            return Err(());
+            return Err(Errno::EINVAL);
         }
 
         let (ptr2, off) = dp.dirlookup(name, ctx)?;
@@ -218,47 +385,88 @@ impl FileSystem for Ufs {
         assert!(ip.deref_inner().nlink >= 1, "unlink: nlink < 1");
 
         if ip.deref_inner().typ == InodeType::Dir && !ip.is_dir_empty(ctx) {
-            return Err(());
+            return Err(Errno::ENOTEMPTY);
         }
 
-        dp.write_kernel(&Dirent::default(), off, tx, ctx)
-            .expect("unlink: writei");
+        dp.dirunlink(off, tx, ctx);
         if ip.deref_inner().typ == InodeType::Dir {
             dp.deref_inner_mut().nlink -= 1;
             dp.update(tx, ctx);
         }
         drop(dp);
-        drop(ptr);
         ip.deref_inner_mut().nlink -= 1;
+        ip.deref_inner_mut().attr.ctime = realtime();
         ip.update(tx, ctx);
         Ok(())
     }
 
+    fn dirlookup(
+        dir: &mut InodeGuard<'_, Self>,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<Self>, u32), Errno> {
+        dir.dirlookup(entry_name(name)?, ctx)
+    }
+
+    fn dirlink(
+        dir: &mut InodeGuard<'_, Self>,
+        name: &FileName<{ MAXPATH }>,
+        inum: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        dir.dirlink(entry_name(name)?, inum, tx, ctx)
+    }
+
+    fn dirunlink(
+        dir: &mut InodeGuard<'_, Self>,
+        off: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        dir.dirunlink(off, tx, ctx);
+    }
+
+    fn dirrelink(
+        dir: &mut InodeGuard<'_, Self>,
+        off: u32,
+        inum: u32,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        dir.dirrelink(off, inum, tx, ctx);
+    }
+
+    fn is_dir_empty(dir: &mut InodeGuard<'_, Self>, ctx: &KernelCtx<'_, '_>) -> bool {
+        dir.is_dir_empty(ctx)
+    }
+
     fn create<F, T>(
         self: StrongPin<'_, Self>,
-        path: &Path,
+        dir: &RcInode<Self>,
+        name: &FileName<{ MAXPATH }>,
         typ: InodeType,
+        attr: Attr,
         tx: &Tx<'_, Self>,
         ctx: &KernelCtx<'_, '_>,
         f: F,
-    ) -> Result<(RcInode<Self>, T), ()>
+    ) -> Result<(RcInode<Self>, T), Errno>
     where
         F: FnOnce(&mut InodeGuard<'_, Self>) -> T,
     {
-        let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
-        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-        let dp = ptr.lock(ctx);
+        let name = entry_name(name)?;
+        let dp = dir.lock(ctx);
         let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
         if let Ok((ptr2, _)) = dp.dirlookup(name, ctx) {
             let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
             drop(dp);
             if typ != InodeType::File {
-                return Err(());
+                return Err(Errno::EEXIST);
             }
             let ip = ptr2.lock(ctx);
             let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
             if let InodeType::None | InodeType::Dir = ip.deref_inner().typ {
-                return Err(());
+                return Err(Errno::EISDIR);
             }
             let ret = f(&mut ip);
             drop(ip);
@@ -268,6 +476,7 @@ impl FileSystem for Ufs {
         let ip = ptr2.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         ip.deref_inner_mut().nlink = 1;
+        ip.deref_inner_mut().attr = attr;
         ip.update(tx, ctx);
 
         // Create . and .. entries.
@@ -290,82 +499,6 @@ impl FileSystem for Ufs {
         Ok((ptr2, ret))
     }
 
-    fn open(
-        self: StrongPin<'_, Self>,
-        path: &Path,
-        omode: FcntlFlags,
-        tx: &Tx<'_, Self>,
-        ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
-        let (ip, typ) = if omode.contains(FcntlFlags::O_CREATE) {
-            self.create(path, InodeType::File, tx, ctx, |ip| ip.deref_inner().typ)?
-        } else {
-            let ptr = self.itable().namei(path, tx, ctx)?;
-            let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
-            let ip = ptr.lock(ctx);
-            let ip = scopeguard::guard(ip, |ip| ip.free(ctx));
-            let typ = ip.deref_inner().typ;
-
-            if typ == InodeType::Dir && omode != FcntlFlags::O_RDONLY {
-                return Err(());
-            }
-            drop(ip);
-            (scopeguard::ScopeGuard::into_inner(ptr), typ)
-        };
-
-        let filetype = match typ {
-            InodeType::Device { major, .. } => FileType::Device { ip, major },
-            _ => {
-                FileType::Inode {
-                    inner: InodeFileType {
-                        ip,
-                        off: UnsafeCell::new(0),
-                    },
-                }
-            }
-        };
-
-        let f = ctx.kernel().ftable().alloc_file(
-            filetype,
-            !omode.intersects(FcntlFlags::O_WRONLY),
-            omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR),
-        )?;
-
-        if omode.contains(FcntlFlags::O_TRUNC) && typ == InodeType::File {
-            match &f.typ {
-                // It is safe to call itrunc because ip.lock() is held
-                FileType::Device { ip, .. }
-                | FileType::Inode {
-                    inner: InodeFileType { ip, .. },
-                } => {
-                    let mut ip = ip.lock(ctx);
-                    ip.trunc(tx, ctx);
-                    ip.free(ctx);
-                }
-                _ => panic!("sys_open : Not reach"),
-            };
-        }
-        let fd = f.fdalloc(ctx)?;
-        Ok(fd as usize)
-    }
-
-    fn chdir(
-        self: StrongPin<'_, Self>,
-        inode: RcInode<Self>,
-        tx: &Tx<'_, Self>,
-        ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
-        let ip = inode.lock(ctx);
-        let typ = ip.deref_inner().typ;
-        ip.free(ctx);
-        if typ != InodeType::Dir {
-            inode.free((tx, ctx));
-            return Err(());
-        }
-        mem::replace(ctx.proc_mut().cwd_mut(), inode).free((tx, ctx));
-        Ok(())
-    }
-
     fn tx_begin(&self, ctx: &KernelCtx<'_, '_>) {
         self.log().begin_op(ctx);
     }
@@ -375,19 +508,29 @@ impl FileSystem for Ufs {
         self.log().end_op(ctx);
     }
 
+    fn sync(&self, ctx: &KernelCtx<'_, '_>) {
+        if let Some(log) = self.log.get() {
+            log.sync(ctx);
+        }
+    }
+
+    fn dirty_since(&self, _ctx: &KernelCtx<'_, '_>) -> Option<u32> {
+        self.log.get().and_then(|log| log.lock().since())
+    }
+
     #[inline]
     fn inode_read<
         'id,
         's,
         K: Deref<Target = KernelCtx<'id, 's>>,
-        F: FnMut(u32, &[u8], &mut K) -> Result<(), ()>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), Errno>,
     >(
         guard: &mut InodeGuard<'_, Self>,
         mut off: u32,
         mut n: u32,
         mut f: F,
         mut k: K,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         let inner = guard.deref_inner();
         if off > inner.size || off.wrapping_add(n) < off {
             return Ok(0);
@@ -395,11 +538,24 @@ impl FileSystem for Ufs {
         if off + n > inner.size {
             n = inner.size - off;
         }
+        // Reading does not write the disk, so the access time reaches the
+        // disk with the next update of the inode.
+        guard.deref_inner_mut().attr.atime = realtime();
+        // The blocks that this read needs are below `end_bn`.
+        let end_bn = ((off + n) as usize + BSIZE - 1) / BSIZE;
+        // The blocks below `ahead` have been read ahead.
+        let mut ahead = 0;
         let mut tot: u32 = 0;
         while tot < n {
-            let bp = hal()
-                .disk()
-                .read(guard.dev, guard.bmap(off as usize / BSIZE, &k), &k);
+            let bn = off as usize / BSIZE;
+            let (addr, run) = guard.bmap_run(bn, &k);
+            if run > 1 && bn >= ahead {
+                // Read the consecutive blocks that this read needs at once.
+                let m = cmp::min(cmp::min(run, end_bn - bn), MAXREADBLOCKS);
+                hal().disks().read_sequential(guard.dev, addr, m, &k);
+                ahead = bn + m;
+            }
+            let bp = hal().disks().read(guard.dev, addr, &k);
             let m = core::cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
             let begin = (off % BSIZE as u32) as usize;
             let end = begin + m as usize;
@@ -417,7 +573,7 @@ impl FileSystem for Ufs {
         'id,
         's,
         K: Deref<Target = KernelCtx<'id, 's>>,
-        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), ()>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), Errno>,
     >(
         guard: &mut InodeGuard<'_, Self>,
         mut off: u32,
@@ -425,16 +581,18 @@ impl FileSystem for Ufs {
         mut f: F,
         tx: &Tx<'_, Self>,
         mut k: K,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, Errno> {
         if off > guard.deref_inner().size {
-            return Err(());
+            return Err(Errno::EINVAL);
         }
-        if off.checked_add(n).ok_or(())? as usize > MAXFILE * BSIZE {
-            return Err(());
+        let end = off.checked_add(n).ok_or(Errno::EFBIG)? as usize;
+        let sb = tx.fs.superblock();
+        if !sb.extents() && end > sb.block_map().max_blocks() * BSIZE {
+            return Err(Errno::EFBIG);
         }
         let mut tot: u32 = 0;
         while tot < n {
-            let mut bp = hal().disk().read(
+            let mut bp = hal().disks().read(
                 guard.dev,
                 guard.bmap_or_alloc(off as usize / BSIZE, tx, &k),
                 &k,
@@ -455,6 +613,11 @@ impl FileSystem for Ufs {
         if off > guard.deref_inner().size {
             guard.deref_inner_mut().size = off;
         }
+        if tot > 0 {
+            let attr = &mut guard.deref_inner_mut().attr;
+            attr.mtime = realtime();
+            attr.ctime = attr.mtime;
+        }
 
         // Write the i-node back to disk even if the size didn't change
         // because the loop above might have called bmap() and added a new
@@ -465,70 +628,48 @@ impl FileSystem for Ufs {
 
     fn inode_trunc(guard: &mut InodeGuard<'_, Self>, tx: &Tx<'_, Self>, ctx: &KernelCtx<'_, '_>) {
         let dev = guard.dev;
-        for addr in &mut guard.deref_inner_mut().addr_direct {
-            if *addr != 0 {
-                tx.bfree(dev, *addr, ctx);
-                *addr = 0;
-            }
-        }
-
-        if guard.deref_inner().addr_indirect != 0 {
-            let mut bp = hal()
-                .disk()
-                .read(dev, guard.deref_inner().addr_indirect, ctx);
-            // SAFETY: u32 does not have internal structure.
-            let (prefix, data, _) = unsafe { bp.data_mut().align_to_mut::<u32>() };
-            debug_assert_eq!(prefix.len(), 0, "itrunc: Buf data unaligned");
-            for a in data {
-                if *a != 0 {
-                    tx.bfree(dev, *a, ctx);
+        if tx.fs.superblock().extents() {
+            guard.extent_trunc(tx, ctx);
+        } else {
+            let map = tx.fs.superblock().block_map();
+            for (i, addr) in guard.deref_inner_mut().addrs.iter_mut().enumerate() {
+                if *addr != 0 {
+                    tx.bfree_tree(dev, *addr, map.level(i), ctx);
+                    *addr = 0;
                 }
             }
-            bp.free(ctx);
-            tx.bfree(dev, guard.deref_inner().addr_indirect, ctx);
-            guard.deref_inner_mut().addr_indirect = 0
         }
 
         guard.deref_inner_mut().size = 0;
+        let attr = &mut guard.deref_inner_mut().attr;
+        attr.mtime = realtime();
+        attr.ctime = attr.mtime;
         guard.update(tx, ctx);
     }
 
     fn inode_lock<'a>(inode: &'a Inode<Self>, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'a, Self> {
         let mut guard = inode.inner.lock(ctx);
         if !guard.valid {
-            let bp = hal().disk().read(
-                inode.dev,
-                ctx.kernel().fs().superblock().iblock(inode.inum),
-                ctx,
-            );
+            let sb = *ctx.kernel().ufs(inode.dev).superblock();
+            let bp = hal().disks().read(inode.dev, sb.iblock(inode.inum), ctx);
+            let (dip, dattr) = inode::dinode(bp.data(), inode.inum, sb);
 
-            // SAFETY: dip is inside bp.data.
-            let dip =
-                unsafe { (bp.data().as_ptr() as *const Dinode).add(inode.inum as usize % IPB) };
-            // SAFETY: i16 does not have internal structure.
-            let t = unsafe { *(dip as *const i16) };
-            // If t >= #(variants of DInodeType), UB will happen when we read dip.typ.
-            assert!(t < core::mem::variant_count::<DInodeType>() as i16);
-            // SAFETY: dip is aligned properly and t < #(variants of DInodeType).
-            let dip = unsafe { &*dip };
-
-            match dip.typ {
-                DInodeType::None => guard.typ = InodeType::None,
-                DInodeType::Dir => guard.typ = InodeType::Dir,
-                DInodeType::File => guard.typ = InodeType::File,
-                DInodeType::Device => {
-                    guard.typ = InodeType::Device {
-                        major: dip.major,
-                        minor: dip.minor,
-                    }
-                }
-            }
+            guard.typ = dip.inode_type();
             guard.nlink = dip.nlink;
             guard.size = dip.size;
-            for (d, s) in guard.addr_direct.iter_mut().zip(&dip.addr_direct) {
-                *d = *s;
-            }
-            guard.addr_indirect = dip.addr_indirect;
+            guard.addrs = dip.addrs;
+            // An inode of an old image belongs to root, and has the default
+            // mode and no times.
+            let typ = guard.typ;
+            let old = || {
+                Attr {
+                    atime: 0,
+                    mtime: 0,
+                    ctime: 0,
+                    ..Attr::new(typ, 0, 0)
+                }
+            };
+            guard.attr = dattr.map_or_else(old, DinodeAttr::attr);
             bp.free(ctx);
             guard.valid = true;
             assert_ne!(guard.typ, InodeType::None, "Inode::lock: no type");
@@ -568,12 +709,53 @@ impl FileSystem for Ufs {
                 InodeType::Dir => 1,
                 InodeType::File => 2,
                 InodeType::Device { .. } => 3,
+                InodeType::Symlink => 4,
             },
             nlink: inner.nlink,
             _padding: 0,
             size: inner.size as usize,
+            mode: inner.attr.mode as u32,
+            uid: inner.attr.uid,
+            gid: inner.attr.gid,
+            atime: inner.attr.atime,
+            mtime: inner.attr.mtime,
+            ctime: inner.attr.ctime,
         };
         inner.free(ctx);
         st
     }
+
+    fn inode_type(guard: &InodeGuard<'_, Self>) -> InodeType {
+        guard.deref_inner().typ
+    }
+
+    fn inode_nlink(guard: &InodeGuard<'_, Self>) -> i16 {
+        guard.deref_inner().nlink
+    }
+
+    fn inode_set_nlink(
+        guard: &mut InodeGuard<'_, Self>,
+        nlink: i16,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        guard.deref_inner_mut().nlink = nlink;
+        guard.deref_inner_mut().attr.ctime = realtime();
+        guard.update(tx, ctx);
+    }
+
+    fn inode_attr(guard: &InodeGuard<'_, Self>) -> Attr {
+        guard.deref_inner().attr
+    }
+
+    fn inode_set_attr(
+        guard: &mut InodeGuard<'_, Self>,
+        attr: Attr,
+        tx: &Tx<'_, Self>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        guard.deref_inner_mut().attr = attr;
+        guard.update(tx, ctx);
+        Ok(())
+    }
 }
@@ -2,14 +2,33 @@ use core::{mem, ptr};
 
 use static_assertions::const_assert;
 
-use super::Dinode;
+use super::{BlockMap, Dinode, DinodeAttr};
 use crate::{
     bio::{Buf, BufData},
+    errno::Errno,
     param::BSIZE,
 };
 
 const FSMAGIC: u32 = 0x10203040;
 
+/// Version of the disk format. Version 1 added the permission bits, owners,
+/// and times to inodes, which changed the size of an inode. An older image
+/// has version 0, which is still read.
+const FSVERSION: u32 = 1;
+
+/// Directories have entries of variable length, with names of up to LDIRSIZ bytes.
+const FEATURE_LONG_NAMES: u32 = 0x1;
+
+/// Inodes have doubly- and triply-indirect blocks, as `BlockMap::BIG`.
+const FEATURE_BIG_FILES: u32 = 0x2;
+
+/// Inodes map the blocks of their files by extent trees, instead of
+/// `BlockMap`s.
+const FEATURE_EXTENTS: u32 = 0x4;
+
+/// The features that the kernel supports.
+const FEATURES: u32 = FEATURE_LONG_NAMES | FEATURE_BIG_FILES | FEATURE_EXTENTS;
+
 /// Disk layout:
 /// [ boot block | super block | log | inode blocks |
 ///                                          free bit map | data blocks]
@@ -42,17 +61,23 @@ pub struct Superblock {
 
     /// Block number of first free map block
     pub bmapstart: u32,
-}
 
-/// Inodes per block.
-pub const IPB: usize = BSIZE / mem::size_of::<Dinode>();
+    /// Optional features of the file system. Zero in an old image.
+    features: u32,
+
+    /// At most FSVERSION
+    version: u32,
+}
 
 /// Bitmap bits per block
 pub const BPB: usize = BSIZE * 8;
 
 impl Superblock {
     /// Read the super block.
-    pub fn new(buf: &Buf) -> Self {
+    /// Returns `Err(EINVAL)` if the disk does not hold a ufs, the ufs has
+    /// a newer version, or the ufs has features that the kernel does not
+    /// support.
+    pub fn new(buf: &Buf) -> Result<Self, Errno> {
         const_assert!(mem::size_of::<Superblock>() <= BSIZE);
         const_assert!(mem::align_of::<BufData>() % mem::align_of::<Superblock>() == 0);
         // SAFETY:
@@ -61,13 +86,56 @@ impl Superblock {
         // * Superblock contains only u32's, so does not have any requirements.
         // * buf is locked, so we can access it exclusively.
         let result = unsafe { ptr::read(buf.data().as_ptr() as *const Superblock) };
-        assert_eq!(result.magic, FSMAGIC, "invalid file system");
-        result
+        if result.magic != FSMAGIC || result.version > FSVERSION || result.features & !FEATURES != 0
+        {
+            return Err(Errno::EINVAL);
+        }
+        Ok(result)
+    }
+
+    /// Returns `true` if directories have long names.
+    pub const fn long_names(self) -> bool {
+        self.features & FEATURE_LONG_NAMES != 0
+    }
+
+    /// Returns `true` if inodes have extent trees.
+    pub const fn extents(self) -> bool {
+        self.features & FEATURE_EXTENTS != 0
+    }
+
+    /// Returns how the block addresses of an inode map the blocks of its file,
+    /// if inodes do not have extent trees.
+    pub const fn block_map(self) -> BlockMap {
+        if self.features & FEATURE_BIG_FILES != 0 {
+            BlockMap::BIG
+        } else {
+            BlockMap::SMALL
+        }
+    }
+
+    /// Returns `true` if inodes have permission bits, owners, and times, as a
+    /// `DinodeAttr` after each `Dinode`.
+    pub const fn attrs(self) -> bool {
+        self.version >= 1
+    }
+
+    /// Returns the size of an on-disk inode.
+    const fn inode_size(self) -> usize {
+        if self.attrs() {
+            mem::size_of::<Dinode>() + mem::size_of::<DinodeAttr>()
+        } else {
+            mem::size_of::<Dinode>()
+        }
     }
 
     /// Block containing inode i
     pub const fn iblock(self, i: u32) -> u32 {
-        i / IPB as u32 + self.inodestart
+        i / (BSIZE / self.inode_size()) as u32 + self.inodestart
+    }
+
+    /// Offset of inode i in its block
+    pub const fn ioffset(self, i: u32) -> usize {
+        i as usize % (BSIZE / self.inode_size()) * self.inode_size()
     }
 
     /// Block of free map containing bit for block b
@@ -0,0 +1,1580 @@
+//! The virtual file system.
+//!
+//! The kernel has an instance of the ufs and of the lfs for each disk, and one
+//! instance of each file system type without a disk, so a file system is named
+//! by its type and device number. The mount table attaches the root directories
+//! of the loaded file systems to directories of other file systems, and path
+//! names are resolved across the mount points.
+//!
+//! `Vfs` is the object-safe version of `FileSystem`, so that the rest of the
+//! kernel can use a file system without knowing its type. Each method of `Vfs`
+//! that may write to the disk runs its own transaction.
+
+use core::{
+    cmp,
+    mem::{self, ManuallyDrop},
+    ops::Range,
+    ptr::NonNull,
+};
+
+use array_macro::array;
+use arrayvec::ArrayVec;
+use zerocopy::{AsBytes, FromBytes};
+
+use super::{
+    Access, Attr, FcntlFlags, FileName, FileSystem, FileSystemExt, InodeGuard, InodeType, Path,
+    RcInode, Stat, Tx,
+};
+use crate::{
+    addr::UVAddr,
+    errno::Errno,
+    file::{FileType, InodeFileType},
+    lock::{SleepLock, SpinLock},
+    param::{MAXPATH, MAXSYMLINKS, NMOUNT},
+    proc::KernelCtx,
+    some_or,
+    timer::realtime,
+    util::strong_pin::StrongPin,
+};
+
+/// The types of file systems.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FsType {
+    Ufs,
+    Lfs,
+    Tmpfs,
+    Procfs,
+    Devfs,
+}
+
+/// A pointer to an inode of a file system, whose type is erased.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct InodePtr(NonNull<()>);
+
+// SAFETY: `InodePtr` points to an inode in the itable of a file system, which
+// is shared by all CPUs.
+unsafe impl Send for InodePtr {}
+// SAFETY: the same as above.
+unsafe impl Sync for InodePtr {}
+
+/// A reference counted pointer to an inode of any file system.
+/// It is an `RcInode` whose file system type is erased.
+/// Must be `free`d when done using it.
+pub struct RcVnode {
+    fs: FsType,
+    dev: u32,
+    ip: InodePtr,
+}
+
+/// A mounted file system.
+struct Mount {
+    /// The root directory of the file system.
+    root: RcVnode,
+
+    /// The directory that `root` covers. `None` for the root file system.
+    covered: Option<RcVnode>,
+
+    /// The disk that the file system is loaded from.
+    dev: u32,
+}
+
+/// The table of mounted file systems.
+pub struct MountTable {
+    mounts: SpinLock<[Option<Mount>; NMOUNT]>,
+}
+
+/// The object-safe version of `FileSystem`.
+pub trait Vfs: Sync {
+    /// Initializes the file system (loading from the disk `dev`).
+    /// Returns Err(EINVAL) if `dev` does not hold this file system, or Err(EBUSY)
+    /// if it has been loaded from another disk.
+    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno>;
+
+    /// Finds the root directory of the disk `dev`.
+    fn root(&self, dev: u32) -> RcVnode;
+
+    /// Returns a new reference to the inode of `vnode`.
+    /// It does not sleep.
+    fn dup(&self, vnode: &RcVnode) -> RcVnode;
+
+    /// Drops the reference `vnode`.
+    fn put(&self, vnode: RcVnode, ctx: &KernelCtx<'_, '_>);
+
+    /// Returns the number of references to the inodes of the file system.
+    fn borrows(&self) -> usize;
+
+    /// Returns the type of the inode of `vnode`.
+    fn typ(&self, vnode: &RcVnode, ctx: &KernelCtx<'_, '_>) -> InodeType;
+
+    /// Copy stat information from the inode of `vnode`.
+    fn stat(&self, vnode: &RcVnode, ctx: &KernelCtx<'_, '_>) -> Stat;
+
+    /// Returns the permission bits, owner, and times of the inode of `vnode`.
+    fn attr(&self, vnode: &RcVnode, ctx: &KernelCtx<'_, '_>) -> Attr;
+
+    /// Lets `f` change the attributes of the inode of `vnode`, and sets its
+    /// change time. The attributes are left unchanged if `f` fails.
+    fn setattr(
+        &self,
+        vnode: &RcVnode,
+        f: &mut dyn FnMut(&mut Attr) -> Result<(), Errno>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno>;
+
+    /// Looks for `name` in the directory `dir`.
+    /// Returns Err(ENOTDIR) if `dir` is not a directory, or Err(ENOENT) if there
+    /// is no such entry.
+    fn lookup(
+        &self,
+        dir: &RcVnode,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcVnode, Errno>;
+
+    /// Calls `f(next, inum, typ, name)` for each entry of the directory `dir`
+    /// from the offset `off`, until `f` returns false. `next` is the offset
+    /// after the entry. Returns Err(ENOTDIR) if `dir` is not a directory.
+    fn readdir(
+        &self,
+        dir: &RcVnode,
+        off: u32,
+        f: &mut dyn FnMut(u32, u32, InodeType, &[u8]) -> bool,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno>;
+
+    /// Create an inode with given type, named `name` in the directory `dir`,
+    /// owned by the current process.
+    /// Returns Ok(created inode, its type) on success, Err(errno) on error.
+    fn create(
+        &self,
+        dir: &RcVnode,
+        name: &FileName<{ MAXPATH }>,
+        typ: InodeType,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcVnode, InodeType), Errno>;
+
+    /// Create a symbolic link named `name` in the directory `dir`, whose content
+    /// is `target`.
+    fn symlink(
+        &self,
+        dir: &RcVnode,
+        name: &FileName<{ MAXPATH }>,
+        target: &[u8],
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno>;
+
+    /// Create another name `name` in the directory `dir` for the file `vnode`.
+    fn link(
+        &self,
+        vnode: &RcVnode,
+        dir: &RcVnode,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno>;
+
+    /// Remove the file `name` from the directory `dir`.
+    fn unlink(
+        &self,
+        dir: &RcVnode,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno>;
+
+    /// Move the entry `name` of the directory `dir` to the entry `newname` of
+    /// the directory `newdir`, replacing an existing entry.
+    fn rename(
+        &self,
+        dir: &RcVnode,
+        name: &FileName<{ MAXPATH }>,
+        newdir: &RcVnode,
+        newname: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno>;
+
+    /// Copy data into `dst` from the content of the inode at offset `off`.
+    /// Return the number of bytes copied.
+    fn read_bytes_kernel(
+        &self,
+        vnode: &RcVnode,
+        dst: &mut [u8],
+        off: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> usize;
+
+    /// Copy data into virtual address `dst` of the current process by `n` bytes
+    /// from the content of the inode at offset `off`.
+    /// Returns Ok(number of bytes copied) on success, Err(EFAULT) on failure due to
+    /// accessing an invalid virtual address.
+    fn read_user(
+        &self,
+        vnode: &RcVnode,
+        dst: UVAddr,
+        off: u32,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno>;
+
+    /// Copy data from virtual address `src` of the current process by `n` bytes
+    /// into the inode at offset `off`.
+    /// Returns Ok(number of bytes copied), which is less than `n` if an error
+    /// occurred after copying some bytes, or Err(errno) if nothing was copied.
+    fn write_user(
+        &self,
+        vnode: &RcVnode,
+        src: UVAddr,
+        off: u32,
+        n: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno>;
+
+    /// Truncate the inode (discard contents).
+    fn trunc(&self, vnode: &RcVnode, ctx: &KernelCtx<'_, '_>);
+
+    /// Writes the transactions that have ended to the disk, if not yet.
+    fn sync(&self, ctx: &KernelCtx<'_, '_>);
+
+    /// Returns the ticks when the oldest change that `sync` would write was made.
+    fn dirty_since(&self, ctx: &KernelCtx<'_, '_>) -> Option<u32>;
+}
+
+impl FsType {
+    /// Returns the type named `name`, as the `fstype` argument of `mount`.
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"ufs" => Some(Self::Ufs),
+            b"lfs" => Some(Self::Lfs),
+            b"tmpfs" => Some(Self::Tmpfs),
+            b"procfs" => Some(Self::Procfs),
+            b"devfs" => Some(Self::Devfs),
+            _ => None,
+        }
+    }
+}
+
+impl RcVnode {
+    fn new<FS: FileSystem>(ip: RcInode<FS>) -> Self {
+        Self {
+            fs: FS::TYPE,
+            dev: ip.dev,
+            ip: InodePtr(ip.into_raw().cast()),
+        }
+    }
+
+    /// Returns the file system of the inode.
+    fn vfs<'s>(&self, ctx: &KernelCtx<'_, 's>) -> &'s dyn Vfs {
+        ctx.kernel().vfs(self.fs, self.dev)
+    }
+
+    /// Returns `true` if `self` and `other` are in the same file system.
+    fn same_fs(&self, other: &Self) -> bool {
+        self.fs == other.fs && self.dev == other.dev
+    }
+
+    /// Returns a new reference to the inode.
+    pub fn dup(&self, ctx: &KernelCtx<'_, '_>) -> Self {
+        self.vfs(ctx).dup(self)
+    }
+
+    pub fn free(self, ctx: &KernelCtx<'_, '_>) {
+        self.vfs(ctx).put(self, ctx);
+    }
+
+    pub fn typ(&self, ctx: &KernelCtx<'_, '_>) -> InodeType {
+        self.vfs(ctx).typ(self, ctx)
+    }
+
+    pub fn stat(&self, ctx: &KernelCtx<'_, '_>) -> Stat {
+        self.vfs(ctx).stat(self, ctx)
+    }
+
+    pub fn attr(&self, ctx: &KernelCtx<'_, '_>) -> Attr {
+        self.vfs(ctx).attr(self, ctx)
+    }
+
+    /// Lets `f` change the attributes of the inode, and sets its change time.
+    pub fn setattr(
+        &self,
+        f: &mut dyn FnMut(&mut Attr) -> Result<(), Errno>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        self.vfs(ctx).setattr(self, f, ctx)
+    }
+
+    /// Checks that the current process may access the inode as `want` says.
+    /// Returns Err(EACCES) if it may not.
+    pub fn access(&self, want: Access, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        let cred = ctx.proc().cred();
+        self.attr(ctx)
+            .check(self.typ(ctx), want, cred.euid, cred.egid)
+    }
+
+    /// Looks for `name` in the directory `self`.
+    pub fn lookup(
+        &self,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Self, Errno> {
+        self.vfs(ctx).lookup(self, name, ctx)
+    }
+
+    /// Calls `f(next, inum, typ, name)` for each entry of the directory `self`
+    /// from the offset `off`, until `f` returns false.
+    pub fn readdir(
+        &self,
+        off: u32,
+        f: &mut dyn FnMut(u32, u32, InodeType, &[u8]) -> bool,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        self.vfs(ctx).readdir(self, off, f, ctx)
+    }
+
+    /// Creates an inode with given type, named `name` in the directory `self`.
+    /// Returns Err(EACCES) if the current process may not write `self`.
+    pub fn create(
+        &self,
+        name: &FileName<{ MAXPATH }>,
+        typ: InodeType,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(Self, InodeType), Errno> {
+        self.access(Access::WRITE | Access::EXEC, ctx)?;
+        self.vfs(ctx).create(self, name, typ, ctx)
+    }
+
+    /// Creates a symbolic link named `name` in the directory `self`, which
+    /// points to `target`.
+    /// Returns Err(EACCES) if the current process may not write `self`.
+    pub fn symlink(
+        &self,
+        name: &FileName<{ MAXPATH }>,
+        target: &[u8],
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        self.access(Access::WRITE | Access::EXEC, ctx)?;
+        self.vfs(ctx).symlink(self, name, target, ctx)
+    }
+
+    /// Creates another name `name` in the directory `dir` for `self`.
+    /// Returns Err(EXDEV) if `dir` is in another file system, or Err(EACCES)
+    /// if the current process may not write `dir`.
+    pub fn link(
+        &self,
+        dir: &Self,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        if !self.same_fs(dir) {
+            return Err(Errno::EXDEV);
+        }
+        dir.access(Access::WRITE | Access::EXEC, ctx)?;
+        self.vfs(ctx).link(self, dir, name, ctx)
+    }
+
+    /// Removes the file `name` from the directory `self`.
+    /// Returns Err(EACCES) if the current process may not write `self`.
+    pub fn unlink(
+        &self,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        self.access(Access::WRITE | Access::EXEC, ctx)?;
+        self.vfs(ctx).unlink(self, name, ctx)
+    }
+
+    /// Moves the entry `name` of the directory `self` to the entry `newname` of
+    /// the directory `newdir`. Returns Err(EXDEV) if `newdir` is in another
+    /// file system, or Err(EACCES) if the current process may not write both
+    /// directories.
+    pub fn rename(
+        &self,
+        name: &FileName<{ MAXPATH }>,
+        newdir: &Self,
+        newname: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        if !self.same_fs(newdir) {
+            return Err(Errno::EXDEV);
+        }
+        self.access(Access::WRITE | Access::EXEC, ctx)?;
+        newdir.access(Access::WRITE | Access::EXEC, ctx)?;
+        self.vfs(ctx).rename(self, name, newdir, newname, ctx)
+    }
+
+    /// Copy data into `dst` from the content of the inode at offset `off`.
+    /// Return Ok(()) on success, Err(EIO) if the inode ends before `dst` is filled.
+    pub fn read_kernel<T: AsBytes + FromBytes>(
+        &self,
+        dst: &mut T,
+        off: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let bytes = self.read_bytes_kernel(dst.as_bytes_mut(), off, ctx);
+        if bytes == mem::size_of::<T>() {
+            Ok(())
+        } else {
+            Err(Errno::EIO)
+        }
+    }
+
+    pub fn read_bytes_kernel(&self, dst: &mut [u8], off: u32, ctx: &KernelCtx<'_, '_>) -> usize {
+        self.vfs(ctx).read_bytes_kernel(self, dst, off, ctx)
+    }
+
+    pub fn read_user(
+        &self,
+        dst: UVAddr,
+        off: u32,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
+        self.vfs(ctx).read_user(self, dst, off, n, ctx)
+    }
+
+    pub fn write_user(
+        &self,
+        src: UVAddr,
+        off: u32,
+        n: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
+        self.vfs(ctx).write_user(self, src, off, n, ctx)
+    }
+
+    /// Writes the transactions of the file system of the inode that have
+    /// ended to the disk, if not yet.
+    pub fn sync(&self, ctx: &KernelCtx<'_, '_>) {
+        self.vfs(ctx).sync(ctx);
+    }
+
+    pub fn trunc(&self, ctx: &KernelCtx<'_, '_>) {
+        self.vfs(ctx).trunc(self, ctx);
+    }
+}
+
+impl PartialEq for RcVnode {
+    fn eq(&self, other: &Self) -> bool {
+        self.same_fs(other) && self.ip == other.ip
+    }
+}
+
+impl Drop for RcVnode {
+    fn drop(&mut self) {
+        // HACK(@efenniht): we really need linear type here:
+        // https://github.com/rust-lang/rfcs/issues/814
+        panic!("RcVnode must never drop.");
+    }
+}
+
+/// Returns the inode of `vnode`. The returned `RcInode` must not be dropped,
+/// since it does not own the reference of `vnode`.
+fn inode<FS: FileSystem>(fs: StrongPin<'_, FS>, vnode: &RcVnode) -> ManuallyDrop<RcInode<FS>> {
+    assert_eq!(vnode.fs, FS::TYPE, "inode: another file system");
+    // SAFETY: `fs` is the instance named by the type and device number of
+    // `vnode`, so `vnode.ip` is made by `RcInode::into_raw` of an `RcInode`
+    // allocated from `fs.itable()`.
+    ManuallyDrop::new(unsafe { RcInode::from_raw(fs.itable(), vnode.ip.0.cast()) })
+}
+
+/// Takes the inode of `vnode` with its reference.
+fn into_inode<FS: FileSystem>(fs: StrongPin<'_, FS>, vnode: RcVnode) -> RcInode<FS> {
+    let ip = inode(fs, &vnode);
+    mem::forget(vnode);
+    ManuallyDrop::into_inner(ip)
+}
+
+impl<FS: FileSystem> Vfs for FS {
+    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        FileSystem::init(self, dev, ctx)
+    }
+
+    fn root(&self, dev: u32) -> RcVnode {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        RcVnode::new(fs.root(dev))
+    }
+
+    fn dup(&self, vnode: &RcVnode) -> RcVnode {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        RcVnode::new(RcInode::clone(&inode(fs, vnode)))
+    }
+
+    fn put(&self, vnode: RcVnode, ctx: &KernelCtx<'_, '_>) {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let ip = into_inode(fs, vnode);
+        let tx = self.begin_tx(ctx);
+        ip.free((&tx, ctx));
+        tx.end(ctx);
+    }
+
+    fn borrows(&self) -> usize {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        fs.itable().borrows()
+    }
+
+    fn typ(&self, vnode: &RcVnode, ctx: &KernelCtx<'_, '_>) -> InodeType {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let ip = inode(fs, vnode);
+        let guard = ip.lock(ctx);
+        let typ = FS::inode_type(&guard);
+        guard.free(ctx);
+        typ
+    }
+
+    fn stat(&self, vnode: &RcVnode, ctx: &KernelCtx<'_, '_>) -> Stat {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        inode(fs, vnode).stat(ctx)
+    }
+
+    fn attr(&self, vnode: &RcVnode, ctx: &KernelCtx<'_, '_>) -> Attr {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let ip = inode(fs, vnode);
+        let guard = ip.lock(ctx);
+        let attr = FS::inode_attr(&guard);
+        guard.free(ctx);
+        attr
+    }
+
+    fn setattr(
+        &self,
+        vnode: &RcVnode,
+        f: &mut dyn FnMut(&mut Attr) -> Result<(), Errno>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let ip = inode(fs, vnode);
+        let tx = self.begin_tx(ctx);
+        let mut guard = ip.lock(ctx);
+        let mut attr = FS::inode_attr(&guard);
+        let res = f(&mut attr).and_then(|_| {
+            attr.ctime = realtime();
+            FS::inode_set_attr(&mut guard, attr, &tx, ctx)
+        });
+        guard.free(ctx);
+        tx.end(ctx);
+        res
+    }
+
+    fn lookup(
+        &self,
+        dir: &RcVnode,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcVnode, Errno> {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let dp = inode(fs, dir);
+        let mut guard = dp.lock(ctx);
+        let res = if FS::inode_type(&guard) != InodeType::Dir {
+            Err(Errno::ENOTDIR)
+        } else {
+            fs.lookup(&mut guard, name, ctx)
+        };
+        guard.free(ctx);
+        res.map(RcVnode::new)
+    }
+
+    fn readdir(
+        &self,
+        dir: &RcVnode,
+        off: u32,
+        f: &mut dyn FnMut(u32, u32, InodeType, &[u8]) -> bool,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let dp = inode(fs, dir);
+        let mut guard = dp.lock(ctx);
+        let res = if FS::inode_type(&guard) != InodeType::Dir {
+            Err(Errno::ENOTDIR)
+        } else {
+            fs.readdir(&mut guard, off, f, ctx);
+            Ok(())
+        };
+        guard.free(ctx);
+        res
+    }
+
+    fn create(
+        &self,
+        dir: &RcVnode,
+        name: &FileName<{ MAXPATH }>,
+        typ: InodeType,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcVnode, InodeType), Errno> {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let dp = inode(fs, dir);
+        let cred = ctx.proc().cred();
+        let attr = Attr::new(typ, cred.euid, cred.egid);
+        let tx = self.begin_tx(ctx);
+        let res = fs.create(&dp, name, typ, attr, &tx, ctx, |ip| FS::inode_type(ip));
+        tx.end(ctx);
+        let (ip, typ) = res?;
+        Ok((RcVnode::new(ip), typ))
+    }
+
+    fn symlink(
+        &self,
+        dir: &RcVnode,
+        name: &FileName<{ MAXPATH }>,
+        target: &[u8],
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let dp = inode(fs, dir);
+        // The link and its content are written in the same transaction, so
+        // that a crash never leaves an empty link.
+        let cred = ctx.proc().cred();
+        let attr = Attr::new(InodeType::Symlink, cred.euid, cred.egid);
+        let tx = self.begin_tx(ctx);
+        let res = fs.create(&dp, name, InodeType::Symlink, attr, &tx, ctx, |ip| {
+            ip.write_bytes_kernel(target, 0, &tx, ctx)
+        });
+        let res = res.and_then(|(ip, written)| {
+            ip.free((&tx, ctx));
+            written.map(|_| ())
+        });
+        tx.end(ctx);
+        res
+    }
+
+    fn link(
+        &self,
+        vnode: &RcVnode,
+        dir: &RcVnode,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let (ip, dp) = (inode(fs, vnode), inode(fs, dir));
+        let tx = self.begin_tx(ctx);
+        let res = fs.link(&ip, &dp, name, &tx, ctx);
+        tx.end(ctx);
+        res
+    }
+
+    fn unlink(
+        &self,
+        dir: &RcVnode,
+        name: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let dp = inode(fs, dir);
+        let tx = self.begin_tx(ctx);
+        let res = fs.unlink(&dp, name, &tx, ctx);
+        tx.end(ctx);
+        res
+    }
+
+    fn rename(
+        &self,
+        dir: &RcVnode,
+        name: &FileName<{ MAXPATH }>,
+        newdir: &RcVnode,
+        newname: &FileName<{ MAXPATH }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let (dp, ndp) = (inode(fs, dir), inode(fs, newdir));
+        // Directories move only while the rename lock is held.
+        let lock = ctx.kernel().rename_lock().lock(ctx);
+        let tx = self.begin_tx(ctx);
+        let res = rename(fs, &dp, name, &ndp, newname, &tx, ctx);
+        tx.end(ctx);
+        lock.free(ctx);
+        res
+    }
+
+    fn read_bytes_kernel(
+        &self,
+        vnode: &RcVnode,
+        dst: &mut [u8],
+        off: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> usize {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let ip = inode(fs, vnode);
+        let mut guard = ip.lock(ctx);
+        let bytes = guard.read_bytes_kernel(dst, off, ctx);
+        guard.free(ctx);
+        bytes
+    }
+
+    fn read_user(
+        &self,
+        vnode: &RcVnode,
+        dst: UVAddr,
+        off: u32,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let ip = inode(fs, vnode);
+        let mut guard = ip.lock(ctx);
+        let res = guard.read_user(dst, off, n, ctx);
+        guard.free(ctx);
+        res
+    }
+
+    fn write_user(
+        &self,
+        vnode: &RcVnode,
+        src: UVAddr,
+        off: u32,
+        n: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let ip = inode(fs, vnode);
+        let mut bytes_written: usize = 0;
+        while bytes_written < n {
+            let bytes_to_write = cmp::min(n - bytes_written, FS::MAX_WRITE);
+            let tx = self.begin_tx(ctx);
+            let mut guard = ip.lock(ctx);
+            let r = guard.write_user(
+                src + bytes_written,
+                off + bytes_written as u32,
+                bytes_to_write as u32,
+                ctx,
+                &tx,
+            );
+            // Free the `InodeGuard` before completing the transacton
+            // to prevent deadlocks (e.g. during the lfs segment cleaner).
+            guard.free(ctx);
+            tx.end(ctx);
+            let r = match r {
+                Ok(r) => r,
+                Err(e) if bytes_written == 0 => return Err(e),
+                Err(_) => break,
+            };
+            bytes_written += r;
+            if r != bytes_to_write {
+                // error from write_user
+                break;
+            }
+        }
+        Ok(bytes_written)
+    }
+
+    fn trunc(&self, vnode: &RcVnode, ctx: &KernelCtx<'_, '_>) {
+        // SAFETY: file systems are reached only from the pinned `Kernel`.
+        let fs = unsafe { StrongPin::new_unchecked(self) };
+        let ip = inode(fs, vnode);
+        let tx = self.begin_tx(ctx);
+        let mut guard = ip.lock(ctx);
+        guard.trunc(&tx, ctx);
+        guard.free(ctx);
+        tx.end(ctx);
+    }
+
+    fn sync(&self, ctx: &KernelCtx<'_, '_>) {
+        FileSystem::sync(self, ctx);
+    }
+
+    fn dirty_since(&self, ctx: &KernelCtx<'_, '_>) -> Option<u32> {
+        FileSystem::dirty_since(self, ctx)
+    }
+}
+
+/// Returns `true` if the directory `dir` is the inode `inum` or is under it.
+/// Must be called with the rename lock held, so that directories do not move.
+fn is_under<FS: FileSystem>(
+    fs: StrongPin<'_, FS>,
+    dir: &RcInode<FS>,
+    inum: u32,
+    tx: &Tx<'_, FS>,
+    ctx: &KernelCtx<'_, '_>,
+) -> bool {
+    // SAFETY: b".." does not contain any NUL characters.
+    let dotdot = unsafe { FileName::from_bytes(b"..") };
+    let mut ip = RcInode::clone(dir);
+    while ip.inum != inum {
+        let mut guard = ip.lock(ctx);
+        let parent = fs.lookup(&mut guard, dotdot, ctx);
+        guard.free(ctx);
+        let parent = some_or!(parent.ok(), {
+            ip.free((tx, ctx));
+            return false;
+        });
+        // The root is its own parent.
+        let is_root = parent.inum == ip.inum;
+        ip.free((tx, ctx));
+        ip = parent;
+        if is_root {
+            ip.free((tx, ctx));
+            return false;
+        }
+    }
+    ip.free((tx, ctx));
+    true
+}
+
+/// Moves the entry `name` of the directory `dir` to the entry `newname` of the
+/// directory `newdir`. Must be called with the rename lock held.
+fn rename<FS: FileSystem>(
+    fs: StrongPin<'_, FS>,
+    dir: &RcInode<FS>,
+    name: &FileName<{ MAXPATH }>,
+    newdir: &RcInode<FS>,
+    newname: &FileName<{ MAXPATH }>,
+    tx: &Tx<'_, FS>,
+    ctx: &KernelCtx<'_, '_>,
+) -> Result<(), Errno> {
+    if [name, newname]
+        .iter()
+        .any(|name| name.as_bytes() == b"." || name.as_bytes() == b"..")
+    {
+        return Err(Errno::EINVAL);
+    }
+
+    // Refuse to move a directory under itself.
+    let mut guard = dir.lock(ctx);
+    let res = fs.lookup(&mut guard, name, ctx);
+    guard.free(ctx);
+    let ip = res?;
+    let guard = ip.lock(ctx);
+    let is_dir = FS::inode_type(&guard) == InodeType::Dir;
+    guard.free(ctx);
+    let moves_under = is_dir && is_under(fs, newdir, ip.inum, tx, ctx);
+    ip.free((tx, ctx));
+    if moves_under {
+        return Err(Errno::EINVAL);
+    }
+
+    if dir.inum == newdir.inum {
+        let mut dp = dir.lock(ctx);
+        let res = rename_locked(&mut dp, name, None, newname, tx, ctx);
+        dp.free(ctx);
+        return res;
+    }
+    // Lock the ancestor first, as other operations lock a directory before
+    // its entries.
+    let (mut dp, mut ndp) = if is_under(fs, newdir, dir.inum, tx, ctx) {
+        let dp = dir.lock(ctx);
+        (dp, newdir.lock(ctx))
+    } else {
+        let ndp = newdir.lock(ctx);
+        (dir.lock(ctx), ndp)
+    };
+    let res = rename_locked(&mut dp, name, Some(&mut ndp), newname, tx, ctx);
+    ndp.free(ctx);
+    dp.free(ctx);
+    res
+}
+
+/// Moves the entry `name` of the locked directory `dir` to the entry `newname`
+/// of the locked directory `newdir`, or of `dir` if `newdir` is `None`. An
+/// existing `newname` is replaced if it has the same kind as `name` and is not
+/// a non-empty directory.
+fn rename_locked<'g, FS: FileSystem>(
+    dir: &mut InodeGuard<'g, FS>,
+    name: &FileName<{ MAXPATH }>,
+    newdir: Option<&mut InodeGuard<'g, FS>>,
+    newname: &FileName<{ MAXPATH }>,
+    tx: &Tx<'_, FS>,
+    ctx: &KernelCtx<'_, '_>,
+) -> Result<(), Errno> {
+    let (ptr, off) = FS::dirlookup(dir, name, ctx)?;
+    let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
+    let ip = ptr.lock(ctx);
+    let is_dir = FS::inode_type(&ip) == InodeType::Dir;
+    ip.free(ctx);
+
+    // Link the inode in the new directory first, which may fail.
+    let moved = is_dir && newdir.is_some();
+    let dir_inum = dir.inum;
+    let ndp = match newdir {
+        Some(ndp) => ndp,
+        None => &mut *dir,
+    };
+    if FS::inode_nlink(ndp) == 0 {
+        return Err(Errno::ENOENT);
+    }
+    let mut nlink = FS::inode_nlink(ndp);
+    match FS::dirlookup(ndp, newname, ctx) {
+        Ok((ptr2, off2)) => {
+            let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+            if ptr2.inum == ptr.inum {
+                // Both names are links to the same inode.
+                return Ok(());
+            }
+            if ptr2.inum == dir_inum {
+                // newname is an ancestor of name.
+                return Err(Errno::ENOTEMPTY);
+            }
+            let ip2 = ptr2.lock(ctx);
+            let mut ip2 = scopeguard::guard(ip2, |ip| ip.free(ctx));
+            let is_dir2 = FS::inode_type(&ip2) == InodeType::Dir;
+            if is_dir && !is_dir2 {
+                return Err(Errno::ENOTDIR);
+            }
+            if !is_dir && is_dir2 {
+                return Err(Errno::EISDIR);
+            }
+            if is_dir2 && !FS::is_dir_empty(&mut ip2, ctx) {
+                return Err(Errno::ENOTEMPTY);
+            }
+            FS::dirrelink(ndp, off2, ptr.inum, tx, ctx);
+            let nlink2 = FS::inode_nlink(&ip2) - 1;
+            FS::inode_set_nlink(&mut ip2, nlink2, tx, ctx);
+            if is_dir2 {
+                // for ".." of the replaced directory
+                nlink -= 1;
+            }
+        }
+        Err(Errno::ENOENT) => FS::dirlink(ndp, newname, ptr.inum, tx, ctx)?,
+        Err(errno) => return Err(errno),
+    }
+    if moved {
+        // for ".." of the moved directory
+        nlink += 1;
+    }
+    if nlink != FS::inode_nlink(ndp) {
+        FS::inode_set_nlink(ndp, nlink, tx, ctx);
+    }
+    let newdir_inum = ndp.inum;
+
+    FS::dirunlink(dir, off, tx, ctx);
+    if moved {
+        let nlink = FS::inode_nlink(dir) - 1;
+        FS::inode_set_nlink(dir, nlink, tx, ctx);
+
+        let ip = ptr.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        // SAFETY: b".." does not contain any NUL characters.
+        let dotdot = unsafe { FileName::from_bytes(b"..") };
+        let (ptr, off) = FS::dirlookup(&mut ip, dotdot, ctx).expect("rename: no ..");
+        ptr.free((tx, ctx));
+        FS::dirrelink(&mut ip, off, newdir_inum, tx, ctx);
+    }
+    Ok(())
+}
+
+impl MountTable {
+    pub const fn new() -> Self {
+        Self {
+            mounts: SpinLock::new("mounts", array![_ => None; NMOUNT]),
+        }
+    }
+
+    /// Mounts `root`, the root directory of the disk `dev`, as the root file system.
+    pub fn init(&mut self, root: RcVnode, dev: u32) {
+        self.mounts.get_mut()[0] = Some(Mount {
+            root,
+            covered: None,
+            dev,
+        });
+    }
+
+    /// Returns a new reference to the root directory. It is the root directory of
+    /// the root file system, unless another file system is mounted on it.
+    pub fn root(&self, ctx: &KernelCtx<'_, '_>) -> RcVnode {
+        let root = self
+            .mounts
+            .lock()
+            .iter()
+            .flatten()
+            .find(|m| m.covered.is_none())
+            .expect("root")
+            .root
+            .dup(ctx);
+        self.cross(root, ctx)
+    }
+
+    /// If a file system is mounted on the directory `vnode`, returns its root
+    /// directory instead of `vnode`.
+    pub fn cross(&self, vnode: RcVnode, ctx: &KernelCtx<'_, '_>) -> RcVnode {
+        let root = self
+            .mounts
+            .lock()
+            .iter()
+            .flatten()
+            .find(|m| m.covered.as_ref() == Some(&vnode))
+            .map(|m| m.root.dup(ctx));
+        match root {
+            Some(root) => {
+                vnode.free(ctx);
+                root
+            }
+            None => vnode,
+        }
+    }
+
+    /// If `vnode` is the root directory of a file system mounted on a directory,
+    /// returns the directory instead of `vnode`, so that ".." of `vnode` is found
+    /// in the file system of the directory.
+    pub fn covered(&self, vnode: RcVnode, ctx: &KernelCtx<'_, '_>) -> RcVnode {
+        let covered = self
+            .mounts
+            .lock()
+            .iter()
+            .flatten()
+            .find(|m| m.root == vnode)
+            .and_then(|m| m.covered.as_ref())
+            .map(|covered| covered.dup(ctx));
+        match covered {
+            Some(covered) => {
+                vnode.free(ctx);
+                covered
+            }
+            None => vnode,
+        }
+    }
+
+    /// Returns Err(EBUSY) if the disk `dev` or the directory `dir` is used by a mount.
+    /// The root directory of the root file system may be covered, so that another
+    /// file system becomes the root.
+    fn check_busy(mounts: &[Option<Mount>; NMOUNT], dev: u32, dir: &RcVnode) -> Result<(), Errno> {
+        let busy = mounts.iter().flatten().any(|m| {
+            m.dev == dev
+                || (m.root == *dir && m.covered.is_some())
+                || m.covered.as_ref() == Some(dir)
+        });
+        if busy {
+            Err(Errno::EBUSY)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Loads the file system of type `typ` from the disk `dev`, and mounts it on
+    /// the directory `dir`. Takes over `dir` from the caller.
+    /// Returns Err(ENOTDIR) if `dir` is not a directory, Err(EINVAL) if `dev` does
+    /// not hold the file system, or Err(EBUSY) if `dev` or `dir` is in use.
+    pub fn mount(
+        &self,
+        typ: FsType,
+        dev: u32,
+        dir: RcVnode,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), Errno> {
+        let vfs = ctx.kernel().vfs(typ, dev);
+        let res = if dir.typ(ctx) != InodeType::Dir {
+            Err(Errno::ENOTDIR)
+        } else {
+            let res = Self::check_busy(&self.mounts.lock(), dev, &dir);
+            res.and_then(|_| vfs.init(dev, ctx))
+        };
+        if let Err(e) = res {
+            dir.free(ctx);
+            return Err(e);
+        }
+
+        // Check again, since we slept while loading the file system.
+        let root = vfs.root(dev);
+        let mut mounts = self.mounts.lock();
+        let res = Self::check_busy(&mounts, dev, &dir)
+            .and_then(|_| mounts.iter().position(|m| m.is_none()).ok_or(Errno::ENOMEM));
+        match res {
+            Ok(i) => {
+                mounts[i] = Some(Mount {
+                    root,
+                    covered: Some(dir),
+                    dev,
+                });
+                Ok(())
+            }
+            Err(e) => {
+                drop(mounts);
+                root.free(ctx);
+                dir.free(ctx);
+                Err(e)
+            }
+        }
+    }
+
+    /// Unmounts the file system whose root directory is `vnode`. Takes over
+    /// `vnode` from the caller.
+    /// Returns Err(EINVAL) if `vnode` is not the root of a mounted file system, or
+    /// Err(EBUSY) if it is the root file system or its files are in use.
+    pub fn umount(&self, vnode: RcVnode, ctx: &KernelCtx<'_, '_>) -> Result<(), Errno> {
+        let vfs = vnode.vfs(ctx);
+        let mut mounts = self.mounts.lock();
+        let res = match mounts
+            .iter()
+            .position(|m| matches!(m, Some(m) if m.root == vnode))
+        {
+            None => Err(Errno::EINVAL),
+            Some(i) => {
+                let m = mounts[i].as_ref().expect("umount");
+                // The instance of the file system is loaded from this mount's
+                // disk only, so `m.root` and `vnode` are the only references to
+                // its inodes, unless its files are in use or another file
+                // system is mounted on one of its directories.
+                let refs = 2;
+                if m.covered.is_none() || vfs.borrows() > refs {
+                    Err(Errno::EBUSY)
+                } else {
+                    Ok(mounts[i].take().expect("umount"))
+                }
+            }
+        };
+        drop(mounts);
+        vnode.free(ctx);
+        let m = res?;
+        vfs.sync(ctx);
+        m.root.free(ctx);
+        if let Some(covered) = m.covered {
+            covered.free(ctx);
+        }
+        Ok(())
+    }
+
+    /// Writes the transactions of all mounted file systems that have ended to
+    /// the disks, if not yet.
+    pub fn sync(&self, ctx: &KernelCtx<'_, '_>) {
+        for (typ, dev) in self.mounted() {
+            ctx.kernel().vfs(typ, dev).sync(ctx);
+        }
+    }
+
+    /// Syncs the mounted file systems that have kept a change from the disk
+    /// for `expire_ticks` or longer at `now` ticks.
+    pub fn sync_expired(&self, now: u32, expire_ticks: u32, ctx: &KernelCtx<'_, '_>) {
+        for (typ, dev) in self.mounted() {
+            let vfs = ctx.kernel().vfs(typ, dev);
+            if let Some(since) = vfs.dirty_since(ctx) {
+                if now.wrapping_sub(since) >= expire_ticks {
+                    vfs.sync(ctx);
+                }
+            }
+        }
+    }
+
+    /// Returns the type and the device of each mounted file system.
+    fn mounted(&self) -> ArrayVec<(FsType, u32), NMOUNT> {
+        self.mounts
+            .lock()
+            .iter()
+            .flatten()
+            .map(|m| (m.root.fs, m.root.dev))
+            .collect()
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Returns the inode of `path`, following symbolic links.
+    pub fn namei(&self, path: &Path) -> Result<RcVnode, Errno> {
+        Ok(self.namex(path, false, true)?.0)
+    }
+
+    /// Returns the inode of `path`. If the last element of `path` is a
+    /// symbolic link, returns the link itself.
+    pub fn namei_nofollow(&self, path: &Path) -> Result<RcVnode, Errno> {
+        Ok(self.namex(path, false, false)?.0)
+    }
+
+    /// Returns the inode of the parent directory of `path`, and the last name of `path`.
+    pub fn nameiparent<'p>(
+        &self,
+        path: &'p Path,
+    ) -> Result<(RcVnode, &'p FileName<{ MAXPATH }>), Errno> {
+        let (vp, name) = self.namex(path, true, false)?;
+        Ok((vp, name.expect("nameiparent")))
+    }
+
+    /// Look up and return the inode for a path name.
+    /// If parent is true, return the inode for the parent and the final path element.
+    /// Crosses mount points in both directions, and follows symbolic links,
+    /// except a link at the final path element if follow is false.
+    fn namex<'p>(
+        &self,
+        path: &'p Path,
+        parent: bool,
+        follow: bool,
+    ) -> Result<(RcVnode, Option<&'p FileName<{ MAXPATH }>>), Errno> {
+        let start = if path.is_absolute() {
+            self.kernel().mounts().root(self)
+        } else {
+            self.proc().files().cwd(self)
+        };
+        self.namex_from(start, path, parent, follow)
+    }
+
+    /// The same as `namex`, except that a relative `path` is resolved from the
+    /// directory `start` instead of the current directory. Takes over `start`
+    /// from the caller, which must be the root directory if `path` is absolute.
+    fn namex_from<'p>(
+        &self,
+        start: RcVnode,
+        path: &'p Path,
+        parent: bool,
+        follow: bool,
+    ) -> Result<(RcVnode, Option<&'p FileName<{ MAXPATH }>>), Errno> {
+        // The path left to resolve is buf[pos..len]. A symbolic link in the
+        // path is replaced by its content.
+        let mut buf = [0u8; MAXPATH];
+        let mut len = path.as_bytes().len();
+        if len > MAXPATH {
+            start.free(self);
+            return Err(Errno::ENAMETOOLONG);
+        }
+        buf[..len].copy_from_slice(path.as_bytes());
+        let mut pos = 0;
+        let mut links = 0;
+
+        let mounts = self.kernel().mounts();
+        let mut vp = start;
+
+        loop {
+            // SAFETY: `buf[..len]` consists of `path` and the contents of
+            // symbolic links, which contain no NUL characters.
+            let rest = unsafe { Path::from_bytes(&buf[pos..len]) };
+            let (rest, name) = some_or!(rest.skipelem(), break);
+            pos = len - rest.as_bytes().len();
+
+            if parent && rest.is_empty_string() {
+                // Stop one level early. A symbolic link is followed only
+                // before the final element, so the final element is the
+                // final element of `path`.
+                if vp.typ(self) != InodeType::Dir {
+                    vp.free(self);
+                    return Err(Errno::ENOTDIR);
+                }
+                return Ok((vp, path.file_name()));
+            }
+            if name.as_bytes() == b".." {
+                vp = mounts.covered(vp, self);
+            }
+            let next = match vp
+                .access(Access::EXEC, self)
+                .and_then(|_| vp.lookup(name, self))
+            {
+                Ok(next) => mounts.cross(next, self),
+                Err(e) => {
+                    vp.free(self);
+                    return Err(e);
+                }
+            };
+
+            if (follow || pos < len) && next.typ(self) == InodeType::Symlink {
+                links += 1;
+                let res = if links > MAXSYMLINKS {
+                    Err(Errno::ELOOP)
+                } else {
+                    self.expand_symlink(&next, &mut buf, pos..len)
+                };
+                next.free(self);
+                match res {
+                    Ok(n) => (pos, len) = (0, n),
+                    Err(e) => {
+                        vp.free(self);
+                        return Err(e);
+                    }
+                }
+                // A relative link is resolved from the directory of the link.
+                if buf[0] == b'/' {
+                    vp.free(self);
+                    vp = mounts.root(self);
+                }
+            } else {
+                vp.free(self);
+                vp = next;
+            }
+        }
+        if parent {
+            vp.free(self);
+            return Err(Errno::ENOENT);
+        }
+        Ok((vp, None))
+    }
+
+    /// Writes the content of the symbolic link `link` followed by `/` and the
+    /// rest of the path `buf[rest]` at the front of `buf`.
+    /// Returns Ok(new length of the path) on success, Err(ENOENT) if the link
+    /// is empty, or Err(ENAMETOOLONG) if the path does not fit in `buf`.
+    fn expand_symlink(
+        &self,
+        link: &RcVnode,
+        buf: &mut [u8; MAXPATH],
+        rest: Range<usize>,
+    ) -> Result<usize, Errno> {
+        let size = link.stat(self).size;
+        if size == 0 {
+            return Err(Errno::ENOENT);
+        }
+        let len = size + 1 + rest.len();
+        if len > MAXPATH {
+            return Err(Errno::ENAMETOOLONG);
+        }
+        buf.copy_within(rest, size + 1);
+        if link.read_bytes_kernel(&mut buf[..size], 0, self) != size || buf[..size].contains(&0) {
+            return Err(Errno::EIO);
+        }
+        buf[size] = b'/';
+        Ok(len)
+    }
+
+    /// Returns the file `path`, after creating it as a regular file if it does
+    /// not exist. If `path` is a symbolic link and `follow` is true, does the
+    /// same for the target of the link, so that a dangling link gets its
+    /// target created.
+    fn lookup_or_create(&self, path: &Path, follow: bool) -> Result<(RcVnode, InodeType), Errno> {
+        let mut buf = [0u8; MAXPATH];
+        let mut links = 0;
+        let (mut dir, mut name) = self.nameiparent(path)?;
+        loop {
+            // Looking up needs the permission to search the directory, and
+            // creating a file also needs the permission to write it.
+            let res = match dir
+                .access(Access::EXEC, self)
+                .and_then(|_| dir.lookup(name, self))
+            {
+                Ok(vp) => {
+                    let typ = vp.typ(self);
+                    Ok((vp, typ))
+                }
+                Err(Errno::ENOENT) => dir.create(name, InodeType::File, self),
+                Err(e) => Err(e),
+            };
+            let vp = match res {
+                Ok((vp, typ)) if typ != InodeType::Symlink || !follow => {
+                    dir.free(self);
+                    return Ok((vp, typ));
+                }
+                Ok((vp, _)) => vp,
+                Err(e) => {
+                    dir.free(self);
+                    return Err(e);
+                }
+            };
+
+            // Go on with the target of the link, which is resolved from the
+            // directory of the link unless it is absolute.
+            links += 1;
+            let res = if links > MAXSYMLINKS {
+                Err(Errno::ELOOP)
+            } else {
+                self.expand_symlink(&vp, &mut buf, 0..0)
+            };
+            vp.free(self);
+            let len = match res {
+                Ok(len) => len,
+                Err(e) => {
+                    dir.free(self);
+                    return Err(e);
+                }
+            };
+            // SAFETY: `buf[..len - 1]` is the content of the link without the
+            // `/` that `expand_symlink` appends, which contains no NUL characters.
+            let target = unsafe { Path::from_bytes(&buf[..len - 1]) };
+            let start = if target.is_absolute() {
+                dir.free(self);
+                self.kernel().mounts().root(self)
+            } else {
+                dir
+            };
+            let (next, next_name) = self.namex_from(start, target, true, false)?;
+            (dir, name) = (next, next_name.expect("lookup_or_create"));
+        }
+    }
+
+    /// Opens the file `path` and returns a new file descriptor. If `omode` has
+    /// O_CREATE, creates the file if it does not exist, or the target of the
+    /// symbolic link `path` if the link is dangling.
+    /// Returns Err(ELOOP) if `path` is a symbolic link and `omode` has O_NOFOLLOW,
+    /// or Err(EACCES) if the current process may not access the file as `omode` says.
+    pub fn open(&mut self, path: &Path, omode: FcntlFlags) -> Result<usize, Errno> {
+        let nofollow = omode.contains(FcntlFlags::O_NOFOLLOW);
+        let (vp, typ) = if omode.contains(FcntlFlags::O_CREATE) {
+            self.lookup_or_create(path, !nofollow)?
+        } else {
+            let (vp, _) = self.namex(path, false, !nofollow)?;
+            let typ = vp.typ(self);
+            (vp, typ)
+        };
+        if typ == InodeType::Symlink {
+            vp.free(self);
+            return Err(Errno::ELOOP);
+        }
+        if typ == InodeType::Dir && omode - FcntlFlags::O_NOFOLLOW != FcntlFlags::O_RDONLY {
+            vp.free(self);
+            return Err(Errno::EISDIR);
+        }
+        let readable = !omode.intersects(FcntlFlags::O_WRONLY);
+        let writable = omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR);
+        let mut want = Access::empty();
+        want.set(Access::READ, readable);
+        want.set(
+            Access::WRITE,
+            writable || omode.contains(FcntlFlags::O_TRUNC),
+        );
+        if let Err(e) = vp.access(want, self) {
+            vp.free(self);
+            return Err(e);
+        }
+
+        let filetype = match typ {
+            InodeType::Device { major, minor } => {
+                FileType::Device {
+                    ip: vp,
+                    major,
+                    minor,
+                }
+            }
+            _ => {
+                FileType::Inode {
+                    inner: InodeFileType {
+                        ip: vp,
+                        off: SleepLock::new("file", 0),
+                    },
+                }
+            }
+        };
+
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(filetype, readable, writable)?;
+
+        if omode.contains(FcntlFlags::O_TRUNC) && typ == InodeType::File {
+            match &f.typ {
+                FileType::Device { ip, .. }
+                | FileType::Inode {
+                    inner: InodeFileType { ip, .. },
+                } => ip.trunc(self),
+                _ => panic!("sys_open : Not reach"),
+            };
+        }
+        let fd = f.fdalloc(self)?;
+        Ok(fd as usize)
+    }
+
+    /// Creates a symbolic link `path` that points to `target`.
+    /// Returns Err(ENOENT) if `target` is empty, or Err(EEXIST) if `path` exists.
+    pub fn symlink(&self, target: &[u8], path: &Path) -> Result<(), Errno> {
+        if target.is_empty() {
+            return Err(Errno::ENOENT);
+        }
+        let (dir, name) = self.nameiparent(path)?;
+        let res = dir.symlink(name, target, self);
+        dir.free(self);
+        res
+    }
+
+    /// Copies the content of the symbolic link `path` into virtual address
+    /// `dst` of the current process by at most `n` bytes, without a NUL.
+    /// Returns Ok(number of bytes copied) on success, or Err(EINVAL) if
+    /// `path` is not a symbolic link.
+    pub fn readlink(&mut self, path: &Path, dst: UVAddr, n: u32) -> Result<usize, Errno> {
+        let vp = self.namei_nofollow(path)?;
+        let res = if vp.typ(self) == InodeType::Symlink {
+            vp.read_user(dst, 0, n, self)
+        } else {
+            Err(Errno::EINVAL)
+        };
+        vp.free(self);
+        res
+    }
+
+    /// Moves the file `old` to `new`, replacing `new` if it exists.
+    pub fn rename(&self, old: &Path, new: &Path) -> Result<(), Errno> {
+        let (dir, name) = self.nameiparent(old)?;
+        let res = try {
+            let (newdir, newname) = self.nameiparent(new)?;
+            let res = dir.rename(name, &newdir, newname, self);
+            newdir.free(self);
+            res?
+        };
+        dir.free(self);
+        res
+    }
+
+    /// Changes the current directory to `path`.
+    pub fn chdir(&mut self, path: &Path) -> Result<(), Errno> {
+        let vp = self.namei(path)?;
+        let res = if vp.typ(self) != InodeType::Dir {
+            Err(Errno::ENOTDIR)
+        } else {
+            vp.access(Access::EXEC, self)
+        };
+        if let Err(e) = res {
+            vp.free(self);
+            return Err(e);
+        }
+        self.proc().files().replace_cwd(vp).free(self);
+        Ok(())
+    }
+
+    /// Sets the permission bits of the file `path` to `mode`.
+    /// Returns Err(EPERM) if the current process neither owns the file nor is root.
+    pub fn chmod(&self, path: &Path, mode: u16) -> Result<(), Errno> {
+        let uid = self.proc().cred().euid;
+        let vp = self.namei(path)?;
+        let res = vp.setattr(
+            &mut |attr| {
+                if uid != 0 && uid != attr.uid {
+                    return Err(Errno::EPERM);
+                }
+                attr.mode = mode & Attr::MODE_MASK;
+                Ok(())
+            },
+            self,
+        );
+        vp.free(self);
+        res
+    }
+
+    /// Changes the owner and the group of the file `path`. `None` keeps the
+    /// current one. Clears the set-user-id and set-group-id bits, so that a
+    /// program does not get the permissions of its new owner.
+    /// Returns Err(EPERM) if the current process is not root.
+    pub fn chown(&self, path: &Path, owner: Option<u32>, group: Option<u32>) -> Result<(), Errno> {
+        if !self.proc().cred().is_root() {
+            return Err(Errno::EPERM);
+        }
+        let vp = self.namei(path)?;
+        let res = vp.setattr(
+            &mut |attr| {
+                attr.uid = owner.unwrap_or(attr.uid);
+                attr.gid = group.unwrap_or(attr.gid);
+                attr.mode &= !(Attr::SETUID | Attr::SETGID);
+                Ok(())
+            },
+            self,
+        );
+        vp.free(self);
+        res
+    }
+
+    /// Sets the access and modification times of the file `path` to `times`,
+    /// or to the current time if `times` is `None`.
+    /// Returns Err(EPERM) if `times` is given but the current process neither
+    /// owns the file nor is root, or Err(EACCES) if `times` is `None` and the
+    /// process may not write the file either.
+    pub fn utime(&self, path: &Path, times: Option<(u32, u32)>) -> Result<(), Errno> {
+        let uid = self.proc().cred().euid;
+        let vp = self.namei(path)?;
+        let writable = vp.access(Access::WRITE, self);
+        let res = vp.setattr(
+            &mut |attr| {
+                if uid != 0 && uid != attr.uid {
+                    match times {
+                        Some(_) => return Err(Errno::EPERM),
+                        None => writable?,
+                    }
+                }
+                let now = realtime();
+                (attr.atime, attr.mtime) = times.unwrap_or((now, now));
+                Ok(())
+            },
+            self,
+        );
+        vp.free(self);
+        res
+    }
+}
@@ -0,0 +1,79 @@
+//! Fast user-space mutexes.
+//!
+//! A futex is an aligned 32-bit word in user memory. A thread sleeps on a
+//! futex only if the word still holds the value it expects, and a thread that
+//! changes the word wakes the sleepers. Sleepers are kept in NFUTEX queues
+//! hashed by the address of the word, and waking a futex wakes every sleeper in
+//! its queue, so a woken thread must check the word again.
+
+use array_macro::array;
+
+use crate::{
+    addr::{Addr, UVAddr},
+    errno::Errno,
+    kernel::KernelRef,
+    lock::SpinLock,
+    param::NFUTEX,
+    proc::{KernelCtx, WaitChannel},
+};
+
+/// Sleep while the word holds a given value.
+pub const FUTEX_WAIT: i32 = 0;
+/// Wake the sleepers.
+pub const FUTEX_WAKE: i32 = 1;
+
+/// A queue of threads sleeping on futexes.
+struct FutexQueue {
+    /// Held while checking a futex and while waking the sleepers, so that
+    /// a wakeup cannot be lost between the check and the sleep.
+    lock: SpinLock<()>,
+    waitchannel: WaitChannel,
+}
+
+pub struct Futexes {
+    queues: [FutexQueue; NFUTEX],
+}
+
+impl Futexes {
+    pub const fn new() -> Self {
+        Self {
+            queues: array![_ => FutexQueue {
+                lock: SpinLock::new("futex", ()),
+                waitchannel: WaitChannel::new(),
+            }; NFUTEX],
+        }
+    }
+
+    fn queue(&self, addr: UVAddr) -> &FutexQueue {
+        &self.queues[(addr.into_usize() >> 2) % NFUTEX]
+    }
+
+    /// Sleep on the futex at addr if it holds val, until it is woken.
+    /// Returns Ok(()) after a wakeup, which may be spurious, Err(EAGAIN) if the
+    /// futex does not hold val, or Err(EINTR) if the process has been killed.
+    pub fn wait(&self, addr: UVAddr, val: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<(), Errno> {
+        if addr.into_usize() % 4 != 0 {
+            return Err(Errno::EINVAL);
+        }
+        let queue = self.queue(addr);
+        let mut guard = queue.lock.lock();
+        let mut cur = 0i32;
+        // SAFETY: i32 does not have any internal structure.
+        unsafe { ctx.proc_mut().memory_mut().copy_in(&mut cur, addr) }?;
+        if cur != val {
+            return Err(Errno::EAGAIN);
+        }
+        if ctx.proc().killed() {
+            return Err(Errno::EINTR);
+        }
+        queue.waitchannel.sleep(&mut guard, ctx);
+        Ok(())
+    }
+
+    /// Wake the threads sleeping on the futex at addr.
+    pub fn wake(&self, addr: UVAddr, kernel: KernelRef<'_, '_>) {
+        let queue = self.queue(addr);
+        let _guard = queue.lock.lock();
+        queue.waitchannel.wakeup(kernel);
+    }
+}
@@ -6,7 +6,8 @@ use core::{mem, pin::Pin};
 use pin_project::pin_project;
 
 use crate::{
-    addr::{pgrounddown, pgroundup, PGSIZE},
+    addr::{pgrounddown, pgroundup, Addr, PAddr, PGSIZE},
+    arch::{interface::MemLayout, TargetArch},
     lock::SpinLock,
     memlayout::PHYSTOP,
     page::Page,
@@ -19,6 +20,14 @@ extern "C" {
     pub static mut end: [u8; 0];
 }
 
+/// Number of physical pages between `KERNBASE` and `PHYSTOP`.
+const NPAGES: usize = (PHYSTOP - TargetArch::KERNBASE) / PGSIZE;
+
+/// Returns the index of the page at `pa` in `Kmem::refcnt`.
+fn page_index(pa: PAddr) -> usize {
+    (pa.into_usize() - TargetArch::KERNBASE) / PGSIZE
+}
+
 #[repr(transparent)]
 #[pin_project]
 struct Run {
@@ -54,7 +63,9 @@ unsafe impl ListNode for Run {
 
 /// # Safety
 ///
-/// The address of each `Run` in `runs` can become a `Page` by `Page::from_usize`.
+/// * The address of each `Run` in `runs` can become a `Page` by `Page::from_usize`.
+/// * `refcnt[page_index(pa)]` is the number of owners of the allocated page at `pa`,
+///   and 0 if the page is in `runs`.
 // This implementation defers from xv6. Kmem of xv6 uses intrusive singly linked list, while this
 // Kmem uses List, which is a intrusive doubly linked list type of rv6. In a intrusive singly
 // linked list, it is impossible to automatically remove an entry from a list when it is dropped.
@@ -65,6 +76,16 @@ unsafe impl ListNode for Run {
 pub struct Kmem {
     #[pin]
     runs: List<Run>,
+
+    /// Reference counts of physical pages. Pages shared copy-on-write by
+    /// several user memories have a count larger than 1.
+    refcnt: [u8; NPAGES],
+
+    /// Number of pages in `runs`.
+    nfree: usize,
+
+    /// Number of pages that `init` created.
+    ntotal: usize,
 }
 
 impl Kmem {
@@ -74,6 +95,9 @@ impl Kmem {
     pub const unsafe fn new() -> Self {
         Self {
             runs: unsafe { List::new() },
+            refcnt: [0; NPAGES],
+            nfree: 0,
+            ntotal: 0,
         }
     }
 
@@ -97,6 +121,8 @@ impl Kmem {
             //   created page does not overlap with existing pages
             self.as_mut().free(unsafe { Page::from_usize(pa) });
         }
+        let this = self.project();
+        *this.ntotal = *this.nfree;
     }
 
     pub fn free(self: Pin<&mut Self>, mut page: Page) {
@@ -105,25 +131,75 @@ impl Kmem {
         let run = run.write(unsafe { Run::new() });
         let mut run = unsafe { Pin::new_unchecked(run) };
         run.as_mut().init();
-        self.project().runs.push_front(run);
+        let this = self.project();
+        this.runs.push_front(run);
+        *this.nfree += 1;
 
         // Since the page has returned to the list, forget the page.
         mem::forget(page);
     }
 
     pub fn alloc(self: Pin<&mut Self>) -> Option<Page> {
-        let run = self.project().runs.pop_front()?;
+        let this = self.project();
+        let run = this.runs.pop_front()?;
+        *this.nfree -= 1;
         // SAFETY: the invariant of `Kmem`.
         let page = unsafe { Page::from_usize(run as _) };
+        this.refcnt[page_index(page.addr())] = 1;
         Some(page)
     }
+
+    /// Decreases the reference count of the page at `pa`.
+    /// Returns the remaining number of owners.
+    fn decref(self: Pin<&mut Self>, pa: PAddr) -> usize {
+        let cnt = &mut self.project().refcnt[page_index(pa)];
+        *cnt = cnt.saturating_sub(1);
+        *cnt as usize
+    }
 }
 
 impl SpinLock<Kmem> {
+    /// Drops a reference to `page`. The page returns to the free list only
+    /// when it was the last reference.
     pub fn free(self: Pin<&Self>, mut page: Page) {
+        let mut kmem = self.pinned_lock();
+        if kmem.get_pin_mut().decref(page.addr()) > 0 {
+            // Other owners still use the page.
+            mem::forget(page);
+            return;
+        }
         // Fill with junk to catch dangling refs.
         page.write_bytes(1);
-        self.pinned_lock().get_pin_mut().free(page);
+        kmem.get_pin_mut().free(page);
+    }
+
+    /// Returns another reference to the page at `pa` by increasing its
+    /// reference count.
+    ///
+    /// # Safety
+    ///
+    /// `pa` must be the address of a page allocated from this allocator that
+    /// is still in use. The returned `Page` aliases the existing one, so the
+    /// owners must not write to the page while it is shared.
+    pub unsafe fn dup(self: Pin<&Self>, pa: PAddr) -> Page {
+        let mut kmem = self.pinned_lock();
+        let cnt = &mut kmem.get_pin_mut().project().refcnt[page_index(pa)];
+        assert!(*cnt > 0 && *cnt < u8::MAX, "Kmem::dup");
+        *cnt += 1;
+        // SAFETY: `pa` is the address of an allocated page.
+        unsafe { Page::from_usize(pa.into_usize()) }
+    }
+
+    /// Returns the number of owners of the page at `pa`.
+    pub fn refcnt(self: Pin<&Self>, pa: PAddr) -> usize {
+        let kmem = self.pinned_lock();
+        kmem.refcnt[page_index(pa)] as usize
+    }
+
+    /// Returns the number of free pages and the number of all pages.
+    pub fn usage(self: Pin<&Self>) -> (usize, usize) {
+        let kmem = self.pinned_lock();
+        (kmem.nfree, kmem.ntotal)
     }
 
     pub fn alloc(self: Pin<&Self>, init_value: Option<u8>) -> Option<Page> {
@@ -4,28 +4,31 @@ use core::ops::Deref;
 use core::pin::Pin;
 use core::sync::atomic::{AtomicBool, Ordering};
 
+use array_macro::array;
 use pin_project::pin_project;
 
 use crate::util::strong_pin::StrongPin;
 use crate::{
     arch::interface::Arch,
     arch::TargetArch,
-    bio::Bcache,
+    bio::{Bcache, BcacheStats},
     console::{console_read, console_write},
     cpu::cpuid,
-    file::{Devsw, FileTable},
-    fs::{DefaultFs, FileSystem},
+    device::{Devices, Devsw, CONSOLE},
+    file::{FileTable, ProcFilesTable},
+    fs::{Devfs, FsType, Lfs, MountTable, Procfs, Tmpfs, Ufs, Vfs, ROOTFS},
+    futex::Futexes,
     hal::{hal, hal_init},
     kalloc::Kmem,
-    lock::{SleepableLock, SpinLock},
-    param::NDEV,
+    lock::{SleepLock, SpinLock},
+    param::{NDISK, ROOTDEV},
     proc::Procs,
+    timer::TimerWheel,
     util::{branded::Branded, spin_loop},
-    vm::KernelMemory,
+    vm::{AddressSpaceTable, KernelMemory},
+    writeback::Writeback,
 };
 
-const CONSOLE_IN_DEVSW: usize = 1;
-
 /// The kernel.
 static mut KERNEL: Kernel<TargetArch> = unsafe { Kernel::new() };
 
@@ -58,6 +61,14 @@ unsafe fn kernel_mut_unchecked<'s>() -> Pin<&'s mut Kernel<TargetArch>> {
     unsafe { Pin::new_unchecked(&mut KERNEL) }
 }
 
+/// Returns the index of the disk `dev` in the arrays of the file systems of disks.
+fn disk_index(dev: u32) -> usize {
+    dev.checked_sub(ROOTDEV)
+        .map(|i| i as usize)
+        .filter(|i| *i < NDISK)
+        .expect("disk_index: no such disk")
+}
+
 /// # Safety
 ///
 /// The `Kernel` is `!Unpin`, since it owns data that are `!Unpin`, such as the `bcache`.
@@ -73,7 +84,13 @@ pub struct Kernel<A: Arch> {
     /// The kernel's memory manager.
     memory: MaybeUninit<KernelMemory<A>>,
 
-    ticks: SleepableLock<u32>,
+    /// The clock ticks since boot.
+    ticks: SpinLock<u32>,
+
+    /// The uptime at boot, in microseconds.
+    boot_time: usize,
+
+    timers: SpinLock<TimerWheel>,
 
     /// Current process system.
     #[pin]
@@ -82,13 +99,51 @@ pub struct Kernel<A: Arch> {
     #[pin]
     bcache: Bcache,
 
-    devsw: [Devsw; NDEV],
+    /// Hits and misses of the buffer cache.
+    bcache_stats: BcacheStats,
+
+    /// The times that control the write-back of the file systems.
+    writeback: Writeback,
+
+    /// The registered drivers and devices.
+    devices: SpinLock<Devices>,
 
     #[pin]
     ftable: FileTable,
 
+    /// User memories, shared by the threads of a process.
+    #[pin]
+    address_spaces: AddressSpaceTable,
+
+    /// Open files and current directories, shared by the threads of a process.
+    #[pin]
+    proc_files: ProcFilesTable,
+
+    futexes: Futexes,
+
+    /// The ufs and the lfs of each disk. `ufs[i]` and `lfs[i]` are loaded from
+    /// the disk `ROOTDEV + i`, if mounted.
+    #[pin]
+    ufs: [Ufs; NDISK],
+
     #[pin]
-    file_system: DefaultFs,
+    lfs: [Lfs; NDISK],
+
+    #[pin]
+    tmpfs: Tmpfs,
+
+    #[pin]
+    procfs: Procfs,
+
+    #[pin]
+    devfs: Devfs,
+
+    /// The mounted file systems.
+    mounts: MountTable,
+
+    /// Serializes renames, so that no directory moves while a rename checks
+    /// that it does not move a directory under itself.
+    rename_lock: SleepLock<()>,
 }
 
 /// A branded reference to a `Kernel`.
@@ -120,10 +175,20 @@ impl<'id, 's> KernelRef<'id, 's> {
     }
 
     /// Returns a reference to the kernel's ticks.
-    pub fn ticks(&self) -> &'s SleepableLock<u32> {
+    pub fn ticks(&self) -> &'s SpinLock<u32> {
         &self.0.as_pin().get_ref().ticks
     }
 
+    /// Returns the uptime at boot, in microseconds.
+    pub fn boot_time(&self) -> usize {
+        self.0.as_pin().get_ref().boot_time
+    }
+
+    /// Returns a reference to the kernel's timers.
+    pub fn timers(&self) -> &'s SpinLock<TimerWheel> {
+        &self.0.as_pin().get_ref().timers
+    }
+
     pub fn ps(&self) -> Pin<&'s Procs> {
         unsafe { Pin::new_unchecked(&self.0.as_pin().get_ref().procs) }
     }
@@ -132,19 +197,81 @@ impl<'id, 's> KernelRef<'id, 's> {
         unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().bcache) }
     }
 
-    /// Returns a reference to the kernel's `Devsw` array.
-    pub fn devsw(&self) -> &'s [Devsw; NDEV] {
-        &self.0.as_pin().get_ref().devsw
+    /// Returns a reference to the counters of the buffer cache.
+    pub fn bcache_stats(&self) -> &'s BcacheStats {
+        &self.0.as_pin().get_ref().bcache_stats
+    }
+
+    /// Returns a reference to the times that control the write-back.
+    pub fn writeback(&self) -> &'s Writeback {
+        &self.0.as_pin().get_ref().writeback
     }
 
-    /// Returns a reference to the kernel's `FileSystem`.
-    pub fn fs(&self) -> StrongPin<'s, DefaultFs> {
-        unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().file_system) }
+    /// Returns a reference to the kernel's registry of devices.
+    pub fn devices(&self) -> &'s SpinLock<Devices> {
+        &self.0.as_pin().get_ref().devices
+    }
+
+    /// Returns the ufs of the disk `dev`.
+    pub fn ufs(&self, dev: u32) -> StrongPin<'s, Ufs> {
+        unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().ufs[disk_index(dev)]) }
+    }
+
+    /// Returns the lfs of the disk `dev`.
+    pub fn lfs(&self, dev: u32) -> StrongPin<'s, Lfs> {
+        unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().lfs[disk_index(dev)]) }
+    }
+
+    pub fn tmpfs(&self) -> StrongPin<'s, Tmpfs> {
+        unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().tmpfs) }
+    }
+
+    pub fn procfs(&self) -> StrongPin<'s, Procfs> {
+        unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().procfs) }
+    }
+
+    pub fn devfs(&self) -> StrongPin<'s, Devfs> {
+        unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().devfs) }
+    }
+
+    /// Returns the kernel's file system of the given type on the disk `dev`.
+    /// `dev` is ignored for the file systems without a disk.
+    pub fn vfs(&self, typ: FsType, dev: u32) -> &'s dyn Vfs {
+        match typ {
+            FsType::Ufs => self.ufs(dev).ptr(),
+            FsType::Lfs => self.lfs(dev).ptr(),
+            FsType::Tmpfs => self.tmpfs().ptr(),
+            FsType::Procfs => self.procfs().ptr(),
+            FsType::Devfs => self.devfs().ptr(),
+        }
+    }
+
+    /// Returns a reference to the kernel's mount table.
+    pub fn mounts(&self) -> &'s MountTable {
+        &self.0.as_pin().get_ref().mounts
+    }
+
+    /// Returns a reference to the lock that serializes renames.
+    pub fn rename_lock(&self) -> &'s SleepLock<()> {
+        &self.0.as_pin().get_ref().rename_lock
     }
 
     pub fn ftable(&self) -> StrongPin<'s, FileTable> {
         unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().ftable) }
     }
+
+    pub fn address_spaces(&self) -> StrongPin<'s, AddressSpaceTable> {
+        unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().address_spaces) }
+    }
+
+    pub fn proc_files(&self) -> StrongPin<'s, ProcFilesTable> {
+        unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().proc_files) }
+    }
+
+    /// Returns a reference to the kernel's futex queues.
+    pub fn futexes(&self) -> &'s Futexes {
+        &self.0.as_pin().get_ref().futexes
+    }
 }
 
 impl<'id, 's> Deref for KernelRef<'id, 's> {
@@ -163,15 +290,25 @@ impl<A: Arch> Kernel<A> {
         Self {
             panicked: AtomicBool::new(false),
             memory: MaybeUninit::uninit(),
-            ticks: SleepableLock::new("time", 0),
+            ticks: SpinLock::new("time", 0),
+            boot_time: 0,
+            timers: SpinLock::new("timers", TimerWheel::new()),
             procs: Procs::new(),
             bcache: unsafe { Bcache::new_bcache() },
-            devsw: [Devsw {
-                read: None,
-                write: None,
-            }; NDEV],
+            bcache_stats: BcacheStats::new(),
+            writeback: Writeback::new(),
+            devices: SpinLock::new("devices", Devices::new()),
             ftable: FileTable::new_ftable(),
-            file_system: DefaultFs::new(),
+            address_spaces: AddressSpaceTable::new_table(),
+            proc_files: ProcFilesTable::new_table(),
+            futexes: Futexes::new(),
+            ufs: array![_ => Ufs::new(); NDISK],
+            lfs: array![_ => Lfs::new(); NDISK],
+            tmpfs: Tmpfs::new(),
+            procfs: Procfs::new(),
+            devfs: Devfs::new(),
+            mounts: MountTable::new(),
+            rename_lock: SleepLock::new("rename", ()),
         }
     }
 
@@ -185,11 +322,20 @@ impl<A: Arch> Kernel<A> {
 
         let mut this = self.project();
 
-        // Connect read and write system calls to consoleread and consolewrite.
-        this.devsw[CONSOLE_IN_DEVSW] = Devsw {
-            read: Some(console_read),
-            write: Some(console_write),
-        };
+        // Connect read and write system calls to consoleread and consolewrite,
+        // and register the built-in devices.
+        let devices = this.devices.get_mut();
+        devices
+            .register_driver(
+                CONSOLE,
+                Devsw {
+                    read: Some(console_read),
+                    write: Some(console_write),
+                },
+            )
+            .and_then(|_| devices.register("console", CONSOLE, 0))
+            .and_then(|_| devices.register_mem())
+            .expect("cannot register the devices");
 
         // Create kernel memory manager.
         let memory = KernelMemory::new(allocator).expect("PageTable::new failed");
@@ -198,6 +344,9 @@ impl<A: Arch> Kernel<A> {
         // SAFETY: `memory.page_table` contains base address for a valid kernel page table.
         unsafe { this.memory.write(memory).init_register() };
 
+        // Now the timer of the device is mapped.
+        *this.boot_time = A::uptime_as_micro().unwrap_or(0);
+
         // Process system.
         this.procs.as_mut().init();
 
@@ -219,9 +368,32 @@ impl<A: Arch> Kernel<A> {
         // Buffer cache.
         this.bcache.init();
 
+        // Mount the root file system. It is initialized later by `forkret`, since
+        // reading the disk needs a process context.
+        let rootfs: &dyn Vfs = match ROOTFS {
+            FsType::Ufs => &this.ufs.as_ref().get_ref()[disk_index(ROOTDEV)],
+            FsType::Lfs => &this.lfs.as_ref().get_ref()[disk_index(ROOTDEV)],
+            FsType::Tmpfs => this.tmpfs.as_ref().get_ref(),
+            FsType::Procfs => this.procfs.as_ref().get_ref(),
+            FsType::Devfs => this.devfs.as_ref().get_ref(),
+        };
+        let root = rootfs.root(ROOTDEV);
+        let cwd = rootfs.dup(&root);
+        let flusher_cwd = rootfs.dup(&root);
+        this.mounts.init(root, ROOTDEV);
+
         // First user process.
-        let fs = unsafe { StrongPin::new_unchecked(this.file_system.as_ref().get_ref()) };
-        this.procs.user_proc_init(fs.root(), allocator);
+        let address_spaces =
+            unsafe { StrongPin::new_unchecked(this.address_spaces.as_ref().get_ref()) };
+        let proc_files = unsafe { StrongPin::new_unchecked(this.proc_files.as_ref().get_ref()) };
+        this.procs
+            .as_mut()
+            .user_proc_init(cwd, address_spaces, proc_files, allocator);
+
+        // The flusher, which writes back the file systems.
+        this.procs
+            .as_ref()
+            .flusher_init(flusher_cwd, address_spaces, proc_files, allocator);
     }
 
     /// Initializes the kernel for a core.
@@ -52,9 +52,12 @@ mod arena;
 mod bio;
 mod console;
 mod cpu;
+mod device;
+mod errno;
 mod exec;
 mod file;
 mod fs;
+mod futex;
 mod hal;
 mod kalloc;
 mod kernel;
@@ -64,9 +67,13 @@ mod page;
 mod param;
 mod pipe;
 mod proc;
+mod sched;
+mod signal;
 mod start;
 mod syscall;
+mod timer;
 mod trap;
 mod util;
 mod virtio;
 mod vm;
+mod writeback;
@@ -21,18 +21,37 @@
 
 use crate::addr::{MAXVA, PGSIZE};
 use crate::arch::{interface::MemLayout, TargetArch};
+use crate::param::{NTHREAD, USTACKSIZE};
 
 /// User memory layout.
 /// Address zero first:
 ///   text
 ///   original data and bss
-///   fixed-size stack
 ///   expandable heap
 ///   ...
-///   TRAPFRAME (p->trapframe, used by the trampoline)
+///   memory mappings (mmap), placed downward from MMAPTOP
+///   ...
+///   guard gap, never mapped
+///   stack, growing downward from USTACKTOP up to USTACKSIZE bytes
+///   trap frames of the other threads, downward from TRAPFRAME to TRAPFRAMES
+///   TRAPFRAME (p->trapframe of the first thread, used by the trampoline)
 ///   TRAMPOLINE (the same page as in the kernel)
 pub const TRAPFRAME: usize = TRAMPOLINE.wrapping_sub(PGSIZE);
 
+/// The lowest trap frame. Each of the NTHREAD threads that share a user
+/// memory maps its trap frame at a page in [TRAPFRAMES, TRAMPOLINE).
+pub const TRAPFRAMES: usize = TRAPFRAME.wrapping_sub((NTHREAD - 1) * PGSIZE);
+
+/// The end of the area for memory mappings created by mmap.
+pub const MMAPTOP: usize = TRAPFRAME.wrapping_sub(1 << 30);
+
+/// The top of the user stack.
+pub const USTACKTOP: usize = TRAPFRAMES;
+
+/// The lowest address the user stack can grow to.
+/// The area between MMAPTOP and USTACKBASE is the guard gap of the stack.
+pub const USTACKBASE: usize = USTACKTOP.wrapping_sub(USTACKSIZE);
+
 /// map the trampoline page to the highest address,
 /// in both user and kernel space.
 pub const TRAMPOLINE: usize = MAXVA.wrapping_sub(PGSIZE);
@@ -1,5 +1,3 @@
-use cfg_if::cfg_if;
-
 /// Maximum number of processes.
 pub const NPROC: usize = 64;
 
@@ -9,6 +7,21 @@ pub const NCPU: usize = 8;
 /// Open files per process.
 pub const NOFILE: usize = 16;
 
+/// Maximum size of the user stack.
+pub const USTACKSIZE: usize = 1024 * 1024;
+
+/// Maximum number of threads sharing a user memory.
+pub const NTHREAD: usize = 16;
+
+/// Number of futex wait queues.
+pub const NFUTEX: usize = 32;
+
+/// Number of kernel timers. One for each sleeping process.
+pub const NTIMER: usize = NPROC;
+
+/// Memory mappings per process.
+pub const NVMA: usize = 16;
+
 /// Open files per system.
 pub const NFILE: usize = 100;
 
@@ -18,6 +31,9 @@ pub const NINODE: usize = 50;
 /// Maximum major device number.
 pub const NDEV: usize = 10;
 
+/// Maximum number of registered devices.
+pub const NDEVICE: usize = 16;
+
 /// Device number of file system root disk.
 pub const ROOTDEV: u32 = 1;
 
@@ -31,38 +47,66 @@ pub const BSIZE: usize = 1024;
 /// Will be handled in #31.
 pub const MAXOPBLOCKS: usize = 10;
 
-cfg_if! {
-    if #[cfg(feature = "lfs")] {
-        // TODO: The following may be actually unknown at compile time.
-
-        /// Size of a segment in blocks
-        ///
-        /// An optimal size of segments for LFS is dependent to
-        /// the performance of a disk and a desired effective bandwith of developers.
-        /// Check the formula for getting the size of segments here:
-        /// https://pages.cs.wisc.edu/~remzi/OSTEP/file-lfs.pdf
-        ///
-        /// TODO: optimize the size of the segment.
-        /// Note that this is much smaller than in sprite-lfs. sprite-lfs uses segments
-        /// of size 512KB ~ 1MB.
-        pub const SEGSIZE: usize = 10;
-
-        /// Size of the imap in blocks
-        pub const IMAPSIZE: usize = 1;
-
-        /// Size of the segment usage table in bytes
-        pub const SEGTABLESIZE: usize = 64;
-    } else {
-        /// Max data blocks in on-disk log.
-        pub const LOGSIZE: usize = MAXOPBLOCKS * 3;
-    }
-}
+// TODO: The following may be actually unknown at compile time.
+
+/// Size of a segment of the lfs in blocks
+///
+/// An optimal size of segments for LFS is dependent to
+/// the performance of a disk and a desired effective bandwith of developers.
+/// Check the formula for getting the size of segments here:
+/// https://pages.cs.wisc.edu/~remzi/OSTEP/file-lfs.pdf
+///
+/// TODO: optimize the size of the segment.
+/// Note that this is much smaller than in sprite-lfs. sprite-lfs uses segments
+/// of size 512KB ~ 1MB.
+pub const SEGSIZE: usize = 10;
+
+/// Size of the imap of the lfs in blocks
+pub const IMAPSIZE: usize = 1;
+
+/// Size of the segment usage table of the lfs in bytes
+pub const SEGTABLESIZE: usize = 64;
+
+/// Max data blocks in on-disk log of the ufs.
+pub const LOGSIZE: usize = MAXOPBLOCKS * 3;
+
+/// Default ticks that the ufs groups finished transactions into one commit.
+pub const COMMIT_TICKS: u32 = 5;
+
+/// Default ticks that a buffer may stay dirty before the flusher writes it back.
+pub const DIRTY_EXPIRE_TICKS: u32 = 30;
+
+/// Max # of blocks that a sequential disk read reads at once.
+pub const MAXREADBLOCKS: usize = 8;
 
 /// Size of disk block cache.
-pub const NBUF: usize = MAXOPBLOCKS * 3;
+/// Each disk may have a ufs or an lfs mounted, so it has room for the log of
+/// a ufs and a segment of an lfs for each disk, and the blocks of a sequential read.
+pub const NBUF: usize = (LOGSIZE + SEGSIZE) * NDISK + MAXREADBLOCKS;
+
+/// Maximum number of mounted file systems.
+pub const NMOUNT: usize = 8;
+
+/// Number of virtio disks. The device number of the first one is `ROOTDEV`.
+pub const NDISK: usize = 2;
+
+/// Device number of the tmpfs, which follows those of the disks.
+pub const TMPDEV: u32 = ROOTDEV + NDISK as u32;
+
+/// Maximum number of inodes of the tmpfs.
+pub const NTMPINODE: usize = 200;
+
+/// Device number of the procfs.
+pub const PROCDEV: u32 = TMPDEV + 1;
+
+/// Device number of the devfs.
+pub const DEVDEV: u32 = PROCDEV + 1;
 
 /// Maximum file path name.
-pub const MAXPATH: usize = 128;
+pub const MAXPATH: usize = 256;
+
+/// Maximum number of symbolic links followed in a path name.
+pub const MAXSYMLINKS: usize = 10;
 
 /// Maximum length of process name.
 pub const MAXPROCNAME: usize = 16;
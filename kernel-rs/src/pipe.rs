@@ -2,6 +2,7 @@ use core::{mem, ops::Deref, ptr::NonNull};
 
 use crate::{
     addr::UVAddr,
+    errno::Errno,
     file::{FileType, RcFile, SelectEvent},
     hal::hal,
     lock::SpinLock,
@@ -41,8 +42,13 @@ impl Pipe {
     /// Tries to read up to `n` bytes using `Pipe::try_read()`.
     /// If successfully read i > 0 bytes, wakeups the `write_waitchannel` and returns `Ok(i: usize)`.
     /// If the pipe was empty, sleeps at `read_waitchannel` and tries again after wakeup.
-    /// If an error happened, returns `Err(())`.
-    pub fn read(&self, addr: UVAddr, n: usize, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+    /// If the process was killed, returns `Err(EINTR)`.
+    pub fn read(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
         let mut inner = self.inner.lock();
         loop {
             match inner.try_read(addr, n, ctx) {
@@ -55,7 +61,7 @@ impl Pipe {
                     //DOC: piperead-sleep
                     self.read_waitchannel.sleep(&mut inner, ctx);
                 }
-                _ => return Err(()),
+                Err(e) => return Err(e.into()),
             }
         }
     }
@@ -65,8 +71,14 @@ impl Pipe {
     /// After successfully writing i >= 0 bytes, returns `Ok(i)`.
     /// Note that we may have i < `n` if an copy-in error happened.
     /// If the pipe was full, sleeps at `write_waitchannel` and tries again after wakeup.
-    /// If an error happened, returns `Err(())`.
-    pub fn write(&self, addr: UVAddr, n: usize, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+    /// If the read end was closed, returns `Err(EPIPE)`, and if the process was killed,
+    /// returns `Err(EINTR)`.
+    pub fn write(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, Errno> {
         let mut written = 0;
         let mut inner = self.inner.lock();
         loop {
@@ -84,7 +96,7 @@ impl Pipe {
                     self.read_waitchannel.wakeup(ctx.kernel());
                     return Ok(written + i);
                 }
-                _ => return Err(()),
+                Err(e) => return Err(e.into()),
             }
         }
     }
@@ -130,9 +142,9 @@ impl Deref for AllocatedPipe {
 }
 
 impl KernelCtx<'_, '_> {
-    pub fn allocate_pipe(&self) -> Result<(RcFile, RcFile), ()> {
+    pub fn allocate_pipe(&self) -> Result<(RcFile, RcFile), Errno> {
         let allocator = hal().kmem();
-        let page = allocator.alloc(None).ok_or(())?;
+        let page = allocator.alloc(None).ok_or(Errno::ENOMEM)?;
         let mut page = scopeguard::guard(page, |page| allocator.free(page));
         let ptr = page.as_uninit_mut();
 
@@ -195,13 +207,28 @@ impl AllocatedPipe {
 
 pub enum PipeError {
     WaitForIO,
-    InvalidStatus,
+    /// The other end has been closed.
+    Closed,
+    /// The process has been killed.
+    Killed,
     InvalidCopyin(usize),
 }
 
+impl From<PipeError> for Errno {
+    fn from(e: PipeError) -> Self {
+        match e {
+            PipeError::Closed => Errno::EPIPE,
+            PipeError::Killed => Errno::EINTR,
+            PipeError::WaitForIO => Errno::EAGAIN,
+            PipeError::InvalidCopyin(_) => Errno::EFAULT,
+        }
+    }
+}
+
 impl PipeInner {
     /// Tries to write up to `n` bytes.
-    /// If the process was killed, returns `Err(InvalidStatus)`.
+    /// If the read end was closed, returns `Err(Closed)`.
+    /// If the process was killed, returns `Err(Killed)`.
     /// If an copy-in error happened after successfully writing i >= 0 bytes, returns `Err(InvalidCopyIn(i))`.
     /// Otherwise, returns `Ok(i)` after successfully writing i >= 0 bytes.
     fn try_write(
@@ -211,8 +238,11 @@ impl PipeInner {
         ctx: &mut KernelCtx<'_, '_>,
     ) -> Result<usize, PipeError> {
         let mut ch = [0u8];
-        if !self.readopen || ctx.proc().killed() {
-            return Err(PipeError::InvalidStatus);
+        if !self.readopen {
+            return Err(PipeError::Closed);
+        }
+        if ctx.proc().killed() {
+            return Err(PipeError::Killed);
         }
         for i in 0..n {
             if self.nwrite == self.nread.wrapping_add(PIPESIZE as u32) {
@@ -236,7 +266,7 @@ impl PipeInner {
     /// Tries to read up to `n` bytes.
     /// If successful read i > 0 bytes, returns `Ok(i: usize)`.
     /// If the pipe was empty, returns `Err(WaitForIO)`.
-    /// If the process was killed, returns `Err(InvalidStatus)`.
+    /// If the process was killed, returns `Err(Killed)`.
     fn try_read(
         &mut self,
         addr: UVAddr,
@@ -246,7 +276,7 @@ impl PipeInner {
         //DOC: pipe-empty
         if self.nread == self.nwrite && self.writeopen {
             if ctx.proc().killed() {
-                return Err(PipeError::InvalidStatus);
+                return Err(PipeError::Killed);
             }
             return Err(PipeError::WaitForIO);
         }
@@ -280,25 +310,24 @@ impl PipeInner {
 
 impl KernelCtx<'_, '_> {
     /// Create a pipe, put read/write file descriptors in fd0 and fd1.
-    /// Returns Ok(()) on success, Err(()) on error.
-    pub fn pipe(&mut self, fdarray: UVAddr) -> Result<(), ()> {
+    /// Returns Ok(()) on success, Err(errno) on error.
+    pub fn pipe(&mut self, fdarray: UVAddr) -> Result<(), Errno> {
         let (pipereader, pipewriter) = self.allocate_pipe()?;
 
-        let fd1 = if let Ok(fd) = pipereader.fdalloc(self) {
-            fd
-        } else {
-            pipewriter.free(self);
-            return Err(());
+        let fd1 = match pipereader.fdalloc(self) {
+            Ok(fd) => fd,
+            Err(e) => {
+                pipewriter.free(self);
+                return Err(e);
+            }
         };
 
-        let fd2 = if let Ok(fd) = pipewriter.fdalloc(self) {
-            fd
-        } else {
-            self.proc_mut().deref_mut_data().open_files[fd1 as usize]
-                .take()
-                .unwrap()
-                .free(self);
-            return Err(());
+        let fd2 = match pipewriter.fdalloc(self) {
+            Ok(fd) => fd,
+            Err(e) => {
+                self.proc().files().take(fd1).unwrap().free(self);
+                return Err(e);
+            }
         };
 
         self.proc_mut().memory_mut().copy_out(fdarray, &[fd1, fd2])
@@ -4,9 +4,10 @@ use super::*;
 use crate::{
     arch::interface::ProcManager,
     arch::TargetArch,
-    fs::DefaultFs,
+    file::ProcFiles,
     kernel::{kernel_ref, KernelRef},
-    vm::UserMemory,
+    memlayout::TRAPFRAME,
+    vm::{RcAddressSpace, UserMemoryGuard},
 };
 
 /// Type that stores the context of the current thread. Consists of
@@ -59,9 +60,17 @@ impl<'id, 'p> KernelCtx<'id, 'p> {
     // Its name cannot be `yield` because `yield` is a reserved keyword.
     pub fn yield_cpu(&self) {
         let mut guard = self.proc.lock();
-        guard.deref_mut_info().state = Procstate::RUNNABLE;
+        self.kernel.procs().make_runnable(&mut guard);
         unsafe { guard.sched() };
     }
+
+    /// Charge a clock tick to the current process, and give up the CPU
+    /// if the scheduler says so.
+    pub fn tick(&self) {
+        if self.kernel.procs().charge_tick(&mut self.proc.lock()) {
+            self.yield_cpu();
+        }
+    }
 }
 
 /// Creates the `KernelCtx` of the current Cpu.
@@ -109,28 +118,55 @@ impl<'id, 'p> CurrentProc<'id, 'p> {
         unsafe { &mut *self.deref_mut_data().trap_frame }
     }
 
-    pub fn memory(&self) -> &UserMemory {
+    /// Returns the address of the trap frame in the user memory.
+    pub fn trap_frame_va(&self) -> usize {
+        self.deref_data().trap_frame_va
+    }
+
+    /// Locks the user memory, which the other threads of the process may share.
+    /// The lock is held until the returned guard is dropped, so the guard
+    /// should not be kept across a sleep.
+    pub fn memory(&self) -> UserMemoryGuard<'_> {
         // SAFETY: memory has been initialized according to the invariants
         // of Proc and CurrentProc.
-        unsafe { self.deref_data().memory.assume_init_ref() }
+        unsafe { self.deref_data().memory.assume_init_ref() }.lock()
     }
 
-    pub fn memory_mut(&mut self) -> &mut UserMemory {
+    pub fn memory_mut(&mut self) -> UserMemoryGuard<'_> {
+        self.memory()
+    }
+
+    /// Replace the user memory with `memory`, which must map the trap frame
+    /// at TRAPFRAME. The old memory may still be used by the other threads.
+    pub fn replace_memory(&mut self, memory: RcAddressSpace) {
+        let data = self.deref_mut_data();
         // SAFETY: memory has been initialized according to the invariants
         // of Proc and CurrentProc.
-        unsafe { self.deref_mut_data().memory.assume_init_mut() }
+        let old = mem::replace(unsafe { data.memory.assume_init_mut() }, memory);
+        old.lock().unmap_trap_frame(data.trap_frame_va);
+        old.free(());
+        data.trap_frame_va = TRAPFRAME;
     }
 
-    pub fn cwd(&self) -> &RcInode<DefaultFs> {
-        // SAFETY: cwd has been initialized according to the invariants
-        // of Proc and CurrentProc.
-        unsafe { self.deref_data().cwd.assume_init_ref() }
+    /// Make this thread the first thread of a new process.
+    /// Returns the id of the process that it belonged to.
+    pub fn become_process(&mut self) -> Pid {
+        self.deref_mut_data().clear_tid = 0;
+        let mut info = self.info.lock();
+        let pid = info.pid;
+        mem::replace(&mut info.tgid, pid)
     }
 
-    pub fn cwd_mut(&mut self) -> &mut RcInode<DefaultFs> {
-        // SAFETY: cwd has been initialized according to the invariants
+    /// Returns the open files and the current directory.
+    pub fn files(&self) -> &ProcFiles {
+        // SAFETY: files has been initialized according to the invariants
         // of Proc and CurrentProc.
-        unsafe { self.deref_mut_data().cwd.assume_init_mut() }
+        unsafe { self.deref_data().files.assume_init_ref() }
+    }
+
+    /// Returns the user and group ids.
+    pub fn cred(&self) -> Cred {
+        self.info.lock().cred
     }
 }
 
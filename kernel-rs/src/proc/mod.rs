@@ -6,20 +6,22 @@ use core::{
     sync::atomic::{AtomicBool, Ordering},
 };
 
-use array_macro::array;
 use derive_more::Deref;
 
 use crate::{
     arch::interface::{ContextManager, ProcManager, TrapManager},
     arch::TargetArch,
-    file::RcFile,
-    fs::{DefaultFs, RcInode},
+    errno::Errno,
+    file::RcProcFiles,
+    fs::Stat,
     hal::hal,
     lock::SpinLock,
     page::Page,
-    param::{MAXPROCNAME, NOFILE},
+    param::MAXPROCNAME,
+    sched::{DefaultEntity, Placement, RunNode},
+    signal::SigState,
     util::branded::Branded,
-    vm::UserMemory,
+    vm::RcAddressSpace,
 };
 
 mod kernel_ctx;
@@ -49,6 +51,20 @@ pub enum Procstate {
 
 type Pid = i32;
 
+/// The user and group ids of a process.
+#[derive(Copy, Clone)]
+pub struct Cred {
+    /// Real user and group ids, which tell who started the process.
+    pub uid: u32,
+    pub gid: u32,
+
+    /// Effective user and group ids, which decide what the process may do.
+    /// They differ from the real ids while running a set-user-id or a
+    /// set-group-id program.
+    pub euid: u32,
+    pub egid: u32,
+}
+
 /// Proc::info's spinlock must be held when using these.
 pub struct ProcInfo {
     /// Process state.
@@ -62,6 +78,29 @@ pub struct ProcInfo {
 
     /// Process ID.
     pid: Pid,
+
+    /// Process ID of the first thread of the process. Equals `pid` unless
+    /// this is a thread created by clone.
+    tgid: Pid,
+
+    /// Pending and blocked signals, and signal actions.
+    pub signals: SigState,
+
+    /// Scheduling state, including the nice value.
+    pub sched: DefaultEntity,
+
+    /// The CPUs that may run the process, and the CPU that ran it last.
+    pub placement: Placement,
+
+    /// User and group ids.
+    pub cred: Cred,
+
+    /// Process name (debugging). Kept here rather than in `ProcData`, so that
+    /// other processes can read it.
+    pub name: [u8; MAXPROCNAME],
+
+    /// Set when the process starts exiting. Its `data.files` is not valid anymore.
+    exiting: bool,
 }
 
 /// Proc::data are private to the process, so lock need not be held.
@@ -72,20 +111,42 @@ pub struct ProcData {
     /// Data page for trampoline.S.
     trap_frame: *mut <TargetArch as ProcManager>::TrapFrame,
 
-    /// User memory manager
-    memory: MaybeUninit<UserMemory>,
+    /// Address of the trap frame in the user memory.
+    trap_frame_va: usize,
+
+    /// User memory manager, shared by the threads of the process.
+    memory: MaybeUninit<RcAddressSpace>,
 
     /// swtch() here to run process.
     context: Context,
 
-    /// Open files.
-    pub open_files: [Option<RcFile>; NOFILE],
+    /// Open files and current directory, shared by the threads of the process.
+    files: MaybeUninit<RcProcFiles>,
+
+    /// If non-zero, the user address to clear and wake futex waiters on at exit.
+    clear_tid: usize,
+}
+
+/// A snapshot of a process, as shown by the procfs.
+pub struct ProcStat {
+    pub pid: Pid,
 
-    /// Current directory.
-    cwd: MaybeUninit<RcInode<DefaultFs>>,
+    /// Process ID of the parent, or 0 if there is none.
+    pub ppid: Pid,
+
+    pub state: Procstate,
 
-    /// Process name (debugging).
     pub name: [u8; MAXPROCNAME],
+
+    /// Size of the user memory in bytes. `None` if the process is running on
+    /// another CPU or has exited, since its memory cannot be looked up then.
+    pub size: Option<usize>,
+
+    /// Open file descriptors as a bitmask. `None` for the same reason as `size`.
+    pub fds: Option<u64>,
+
+    /// The current directory. `None` for the same reason as `size`.
+    pub cwd: Option<Stat>,
 }
 
 /// Per-process state.
@@ -94,9 +155,9 @@ pub struct ProcData {
 ///
 /// * If `info.state` ≠ `UNUSED`, then
 ///   - `data.trap_frame` is a valid pointer, and `Page::from_usize(data.trap_frame)` is safe.
-///   - `data.memory` has been initialized.
-/// * If `info.state` ∉ { `UNUSED`, `USED` }, then
-///   - `data.cwd` has been initialized.
+///   - `data.memory` has been initialized, and it maps `data.trap_frame` at `data.trap_frame_va`.
+/// * If `info.state` ∉ { `UNUSED`, `USED` } and `info.exiting` is false, then
+///   - `data.files` has been initialized.
 ///   - `parent` contains null or a valid pointer. `parent` can be null only when `self` is the same
 ///     as `initial_proc` of `Procs` that contains `self`.
 pub struct Proc {
@@ -107,6 +168,12 @@ pub struct Proc {
 
     data: UnsafeCell<ProcData>,
 
+    /// Links the process in a run queue while it is RUNNABLE.
+    run_node: UnsafeCell<RunNode>,
+
+    /// Links the process in the sleepers of the waitchannel it sleeps on.
+    wait_node: UnsafeCell<WaitNode>,
+
     /// Waitchannel saying child proc is dead.
     child_waitchannel: WaitChannel,
 
@@ -129,7 +196,7 @@ pub struct ProcGuard<'id, 's> {
 }
 
 impl Procstate {
-    fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             Procstate::USED => "used",
             Procstate::UNUSED => "unused",
@@ -141,16 +208,71 @@ impl Procstate {
     }
 }
 
+impl Cred {
+    /// The ids of the superuser, with which the first process starts.
+    pub const fn root() -> Self {
+        Self {
+            uid: 0,
+            gid: 0,
+            euid: 0,
+            egid: 0,
+        }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.euid == 0
+    }
+
+    /// Returns true if a process with these ids may signal or change the
+    /// priority of a process with the ids `target`, which it may if it is the
+    /// superuser, or if its real or effective user id is one of those of `target`.
+    pub fn may_control(&self, target: &Cred) -> bool {
+        self.is_root()
+            || [self.uid, self.euid]
+                .iter()
+                .any(|uid| *uid == target.uid || *uid == target.euid)
+    }
+
+    /// Sets the user ids to `uid`. The superuser sets both the real and the
+    /// effective ids, and the others may only set the effective id back to
+    /// the real one, or to itself.
+    /// Returns Err(EPERM) if it is not allowed.
+    pub fn setuid(&mut self, uid: u32) -> Result<(), Errno> {
+        if self.is_root() {
+            self.uid = uid;
+            self.euid = uid;
+        } else if uid == self.uid || uid == self.euid {
+            self.euid = uid;
+        } else {
+            return Err(Errno::EPERM);
+        }
+        Ok(())
+    }
+
+    /// Sets the group ids to `gid`, with the same rules as `setuid`.
+    pub fn setgid(&mut self, gid: u32) -> Result<(), Errno> {
+        if self.is_root() {
+            self.gid = gid;
+            self.egid = gid;
+        } else if gid == self.gid || gid == self.egid {
+            self.egid = gid;
+        } else {
+            return Err(Errno::EPERM);
+        }
+        Ok(())
+    }
+}
+
 impl ProcData {
     const fn new() -> Self {
         Self {
             kstack: 0,
             trap_frame: ptr::null_mut(),
+            trap_frame_va: 0,
             memory: MaybeUninit::uninit(),
             context: Context::new(),
-            open_files: array![_ => None; NOFILE],
-            cwd: MaybeUninit::uninit(),
-            name: [0; MAXPROCNAME],
+            files: MaybeUninit::uninit(),
+            clear_tid: 0,
         }
     }
 }
@@ -166,9 +288,19 @@ impl Proc {
                     waitchannel: ptr::null(),
                     xstate: 0,
                     pid: 0,
+                    tgid: 0,
+                    signals: SigState::new(),
+                    sched: DefaultEntity::new(),
+                    placement: Placement::new(),
+                    cred: Cred::root(),
+                    name: [0; MAXPROCNAME],
+                    exiting: false,
                 },
             ),
             data: UnsafeCell::new(ProcData::new()),
+            // SAFETY: `Procs::init` initializes it.
+            run_node: UnsafeCell::new(unsafe { RunNode::new() }),
+            wait_node: UnsafeCell::new(WaitNode::new()),
             child_waitchannel: WaitChannel::new(),
             killed: AtomicBool::new(false),
         }
@@ -221,6 +353,16 @@ impl<'id> ProcGuard<'id, '_> {
         unsafe { &mut *self.info.get_mut_raw() }
     }
 
+    /// Returns a reference to its `ProcData`.
+    ///
+    /// # Safety
+    ///
+    /// The process must not be running, so that no `CurrentProc` refers to
+    /// the same `Proc` and modifies the `ProcData` meanwhile.
+    unsafe fn deref_data(&self) -> &ProcData {
+        unsafe { &*self.data.get() }
+    }
+
     /// This method returns a mutable reference to its `ProcData`. There is no
     /// data race between `ProcGuard`s since this method can be called only after
     /// acquiring the lock of `info`. However, `CurrentProc` can create a mutable
@@ -269,21 +411,19 @@ impl<'id> ProcGuard<'id, '_> {
     unsafe fn clear(&mut self, mut parent_guard: WaitGuard<'id, '_>) {
         // SAFETY: this process cannot be the current process any longer.
         let data = unsafe { self.deref_mut_data() };
-        let trap_frame = mem::replace(&mut data.trap_frame, ptr::null_mut());
-        let allocator = hal().kmem();
-        // SAFETY: trap_frame uniquely refers to a valid page.
-        allocator.free(unsafe { Page::from_usize(trap_frame as _) });
         // SAFETY:
         // * ok to assume_init() because memory has been initialized according to the invariant.
         // * ok to replace memory with uninit() because state will become UNUSED.
-        unsafe {
-            mem::replace(&mut data.memory, MaybeUninit::uninit())
-                .assume_init()
-                .free(allocator)
-        };
-
-        // Clear the name.
-        data.name[0] = 0;
+        let memory = unsafe { mem::replace(&mut data.memory, MaybeUninit::uninit()).assume_init() };
+        // Other threads may still use the memory, so give back the trap frame's page in it.
+        memory.lock().unmap_trap_frame(data.trap_frame_va);
+        memory.free(());
+        let trap_frame = mem::replace(&mut data.trap_frame, ptr::null_mut());
+        let allocator = hal().kmem();
+        // SAFETY: trap_frame uniquely refers to a valid page, which is not mapped anymore.
+        allocator.free(unsafe { Page::from_usize(trap_frame as _) });
+        data.trap_frame_va = 0;
+        data.clear_tid = 0;
 
         // Clear the process's parent field.
         *self.get_mut_parent(&mut parent_guard) = ptr::null_mut();
@@ -293,16 +433,32 @@ impl<'id> ProcGuard<'id, '_> {
         let info = self.deref_mut_info();
         info.waitchannel = ptr::null();
         info.pid = 0;
+        info.tgid = 0;
         info.xstate = 0;
+        info.signals = SigState::new();
+        info.sched = DefaultEntity::new();
+        info.placement = Placement::new();
+        info.cred = Cred::root();
+        info.name[0] = 0;
+        info.exiting = false;
         info.state = Procstate::UNUSED;
 
         self.killed.store(false, Ordering::Release);
     }
 
-    /// Wake process from sleep().
-    fn wakeup(&mut self) {
+    /// Post signal `sig` to the process. If the signal terminates the process,
+    /// kill and wake it up.
+    fn signal(&mut self, sig: usize, procs: &ProcsRef<'id, '_>) {
+        if self.deref_mut_info().signals.post(sig) {
+            self.kill();
+            self.wakeup(procs);
+        }
+    }
+
+    /// Wake process from sleep(), and hand it to the scheduler of `procs`.
+    fn wakeup(&mut self, procs: &ProcsRef<'id, '_>) {
         if self.state() == Procstate::SLEEPING {
-            self.deref_mut_info().state = Procstate::RUNNABLE;
+            procs.make_runnable(self);
         }
     }
 
@@ -310,6 +466,15 @@ impl<'id> ProcGuard<'id, '_> {
         self.deref_info().state
     }
 
+    /// Returns true if this is a thread created by clone.
+    fn is_thread(&self) -> bool {
+        self.deref_info().tgid != self.deref_info().pid
+    }
+
+    fn is_zombie_thread(&self) -> bool {
+        self.state() == Procstate::ZOMBIE && self.is_thread()
+    }
+
     fn reacquire_after<F, U>(&mut self, f: F) -> U
     where
         F: FnOnce(ProcRef<'id, '_>) -> U,
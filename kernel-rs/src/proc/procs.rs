@@ -3,27 +3,35 @@ use core::{
     ops::Deref,
     pin::Pin,
     ptr, str,
-    sync::atomic::{AtomicI32, Ordering},
+    sync::atomic::{AtomicI32, AtomicUsize, Ordering},
 };
 
 use array_macro::array;
-use itertools::izip;
+use arrayvec::ArrayVec;
 use pin_project::pin_project;
 
 use super::*;
 use crate::{
     addr::{Addr, UVAddr, PGSIZE},
     arch::interface::TrapFrameManager,
-    fs::{DefaultFs, FileSystem, FileSystemExt},
+    cpu::cpuid,
+    errno::Errno,
+    file::ProcFilesTable,
+    fs::{RcVnode, ROOTFS},
     hal::hal,
     kalloc::Kmem,
     kernel::KernelRef,
     lock::{SpinLock, SpinLockGuard},
-    memlayout::kstack,
+    memlayout::{kstack, TRAPFRAME},
+    ok_or,
     page::Page,
-    param::{NPROC, ROOTDEV},
-    util::branded::Branded,
-    vm::UserMemory,
+    param::{NCPU, NPROC, ROOTDEV},
+    sched::{DefaultScheduler, RunNode, SchedEntity, Scheduler},
+    signal::{SIGCHLD, SIGKILL},
+    some_or,
+    util::{branded::Branded, strong_pin::StrongPin},
+    vm::{AddressSpaceTable, RcAddressSpace, UserMemory},
+    writeback::flusher,
 };
 
 /// Process system type containing & managing whole processes.
@@ -43,6 +51,16 @@ pub struct Procs {
     // memory model when using p->parent.
     // Must be acquired before any p->lock.
     wait_lock: SpinLock<()>,
+    /// Woken up when a thread exits, for `kill_threads`. Sleep on it with
+    /// `wait_lock` held.
+    thread_waitchannel: WaitChannel,
+    /// The run queue of each CPU, which picks the next process to run among
+    /// the runnable ones. Must be acquired after any p->lock, and at most one
+    /// at a time.
+    #[pin]
+    run_queues: [SpinLock<DefaultScheduler>; NCPU],
+    /// The CPUs that have started scheduling, as a bitmask.
+    online: AtomicUsize,
     #[pin]
     _marker: PhantomPinned,
 }
@@ -74,6 +92,13 @@ impl Procs {
             process_pool: array![_ => Proc::new(); NPROC],
             initial_proc: ptr::null(),
             wait_lock: SpinLock::new("wait_lock", ()),
+            thread_waitchannel: WaitChannel::new(),
+            // SAFETY: `Procs::init` initializes them.
+            run_queues: array![
+                _ => SpinLock::new("run_queue", unsafe { DefaultScheduler::new() });
+                NCPU
+            ],
+            online: AtomicUsize::new(0),
             _marker: PhantomPinned,
         }
     }
@@ -84,13 +109,23 @@ impl Procs {
         let this = unsafe { self.get_unchecked_mut() };
         for (i, p) in this.process_pool.iter_mut().enumerate() {
             p.data.get_mut().kstack = kstack(i);
+            // SAFETY: the process pool is pinned.
+            unsafe { Pin::new_unchecked(p.run_node.get_mut()) }.init(i);
+        }
+        for run_queue in &mut this.run_queues {
+            // SAFETY: the run queues are pinned.
+            unsafe { Pin::new_unchecked(run_queue) }
+                .get_pin_mut()
+                .init();
         }
     }
 
     /// Set up first user process.
     pub fn user_proc_init(
         self: Pin<&mut Self>,
-        cwd: RcInode<DefaultFs>,
+        cwd: RcVnode,
+        address_spaces: StrongPin<'_, AddressSpaceTable>,
+        proc_files: StrongPin<'_, ProcFilesTable>,
         allocator: Pin<&SpinLock<Kmem>>,
     ) {
         let initial_proc = Branded::new(self.as_ref(), |procs| {
@@ -110,9 +145,20 @@ impl Procs {
                 allocator,
             )
             .expect("user_proc_init: UserMemory::new");
+            let memory = address_spaces
+                .alloc_space(memory)
+                .expect("user_proc_init: alloc_space");
+            let files = ok_or!(
+                proc_files.alloc_files(cwd),
+                panic!("user_proc_init: alloc_files")
+            );
 
             let mut guard = procs
-                .alloc(scopeguard::ScopeGuard::into_inner(trap_frame), memory)
+                .alloc(
+                    scopeguard::ScopeGuard::into_inner(trap_frame),
+                    TRAPFRAME,
+                    memory,
+                )
                 .expect("user_proc_init: Procs::alloc");
 
             // SAFETY: this process cannot be the current process yet.
@@ -132,11 +178,12 @@ impl Procs {
             // SAFETY: trap_frame has been initialized by alloc.
             unsafe { (*data.trap_frame).init_reg() };
 
+            let _ = data.files.write(files);
+            // It's safe because files now has been initialized.
+
             let name = b"initcode\x00";
-            (&mut data.name[..name.len()]).copy_from_slice(name);
-            let _ = data.cwd.write(cwd);
-            // It's safe because cwd now has been initialized.
-            guard.deref_mut_info().state = Procstate::RUNNABLE;
+            (&mut guard.deref_mut_info().name[..name.len()]).copy_from_slice(name);
+            procs.make_runnable(&mut guard);
 
             guard.deref().deref() as *const _
         });
@@ -147,6 +194,54 @@ impl Procs {
         *self.project().initial_proc = initial_proc;
     }
 
+    /// Set up the flusher, a kernel process that writes back the file systems.
+    /// Its user memory has only the trap frame, and it never returns to user space.
+    pub fn flusher_init(
+        self: Pin<&Self>,
+        cwd: RcVnode,
+        address_spaces: StrongPin<'_, AddressSpaceTable>,
+        proc_files: StrongPin<'_, ProcFilesTable>,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) {
+        Branded::new(self, |procs| {
+            let procs = ProcsRef(procs);
+
+            let trap_frame = scopeguard::guard(
+                allocator.alloc(None).expect("flusher_init: alloc"),
+                |page| allocator.free(page),
+            );
+            let memory = UserMemory::new(trap_frame.addr(), None, allocator)
+                .expect("flusher_init: UserMemory::new");
+            let memory = address_spaces
+                .alloc_space(memory)
+                .expect("flusher_init: alloc_space");
+            let files = ok_or!(
+                proc_files.alloc_files(cwd),
+                panic!("flusher_init: alloc_files")
+            );
+
+            let mut guard = procs
+                .alloc(
+                    scopeguard::ScopeGuard::into_inner(trap_frame),
+                    TRAPFRAME,
+                    memory,
+                )
+                .expect("flusher_init: Procs::alloc");
+
+            // SAFETY: this process cannot be the current process yet.
+            let data = unsafe { guard.deref_mut_data() };
+
+            // Start executing at flusherret instead of forkret.
+            data.context.set_ret_addr(flusherret as usize);
+
+            let _ = data.files.write(files);
+
+            let name = b"flusher\x00";
+            (&mut guard.deref_mut_info().name[..name.len()]).copy_from_slice(name);
+            procs.make_runnable(&mut guard);
+        });
+    }
+
     fn initial_proc(self: Pin<&Self>) -> &Proc {
         assert!(!self.initial_proc.is_null());
         // SAFETY: invariant
@@ -169,11 +264,100 @@ impl<'id, 's> ProcsRef<'id, 's> {
         WaitGuard(self.0.brand(self.0.get_ref().wait_lock.lock()))
     }
 
+    fn run_queue(&self, cpu: usize) -> Pin<&'s SpinLock<DefaultScheduler>> {
+        // SAFETY: the run queues are pinned together with the `Procs`.
+        unsafe { Pin::new_unchecked(&self.0.get_ref().run_queues[cpu]) }
+    }
+
+    /// Returns the run queue link of the locked process.
+    ///
+    /// # Safety
+    ///
+    /// The process must be in no run queue, and the returned reference must
+    /// be the only reference to its node.
+    unsafe fn run_node<'a>(guard: &ProcGuard<'id, 'a>) -> Pin<&'a mut RunNode> {
+        // SAFETY: the process pool is pinned, and the node is accessed only
+        // with p->lock held while it is in no run queue.
+        unsafe { Pin::new_unchecked(&mut *guard.run_node.get()) }
+    }
+
+    /// Mark the current CPU as ready to run processes.
+    fn set_online(&self, cpu: usize) {
+        let _ = self.0.online.fetch_or(1 << cpu, Ordering::AcqRel);
+    }
+
+    /// Returns the CPUs that have started scheduling, as a bitmask.
+    pub fn online(&self) -> usize {
+        self.0.online.load(Ordering::Acquire)
+    }
+
+    /// Returns the index of the locked process in the process pool.
+    fn index(&self, guard: &ProcGuard<'id, '_>) -> usize {
+        let pool = &self.0.get_ref().process_pool;
+        let p: &Proc = guard;
+        // SAFETY: `p` is in `pool` since `guard` has the same `'id` tag.
+        unsafe { (p as *const Proc).offset_from(pool.as_ptr()) as usize }
+    }
+
+    /// Make the locked process runnable, and hand it to the run queue of the
+    /// CPU that ran it last, or of another CPU its affinity allows.
+    /// The process must be in no run queue: it is not RUNNABLE, or the
+    /// scheduler has just taken it off a run queue.
+    pub fn make_runnable(&self, guard: &mut ProcGuard<'id, '_>) {
+        // SAFETY: the process is in no run queue.
+        let mut node = unsafe { Self::run_node(guard) };
+        let info = guard.deref_mut_info();
+        info.state = Procstate::RUNNABLE;
+        node.as_mut().set_affinity(info.placement.affinity);
+        self.run_queue(info.placement.target(self.online()))
+            .pinned_lock()
+            .get_pin_mut()
+            .enqueue(node, &mut info.sched);
+    }
+
+    /// Charge a clock tick to the locked running process.
+    /// Returns true if it should give up the CPU.
+    pub fn charge_tick(&self, guard: &mut ProcGuard<'id, '_>) -> bool {
+        let info = guard.deref_mut_info();
+        // We hold p->lock, so interrupts are off and cpuid() is stable.
+        self.run_queue(cpuid())
+            .pinned_lock()
+            .get_pin_mut()
+            .tick(&mut info.sched)
+    }
+
+    /// Take the next process to run on CPU `cpu` off its run queue.
+    /// If the queue is empty, steal a process from another CPU.
+    fn pick_next(&self, cpu: usize) -> Option<ProcRef<'id, 's>> {
+        let mut index = self.run_queue(cpu).pinned_lock().get_pin_mut().dequeue();
+        if index.is_none() {
+            // Hold one run queue lock at a time, so that idle CPUs stealing
+            // from each other cannot deadlock.
+            index = (1..NCPU)
+                .map(|i| (cpu + i) % NCPU)
+                .filter(|victim| self.online() & (1 << victim) != 0)
+                .find_map(|victim| {
+                    self.run_queue(victim)
+                        .pinned_lock()
+                        .get_pin_mut()
+                        .steal(cpu)
+                });
+        }
+        let index = index?;
+        Some(ProcRef(self.0.brand(&self.0.get_ref().process_pool[index])))
+    }
+
     /// Look into process system for an UNUSED proc.
     /// If found, initialize state required to run in the kernel,
-    /// and return with p->lock held.
+    /// and return with p->lock held. `memory` must map `trap_frame` at `trap_frame_va`.
     /// If there are no free procs, or a memory allocation fails, return Err.
-    fn alloc(&self, trap_frame: Page, memory: UserMemory) -> Result<ProcGuard<'id, '_>, ()> {
+    fn alloc(
+        &self,
+        trap_frame: Page,
+        trap_frame_va: usize,
+        memory: RcAddressSpace,
+    ) -> Result<ProcGuard<'id, '_>, ()> {
+        self.reap_threads();
         for p in self.process_pool() {
             let mut guard = p.lock();
             if guard.deref_info().state == Procstate::UNUSED {
@@ -182,6 +366,7 @@ impl<'id, 's> ProcsRef<'id, 's> {
 
                 // Initialize trap frame and page table.
                 data.trap_frame = trap_frame.into_usize() as _;
+                data.trap_frame_va = trap_frame_va;
                 let _ = data.memory.write(memory);
 
                 // Set up new context to start executing at forkret,
@@ -192,6 +377,7 @@ impl<'id, 's> ProcsRef<'id, 's> {
 
                 let info = guard.deref_mut_info();
                 info.pid = self.0.allocpid();
+                info.tgid = info.pid;
                 // It's safe because trap_frame and memory now have been initialized.
                 info.state = Procstate::USED;
 
@@ -199,26 +385,44 @@ impl<'id, 's> ProcsRef<'id, 's> {
             }
         }
 
-        let allocator = hal().kmem();
-        allocator.free(trap_frame);
-        memory.free(allocator);
+        memory.lock().unmap_trap_frame(trap_frame_va);
+        memory.free(());
+        hal().kmem().free(trap_frame);
         Err(())
     }
 
-    /// Wake up all processes in the pool sleeping on waitchannel.
-    /// Must be called without any p->lock.
-    pub fn wakeup_pool(&self, target: &WaitChannel, kernel: KernelRef<'_, '_>) {
-        let current_proc = kernel.current_proc();
+    /// Free the threads that have exited. Unlike processes, threads are not
+    /// waited for by their parents.
+    fn reap_threads(&self) {
         for p in self.process_pool() {
-            if p.deref() as *const _ != current_proc {
-                let mut guard = p.lock();
-                if guard.deref_info().waitchannel == target as _ {
-                    guard.wakeup()
-                }
+            if !p.lock().is_zombie_thread() {
+                continue;
+            }
+            let parent_guard = self.wait_guard();
+            let mut guard = p.lock();
+            // Another CPU may have reaped it in the meantime.
+            if guard.is_zombie_thread() {
+                // SAFETY: guard.state() equals ZOMBIE.
+                unsafe { guard.clear(parent_guard) };
             }
         }
     }
 
+    /// Wake up `p`, which has been taken off the sleepers of `target`, if it
+    /// still sleeps on `target`. Returns true if it woke up.
+    /// Must be called without any p->lock.
+    pub fn wakeup_sleeper(&self, p: *const Proc, target: &WaitChannel) -> bool {
+        // SAFETY: sleepers are in the process pool.
+        let p = ProcRef(self.0.brand(unsafe { &*p }));
+        let mut guard = p.lock();
+        if guard.state() == Procstate::SLEEPING && guard.deref_info().waitchannel == target as _ {
+            guard.wakeup(self);
+            true
+        } else {
+            false
+        }
+    }
+
     /// Pass p's abandoned children to init.
     /// Caller must provide a `SpinLockGuard`.
     fn reparent<'a: 'b, 'b>(
@@ -238,17 +442,18 @@ impl<'id, 's> ProcsRef<'id, 's> {
 
     /// Create a new process, copying the parent.
     /// Sets up child kernel stack to return as if from fork() system call.
-    /// Returns Ok(new process id) on success, Err(()) on error.
+    /// Returns Ok(new process id) on success, Err(ENOMEM) if memory runs out,
+    /// or Err(EAGAIN) if there are no free procs.
     ///
     /// # Note
     ///
     /// `self` and `ctx` must have the same `'id` tag attached.
     /// Otherwise, UB may happen if the new `Proc` tries to read its `parent` field
     /// that points to a `Proc` that already dropped.
-    pub fn fork(&self, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, ()> {
+    pub fn fork(&self, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, Errno> {
         let allocator = hal().kmem();
         // Allocate trap frame.
-        let trap_frame = scopeguard::guard(allocator.alloc(None).ok_or(())?, |page| {
+        let trap_frame = scopeguard::guard(allocator.alloc(None).ok_or(Errno::ENOMEM)?, |page| {
             allocator.free(page)
         });
 
@@ -257,10 +462,48 @@ impl<'id, 's> ProcsRef<'id, 's> {
             .proc_mut()
             .memory_mut()
             .clone(trap_frame.addr(), allocator)
-            .ok_or(())?;
+            .ok_or(Errno::ENOMEM)?;
+        let memory = ctx
+            .kernel()
+            .address_spaces()
+            .alloc_space(memory)
+            .map_err(|_| Errno::EAGAIN)?;
+
+        // Increment reference counts on open file descriptors.
+        let files = ok_or!(
+            ctx.kernel().proc_files().dup_files(ctx.proc().files(), ctx),
+            {
+                memory.free(());
+                return Err(Errno::EAGAIN);
+            }
+        );
+
+        // The child inherits the signal actions, the blocked mask, the nice value,
+        // the affinity, the user and group ids, and the name. It starts on the
+        // current CPU.
+        let (signals, sched, placement, cred, name) = {
+            let info = ctx.proc().info.lock();
+            (
+                info.signals.fork(),
+                info.sched.fork(),
+                info.placement,
+                info.cred,
+                info.name,
+            )
+        };
 
         // Allocate process.
-        let mut np = self.alloc(scopeguard::ScopeGuard::into_inner(trap_frame), memory)?;
+        let mut np = ok_or!(
+            self.alloc(
+                scopeguard::ScopeGuard::into_inner(trap_frame),
+                TRAPFRAME,
+                memory
+            ),
+            {
+                files.free(ctx);
+                return Err(Errno::EAGAIN);
+            }
+        );
         // SAFETY: this process cannot be the current process yet.
         let npdata = unsafe { np.deref_mut_data() };
 
@@ -272,20 +515,15 @@ impl<'id, 's> ProcsRef<'id, 's> {
         // SAFETY: trap_frame has been initialized by alloc.
         unsafe { (*npdata.trap_frame).set_ret_val(0) };
 
-        // Increment reference counts on open file descriptors.
-        for (nf, f) in izip!(
-            npdata.open_files.iter_mut(),
-            ctx.proc().deref_data().open_files.iter()
-        ) {
-            if let Some(file) = f {
-                *nf = Some(file.clone());
-            }
-        }
-        let _ = npdata.cwd.write(ctx.proc().cwd().clone());
-
-        npdata.name.copy_from_slice(&ctx.proc().deref_data().name);
+        let _ = npdata.files.write(files);
 
-        let pid = np.deref_mut_info().pid;
+        let info = np.deref_mut_info();
+        info.signals = signals;
+        info.sched = sched;
+        info.placement = placement;
+        info.cred = cred;
+        info.name = name;
+        let pid = info.pid;
 
         // Now drop the guard before we acquire the `wait_lock`.
         // This is because the lock order must be `wait_lock` -> `Proc::info`.
@@ -296,15 +534,123 @@ impl<'id, 's> ProcsRef<'id, 's> {
         });
 
         // Set the process's state to RUNNABLE.
-        // It does not break the invariant because cwd now has been initialized.
-        np.deref_mut_info().state = Procstate::RUNNABLE;
+        // It does not break the invariant because files now has been initialized.
+        self.make_runnable(&mut np);
+
+        Ok(pid)
+    }
+
+    /// Create a thread that shares the user memory, the open files, and the
+    /// current directory with the current process. The thread starts at `pc`
+    /// with the stack pointer `sp` and `arg` as its first argument, and it has
+    /// a copy of the signal actions, the blocked mask, the nice value, and the
+    /// user and group ids.
+    /// If `ctid` is non-zero, the thread id is stored at `ctid` before the
+    /// thread starts, and the thread clears it and wakes the futex at `ctid`
+    /// when it exits.
+    /// Returns Ok(thread id) on success, Err(EFAULT) if the thread id cannot be
+    /// stored, Err(ENOMEM) if memory runs out, or Err(EAGAIN) if there are no
+    /// free procs.
+    pub fn clone(
+        &self,
+        pc: usize,
+        sp: usize,
+        arg: usize,
+        ctid: usize,
+        ctx: &mut KernelCtx<'id, '_>,
+    ) -> Result<Pid, Errno> {
+        if ctid != 0 {
+            // Check that the thread id can be stored.
+            ctx.proc_mut().memory_mut().copy_out(ctid.into(), &0)?;
+        }
+
+        let allocator = hal().kmem();
+        // Allocate trap frame, and map it into the shared memory.
+        let trap_frame = scopeguard::guard(allocator.alloc(None).ok_or(Errno::ENOMEM)?, |page| {
+            allocator.free(page)
+        });
+        let trap_frame_va = ctx
+            .proc_mut()
+            .memory_mut()
+            .map_trap_frame(trap_frame.addr(), allocator)
+            .map_err(|_| Errno::EAGAIN)?;
+
+        // SAFETY: memory and files have been initialized according to the
+        // invariants of Proc and CurrentProc.
+        let (memory, files) = unsafe {
+            let data = ctx.proc().deref_data();
+            (
+                data.memory.assume_init_ref().clone(),
+                data.files.assume_init_ref().clone(),
+            )
+        };
+        let (tgid, signals, sched, placement, cred, name) = {
+            let info = ctx.proc().info.lock();
+            (
+                info.tgid,
+                info.signals.fork(),
+                info.sched.fork(),
+                info.placement,
+                info.cred,
+                info.name,
+            )
+        };
+
+        // Allocate process.
+        let mut np = ok_or!(
+            self.alloc(
+                scopeguard::ScopeGuard::into_inner(trap_frame),
+                trap_frame_va,
+                memory
+            ),
+            {
+                files.free(ctx);
+                return Err(Errno::EAGAIN);
+            }
+        );
+        // SAFETY: this process cannot be the current process yet.
+        let npdata = unsafe { np.deref_mut_data() };
+
+        // SAFETY: trap_frame has been initialized by alloc.
+        let trap_frame = unsafe { &mut *npdata.trap_frame };
+        *trap_frame = *ctx.proc().trap_frame();
+        trap_frame.set_pc(pc);
+        trap_frame.set_sp(sp);
+        *trap_frame.param_reg_mut(RegNum::R0) = arg;
+
+        let _ = npdata.files.write(files);
+        npdata.clear_tid = ctid;
+
+        let info = np.deref_mut_info();
+        info.tgid = tgid;
+        info.signals = signals;
+        info.sched = sched;
+        info.placement = placement;
+        info.cred = cred;
+        info.name = name;
+        let pid = info.pid;
+        if ctid != 0 {
+            // It has been checked above, and fails only if another thread has
+            // unmapped it since.
+            let _ = ctx.proc_mut().memory_mut().copy_out(ctid.into(), &pid);
+        }
+
+        np.reacquire_after(|np| {
+            let mut parent_guard = self.wait_guard();
+            *np.get_mut_parent(&mut parent_guard) = ctx.proc().deref().deref();
+        });
+
+        // It does not break the invariant because files now has been initialized.
+        self.make_runnable(&mut np);
 
         Ok(pid)
     }
 
     /// Wait for a child process to exit and return its pid.
-    /// Return Err(()) if this process has no children.
-    pub fn wait(&self, addr: UVAddr, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, ()> {
+    /// Return Err(ECHILD) if this process has no children, or Err(EINTR) if it
+    /// has been killed.
+    pub fn wait(&self, addr: UVAddr, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, Errno> {
+        self.reap_threads();
         let mut parent_guard = self.wait_guard();
 
         loop {
@@ -315,18 +661,17 @@ impl<'id, 's> ProcsRef<'id, 's> {
                     // Found a child.
                     // Make sure the child isn't still in exit() or swtch().
                     let mut np = np.lock();
+                    if np.is_thread() {
+                        continue;
+                    }
 
                     havekids = true;
                     if np.state() == Procstate::ZOMBIE {
                         let pid = np.deref_mut_info().pid;
-                        if !addr.is_null()
-                            && ctx
-                                .proc_mut()
+                        if !addr.is_null() {
+                            ctx.proc_mut()
                                 .memory_mut()
-                                .copy_out(addr, &np.deref_info().xstate)
-                                .is_err()
-                        {
-                            return Err(());
+                                .copy_out(addr, &np.deref_info().xstate)?;
                         }
                         // Reap the zombie child process.
                         // SAFETY: np.state() equals ZOMBIE.
@@ -337,8 +682,11 @@ impl<'id, 's> ProcsRef<'id, 's> {
             }
 
             // No point waiting if we don't have any children.
-            if !havekids || ctx.proc().killed() {
-                return Err(());
+            if !havekids {
+                return Err(Errno::ECHILD);
+            }
+            if ctx.proc().killed() {
+                return Err(Errno::EINTR);
             }
 
             // Wait for a child to exit.
@@ -348,7 +696,12 @@ impl<'id, 's> ProcsRef<'id, 's> {
     }
 
     // Wait for a child process with `pid` to exit.
-    pub fn waitpid(&self, pid: Pid, addr: UVAddr, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, ()> {
+    pub fn waitpid(
+        &self,
+        pid: Pid,
+        addr: UVAddr,
+        ctx: &mut KernelCtx<'id, '_>,
+    ) -> Result<Pid, Errno> {
         let mut parent_guard = self.wait_guard();
 
         let mut found = false;
@@ -358,9 +711,11 @@ impl<'id, 's> ProcsRef<'id, 's> {
                 let mut np = np.lock();
                 if np.deref_mut_info().pid == pid {
                     found = true;
-                    if *np.get_mut_parent(&mut parent_guard) != ctx.proc().deref().deref() {
+                    if *np.get_mut_parent(&mut parent_guard) != ctx.proc().deref().deref()
+                        || np.is_thread()
+                    {
                         // Found a process, but not a child
-                        return Err(());
+                        return Err(Errno::ECHILD);
                     }
 
                     // Make sure the child isn't still in exit() or swtch().
@@ -368,14 +723,10 @@ impl<'id, 's> ProcsRef<'id, 's> {
 
                     if np.state() == Procstate::ZOMBIE {
                         let pid = np.deref_mut_info().pid;
-                        if !addr.is_null()
-                            && ctx
-                                .proc_mut()
+                        if !addr.is_null() {
+                            ctx.proc_mut()
                                 .memory_mut()
-                                .copy_out(addr, &np.deref_info().xstate)
-                                .is_err()
-                        {
-                            return Err(());
+                                .copy_out(addr, &np.deref_info().xstate)?;
                         }
                         // Reap the zombie child process.
                         // SAFETY: np.state() equals ZOMBIE.
@@ -386,8 +737,11 @@ impl<'id, 's> ProcsRef<'id, 's> {
             }
 
             // No point waiting if we don't have any children.
-            if !found || ctx.proc().killed() {
-                return Err(());
+            if !found {
+                return Err(Errno::ECHILD);
+            }
+            if ctx.proc().killed() {
+                return Err(Errno::EINTR);
             }
 
             // Wait for a child to exit.
@@ -397,25 +751,215 @@ impl<'id, 's> ProcsRef<'id, 's> {
         }
     }
 
-    /// Kill the process with the given pid.
-    /// The victim won't exit until it tries to return
+    /// Send signal `sig` to the process with the given pid.
+    /// If `sig` is 0, only checks that the process exists.
+    /// If the signal terminates the victim, it won't exit until it tries to return
     /// to user space (see usertrap() in trap.c).
-    /// Returns Ok(()) on success, Err(()) on error.
-    pub fn kill(&self, pid: Pid) -> Result<(), ()> {
+    /// Returns Ok(()) on success, Err(ESRCH) if there is no such process, or
+    /// Err(EPERM) if a process with the ids `cred` may not signal it.
+    pub fn kill(&self, pid: Pid, sig: usize, cred: &Cred) -> Result<(), Errno> {
+        let mut guard = self.lock_pid(pid).ok_or(Errno::ESRCH)?;
+        if !cred.may_control(&guard.deref_info().cred) {
+            return Err(Errno::EPERM);
+        }
+        if sig != 0 {
+            guard.signal(sig, self);
+        }
+        Ok(())
+    }
+
+    /// Returns the nice value of the process with the given pid.
+    pub fn nice(&self, pid: Pid) -> Result<i32, Errno> {
+        let guard = self.lock_pid(pid).ok_or(Errno::ESRCH)?;
+        Ok(guard.deref_info().sched.nice())
+    }
+
+    /// Set the nice value of the process with the given pid.
+    /// `nice` must be from NICE_MIN to NICE_MAX.
+    /// Returns Err(EPERM) if a process with the ids `cred` may not change it.
+    pub fn set_nice(&self, pid: Pid, nice: i32, cred: &Cred) -> Result<(), Errno> {
+        let mut guard = self.lock_pid(pid).ok_or(Errno::ESRCH)?;
+        if !cred.may_control(&guard.deref_info().cred) {
+            return Err(Errno::EPERM);
+        }
+        guard.deref_mut_info().sched.set_nice(nice);
+        Ok(())
+    }
+
+    /// Returns the affinity of the process with the given pid, as a bitmask of
+    /// the CPUs that may run it.
+    pub fn affinity(&self, pid: Pid) -> Result<usize, Errno> {
+        let guard = self.lock_pid(pid).ok_or(Errno::ESRCH)?;
+        Ok(guard.deref_info().placement.affinity & self.online())
+    }
+
+    /// Set the affinity of the process with the given pid. Returns Err(EINVAL) if
+    /// `affinity` allows no CPU that runs processes. If the process is
+    /// waiting in the run queue of a CPU it may no longer run on, that CPU
+    /// hands it to another one when it picks the process.
+    pub fn set_affinity(&self, pid: Pid, affinity: usize) -> Result<(), Errno> {
+        if affinity & self.online() == 0 {
+            return Err(Errno::EINVAL);
+        }
+        let mut guard = self.lock_pid(pid).ok_or(Errno::ESRCH)?;
+        guard.deref_mut_info().placement.affinity = affinity;
+        Ok(())
+    }
+
+    /// Returns the pids of the processes, including the threads.
+    pub fn pids(&self) -> ArrayVec<Pid, NPROC> {
+        self.process_pool()
+            .filter_map(|p| {
+                let guard = p.lock();
+                let pid = guard.deref_info().pid;
+                (!matches!(guard.state(), Procstate::UNUSED | Procstate::USED)).then(|| pid)
+            })
+            .collect()
+    }
+
+    /// Returns the pid of the process that the CPU `cpu` runs, if any.
+    pub fn running(&self, cpu: usize) -> Option<Pid> {
+        self.process_pool().find_map(|p| {
+            let guard = p.lock();
+            let info = guard.deref_info();
+            (info.state == Procstate::RUNNING && info.placement.cpu == cpu).then(|| info.pid)
+        })
+    }
+
+    /// Returns a snapshot of the process with the given pid.
+    /// Returns Err(ESRCH) if there is no such process.
+    pub fn stat(&self, pid: Pid, ctx: &KernelCtx<'id, '_>) -> Result<ProcStat, Errno> {
+        let mut parent_guard = self.wait_guard();
+        let guard = self.lock_pid(pid).ok_or(Errno::ESRCH)?;
+        let info = guard.deref_info();
+        let (state, name) = (info.state, info.name);
+
+        // The memory and files of a process running on another CPU may be
+        // replaced at any time, and the files of an exiting process are freed.
+        let p: &Proc = &guard;
+        let space = if ptr::eq(p, ctx.proc().deref().deref()) {
+            // SAFETY: memory and files have been initialized according to the
+            // invariants of Proc and CurrentProc.
+            Some(unsafe {
+                let data = ctx.proc().deref_data();
+                (
+                    data.memory.assume_init_ref().clone(),
+                    data.files.assume_init_ref().clone(),
+                )
+            })
+        } else if matches!(state, Procstate::SLEEPING | Procstate::RUNNABLE) && !info.exiting {
+            // SAFETY:
+            // * the process is not running.
+            // * memory and files have been initialized according to the
+            //   invariants of Proc.
+            Some(unsafe {
+                let data = guard.deref_data();
+                (
+                    data.memory.assume_init_ref().clone(),
+                    data.files.assume_init_ref().clone(),
+                )
+            })
+        } else {
+            None
+        };
+
+        let parent = *guard.get_mut_parent(&mut parent_guard);
+        drop(guard);
+        let ppid = if parent.is_null() {
+            0
+        } else {
+            // SAFETY: `parent` is a valid pointer according to the invariants
+            // of `Proc`, and it stays so while `parent_guard` is held.
+            unsafe { (*parent).info.lock() }.pid
+        };
+        drop(parent_guard);
+
+        let (size, fds, cwd) = match space {
+            Some((memory, files)) => {
+                let size = memory.lock().size();
+                memory.free(());
+                let fds = files.open_fds();
+                let cwd = files.cwd(ctx);
+                let st = cwd.stat(ctx);
+                cwd.free(ctx);
+                files.free(ctx);
+                (Some(size), Some(fds), Some(st))
+            }
+            None => (None, None, None),
+        };
+
+        Ok(ProcStat {
+            pid,
+            ppid,
+            state,
+            name,
+            size,
+            fds,
+            cwd,
+        })
+    }
+
+    /// Find the process with the given pid, and return it locked.
+    fn lock_pid(&self, pid: Pid) -> Option<ProcGuard<'id, 's>> {
+        for p in self.process_pool() {
+            let guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::UNUSED {
+                return Some(guard);
+            }
+        }
+        None
+    }
+
+    /// Send signal `sig` to every process but init.
+    /// Without process groups, this is how the console interrupts the
+    /// foreground program; init and the shell ignore such signals.
+    pub fn kill_all(&self, sig: usize) {
         for p in self.process_pool() {
+            if ptr::eq(p.deref(), self.0.initial_proc()) {
+                continue;
+            }
             let mut guard = p.lock();
-            if guard.deref_info().pid == pid {
-                p.kill();
-                guard.wakeup();
-                return Ok(());
+            if !matches!(guard.state(), Procstate::UNUSED | Procstate::ZOMBIE) {
+                guard.signal(sig, self);
             }
         }
-        Err(())
     }
 
-    /// Exit the current process.  Does not return.
+    /// Kill the threads of the process `tgid`, except the current thread, and
+    /// wait until they have exited, so that none of them runs in the user
+    /// memory of the process any longer.
+    pub fn kill_threads(&self, tgid: Pid, ctx: &KernelCtx<'id, '_>) {
+        let current: &Proc = ctx.proc().deref().deref();
+        let mut parent_guard = self.wait_guard();
+        loop {
+            let mut running = false;
+            for p in self.process_pool() {
+                if ptr::eq(p.deref(), current) {
+                    continue;
+                }
+                let mut guard = p.lock();
+                if guard.deref_info().tgid == tgid
+                    && !matches!(guard.state(), Procstate::UNUSED | Procstate::ZOMBIE)
+                {
+                    guard.signal(SIGKILL, self);
+                    running = true;
+                }
+            }
+            if !running {
+                break;
+            }
+            self.0
+                .get_ref()
+                .thread_waitchannel
+                .sleep(&mut parent_guard.0, ctx);
+        }
+    }
+
+    /// Exit the current thread.  Does not return.
+    /// If it is the first thread of the process, the other threads are killed.
     /// An exited process remains in the zombie state
-    /// until its parent calls wait().
+    /// until its parent calls wait(), while an exited thread is freed
+    /// without being waited for.
     pub fn exit_current(&self, status: i32, ctx: &mut KernelCtx<'id, '_>) -> ! {
         assert_ne!(
             ctx.proc().deref().deref() as *const _,
@@ -423,32 +967,51 @@ impl<'id, 's> ProcsRef<'id, 's> {
             "init exiting"
         );
 
-        for i in 0..NOFILE {
-            let files = &mut ctx.proc_mut().deref_mut_data().open_files;
-            if let Some(f) = unsafe { files.get_unchecked_mut(i) }.take() {
-                f.free(ctx);
-            }
+        let clear_tid = ctx.proc().deref_data().clear_tid;
+        if clear_tid != 0 {
+            // Tell the threads joining this thread that it has exited.
+            let _ = ctx.proc_mut().memory_mut().copy_out(clear_tid.into(), &0);
+            ctx.kernel().futexes().wake(clear_tid.into(), ctx.kernel());
+        }
+
+        let (pid, tgid) = {
+            let mut info = ctx.proc().info.lock();
+            info.exiting = true;
+            (info.pid, info.tgid)
+        };
+        let is_thread = pid != tgid;
+        if !is_thread {
+            // The exit of the first thread ends the whole process.
+            self.kill_threads(tgid, ctx);
         }
 
-        let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
         // SAFETY:
-        // * CurrentProc's cwd has been initialized.
-        // * It's ok to take cwd because proc will not be used any longer.
-        let cwd = unsafe { ctx.proc_mut().deref_mut_data().cwd.assume_init_read() };
-        cwd.free((&tx, ctx));
-        tx.end(ctx);
+        // * CurrentProc's files has been initialized.
+        // * It's ok to take files because proc will not be used any longer.
+        let files = unsafe { ctx.proc_mut().deref_mut_data().files.assume_init_read() };
+        files.free(ctx);
 
         // Give all children to init.
         let mut parent_guard = self.wait_guard();
         self.reparent(ctx.proc().deref().deref(), &mut parent_guard, ctx.kernel());
 
-        // Parent might be sleeping in wait().
-        let parent = *ctx.proc().get_mut_parent(&mut parent_guard);
-        // SAFETY:
-        // * `parent` cannot be null because it is not the initial process.
-        // * `parent` is a valid pointer according to the invariants of
-        //   `Proc` and `CurrentProc`.
-        unsafe { (*parent).child_waitchannel.wakeup(ctx.kernel()) };
+        // Parent might be sleeping in wait(). Threads are not waited for, but
+        // a thread killing the other threads may be sleeping in kill_threads().
+        if is_thread {
+            self.0.get_ref().thread_waitchannel.wakeup(ctx.kernel());
+        } else {
+            let parent = *ctx.proc().get_mut_parent(&mut parent_guard);
+            // SAFETY:
+            // * `parent` cannot be null because it is not the initial process.
+            // * `parent` is a valid pointer according to the invariants of
+            //   `Proc` and `CurrentProc`.
+            unsafe { (*parent).child_waitchannel.wakeup(ctx.kernel()) };
+            // SAFETY: the same as above.
+            if unsafe { (*parent).info.lock() }.signals.post(SIGCHLD) {
+                // SAFETY: the same as above.
+                unsafe { (*parent).kill() };
+            }
+        }
 
         let mut guard = ctx.proc().lock();
 
@@ -490,13 +1053,27 @@ unsafe fn forkret() -> ! {
         // File system initialization must be run in the context of a
         // regular process (e.g., because it calls sleep), and thus cannot
         // be run from main().
-        ctx.kernel().fs().init(ROOTDEV, &ctx);
+        ctx.kernel()
+            .vfs(ROOTFS, ROOTDEV)
+            .init(ROOTDEV, &ctx)
+            .expect("forkret: cannot load the root file system");
         unsafe { ctx.user_trap_ret() }
     };
 
     unsafe { kernel_ctx(forkret_inner) }
 }
 
+/// The flusher's very first scheduling by scheduler() will swtch to flusherret.
+unsafe fn flusherret() -> ! {
+    let flusherret_inner = |ctx: KernelCtx<'_, '_>| {
+        // Still holding p->lock from scheduler.
+        unsafe { ctx.proc().info.unlock() };
+        flusher(ctx)
+    };
+
+    unsafe { kernel_ctx(flusherret_inner) }
+}
+
 impl<'id, 's> ProcIter<'id, 's> {
     fn new(procs: &ProcsRef<'id, 's>) -> Self {
         Self(procs.0.brand(procs.0.get_ref().process_pool.iter()))
@@ -520,33 +1097,43 @@ impl<'id, 's> KernelRef<'id, 's> {
     /// Per-CPU process scheduler.
     /// Each CPU calls scheduler() after setting itself up.
     /// Scheduler never returns.  It loops, doing:
-    ///  - choose a process to run.
+    ///  - take the process to run from the scheduling policy.
     ///  - swtch to start running that process.
     ///  - eventually that process transfers control
     ///    via swtch back to the scheduler.
     pub unsafe fn scheduler(self) -> ! {
         // SAFETY: this function never moves to another CPU.
         let cpu = unsafe { hal().get_ref().cpus().current_unchecked() };
+        let id = cpuid();
         cpu.set_proc(ptr::null_mut());
+        self.procs().set_online(id);
         loop {
             // Avoid deadlock by ensuring that devices can interrupt.
             unsafe { TargetArch::intr_on() };
 
-            for p in self.procs().process_pool() {
-                let mut guard = p.lock();
-                if guard.state() == Procstate::RUNNABLE {
-                    // Switch to chosen process.  It is the process's job
-                    // to release its lock and then reacquire it
-                    // before jumping back to us.
-                    guard.deref_mut_info().state = Procstate::RUNNING;
-                    cpu.set_proc(p.deref());
-                    unsafe { swtch(cpu.context_raw_mut(), &mut guard.deref_mut_data().context) };
-
-                    // Process is done running for now.
-                    // It should have changed its p->state before coming back.
-                    cpu.set_proc(ptr::null_mut());
-                }
+            let p = some_or!(self.procs().pick_next(id), continue);
+            let mut guard = p.lock();
+            // Only the scheduler takes a process out of the RUNNABLE state.
+            assert_eq!(guard.state(), Procstate::RUNNABLE, "scheduler");
+
+            // The affinity may have changed while the process was waiting.
+            let info = guard.deref_mut_info();
+            if info.placement.affinity & (1 << id) == 0 {
+                self.procs().make_runnable(&mut guard);
+                continue;
             }
+            info.placement.cpu = id;
+
+            // Switch to chosen process.  It is the process's job
+            // to release its lock and then reacquire it
+            // before jumping back to us.
+            info.state = Procstate::RUNNING;
+            cpu.set_proc(p.deref());
+            unsafe { swtch(cpu.context_raw_mut(), &mut guard.deref_mut_data().context) };
+
+            // Process is done running for now.
+            // It should have changed its p->state before coming back.
+            cpu.set_proc(ptr::null_mut());
         }
     }
 
@@ -563,7 +1150,7 @@ impl<'id, 's> KernelRef<'id, 's> {
             let info = p.info.get_mut_raw();
             let state = unsafe { &(*info).state };
             if *state != Procstate::UNUSED {
-                let name = unsafe { &(*p.data.get()).name };
+                let name = unsafe { &(*info).name };
                 // For null character recognization.
                 // Required since str::from_utf8 cannot recognize interior null characters.
                 let length = name.iter().position(|&c| c == 0).unwrap_or(name.len());
@@ -1,18 +1,133 @@
+use core::iter;
+
+use arrayvec::ArrayVec;
+
 use super::*;
 use crate::{
     kernel::KernelRef,
     lock::{Guard, RawLock},
+    param::NPROC,
+    some_or,
 };
 
+/// The processes sleeping on a `WaitChannel`, in the order they fell asleep.
+///
+/// The list is linked through `Proc::wait_node` and ends with null pointers,
+/// instead of being a `util::intrusive_list::List`, whose head points to
+/// itself. Hence a `WaitChannel` may move or be dropped while no process
+/// sleeps on it.
+///
+/// # Safety
+///
+/// * Each process in the list is in the process pool, and in no other list.
+/// * The `wait_node` of a process in the list is accessed only with the lock
+///   of the list held.
+struct Sleepers {
+    head: *const Proc,
+    tail: *const Proc,
+}
+
+/// The link of a process in `Sleepers`.
+pub struct WaitNode {
+    prev: *const Proc,
+    next: *const Proc,
+}
+
 pub struct WaitChannel {
-    /// Required to make this type non-zero-sized. If it were zero-sized, multiple wait channels may
-    /// have the same address, spuriously waking up more threads.
-    _padding: u8,
+    sleepers: SpinLock<Sleepers>,
+}
+
+impl WaitNode {
+    pub const fn new() -> Self {
+        Self {
+            prev: ptr::null(),
+            next: ptr::null(),
+        }
+    }
+}
+
+/// Returns the link of `p`.
+///
+/// # Safety
+///
+/// `p` is in the process pool. It is in a list whose lock is held, or it is
+/// in no list and its p->lock is held. No other reference to the link exists.
+unsafe fn wait_node<'a>(p: *const Proc) -> &'a mut WaitNode {
+    unsafe { &mut *(*p).wait_node.get() }
+}
+
+impl Sleepers {
+    const fn new() -> Self {
+        Self {
+            head: ptr::null(),
+            tail: ptr::null(),
+        }
+    }
+
+    /// Add `p` at the back.
+    ///
+    /// # Safety
+    ///
+    /// `p` is in the process pool and in no list, and its p->lock is held.
+    unsafe fn push_back(&mut self, p: &Proc) {
+        // SAFETY: safety condition of this method.
+        let node = unsafe { wait_node(p) };
+        node.prev = self.tail;
+        node.next = ptr::null();
+        if self.tail.is_null() {
+            self.head = p;
+        } else {
+            // SAFETY: `self.tail` is in this list.
+            unsafe { wait_node(self.tail) }.next = p;
+        }
+        self.tail = p;
+    }
+
+    /// Take the process at the front off the list.
+    fn pop_front(&mut self) -> Option<*const Proc> {
+        if self.head.is_null() {
+            return None;
+        }
+        let p = self.head;
+        // SAFETY: `p` is in this list.
+        unsafe { self.remove(p) };
+        Some(p)
+    }
+
+    /// Take `p` off the list. Does nothing if `p` is not in the list.
+    ///
+    /// # Safety
+    ///
+    /// `p` is in the process pool. It is in this list, or it is in no list
+    /// and its p->lock is held.
+    unsafe fn remove(&mut self, p: *const Proc) {
+        // SAFETY: safety condition of this method.
+        let node = unsafe { wait_node(p) };
+        if node.prev.is_null() && !ptr::eq(self.head, p) {
+            return;
+        }
+        if node.prev.is_null() {
+            self.head = node.next;
+        } else {
+            // SAFETY: the neighbors of `p` are in this list.
+            unsafe { wait_node(node.prev) }.next = node.next;
+        }
+        if node.next.is_null() {
+            self.tail = node.prev;
+        } else {
+            // SAFETY: the neighbors of `p` are in this list.
+            unsafe { wait_node(node.next) }.prev = node.prev;
+        }
+        node.prev = ptr::null();
+        node.next = ptr::null();
+    }
 }
 
 impl WaitChannel {
     pub const fn new() -> Self {
-        Self { _padding: 0 }
+        Self {
+            sleepers: SpinLock::new("waitchannel", Sleepers::new()),
+        }
     }
 
     /// Atomically release lock and sleep on waitchannel.
@@ -27,6 +142,10 @@ impl WaitChannel {
 
         //DOC: sleeplock1
         let mut guard = ctx.proc().lock();
+        // Join the sleepers before releasing lk, so that a wakeup after the
+        // release finds us.
+        // SAFETY: the current process is running, so it is in no list.
+        unsafe { self.sleepers.lock().push_back(&guard) };
         // Release the lock while we sleep on the waitchannel, and reacquire after the process wakes up.
         lock_guard.reacquire_after(move || {
             // Go to sleep.
@@ -36,7 +155,9 @@ impl WaitChannel {
             // and device interrupts are disabled by `push_off()` in `p.lock()`.
             unsafe { guard.sched() };
 
-            // Tidy up.
+            // Tidy up. A process woken up by kill is still in the list.
+            // SAFETY: the process is in this list or in no list, and we hold `p.lock()`.
+            unsafe { self.sleepers.lock().remove(&**guard) };
             guard.deref_mut_info().waitchannel = ptr::null();
 
             // Now we can drop the process guard since the process woke up.
@@ -49,6 +170,24 @@ impl WaitChannel {
     /// Wake up all processes sleeping on waitchannel.
     /// Must be called without any p->lock.
     pub fn wakeup(&self, kernel: KernelRef<'_, '_>) {
-        kernel.procs().wakeup_pool(self, kernel);
+        let sleepers = {
+            let mut sleepers = self.sleepers.lock();
+            iter::from_fn(|| sleepers.pop_front()).collect::<ArrayVec<_, NPROC>>()
+        };
+        for p in sleepers {
+            let _ = kernel.procs().wakeup_sleeper(p, self);
+        }
+    }
+
+    /// Wake up the process that has slept longest on waitchannel.
+    /// Must be called without any p->lock.
+    pub fn wakeup_one(&self, kernel: KernelRef<'_, '_>) {
+        loop {
+            let p = some_or!(self.sleepers.lock().pop_front(), break);
+            // A process woken up by kill has not left the list yet. Skip it.
+            if kernel.procs().wakeup_sleeper(p, self) {
+                break;
+            }
+        }
     }
 }
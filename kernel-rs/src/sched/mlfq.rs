@@ -0,0 +1,190 @@
+//! Multi-level feedback queue scheduling.
+//!
+//! Runnable processes wait in one of NLEVEL queues, and the first process in
+//! the highest non-empty queue runs next. A process starts at a level decided
+//! by its nice value, and moves one level down each time it uses up the time
+//! slice of its level, which doubles at every level. Hence processes that
+//! sleep often stay above processes that keep computing. Every BOOST_TICKS
+//! ticks, all processes go back up, so that no process starves.
+//!
+//! Each CPU counts its own boosts, so a process that moves to another CPU goes
+//! back to its starting level.
+
+use core::pin::Pin;
+
+use array_macro::array;
+use pin_project::pin_project;
+
+use super::{steal_back, RunNode, SchedEntity, Scheduler, NICE_MAX, NICE_MIN};
+use crate::util::intrusive_list::List;
+
+/// Number of starting levels, among which the nice values are spread.
+const NBASE: usize = 3;
+/// How many levels a process can move down from its starting level.
+const NDEMOTE: usize = 3;
+const NLEVEL: usize = NBASE + NDEMOTE;
+/// Ticks between priority boosts.
+const BOOST_TICKS: u32 = 100;
+
+#[derive(Clone, Copy)]
+pub struct MlfqEntity {
+    nice: i32,
+    /// The current level. Lower levels run first.
+    level: usize,
+    /// Ticks used at the current level.
+    used: u32,
+    /// The number of the last boost that has reset the level.
+    boost: u32,
+}
+
+#[pin_project]
+pub struct Mlfq {
+    /// A FIFO queue of processes for each level.
+    #[pin]
+    queues: [List<RunNode>; NLEVEL],
+    /// Ticks charged since the last boost.
+    ticks: u32,
+    /// Number of boosts so far.
+    boost: u32,
+}
+
+/// Returns the starting level of a process with the given nice value.
+const fn base_level(nice: i32) -> usize {
+    (nice - NICE_MIN) as usize * NBASE / (NICE_MAX - NICE_MIN + 1) as usize
+}
+
+impl MlfqEntity {
+    pub const fn new() -> Self {
+        Self {
+            nice: 0,
+            level: base_level(0),
+            used: 0,
+            boost: 0,
+        }
+    }
+
+    /// Move back to the starting level if a boost has happened since the
+    /// level was last reset.
+    fn refresh(&mut self, boost: u32) {
+        if self.boost != boost {
+            self.level = base_level(self.nice);
+            self.used = 0;
+            self.boost = boost;
+        }
+    }
+
+    /// Returns the number of ticks the process may run at its level.
+    fn time_slice(&self) -> u32 {
+        1 << (self.level - base_level(self.nice))
+    }
+}
+
+impl SchedEntity for MlfqEntity {
+    fn nice(&self) -> i32 {
+        self.nice
+    }
+
+    fn set_nice(&mut self, nice: i32) {
+        self.nice = nice;
+        let base = base_level(nice);
+        self.level = self.level.clamp(base, base + NDEMOTE);
+        self.used = 0;
+    }
+
+    fn fork(&self) -> Self {
+        Self {
+            nice: self.nice,
+            level: base_level(self.nice),
+            used: 0,
+            boost: self.boost,
+        }
+    }
+}
+
+impl Mlfq {
+    /// Returns an uninitialized `Mlfq`.
+    ///
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `Scheduler::init`.
+    pub const unsafe fn new() -> Self {
+        Self {
+            queues: array![_ => unsafe { List::new() }; NLEVEL],
+            ticks: 0,
+            boost: 0,
+        }
+    }
+
+    fn queue(self: Pin<&mut Self>, level: usize) -> Pin<&mut List<RunNode>> {
+        // SAFETY: the queues are pinned together with `self`.
+        unsafe { self.map_unchecked_mut(|this| &mut this.queues[level]) }
+    }
+
+    fn queue_ref(self: Pin<&Self>, level: usize) -> Pin<&List<RunNode>> {
+        // SAFETY: the queues are pinned together with `self`.
+        unsafe { self.map_unchecked(|this| &this.queues[level]) }
+    }
+
+    /// Move every waiting process to the highest level. Each process goes
+    /// back to its starting level when it is queued next time.
+    fn boost_all(mut self: Pin<&mut Self>) {
+        let this = self.as_mut().project();
+        *this.boost = this.boost.wrapping_add(1);
+        for level in 1..NLEVEL {
+            while let Some(node) = self.as_mut().queue(level).pop_front() {
+                // SAFETY: `node` has been in a queue, so it is pinned and only
+                // we can access it.
+                let node = unsafe { Pin::new_unchecked(&mut *node) };
+                self.as_mut().queue(0).push_back(node);
+            }
+        }
+    }
+}
+
+impl Scheduler for Mlfq {
+    type Entity = MlfqEntity;
+
+    fn init(mut self: Pin<&mut Self>) {
+        for level in 0..NLEVEL {
+            self.as_mut().queue(level).init();
+        }
+    }
+
+    fn enqueue(mut self: Pin<&mut Self>, node: Pin<&mut RunNode>, entity: &mut MlfqEntity) {
+        entity.refresh(self.boost);
+        self.as_mut().queue(entity.level).push_back(node);
+    }
+
+    fn dequeue(mut self: Pin<&mut Self>) -> Option<usize> {
+        (0..NLEVEL).find_map(|level| {
+            let node = self.as_mut().queue(level).pop_front()?;
+            // SAFETY: `node` has been in the queue, so only we can access it.
+            Some(unsafe { (*node).index })
+        })
+    }
+
+    fn steal(mut self: Pin<&mut Self>, cpu: usize) -> Option<usize> {
+        (0..NLEVEL).find_map(|level| steal_back(self.as_mut().queue(level), cpu))
+    }
+
+    fn tick(mut self: Pin<&mut Self>, entity: &mut MlfqEntity) -> bool {
+        let this = self.as_mut().project();
+        *this.ticks += 1;
+        if *this.ticks >= BOOST_TICKS {
+            *this.ticks = 0;
+            self.as_mut().boost_all();
+        }
+        entity.refresh(self.boost);
+
+        entity.used += 1;
+        if entity.used >= entity.time_slice() {
+            entity.used = 0;
+            if entity.level < base_level(entity.nice) + NDEMOTE {
+                entity.level += 1;
+            }
+            return true;
+        }
+        // Give way to a process of a higher level.
+        (0..entity.level).any(|level| !self.as_ref().queue_ref(level).is_empty())
+    }
+}
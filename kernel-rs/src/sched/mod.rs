@@ -0,0 +1,189 @@
+//! Scheduling policies.
+//!
+//! A `Scheduler` is the run queue of one CPU: it keeps the runnable processes
+//! that wait for the CPU and chooses the one to run next. The queues link the
+//! `RunNode`s embedded in the processes, and name a process by its index in the
+//! process pool. The state that a policy keeps for each process, its
+//! `SchedEntity`, lives in the process itself. The policy is chosen at build
+//! time: stride scheduling by default, or a multi-level feedback queue with the
+//! `mlfq` feature.
+//!
+//! A process is queued on the CPU that ran it last, so that it finds its data
+//! in that CPU's cache. A CPU whose queue is empty steals a process from the
+//! queue of another CPU. The affinity of a process limits the CPUs it may be
+//! queued on.
+
+use core::pin::Pin;
+
+use cfg_if::cfg_if;
+use pin_project::pin_project;
+
+use crate::util::intrusive_list::{List, ListEntry, ListNode};
+
+cfg_if! {
+    if #[cfg(feature = "mlfq")] {
+        mod mlfq;
+        pub use mlfq::{Mlfq, MlfqEntity};
+        pub type DefaultScheduler = Mlfq;
+        pub type DefaultEntity = MlfqEntity;
+    } else {
+        mod stride;
+        pub use stride::{Stride, StrideEntity};
+        pub type DefaultScheduler = Stride;
+        pub type DefaultEntity = StrideEntity;
+    }
+}
+
+/// The highest priority a process can have.
+pub const NICE_MIN: i32 = -20;
+/// The lowest priority a process can have.
+pub const NICE_MAX: i32 = 19;
+
+/// The `which` argument of getpriority and setpriority that names a process.
+pub const PRIO_PROCESS: i32 = 0;
+
+/// The affinity that allows every CPU.
+pub const AFFINITY_ALL: usize = usize::MAX;
+
+/// The link of a runnable process in a run queue.
+///
+/// # Safety
+///
+/// While the node is in a run queue, it is accessed only with the lock of that
+/// run queue held. Otherwise, it is accessed only with the lock of its process
+/// held.
+#[repr(C)]
+#[pin_project]
+pub struct RunNode {
+    #[pin]
+    list_entry: ListEntry,
+    /// The index of the process in the process pool.
+    index: usize,
+    /// The CPUs that may run the process, as a bitmask.
+    affinity: usize,
+    /// The key by which a policy orders the queue. Not every policy uses it.
+    #[allow(dead_code)]
+    key: u64,
+}
+
+/// Where a process may run, and where it ran last.
+#[derive(Clone, Copy)]
+pub struct Placement {
+    /// The CPUs that may run the process, as a bitmask.
+    pub affinity: usize,
+    /// The CPU that ran the process last.
+    pub cpu: usize,
+}
+
+/// The scheduling state of a process.
+pub trait SchedEntity: Copy {
+    /// Returns the nice value, from NICE_MIN to NICE_MAX.
+    /// A lower nice value means a higher priority.
+    fn nice(&self) -> i32;
+
+    /// Sets the nice value, which must be from NICE_MIN to NICE_MAX.
+    fn set_nice(&mut self, nice: i32);
+
+    /// The state of a child created by fork or clone, which inherits the
+    /// nice value.
+    fn fork(&self) -> Self;
+}
+
+/// A scheduling policy, as the run queue of a CPU.
+pub trait Scheduler {
+    type Entity: SchedEntity;
+
+    /// Initialize the run queue. Use the queue only after this.
+    fn init(self: Pin<&mut Self>);
+
+    /// Add the runnable process of `node`, whose state is `entity`.
+    fn enqueue(self: Pin<&mut Self>, node: Pin<&mut RunNode>, entity: &mut Self::Entity);
+
+    /// Remove the process to run next, and return its index.
+    fn dequeue(self: Pin<&mut Self>) -> Option<usize>;
+
+    /// Remove a process that may run on CPU `cpu`, so that `cpu` can run it
+    /// instead of this queue's CPU. Returns its index.
+    fn steal(self: Pin<&mut Self>, cpu: usize) -> Option<usize>;
+
+    /// Charge a clock tick to the running process whose state is `entity`.
+    /// Returns true if the process should give up the CPU.
+    fn tick(self: Pin<&mut Self>, entity: &mut Self::Entity) -> bool;
+}
+
+impl RunNode {
+    /// Returns an uninitialized `RunNode`.
+    ///
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `RunNode::init`.
+    pub const unsafe fn new() -> Self {
+        Self {
+            list_entry: unsafe { ListEntry::new() },
+            index: 0,
+            affinity: AFFINITY_ALL,
+            key: 0,
+        }
+    }
+
+    /// Initialize the node of the process at `index` in the process pool.
+    pub fn init(self: Pin<&mut Self>, index: usize) {
+        let this = self.project();
+        this.list_entry.init();
+        *this.index = index;
+    }
+
+    pub fn set_affinity(self: Pin<&mut Self>, affinity: usize) {
+        *self.project().affinity = affinity;
+    }
+
+    fn allows(&self, cpu: usize) -> bool {
+        self.affinity & (1 << cpu) != 0
+    }
+}
+
+// SAFETY: `RunNode` owns a `ListEntry` as its first field.
+unsafe impl ListNode for RunNode {
+    fn get_list_entry(self: Pin<&mut Self>) -> Pin<&mut ListEntry> {
+        self.project().list_entry
+    }
+
+    fn from_list_entry(list_entry: *mut ListEntry) -> *mut Self {
+        list_entry as _
+    }
+}
+
+/// Remove the node nearest to the back of `queue` that may run on CPU `cpu`,
+/// and return its index. The back holds the processes queued last, whose data
+/// are the least likely to be in the cache of the queue's CPU.
+fn steal_back(queue: Pin<&mut List<RunNode>>, cpu: usize) -> Option<usize> {
+    // SAFETY: no other reference to the nodes in `queue` exists, since we own
+    // the lock of the queue, and we stop iterating once we remove a node.
+    let node = unsafe { queue.iter_pin_mut_unchecked() }
+        .rev()
+        .find(|node| node.allows(cpu))?;
+    let index = node.index;
+    node.get_list_entry().remove();
+    Some(index)
+}
+
+impl Placement {
+    pub const fn new() -> Self {
+        Self {
+            affinity: AFFINITY_ALL,
+            cpu: 0,
+        }
+    }
+
+    /// Returns the CPU whose run queue the process should join, among the
+    /// CPUs in `online`: the CPU that ran it last if allowed, or else the
+    /// first allowed CPU.
+    pub fn target(&self, online: usize) -> usize {
+        let allowed = self.affinity & online;
+        if allowed & (1 << self.cpu) != 0 || allowed == 0 {
+            self.cpu
+        } else {
+            allowed.trailing_zeros() as usize
+        }
+    }
+}
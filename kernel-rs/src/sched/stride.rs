@@ -0,0 +1,118 @@
+//! Stride scheduling.
+//!
+//! Each process has a weight decided by its nice value, and a pass that
+//! advances by the inverse of the weight, its stride, for every tick it runs.
+//! The runnable process with the smallest pass runs next, so processes get
+//! CPU time in proportion to their weights.
+
+use core::pin::Pin;
+
+use pin_project::pin_project;
+
+use super::{steal_back, RunNode, SchedEntity, Scheduler, NICE_MIN};
+use crate::util::intrusive_list::{List, ListNode};
+
+/// The stride of a process of weight 1.
+const STRIDE1: u64 = 1 << 30;
+
+/// Weights for nice values from NICE_MIN to NICE_MAX. Each step is about 1.25
+/// times the next, so that a process gets about 10% more CPU time than a
+/// process with a nice value one larger.
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+#[derive(Clone, Copy)]
+pub struct StrideEntity {
+    nice: i32,
+    pass: u64,
+}
+
+#[pin_project]
+pub struct Stride {
+    /// Runnable processes, whose keys are their passes.
+    #[pin]
+    queue: List<RunNode>,
+    /// The pass of the process dequeued last. A process that wakes up starts
+    /// from here, so that it cannot make up for the time it slept.
+    global_pass: u64,
+}
+
+impl StrideEntity {
+    pub const fn new() -> Self {
+        Self { nice: 0, pass: 0 }
+    }
+
+    fn stride(&self) -> u64 {
+        STRIDE1 / NICE_TO_WEIGHT[(self.nice - NICE_MIN) as usize]
+    }
+}
+
+impl SchedEntity for StrideEntity {
+    fn nice(&self) -> i32 {
+        self.nice
+    }
+
+    fn set_nice(&mut self, nice: i32) {
+        self.nice = nice;
+    }
+
+    fn fork(&self) -> Self {
+        Self {
+            nice: self.nice,
+            pass: 0,
+        }
+    }
+}
+
+impl Stride {
+    /// Returns an uninitialized `Stride`.
+    ///
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `Scheduler::init`.
+    pub const unsafe fn new() -> Self {
+        Self {
+            queue: unsafe { List::new() },
+            global_pass: 0,
+        }
+    }
+}
+
+impl Scheduler for Stride {
+    type Entity = StrideEntity;
+
+    fn init(self: Pin<&mut Self>) {
+        self.project().queue.init();
+    }
+
+    fn enqueue(self: Pin<&mut Self>, mut node: Pin<&mut RunNode>, entity: &mut StrideEntity) {
+        let this = self.project();
+        entity.pass = entity.pass.max(*this.global_pass);
+        *node.as_mut().project().key = entity.pass;
+        this.queue.push_back(node);
+    }
+
+    fn dequeue(self: Pin<&mut Self>) -> Option<usize> {
+        let this = self.project();
+        // The queue is short, so a linear search is cheap enough.
+        // SAFETY: no other reference to the nodes in the queue exists, since
+        // we own the lock of the queue.
+        let node = unsafe { this.queue.iter_pin_mut_unchecked() }.min_by_key(|node| node.key)?;
+        *this.global_pass = node.key;
+        let index = node.index;
+        node.get_list_entry().remove();
+        Some(index)
+    }
+
+    fn steal(self: Pin<&mut Self>, cpu: usize) -> Option<usize> {
+        steal_back(self.project().queue, cpu)
+    }
+
+    fn tick(self: Pin<&mut Self>, entity: &mut StrideEntity) -> bool {
+        entity.pass += entity.stride();
+        true
+    }
+}
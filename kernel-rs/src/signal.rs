@@ -0,0 +1,330 @@
+//! POSIX-style signals.
+//!
+//! Each process has a set of pending signals, a mask of blocked signals, and
+//! an action for every signal. A signal whose default action terminates the
+//! process marks the process killed as soon as it is posted, so that a
+//! sleeping process is woken up. Every other signal is delivered when the
+//! process is about to return to user space: the kernel saves the user
+//! registers in a frame on the user stack and enters the handler, which
+//! returns through the restorer that calls sigreturn.
+//!
+//! Signals that are caught do not interrupt a sleeping system call; they are
+//! delivered after the system call has finished.
+
+use core::{mem, slice};
+
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    addr::UVAddr,
+    arch::interface::{ProcManager, TrapFrameManager},
+    arch::TargetArch,
+    proc::{KernelCtx, RegNum},
+    some_or,
+};
+
+/// Number of signals, including the unused signal 0.
+pub const NSIG: usize = 32;
+
+pub const SIGINT: usize = 2;
+pub const SIGKILL: usize = 9;
+pub const SIGSEGV: usize = 11;
+const SIGURG: usize = 16;
+const SIGCONT: usize = 19;
+pub const SIGCHLD: usize = 20;
+const SIGWINCH: usize = 28;
+
+/// Default signal handling.
+const SIG_DFL: usize = 0;
+/// Ignore the signal.
+const SIG_IGN: usize = 1;
+
+/// Restore the default action when the handler is entered.
+const SA_RESETHAND: u32 = 0x4;
+/// Do not block the signal while its handler runs.
+const SA_NODEFER: u32 = 0x10;
+
+/// How sigprocmask changes the blocked mask.
+const SIG_BLOCK: i32 = 1;
+const SIG_UNBLOCK: i32 = 2;
+const SIG_SETMASK: i32 = 3;
+
+/// Signals that cannot be caught, ignored, or blocked.
+const UNCATCHABLE: u32 = 1 << SIGKILL;
+
+/// The action taken on a signal, as passed to sigaction.
+// It needs repr(C) because it is shared with user space.
+#[repr(C)]
+#[derive(Clone, Copy, Default, AsBytes, FromBytes)]
+pub struct SigAction {
+    /// SIG_DFL, SIG_IGN, or the address of the handler.
+    handler: usize,
+    /// Signals blocked while the handler runs.
+    mask: u32,
+    flags: u32,
+    /// Where the handler returns to. It should call sigreturn.
+    restorer: usize,
+}
+
+/// What to do with a signal taken by `SigState::take`.
+pub enum Delivery {
+    /// Terminate the process.
+    Terminate,
+    /// Run the handler. `mask` is the blocked mask to restore on sigreturn.
+    Handle {
+        sig: usize,
+        handler: usize,
+        restorer: usize,
+        mask: u32,
+    },
+}
+
+/// The signal state of a process.
+#[derive(Clone, Copy)]
+pub struct SigState {
+    /// Signals posted but not delivered yet.
+    pending: u32,
+    /// Signals whose delivery is deferred.
+    blocked: u32,
+    actions: [SigAction; NSIG],
+}
+
+/// The frame pushed on the user stack when a handler is entered.
+#[repr(C)]
+struct SigFrame {
+    regs: <TargetArch as ProcManager>::TrapFrame,
+    mask: usize,
+}
+
+impl SigAction {
+    const DEFAULT: Self = Self {
+        handler: SIG_DFL,
+        mask: 0,
+        flags: 0,
+        restorer: 0,
+    };
+}
+
+const fn bit(sig: usize) -> u32 {
+    1 << sig
+}
+
+/// Returns true if the default action of `sig` is to ignore it.
+const fn ignored_by_default(sig: usize) -> bool {
+    matches!(sig, SIGURG | SIGCONT | SIGCHLD | SIGWINCH)
+}
+
+/// Returns true if `sig` is a valid signal number.
+pub const fn is_valid(sig: usize) -> bool {
+    sig > 0 && sig < NSIG
+}
+
+impl SigState {
+    pub const fn new() -> Self {
+        Self {
+            pending: 0,
+            blocked: 0,
+            actions: [SigAction::DEFAULT; NSIG],
+        }
+    }
+
+    /// Returns true if `sig` would be discarded when posted.
+    fn ignores(&self, sig: usize) -> bool {
+        match self.actions[sig].handler {
+            SIG_DFL => ignored_by_default(sig),
+            SIG_IGN => true,
+            _ => false,
+        }
+    }
+
+    /// Post `sig`. Returns true if the signal terminates the process right away.
+    pub fn post(&mut self, sig: usize) -> bool {
+        if self.ignores(sig) {
+            return false;
+        }
+        self.pending |= bit(sig);
+        self.actions[sig].handler == SIG_DFL && self.blocked & bit(sig) == 0
+    }
+
+    /// Post `sig`, which was raised by the process itself and cannot be
+    /// deferred. If it is blocked or ignored, its default action is restored.
+    /// Returns true if the signal terminates the process right away.
+    pub fn force(&mut self, sig: usize) -> bool {
+        if self.actions[sig].handler == SIG_IGN || self.blocked & bit(sig) != 0 {
+            self.actions[sig] = SigAction::DEFAULT;
+            self.blocked &= !bit(sig);
+        }
+        self.post(sig)
+    }
+
+    /// Take the next deliverable signal, skipping the ignored ones.
+    /// If a handler is to run, blocks the signals that it requests.
+    pub fn take(&mut self) -> Option<Delivery> {
+        loop {
+            let deliverable = self.pending & !self.blocked;
+            if deliverable == 0 {
+                return None;
+            }
+            let sig = deliverable.trailing_zeros() as usize;
+            self.pending &= !bit(sig);
+
+            let action = self.actions[sig];
+            match action.handler {
+                SIG_DFL if ignored_by_default(sig) => continue,
+                SIG_DFL => return Some(Delivery::Terminate),
+                SIG_IGN => continue,
+                handler => {
+                    let mask = self.blocked;
+                    self.blocked |= action.mask;
+                    if action.flags & SA_NODEFER == 0 {
+                        self.blocked |= bit(sig);
+                    }
+                    self.blocked &= !UNCATCHABLE;
+                    if action.flags & SA_RESETHAND != 0 {
+                        self.actions[sig] = SigAction::DEFAULT;
+                    }
+                    return Some(Delivery::Handle {
+                        sig,
+                        handler,
+                        restorer: action.restorer,
+                        mask,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Returns the action for `sig`, and replaces it with `act` if given.
+    /// Returns Err(()) if the action for `sig` cannot be changed.
+    pub fn sigaction(&mut self, sig: usize, act: Option<SigAction>) -> Result<SigAction, ()> {
+        let old = self.actions[sig];
+        if let Some(mut act) = act {
+            if bit(sig) & UNCATCHABLE != 0 {
+                return Err(());
+            }
+            act.mask &= !UNCATCHABLE;
+            self.actions[sig] = act;
+            // Setting a signal to be ignored discards its pending instance.
+            if self.ignores(sig) {
+                self.pending &= !bit(sig);
+            }
+        }
+        Ok(old)
+    }
+
+    /// Change the blocked mask according to `how`, and return the old one.
+    /// Returns Err(()) if `how` is invalid.
+    pub fn sigprocmask(&mut self, how: i32, set: Option<u32>) -> Result<u32, ()> {
+        let old = self.blocked;
+        if let Some(set) = set {
+            self.blocked = match how {
+                SIG_BLOCK => old | set,
+                SIG_UNBLOCK => old & !set,
+                SIG_SETMASK => set,
+                _ => return Err(()),
+            } & !UNCATCHABLE;
+        }
+        Ok(old)
+    }
+
+    /// The signal state of a child created by fork: the actions and the mask are
+    /// inherited, but no signal is pending.
+    pub fn fork(&self) -> Self {
+        Self {
+            pending: 0,
+            ..*self
+        }
+    }
+
+    /// Restore the default action of caught signals, since exec discards the
+    /// handlers. Ignored signals remain ignored.
+    pub fn reset_handlers(&mut self) {
+        for action in &mut self.actions {
+            if action.handler != SIG_IGN {
+                *action = SigAction::DEFAULT;
+            }
+        }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Deliver the pending signals of the current process. Terminates the process
+    /// if a signal says so, or if a signal frame cannot be pushed.
+    pub fn handle_signals(&mut self) {
+        loop {
+            let delivery = some_or!(self.proc().info.lock().signals.take(), break);
+            let res = match delivery {
+                Delivery::Terminate => Err(()),
+                Delivery::Handle {
+                    sig,
+                    handler,
+                    restorer,
+                    mask,
+                } => self.push_signal_frame(sig, handler, restorer, mask),
+            };
+            if res.is_err() {
+                self.kernel().procs().exit_current(-1, self);
+            }
+        }
+    }
+
+    /// Save the user registers and `mask` on the user stack, and set up the
+    /// registers so that returning to user space runs `handler(sig)`, which
+    /// returns to `restorer`.
+    fn push_signal_frame(
+        &mut self,
+        sig: usize,
+        handler: usize,
+        restorer: usize,
+        mask: u32,
+    ) -> Result<(), ()> {
+        let frame = SigFrame {
+            regs: self.proc().trap_frame().user_regs(),
+            mask: mask as usize,
+        };
+        let sp = frame
+            .regs
+            .get_sp()
+            .checked_sub(mem::size_of::<SigFrame>())
+            .ok_or(())?
+            & !0xf;
+        // SAFETY: SigFrame consists only of usize fields, so it has no padding.
+        let bytes = unsafe {
+            slice::from_raw_parts(&frame as *const _ as *const u8, mem::size_of::<SigFrame>())
+        };
+        self.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(UVAddr::from(sp), bytes)
+            .map_err(|_| ())?;
+
+        let trap_frame = self.proc_mut().trap_frame_mut();
+        trap_frame.set_pc(handler);
+        trap_frame.set_sp(sp);
+        trap_frame.set_ret_addr(restorer);
+        *trap_frame.param_reg_mut(RegNum::R0) = sig;
+        Ok(())
+    }
+
+    /// Return from a handler: restore the user registers and the blocked mask
+    /// saved in the signal frame at the user stack pointer.
+    /// Returns the restored value of the return value register.
+    pub fn sigreturn(&mut self) -> Result<usize, ()> {
+        let sp = self.proc().trap_frame().get_sp();
+        let mut frame = SigFrame {
+            regs: *self.proc().trap_frame(),
+            mask: 0,
+        };
+        // SAFETY: SigFrame consists only of usize fields, so any bytes form a valid SigFrame.
+        let bytes = unsafe {
+            slice::from_raw_parts_mut(&mut frame as *mut _ as *mut u8, mem::size_of::<SigFrame>())
+        };
+        self.proc_mut()
+            .memory_mut()
+            .copy_in_bytes(bytes, UVAddr::from(sp))
+            .map_err(|_| ())?;
+
+        self.proc().info.lock().signals.blocked = frame.mask as u32 & !UNCATCHABLE;
+        self.proc_mut().trap_frame_mut().set_user_regs(&frame.regs);
+        Ok(frame.regs.get_param_reg(RegNum::R0))
+    }
+}
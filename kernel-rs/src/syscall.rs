@@ -13,27 +13,28 @@ use crate::{
     addr::{Addr, UVAddr},
     arch::interface::{PowerOff, TimeManager, TrapFrameManager},
     arch::TargetArch,
+    errno::Errno,
     file::{RcFile, SeekWhence, SelectEvent},
-    fs::{FcntlFlags, FileSystem, FileSystemExt, InodeType, Path},
+    fs::{FcntlFlags, FsType, InodeType, Path, Utimbuf},
+    futex::{FUTEX_WAIT, FUTEX_WAKE},
     hal::hal,
-    ok_or,
     page::{Page, PGSIZE},
-    param::{MAXARG, MAXPATH},
+    param::{DEVDEV, MAXARG, MAXPATH, PROCDEV, TMPDEV},
     proc::{CurrentProc, KernelCtx},
+    sched::{SchedEntity, NICE_MAX, NICE_MIN, PRIO_PROCESS},
+    signal::{self, SigAction},
     some_or,
+    timer::{Timespec, CLOCK_MONOTONIC, CLOCK_REALTIME, TICK_MICROS, TIMER_ABSTIME},
+    vm::{MapFlags, ProtFlags},
 };
 
 impl CurrentProc<'_, '_> {
     /// Fetch the usize at addr from the current process.
-    /// Returns Ok(fetched integer) on success, Err(()) on error.
-    pub fn fetchaddr(&mut self, addr: UVAddr) -> Result<usize, ()> {
+    /// Returns Ok(fetched integer) on success, Err(EFAULT) on error.
+    pub fn fetchaddr(&mut self, addr: UVAddr) -> Result<usize, Errno> {
         let mut ip = 0;
-        let sz = mem::size_of::<usize>();
-        if addr.into_usize() >= self.memory().size()
-            || addr.into_usize() + sz > self.memory().size()
-        {
-            return Err(());
-        }
+        // The address may be in the heap, the stack, or a mapping, and
+        // copy_in checks that it is a user address.
         // SAFETY: usize does not have any internal structure.
         unsafe { self.memory_mut().copy_in(&mut ip, addr) }?;
         Ok(ip)
@@ -41,7 +42,7 @@ impl CurrentProc<'_, '_> {
 
     /// Fetch the nul-terminated string at addr from the current process.
     /// Returns reference to the string in the buffer.
-    pub fn fetchstr<'a>(&mut self, addr: UVAddr, buf: &'a mut [u8]) -> Result<&'a CStr, ()> {
+    pub fn fetchstr<'a>(&mut self, addr: UVAddr, buf: &'a mut [u8]) -> Result<&'a CStr, Errno> {
         self.memory_mut().copy_in_str(buf, addr)?;
 
         // SAFETY: buf contains '\0' as copy_in_str has succeeded.
@@ -53,42 +54,51 @@ impl CurrentProc<'_, '_> {
     }
 
     /// Fetch the nth 32-bit system call argument.
-    pub fn argint(&self, n: usize) -> Result<i32, ()> {
+    pub fn argint(&self, n: usize) -> Result<i32, Errno> {
         Ok(self.argraw(n) as i32)
     }
 
     /// Retrieve an argument as a pointer.
     /// Doesn't check for legality, since
     /// copyin/copyout will do that.
-    pub fn argaddr(&self, n: usize) -> Result<usize, ()> {
+    pub fn argaddr(&self, n: usize) -> Result<usize, Errno> {
         Ok(self.argraw(n))
     }
 
     /// Fetch the nth word-sized system call argument as a null-terminated string.
     /// Copies into buf, at most max.
     /// Returns reference to the string in the buffer.
-    pub fn argstr<'a>(&mut self, n: usize, buf: &'a mut [u8]) -> Result<&'a CStr, ()> {
+    pub fn argstr<'a>(&mut self, n: usize, buf: &'a mut [u8]) -> Result<&'a CStr, Errno> {
         let addr = self.argaddr(n)?;
         self.fetchstr(addr.into(), buf)
     }
 
     /// Fetch the nth word-sized system call argument as a file descriptor
-    /// and return both the descriptor and the corresponding struct file.
-    fn argfd(&self, n: usize) -> Result<(i32, &RcFile), ()> {
+    /// and return both the descriptor and a new reference to the corresponding
+    /// struct file, which the caller must free.
+    /// Returns Err(EBADF) if the descriptor is not open.
+    fn argfd(&self, n: usize) -> Result<(i32, RcFile), Errno> {
         let fd = self.argint(n)?;
-        let f = self
-            .deref_data()
-            .open_files
-            .get(fd as usize)
-            .ok_or(())?
-            .as_ref()
-            .ok_or(())?;
+        let f = self.files().get(fd).ok_or(Errno::EBADF)?;
         Ok((fd, f))
     }
 }
 
+/// Returns the uptime in microseconds, or Err(ENOSYS) if the machine cannot tell it.
+fn uptime() -> Result<usize, Errno> {
+    TargetArch::uptime_as_micro().map_err(|_| Errno::ENOSYS)
+}
+
+/// Returns the value of nice and getpriority for the nice value `nice`: 20 - nice,
+/// from 1 to 40 as in Linux, so that a negative nice value is not taken for an error.
+fn nice_to_ret(nice: i32) -> usize {
+    (NICE_MAX + 1 - nice) as usize
+}
+
 impl KernelCtx<'_, '_> {
-    pub fn syscall(&mut self, num: i32) -> Result<usize, ()> {
+    /// Returns Ok(return value) on success, or Err(errno), which user space
+    /// receives as its negation.
+    pub fn syscall(&mut self, num: i32) -> Result<usize, Errno> {
         match num {
             1 => self.sys_fork(),
             2 => self.sys_exit(),
@@ -119,275 +129,826 @@ impl KernelCtx<'_, '_> {
             27 => self.sys_lseek(),
             28 => self.sys_uptime_as_micro(),
             29 => self.sys_clock(),
+            30 => self.sys_mmap(),
+            31 => self.sys_munmap(),
+            32 => self.sys_mprotect(),
+            33 => self.sys_sigaction(),
+            34 => self.sys_sigprocmask(),
+            35 => self.sys_sigreturn(),
+            36 => self.sys_clone(),
+            37 => self.sys_futex(),
+            38 => self.sys_nice(),
+            39 => self.sys_getpriority(),
+            40 => self.sys_setpriority(),
+            41 => self.sys_sched_setaffinity(),
+            42 => self.sys_sched_getaffinity(),
+            43 => self.sys_nanosleep(),
+            44 => self.sys_clock_nanosleep(),
+            45 => self.sys_mount(),
+            46 => self.sys_umount(),
+            47 => self.sys_symlink(),
+            48 => self.sys_readlink(),
+            49 => self.sys_rename(),
+            50 => self.sys_chmod(),
+            51 => self.sys_chown(),
+            52 => self.sys_utime(),
+            53 => self.sys_getuid(),
+            54 => self.sys_setuid(),
+            55 => self.sys_getgid(),
+            56 => self.sys_setgid(),
+            57 => self.sys_getdents(),
+            58 => self.sys_fsync(),
+            59 => self.sys_fdatasync(),
+            60 => self.sys_sync(),
+            61 => self.sys_writeback(),
             _ => {
+                let name = self.proc().info.lock().name;
                 self.kernel().as_ref().write_fmt(format_args!(
                     "{} {}: unknown sys call {}",
                     self.proc().pid(),
-                    str::from_utf8(&self.proc().deref_data().name).unwrap_or("???"),
+                    str::from_utf8(&name).unwrap_or("???"),
                     num
                 ));
-                Err(())
+                Err(Errno::ENOSYS)
             }
         }
     }
 
     /// Terminate the current process; status reported to wait(). No return.
-    pub fn sys_exit(&mut self) -> Result<usize, ()> {
+    pub fn sys_exit(&mut self) -> Result<usize, Errno> {
         let n = self.proc().argint(0)?;
         self.kernel().procs().exit_current(n, self);
     }
 
     /// Create a process.
-    /// Returns Ok(child’s PID) on success, Err(()) on error.
-    pub fn sys_fork(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(child’s PID) on success, Err(errno) on error.
+    pub fn sys_fork(&mut self) -> Result<usize, Errno> {
         Ok(self.kernel().procs().fork(self)? as _)
     }
 
     /// Wait for a child to exit.
-    /// Returns Ok(child’s PID) on success, Err(()) on error.
-    pub fn sys_wait(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(child’s PID) on success, Err(errno) on error.
+    pub fn sys_wait(&mut self) -> Result<usize, Errno> {
         let p = self.proc().argaddr(0)?;
         Ok(self.kernel().procs().wait(p.into(), self)? as _)
     }
 
     /// Return the current process’s PID.
-    pub fn sys_getpid(&self) -> Result<usize, ()> {
+    pub fn sys_getpid(&self) -> Result<usize, Errno> {
         Ok(self.proc().pid() as _)
     }
 
+    /// Return the real user id of the current process.
+    pub fn sys_getuid(&self) -> Result<usize, Errno> {
+        Ok(self.proc().cred().uid as _)
+    }
+
+    /// Set the user id of the current process. Root sets both the real and the
+    /// effective user ids, and the others may only set the effective one to the
+    /// real one.
+    /// Returns Ok(0) on success, Err(EPERM) if not allowed.
+    pub fn sys_setuid(&self) -> Result<usize, Errno> {
+        let uid = self.proc().argint(0)? as u32;
+        self.proc().info.lock().cred.setuid(uid).map(|_| 0)
+    }
+
+    /// Return the real group id of the current process.
+    pub fn sys_getgid(&self) -> Result<usize, Errno> {
+        Ok(self.proc().cred().gid as _)
+    }
+
+    /// Set the group id of the current process, with the same rules as setuid.
+    /// Returns Ok(0) on success, Err(EPERM) if not allowed.
+    pub fn sys_setgid(&self) -> Result<usize, Errno> {
+        let gid = self.proc().argint(0)? as u32;
+        self.proc().info.lock().cred.setgid(gid).map(|_| 0)
+    }
+
     /// Grow process’s memory by n bytes.
-    /// Returns Ok(start of new memory) on success, Err(()) on error.
-    pub fn sys_sbrk(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(start of new memory) on success, Err(ENOMEM) on error.
+    pub fn sys_sbrk(&mut self) -> Result<usize, Errno> {
         let n = self.proc().argint(0)?;
-        self.proc_mut().memory_mut().resize(n, hal().kmem())
+        self.proc_mut()
+            .memory_mut()
+            .resize(n, hal().kmem())
+            .map_err(|_| Errno::ENOMEM)
+    }
+
+    /// Map len bytes of an anonymous memory or a file at offset off into the
+    /// memory. Only private mappings are supported, as writes to a mapping are
+    /// never written back to the file.
+    /// Returns Ok(start of the mapping) on success, Err(errno) on error.
+    pub fn sys_mmap(&mut self) -> Result<usize, Errno> {
+        let len = self.proc().argaddr(1)?;
+        let prot = ProtFlags::from_bits(self.proc().argint(2)?).ok_or(Errno::EINVAL)?;
+        let flags = MapFlags::from_bits(self.proc().argint(3)?).ok_or(Errno::EINVAL)?;
+        let off = self.proc().argint(5)?;
+
+        if !prot.contains(ProtFlags::PROT_READ)
+            || flags.contains(MapFlags::MAP_FIXED)
+            || flags.contains(MapFlags::MAP_SHARED) == flags.contains(MapFlags::MAP_PRIVATE)
+        {
+            return Err(Errno::EINVAL);
+        }
+
+        if flags.contains(MapFlags::MAP_SHARED) {
+            return Err(Errno::EINVAL);
+        }
+
+        if flags.contains(MapFlags::MAP_ANONYMOUS) {
+            let va = self
+                .proc_mut()
+                .memory_mut()
+                .map(len, prot.into())
+                .map_err(|_| Errno::ENOMEM)?;
+            return Ok(va.into_usize());
+        }
+
+        if off < 0 || off as usize % PGSIZE != 0 {
+            return Err(Errno::EINVAL);
+        }
+        let (_, f) = self.proc().argfd(4)?;
+        let va = self
+            .proc_mut()
+            .memory_mut()
+            .map(len, prot.into())
+            .map_err(|_| Errno::ENOMEM);
+        let res = va.and_then(|va| {
+            if let Err(e) = f.mmap(va, len, off as u32, self) {
+                let _ = self.proc_mut().memory_mut().unmap(va, len, hal().kmem());
+                return Err(e);
+            }
+            Ok(va.into_usize())
+        });
+        f.free(self);
+        res
+    }
+
+    /// Remove the mappings in the len bytes from addr.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_munmap(&mut self) -> Result<usize, Errno> {
+        let addr = self.proc().argaddr(0)?;
+        let len = self.proc().argaddr(1)?;
+        self.proc_mut()
+            .memory_mut()
+            .unmap(addr.into(), len, hal().kmem())
+            .map_err(|_| Errno::EINVAL)?;
+        Ok(0)
+    }
+
+    /// Change the protection of the len bytes of memory from addr.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_mprotect(&mut self) -> Result<usize, Errno> {
+        let addr = self.proc().argaddr(0)?;
+        let len = self.proc().argaddr(1)?;
+        let prot = ProtFlags::from_bits(self.proc().argint(2)?).ok_or(Errno::EINVAL)?;
+        if !prot.contains(ProtFlags::PROT_READ) {
+            return Err(Errno::EINVAL);
+        }
+        self.proc_mut()
+            .memory_mut()
+            .protect(addr.into(), len, prot.into(), hal().kmem())
+            .map_err(|_| Errno::EINVAL)?;
+        Ok(0)
+    }
+
+    /// Examine and change the action for a signal.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_sigaction(&mut self) -> Result<usize, Errno> {
+        let sig = self.proc().argint(0)? as usize;
+        let act = self.proc().argaddr(1)?;
+        let oldact = self.proc().argaddr(2)?;
+        if !signal::is_valid(sig) {
+            return Err(Errno::EINVAL);
+        }
+        let act = if act == 0 {
+            None
+        } else {
+            let mut action = SigAction::default();
+            // SAFETY: SigAction does not have any internal structure.
+            unsafe {
+                self.proc_mut()
+                    .memory_mut()
+                    .copy_in(&mut action, act.into())
+            }?;
+            Some(action)
+        };
+        let old = self
+            .proc()
+            .info
+            .lock()
+            .signals
+            .sigaction(sig, act)
+            .map_err(|_| Errno::EINVAL)?;
+        if oldact != 0 {
+            self.proc_mut().memory_mut().copy_out(oldact.into(), &old)?;
+        }
+        Ok(0)
+    }
+
+    /// Examine and change the blocked signals.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_sigprocmask(&mut self) -> Result<usize, Errno> {
+        let how = self.proc().argint(0)?;
+        let set = self.proc().argaddr(1)?;
+        let oldset = self.proc().argaddr(2)?;
+        let set = if set == 0 {
+            None
+        } else {
+            let mut mask = 0u32;
+            // SAFETY: u32 does not have any internal structure.
+            unsafe { self.proc_mut().memory_mut().copy_in(&mut mask, set.into()) }?;
+            Some(mask)
+        };
+        let old = self
+            .proc()
+            .info
+            .lock()
+            .signals
+            .sigprocmask(how, set)
+            .map_err(|_| Errno::EINVAL)?;
+        if oldset != 0 {
+            self.proc_mut().memory_mut().copy_out(oldset.into(), &old)?;
+        }
+        Ok(0)
+    }
+
+    /// Return from a signal handler, restoring the state saved when it was entered.
+    /// Returns Ok(the restored return value register). Terminates the process if
+    /// the saved state cannot be read, since there is nowhere to return to.
+    pub fn sys_sigreturn(&mut self) -> Result<usize, Errno> {
+        match self.sigreturn() {
+            Ok(ret) => Ok(ret),
+            Err(()) => self.kernel().procs().exit_current(-1, self),
+        }
+    }
+
+    /// Create a thread that runs fn(arg) on the given stack.
+    /// Returns Ok(thread id) on success, Err(errno) on error.
+    pub fn sys_clone(&mut self) -> Result<usize, Errno> {
+        let pc = self.proc().argaddr(0)?;
+        let arg = self.proc().argaddr(1)?;
+        let sp = self.proc().argaddr(2)?;
+        let ctid = self.proc().argaddr(3)?;
+        Ok(self.kernel().procs().clone(pc, sp, arg, ctid, self)? as _)
+    }
+
+    /// Wait on or wake the futex at addr.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_futex(&mut self) -> Result<usize, Errno> {
+        let addr = self.proc().argaddr(0)?;
+        let op = self.proc().argint(1)?;
+        let val = self.proc().argint(2)?;
+        match op {
+            FUTEX_WAIT => self.kernel().futexes().wait(addr.into(), val, self)?,
+            FUTEX_WAKE => self.kernel().futexes().wake(addr.into(), self.kernel()),
+            _ => return Err(Errno::ENOSYS),
+        }
+        Ok(0)
+    }
+
+    /// Add inc to the nice value of the current process.
+    /// Returns Ok(20 - new nice value) on success, Err(errno) on error.
+    pub fn sys_nice(&self) -> Result<usize, Errno> {
+        let inc = self.proc().argint(0)?;
+        let mut info = self.proc().info.lock();
+        let nice = info
+            .sched
+            .nice()
+            .saturating_add(inc)
+            .clamp(NICE_MIN, NICE_MAX);
+        info.sched.set_nice(nice);
+        Ok(nice_to_ret(nice))
+    }
+
+    /// Return the nice value of the process who, or of the current process if who is 0.
+    /// Returns Ok(20 - nice value) on success, Err(errno) on error.
+    pub fn sys_getpriority(&self) -> Result<usize, Errno> {
+        let pid = self.priority_target()?;
+        Ok(nice_to_ret(self.kernel().procs().nice(pid)?))
+    }
+
+    /// Set the nice value of the process who, or of the current process if who is 0.
+    /// Values out of range are clamped.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_setpriority(&self) -> Result<usize, Errno> {
+        let pid = self.priority_target()?;
+        let nice = self.proc().argint(2)?.clamp(NICE_MIN, NICE_MAX);
+        self.kernel()
+            .procs()
+            .set_nice(pid, nice, &self.proc().cred())?;
+        Ok(0)
+    }
+
+    /// Returns the pid that the which and who arguments of getpriority and
+    /// setpriority name. Only PRIO_PROCESS is supported.
+    fn priority_target(&self) -> Result<i32, Errno> {
+        let which = self.proc().argint(0)?;
+        let who = self.proc().argint(1)?;
+        if which != PRIO_PROCESS {
+            return Err(Errno::EINVAL);
+        }
+        Ok(if who == 0 { self.proc().pid() } else { who })
+    }
+
+    /// Restrict the process pid, or the current process if pid is 0, to the
+    /// CPUs in the mask at the given address, which has the given size in bytes.
+    /// Only the first word of the mask is used, since it covers every CPU.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_sched_setaffinity(&mut self) -> Result<usize, Errno> {
+        let pid = self.affinity_target()?;
+        let size = self.proc().argint(1)?;
+        let addr = self.proc().argaddr(2)?;
+        if size < mem::size_of::<usize>() as i32 {
+            return Err(Errno::EINVAL);
+        }
+        let mut mask = 0usize;
+        // SAFETY: usize does not have any internal structure.
+        unsafe { self.proc_mut().memory_mut().copy_in(&mut mask, addr.into()) }?;
+        self.kernel().procs().set_affinity(pid, mask)?;
+        Ok(0)
+    }
+
+    /// Store the mask of the CPUs that the process pid, or the current process
+    /// if pid is 0, may run on at the given address, which has room for the
+    /// given size in bytes.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_sched_getaffinity(&mut self) -> Result<usize, Errno> {
+        let pid = self.affinity_target()?;
+        let size = self.proc().argint(1)?;
+        let addr = self.proc().argaddr(2)?;
+        if size < mem::size_of::<usize>() as i32 {
+            return Err(Errno::EINVAL);
+        }
+        let mask = self.kernel().procs().affinity(pid)?;
+        self.proc_mut().memory_mut().copy_out(addr.into(), &mask)?;
+        Ok(0)
+    }
+
+    /// Returns the pid that the first argument of sched_setaffinity and
+    /// sched_getaffinity names.
+    fn affinity_target(&self) -> Result<i32, Errno> {
+        let pid = self.proc().argint(0)?;
+        Ok(if pid == 0 { self.proc().pid() } else { pid })
     }
 
     /// Pause for n clock ticks.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_sleep(&self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_sleep(&self) -> Result<usize, Errno> {
         let n = self.proc().argint(0)?;
-        assert!(n >= 0);
+        if n < 0 {
+            return Err(Errno::EINVAL);
+        }
 
-        let mut ticks = self.kernel().ticks().lock();
-        let ticks0 = *ticks;
-        while ticks.wrapping_sub(ticks0) < n as u32 {
-            if self.proc().killed() {
-                return Err(());
+        let now = uptime()?;
+        self.sleep_until(now + n as usize * TICK_MICROS)?;
+        Ok(0)
+    }
+
+    /// Pause for the time given by the Timespec at req, in microseconds.
+    /// If killed before, stores the time left at rem unless it is null.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_nanosleep(&mut self) -> Result<usize, Errno> {
+        let req = self.proc().argaddr(0)?;
+        let rem = self.proc().argaddr(1)?;
+        let duration = self.fetch_timespec(req)?;
+        let deadline = uptime()?.checked_add(duration).ok_or(Errno::EINVAL)?;
+        self.nanosleep_until(deadline, rem)
+    }
+
+    /// Pause on the given clock for the time given by the Timespec at req, or
+    /// until that time if flags has TIMER_ABSTIME. CLOCK_REALTIME counts from the
+    /// Unix epoch, and CLOCK_MONOTONIC from power-on.
+    /// If killed before, stores the time left at rem unless it is null or the time is absolute.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_clock_nanosleep(&mut self) -> Result<usize, Errno> {
+        let clock = self.proc().argint(0)?;
+        let flags = self.proc().argint(1)?;
+        let req = self.proc().argaddr(2)?;
+        let rem = self.proc().argaddr(3)?;
+        if clock != CLOCK_REALTIME && clock != CLOCK_MONOTONIC {
+            return Err(Errno::EINVAL);
+        }
+        let time = self.fetch_timespec(req)?;
+        if flags & TIMER_ABSTIME != 0 {
+            let deadline = if clock == CLOCK_REALTIME {
+                // The uptime at which the real time reaches `time`.
+                let now = uptime()?;
+                let realtime = TargetArch::realtime_as_micro().map_err(|_| Errno::ENOSYS)?;
+                time.checked_add(now)
+                    .ok_or(Errno::EINVAL)?
+                    .saturating_sub(realtime)
+            } else {
+                time
+            };
+            return self.nanosleep_until(deadline, 0);
+        }
+        let deadline = uptime()?.checked_add(time).ok_or(Errno::EINVAL)?;
+        self.nanosleep_until(deadline, rem)
+    }
+
+    /// Read the Timespec at addr, in microseconds.
+    fn fetch_timespec(&mut self, addr: usize) -> Result<usize, Errno> {
+        let mut ts = Timespec::default();
+        // SAFETY: Timespec does not have any internal structure.
+        unsafe { self.proc_mut().memory_mut().copy_in(&mut ts, addr.into()) }?;
+        ts.to_micros()
+    }
+
+    /// Sleep until the uptime reaches deadline, in microseconds.
+    /// If killed before, stores the time left at rem unless it is null.
+    fn nanosleep_until(&mut self, deadline: usize, rem: usize) -> Result<usize, Errno> {
+        if let Err(e) = self.sleep_until(deadline) {
+            if rem != 0 {
+                let left = deadline.saturating_sub(uptime()?);
+                self.proc_mut()
+                    .memory_mut()
+                    .copy_out(rem.into(), &Timespec::from_micros(left))?;
             }
-            ticks.sleep(self);
+            return Err(e);
         }
         Ok(0)
     }
 
-    /// Terminate process PID.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_kill(&self) -> Result<usize, ()> {
+    /// Send a signal to process PID.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_kill(&self) -> Result<usize, Errno> {
         let pid = self.proc().argint(0)?;
-        self.kernel().procs().kill(pid)?;
+        let sig = self.proc().argint(1)? as usize;
+        if sig != 0 && !signal::is_valid(sig) {
+            return Err(Errno::EINVAL);
+        }
+        self.kernel().procs().kill(pid, sig, &self.proc().cred())?;
         Ok(0)
     }
 
-    /// Return how many clock tick interrupts have occurred
+    /// Return how many clock ticks have passed
     /// since start.
-    pub fn sys_uptime(&self) -> Result<usize, ()> {
+    pub fn sys_uptime(&self) -> Result<usize, Errno> {
         Ok(*self.kernel().ticks().lock() as usize)
     }
 
     /// Return how much time has passed since start,
     /// in microseconds.
-    pub fn sys_uptime_as_micro(&self) -> Result<usize, ()> {
-        TargetArch::uptime_as_micro()
+    pub fn sys_uptime_as_micro(&self) -> Result<usize, Errno> {
+        uptime()
     }
 
     /// Shutdowns this machine, discarding all unsaved data. No return.
-    pub fn sys_poweroff(&self) -> Result<usize, ()> {
+    /// Returns Err(EPERM) if the current process is not root.
+    pub fn sys_poweroff(&self) -> Result<usize, Errno> {
         let exitcode = self.proc().argint(0)?;
+        if !self.proc().cred().is_root() {
+            return Err(Errno::EPERM);
+        }
         TargetArch::machine_poweroff(exitcode as _);
     }
 
     /// Return a new file descriptor referring to the same file as given fd.
-    /// Returns Ok(new file descriptor) on success, Err(()) on error.
-    pub fn sys_dup(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(new file descriptor) on success, Err(errno) on error.
+    pub fn sys_dup(&mut self) -> Result<usize, Errno> {
         let (_, f) = self.proc().argfd(0)?;
-        let newfile = f.clone();
-        let fd = newfile.fdalloc(self)?;
+        let fd = f.fdalloc(self)?;
         Ok(fd as usize)
     }
 
     /// Read n bytes into buf.
-    /// Returns Ok(number read) on success, Err(()) on error.
-    pub fn sys_read(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(number read) on success, Err(errno) on error.
+    pub fn sys_read(&mut self) -> Result<usize, Errno> {
         let (_, f) = self.proc().argfd(0)?;
         let n = self.proc().argint(2)?;
         let p = self.proc().argaddr(1)?;
-        // SAFETY: read will not access proc's open_files.
-        unsafe { (*(f as *const RcFile)).read(p.into(), n, self) }
+        let res = f.read(p.into(), n, self);
+        f.free(self);
+        res
+    }
+
+    /// Read the entries of the directory fd into buf of n bytes, as
+    /// `struct dirent64`s.
+    /// Returns Ok(number of bytes read), which is 0 at the end of the directory,
+    /// or Err(errno) on error.
+    pub fn sys_getdents(&mut self) -> Result<usize, Errno> {
+        let n = usize::try_from(self.proc().argint(2)?).map_err(|_| Errno::EINVAL)?;
+        let p = self.proc().argaddr(1)?;
+        let (_, f) = self.proc().argfd(0)?;
+        let res = f.getdents(p.into(), n, self);
+        f.free(self);
+        res
     }
 
     /// Write n bytes from buf to given file descriptor fd.
-    /// Returns Ok(n) on success, Err(()) on error.
-    pub fn sys_write(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(n) on success, Err(errno) on error.
+    pub fn sys_write(&mut self) -> Result<usize, Errno> {
         let (_, f) = self.proc().argfd(0)?;
         let n = self.proc().argint(2)?;
         let p = self.proc().argaddr(1)?;
-        // SAFETY: write will not access proc's open_files.
-        unsafe { (*(f as *const RcFile)).write(p.into(), n, self) }
+        let res = f.write(p.into(), n, self);
+        f.free(self);
+        res
     }
 
     /// Release open file fd.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_close(&mut self) -> Result<usize, ()> {
-        let (fd, _) = self.proc().argfd(0)?;
-        if let Some(f) = self.proc_mut().deref_mut_data().open_files[fd as usize].take() {
-            f.free(self);
-        }
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_close(&mut self) -> Result<usize, Errno> {
+        let fd = self.proc().argint(0)?;
+        let f = self.proc().files().take(fd).ok_or(Errno::EBADF)?;
+        f.free(self);
         Ok(0)
     }
 
     /// Place info about an open file into struct stat.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_fstat(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_fstat(&mut self) -> Result<usize, Errno> {
         let (_, f) = self.proc().argfd(0)?;
         // user pointer to struct stat
         let st = self.proc().argaddr(1)?;
-        // SAFETY: stat will not access proc's open_files.
-        unsafe { (*(f as *const RcFile)).stat(st.into(), self) }?;
+        let res = f.stat(st.into(), self);
+        f.free(self);
+        res.map(|_| 0)
+    }
+
+    /// Write the changes of the open file fd to the disk, and wait until they are there.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_fsync(&mut self) -> Result<usize, Errno> {
+        let (_, f) = self.proc().argfd(0)?;
+        let res = f.sync(self);
+        f.free(self);
+        res.map(|_| 0)
+    }
+
+    /// The same as fsync, since the file systems log the data and the metadata
+    /// of a file together.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_fdatasync(&mut self) -> Result<usize, Errno> {
+        self.sys_fsync()
+    }
+
+    /// Write the changes of all mounted file systems to the disks.
+    /// Returns Ok(0).
+    pub fn sys_sync(&mut self) -> Result<usize, Errno> {
+        self.kernel().mounts().sync(self);
         Ok(0)
     }
 
     /// Create the path new as a link to the same inode as old.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_link(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_link(&mut self) -> Result<usize, Errno> {
         let mut new: [u8; MAXPATH] = [0; MAXPATH];
         let mut old: [u8; MAXPATH] = [0; MAXPATH];
         let old = Path::new(self.proc_mut().argstr(0, &mut old)?);
         let new = Path::new(self.proc_mut().argstr(1, &mut new)?);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
+        let ip = self.namei(old)?;
         let res = try {
-            let inode = self.kernel().fs().namei(old, &tx, self)?;
-            let _ = self.kernel().fs().link(inode, new, &tx, self)?;
+            let (dir, name) = self.nameiparent(new)?;
+            let res = ip.link(&dir, name, self);
+            dir.free(self);
+            res?;
             0
         };
-        tx.end(self);
+        ip.free(self);
         res
     }
 
+    /// Create the path path as a symbolic link to target.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_symlink(&mut self) -> Result<usize, Errno> {
+        let mut target: [u8; MAXPATH] = [0; MAXPATH];
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let target = self.proc_mut().argstr(0, &mut target)?;
+        let path = Path::new(self.proc_mut().argstr(1, &mut path)?);
+        self.symlink(target.to_bytes(), path).map(|_| 0)
+    }
+
+    /// Read the target of the symbolic link path into buf, by at most n bytes.
+    /// The target is not NUL-terminated.
+    /// Returns Ok(number of bytes read) on success, Err(errno) on error.
+    pub fn sys_readlink(&mut self) -> Result<usize, Errno> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let buf = self.proc().argaddr(1)?;
+        let n = self.proc().argint(2)?;
+        if n <= 0 {
+            return Err(Errno::EINVAL);
+        }
+        self.readlink(path, buf.into(), n as u32)
+    }
+
+    /// Move the path old to the path new, replacing new if it exists.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_rename(&mut self) -> Result<usize, Errno> {
+        let mut new: [u8; MAXPATH] = [0; MAXPATH];
+        let mut old: [u8; MAXPATH] = [0; MAXPATH];
+        let old = Path::new(self.proc_mut().argstr(0, &mut old)?);
+        let new = Path::new(self.proc_mut().argstr(1, &mut new)?);
+        self.rename(old, new).map(|_| 0)
+    }
+
+    /// Set the permission bits of path to mode.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_chmod(&mut self) -> Result<usize, Errno> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let mode = self.proc().argint(1)?;
+        self.chmod(path, mode as u16).map(|_| 0)
+    }
+
+    /// Change the owner and the group of path. An id of -1 is left unchanged.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_chown(&mut self) -> Result<usize, Errno> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let owner = self.proc().argint(1)?;
+        let group = self.proc().argint(2)?;
+        let id = |id: i32| (id != -1).then(|| id as u32);
+        self.chown(path, id(owner), id(group)).map(|_| 0)
+    }
+
+    /// Set the access and modification times of path to the utimbuf at times,
+    /// or to the current time if times is null.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_utime(&mut self) -> Result<usize, Errno> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let addr = self.proc().argaddr(1)?;
+        let times = if addr == 0 {
+            None
+        } else {
+            let mut buf = Utimbuf::default();
+            // SAFETY: Utimbuf does not have any internal structure.
+            unsafe { self.proc_mut().memory_mut().copy_in(&mut buf, addr.into()) }?;
+            Some((buf.actime, buf.modtime))
+        };
+        self.utime(path, times).map(|_| 0)
+    }
+
     /// Remove a file.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_unlink(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_unlink(&mut self) -> Result<usize, Errno> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self.kernel().fs().unlink(path, &tx, self).map(|_| 0);
-        tx.end(self);
-        res
+        let (dir, name) = self.nameiparent(path)?;
+        let res = dir.unlink(name, self);
+        dir.free(self);
+        res.map(|_| 0)
     }
 
     /// Open a file.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_open(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_open(&mut self) -> Result<usize, Errno> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
         let omode = self.proc().argint(1)?;
         let omode = FcntlFlags::from_bits_truncate(omode);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self.kernel().fs().open(path, omode, &tx, self);
-        tx.end(self);
-        res
+        self.open(path, omode)
     }
 
     /// Create a new directory.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_mkdir(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_mkdir(&mut self) -> Result<usize, Errno> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self
-            .kernel()
-            .fs()
-            .create(path, InodeType::Dir, &tx, self, |_| ())
-            .map(|(ptr, _)| {
-                ptr.free((&tx, self));
-                0
-            });
-        tx.end(self);
-        res
+        self.create(path, InodeType::Dir)
     }
 
     /// Create a new device file.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_mknod(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(EPERM) if the current process is not root,
+    /// or Err(errno) on other errors.
+    pub fn sys_mknod(&mut self) -> Result<usize, Errno> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
         let major = self.proc().argint(1)? as u16;
         let minor = self.proc().argint(2)? as u16;
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self
-            .kernel()
-            .fs()
-            .create(path, InodeType::Device { major, minor }, &tx, self, |_| ())
-            .map(|(ptr, _)| {
-                ptr.free((&tx, self));
-                0
-            });
-        tx.end(self);
-        res
+        if !self.proc().cred().is_root() {
+            return Err(Errno::EPERM);
+        }
+        self.create(path, InodeType::Device { major, minor })
+    }
+
+    /// Create an inode of type `typ` at `path`.
+    /// Returns Ok(0) on success, Err(errno) on error.
+    fn create(&self, path: &Path, typ: InodeType) -> Result<usize, Errno> {
+        let (dir, name) = self.nameiparent(path)?;
+        let res = dir.create(name, typ, self);
+        dir.free(self);
+        let (ip, _) = res?;
+        ip.free(self);
+        Ok(0)
     }
 
     /// Change the current directory.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_chdir(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_chdir(&mut self) -> Result<usize, Errno> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = try {
-            let inode = self.kernel().fs().namei(path, &tx, self)?;
-            let _ = self.kernel().fs().chdir(inode, &tx, self)?;
-            0
+        self.chdir(path).map(|_| 0)
+    }
+
+    /// Mount the file system of type fstype on the disk dev at the directory target.
+    /// A tmpfs is mounted with dev TMPDEV, a procfs with dev PROCDEV, and a devfs
+    /// with dev DEVDEV, as they have no disk.
+    /// Returns Ok(0) on success, Err(EPERM) if the current process is not root,
+    /// Err(ENODEV) if there is no such file system type or disk, or Err(errno) on
+    /// other errors.
+    pub fn sys_mount(&mut self) -> Result<usize, Errno> {
+        let mut target: [u8; MAXPATH] = [0; MAXPATH];
+        let mut fstype: [u8; MAXPATH] = [0; MAXPATH];
+        let dev = self.proc().argint(0)? as u32;
+        let target = Path::new(self.proc_mut().argstr(1, &mut target)?);
+        let fstype = self.proc_mut().argstr(2, &mut fstype)?;
+        if !self.proc().cred().is_root() {
+            return Err(Errno::EPERM);
+        }
+        let typ = FsType::from_name(fstype.to_bytes()).ok_or(Errno::ENODEV)?;
+        let present = match typ {
+            // A tmpfs, a procfs, and a devfs do not have a disk.
+            FsType::Tmpfs => dev == TMPDEV,
+            FsType::Procfs => dev == PROCDEV,
+            FsType::Devfs => dev == DEVDEV,
+            FsType::Ufs | FsType::Lfs => hal().disks().is_present(dev),
         };
-        tx.end(self);
-        res
+        if !present {
+            return Err(Errno::ENODEV);
+        }
+        let dir = self.namei(target)?;
+        self.kernel().mounts().mount(typ, dev, dir, self)?;
+        Ok(0)
+    }
+
+    /// Unmount the file system mounted at the directory target.
+    /// Returns Ok(0) on success, Err(EPERM) if the current process is not root,
+    /// or Err(errno) on other errors.
+    pub fn sys_umount(&mut self) -> Result<usize, Errno> {
+        let mut target: [u8; MAXPATH] = [0; MAXPATH];
+        let target = Path::new(self.proc_mut().argstr(0, &mut target)?);
+        if !self.proc().cred().is_root() {
+            return Err(Errno::EPERM);
+        }
+        let root = self.namei(target)?;
+        self.kernel().mounts().umount(root, self)?;
+        Ok(0)
+    }
+
+    /// Set the commit latency and the expire time of dirty buffers to
+    /// commit_ticks and expire_ticks, in clock ticks. A negative value keeps the time.
+    /// Returns Ok(0) on success, Err(EPERM) if the current process is not root.
+    pub fn sys_writeback(&mut self) -> Result<usize, Errno> {
+        let commit_ticks = self.proc().argint(0)?;
+        let expire_ticks = self.proc().argint(1)?;
+        if !self.proc().cred().is_root() {
+            return Err(Errno::EPERM);
+        }
+        self.kernel()
+            .writeback()
+            .set(commit_ticks.try_into().ok(), expire_ticks.try_into().ok());
+        Ok(0)
     }
 
     /// Load a file and execute it with arguments.
-    /// Returns Ok(argc argument to user main) on success, Err(()) on error.
-    pub fn sys_exec(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(argc argument to user main) on success, Err(errno) on error.
+    pub fn sys_exec(&mut self) -> Result<usize, Errno> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let mut args = ArrayVec::<Page, MAXARG>::new();
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
         let uargv = self.proc().argaddr(1)?;
         let allocator = hal().kmem();
 
-        let mut success = false;
+        // Stays Err(E2BIG) if argv has no null within MAXARG entries.
+        let mut res = Err(Errno::E2BIG);
         for i in 0..MAXARG {
-            let uarg = ok_or!(
-                self.proc_mut()
-                    .fetchaddr((uargv + mem::size_of::<usize>() * i).into()),
-                break
-            );
+            let uarg = match self
+                .proc_mut()
+                .fetchaddr((uargv + mem::size_of::<usize>() * i).into())
+            {
+                Ok(uarg) => uarg,
+                Err(e) => {
+                    res = Err(e);
+                    break;
+                }
+            };
 
             if uarg == 0 {
-                success = true;
+                res = Ok(());
                 break;
             }
 
-            let mut page = some_or!(allocator.alloc(None), break);
-            if self
-                .proc_mut()
-                .fetchstr(uarg.into(), &mut page[..])
-                .is_err()
-            {
+            let mut page = some_or!(allocator.alloc(None), {
+                res = Err(Errno::ENOMEM);
+                break;
+            });
+            if let Err(e) = self.proc_mut().fetchstr(uarg.into(), &mut page[..]) {
                 allocator.free(page);
+                // An argument that does not fit in a page is too long.
+                res = Err(if e == Errno::ENAMETOOLONG {
+                    Errno::E2BIG
+                } else {
+                    e
+                });
                 break;
             }
             args.push(page);
         }
 
-        let ret = if success {
-            self.exec(path, &args)
-        } else {
-            Err(())
-        };
+        let ret = res.and_then(|_| self.exec(path, &args));
 
         for page in args.drain(..) {
             allocator.free(page);
@@ -397,8 +958,8 @@ impl KernelCtx<'_, '_> {
     }
 
     /// Create a pipe.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_pipe(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(errno) on error.
+    pub fn sys_pipe(&mut self) -> Result<usize, Errno> {
         // user pointer to array of two integers
         let fdarray = self.proc().argaddr(0)?.into();
         self.pipe(fdarray)?;
@@ -410,8 +971,8 @@ impl KernelCtx<'_, '_> {
     (if not NULL) for exceptional conditions.  If TIMEOUT is not NULL, time out
     after waiting the interval specified therein.  Returns the number of ready
     descriptors, or -1 for errors.
-    Returns Ok(number of ready descriptors) on success, Err(()) on error.  */
-    pub fn sys_select(&mut self) -> Result<usize, ()> {
+    Returns Ok(number of ready descriptors) on success, Err(errno) on error.  */
+    pub fn sys_select(&mut self) -> Result<usize, Errno> {
         let nfds = self.proc().argint(0)?;
         let read_fds = self.proc().argaddr(1)?;
         let write_fds = self.proc().argaddr(2)?;
@@ -460,16 +1021,10 @@ impl KernelCtx<'_, '_> {
                     let mask = 1 << (fd % 8);
 
                     if fds[i][idx] & mask != 0 {
-                        let f = self
-                            .proc()
-                            .deref_data()
-                            .open_files
-                            .get(fd as usize)
-                            .ok_or(())?
-                            .as_ref()
-                            .ok_or(())?;
-                        // SAFETY: `is_ready` will not access proc's open_files.
-                        if unsafe { (*(f as *const RcFile)).is_ready(event)? } {
+                        let f = self.proc().files().get(fd).ok_or(Errno::EBADF)?;
+                        let ready = f.is_ready(event);
+                        f.free(self);
+                        if ready? {
                             ready_cnt += 1;
                         } else {
                             // If the fd is not ready, clear the bit.
@@ -515,22 +1070,21 @@ impl KernelCtx<'_, '_> {
         Ok(ready_cnt)
     }
 
-    pub fn sys_getpagesize(&mut self) -> Result<usize, ()> {
+    pub fn sys_getpagesize(&mut self) -> Result<usize, Errno> {
         Ok(PGSIZE)
     }
 
-    pub fn sys_waitpid(&mut self) -> Result<usize, ()> {
+    pub fn sys_waitpid(&mut self) -> Result<usize, Errno> {
         let pid = self.proc().argint(0)?;
         let stat = self.proc().argaddr(1)?;
         Ok(self.kernel().procs().waitpid(pid, stat.into(), self)? as _)
     }
 
-    pub fn sys_getppid(&mut self) -> Result<usize, ()> {
+    pub fn sys_getppid(&mut self) -> Result<usize, Errno> {
         Ok(self.kernel().procs().get_parent_pid(self) as _)
     }
 
-    pub fn sys_lseek(&mut self) -> Result<usize, ()> {
-        let (_, f) = self.proc().argfd(0)?;
+    pub fn sys_lseek(&mut self) -> Result<usize, Errno> {
         let offset = self.proc().argint(1)?;
         let whence = self.proc().argint(2)?;
 
@@ -538,13 +1092,15 @@ impl KernelCtx<'_, '_> {
             0 => SeekWhence::Set,
             1 => SeekWhence::Cur,
             2 => SeekWhence::End,
-            _ => return Err(()),
+            _ => return Err(Errno::EINVAL),
         };
-        // SAFETY: `lseek` will not access proc's open_files.
-        unsafe { (*(f as *const RcFile)).lseek(offset, whence, self) }
+        let (_, f) = self.proc().argfd(0)?;
+        let res = f.lseek(offset, whence, self);
+        f.free(self);
+        res
     }
 
-    pub fn sys_clock(&mut self) -> Result<usize, ()> {
+    pub fn sys_clock(&mut self) -> Result<usize, Errno> {
         let p = self.proc().argaddr(0)?;
         let addr = UVAddr::from(p);
 
@@ -0,0 +1,411 @@
+//! Kernel timers.
+//!
+//! A timer calls a function once, when the uptime (`TimeManager::uptime_as_micro`)
+//! reaches the timer's expiry time in microseconds.
+//!
+//! Pending timers are kept in a hierarchical timer wheel. Level `l` of the wheel
+//! has `NSLOT` slots, each of which covers `NSLOT^l` microseconds. A timer sits in
+//! the lowest level whose slots tell its expiry time apart from the wheel's clock,
+//! and moves down to a lower level ("cascades") once the clock enters its slot.
+//! Hence adding or cancelling a timer takes constant time, and the wheel only
+//! does work for the occupied slots its clock passes, skipping the empty ones.
+//!
+//! Each CPU runs the expired timers on its timer interrupts, and adding a timer
+//! asks the current CPU for an interrupt at its expiry time. Therefore timers are
+//! not bound to the granularity of clock ticks.
+
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    arch::{interface::TimeManager, TargetArch},
+    errno::Errno,
+    kernel::KernelRef,
+    param::NTIMER,
+    proc::{KernelCtx, WaitChannel},
+};
+
+/// The number of microseconds between two clock ticks.
+pub const TICK_MICROS: usize = 100_000;
+
+/// Clocks of clock_nanosleep. CLOCK_REALTIME counts from the Unix epoch, and
+/// CLOCK_MONOTONIC from power-on.
+pub const CLOCK_REALTIME: i32 = 0;
+pub const CLOCK_MONOTONIC: i32 = 1;
+
+/// A flag of clock_nanosleep: the time is absolute.
+pub const TIMER_ABSTIME: i32 = 1;
+
+/// The number of bits of a time that select a slot in a level.
+const LEVEL_BITS: usize = 6;
+
+const NSLOT: usize = 1 << LEVEL_BITS;
+
+const SLOT_MASK: usize = NSLOT - 1;
+
+/// The number of levels. The wheel tells apart times up to 2^30 microseconds
+/// (about 18 minutes) ahead of its clock. Timers even further ahead wait in
+/// slot 0 of the top level, which cascades whenever the top level wraps around.
+const NLEVEL: usize = 5;
+
+/// The end of a list of timers.
+const NONE: usize = usize::MAX;
+
+/// A function that a timer calls with the kernel and the timer's argument.
+///
+/// It runs with the lock of the `TimerWheel` held, possibly in an interrupt
+/// handler, so it must neither sleep nor use the `TimerWheel`.
+pub type TimerFn = for<'id, 's> fn(KernelRef<'id, 's>, usize);
+
+/// Names a timer added to a `TimerWheel`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct TimerId {
+    index: usize,
+    generation: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Timer {
+    /// `None` if the timer is free.
+    callback: Option<TimerFn>,
+    arg: usize,
+    expires: usize,
+    /// Incremented whenever the timer is freed, so that a stale `TimerId`
+    /// never names a reused timer.
+    generation: usize,
+    level: usize,
+    slot: usize,
+    prev: usize,
+    next: usize,
+}
+
+pub struct TimerWheel {
+    timers: [Timer; NTIMER],
+    /// The head of the list of timers in each slot.
+    slots: [[usize; NSLOT]; NLEVEL],
+    /// Bit `s` of `occupied[l]` is set iff `slots[l][s]` is not empty.
+    occupied: [usize; NLEVEL],
+    /// All timers that expire before `clock` have run.
+    clock: usize,
+}
+
+/// `struct timespec` of the user library.
+// It needs repr(C) because it is shared with user space.
+#[repr(C)]
+#[derive(Clone, Copy, Default, AsBytes, FromBytes)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+impl Timer {
+    const fn new() -> Self {
+        Self {
+            callback: None,
+            arg: 0,
+            expires: 0,
+            generation: 0,
+            level: 0,
+            slot: 0,
+            prev: NONE,
+            next: NONE,
+        }
+    }
+}
+
+impl TimerWheel {
+    pub const fn new() -> Self {
+        Self {
+            timers: [Timer::new(); NTIMER],
+            slots: [[NONE; NSLOT]; NLEVEL],
+            occupied: [0; NLEVEL],
+            clock: 0,
+        }
+    }
+
+    /// Adds a timer that calls `callback(kernel, arg)` once the uptime reaches
+    /// `expires` microseconds. Returns `Err(())` if all timers are in use.
+    pub fn add(&mut self, expires: usize, callback: TimerFn, arg: usize) -> Result<TimerId, ()> {
+        let index = self
+            .timers
+            .iter()
+            .position(|t| t.callback.is_none())
+            .ok_or(())?;
+        let timer = &mut self.timers[index];
+        timer.callback = Some(callback);
+        timer.arg = arg;
+        timer.expires = expires;
+        let id = TimerId {
+            index,
+            generation: timer.generation,
+        };
+        self.link(index);
+        TargetArch::set_timer_deadline(expires);
+        Ok(id)
+    }
+
+    /// Returns whether the timer has neither run nor been cancelled yet.
+    pub fn pending(&self, id: TimerId) -> bool {
+        let timer = &self.timers[id.index];
+        timer.callback.is_some() && timer.generation == id.generation
+    }
+
+    /// Cancels the timer. Returns `false` if it has already run or been cancelled.
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        if !self.pending(id) {
+            return false;
+        }
+        self.unlink(id.index);
+        self.free(id.index);
+        true
+    }
+
+    /// Runs the timers that expire at or before `now`, in the order of their
+    /// expiry times.
+    pub fn run(&mut self, now: usize, kernel: KernelRef<'_, '_>) {
+        while self.clock <= now {
+            let base = self.clock & !SLOT_MASK;
+            let end = now.min(base + SLOT_MASK);
+
+            // Run the level 0 slots between the clock and `end`.
+            loop {
+                let from = self.clock - base;
+                let to = end - base;
+                let due =
+                    self.occupied[0] & (usize::MAX >> (SLOT_MASK - to)) & (usize::MAX << from);
+                if due == 0 {
+                    break;
+                }
+                let slot = due.trailing_zeros() as usize;
+                self.clock = base + slot;
+                while self.slots[0][slot] != NONE {
+                    let index = self.slots[0][slot];
+                    self.unlink(index);
+                    let Timer { callback, arg, .. } = self.timers[index];
+                    self.free(index);
+                    if let Some(callback) = callback {
+                        callback(kernel, arg);
+                    }
+                }
+            }
+
+            if end == now {
+                self.clock = now + 1;
+                if self.clock & SLOT_MASK == 0 {
+                    self.cascade();
+                }
+                break;
+            }
+
+            // Level 0 is empty until the clock enters the next occupied slot
+            // of a higher level, so jump there.
+            match self.next_cascade(end) {
+                Some(next) if next <= now + 1 => {
+                    self.clock = next;
+                    self.cascade();
+                }
+                _ => self.clock = now + 1,
+            }
+        }
+
+        // This interrupt may have replaced the one asked for the next timer.
+        if let Some(next) = self.next_expiry() {
+            TargetArch::set_timer_deadline(next);
+        }
+    }
+
+    /// Returns the first time after `time` at which the clock enters an
+    /// occupied slot of a level above 0. `time` must be the last microsecond
+    /// of the clock's level 0 slots.
+    fn next_cascade(&self, time: usize) -> Option<usize> {
+        let mut next = None;
+        for level in 1..NLEVEL {
+            let shift = LEVEL_BITS * level;
+            let slot = self.slot_of(time, level);
+            let later = self.occupied[level] & !(usize::MAX >> (SLOT_MASK - slot));
+            let entered = if later != 0 {
+                let window = time >> (shift + LEVEL_BITS) << (shift + LEVEL_BITS);
+                window + ((later.trailing_zeros() as usize) << shift)
+            } else if level == NLEVEL - 1 && self.occupied[level] & 1 != 0 {
+                // Slot 0 of the top level cascades when the top level wraps around.
+                ((time >> (shift + LEVEL_BITS)) + 1) << (shift + LEVEL_BITS)
+            } else {
+                continue;
+            };
+            next = Some(next.map_or(entered, |next: usize| next.min(entered)));
+        }
+        next
+    }
+
+    /// Returns the earliest expiry time of the pending timers.
+    fn next_expiry(&self) -> Option<usize> {
+        // The timers of a level expire before those of the levels above, and,
+        // within a level, those of a lower slot first. Above level 0, slot 0
+        // holds only the timers beyond the top level, which expire last.
+        let level = (0..NLEVEL).find(|level| self.occupied[*level] != 0)?;
+        let occupied = self.occupied[level];
+        let slot = if level > 0 && occupied & !1 != 0 {
+            (occupied & !1).trailing_zeros()
+        } else {
+            occupied.trailing_zeros()
+        } as usize;
+
+        let mut index = self.slots[level][slot];
+        let mut next = usize::MAX;
+        while index != NONE {
+            next = next.min(self.timers[index].expires);
+            index = self.timers[index].next;
+        }
+        Some(next)
+    }
+
+    /// Moves the timers in the slots the clock has just entered down to lower
+    /// levels. The clock must be a multiple of `NSLOT`.
+    fn cascade(&mut self) {
+        // The levels above 1 whose slot index wrapped around to 0 also entered
+        // a new slot. Start from the highest one, since its timers may move to
+        // the slots of the lower ones.
+        let mut top = 1;
+        while top < NLEVEL - 1 && self.slot_of(self.clock, top) == 0 {
+            top += 1;
+        }
+        for level in (1..=top).rev() {
+            let slot = self.slot_of(self.clock, level);
+            let mut index = self.slots[level][slot];
+            self.slots[level][slot] = NONE;
+            self.occupied[level] &= !(1 << slot);
+            while index != NONE {
+                let next = self.timers[index].next;
+                self.link(index);
+                index = next;
+            }
+        }
+    }
+
+    fn slot_of(&self, time: usize, level: usize) -> usize {
+        (time >> (LEVEL_BITS * level)) & SLOT_MASK
+    }
+
+    /// Puts the timer into the slot for its expiry time.
+    fn link(&mut self, index: usize) {
+        let expires = self.timers[index].expires.max(self.clock);
+        let (level, slot) = (0..NLEVEL)
+            .find(|level| {
+                let shift = LEVEL_BITS * (level + 1);
+                expires >> shift == self.clock >> shift
+            })
+            .map_or((NLEVEL - 1, 0), |level| {
+                (level, self.slot_of(expires, level))
+            });
+
+        let head = self.slots[level][slot];
+        let timer = &mut self.timers[index];
+        timer.level = level;
+        timer.slot = slot;
+        timer.prev = NONE;
+        timer.next = head;
+        if head != NONE {
+            self.timers[head].prev = index;
+        }
+        self.slots[level][slot] = index;
+        self.occupied[level] |= 1 << slot;
+    }
+
+    /// Takes the timer off the list of its slot.
+    fn unlink(&mut self, index: usize) {
+        let Timer {
+            level,
+            slot,
+            prev,
+            next,
+            ..
+        } = self.timers[index];
+        if prev == NONE {
+            self.slots[level][slot] = next;
+            if next == NONE {
+                self.occupied[level] &= !(1 << slot);
+            }
+        } else {
+            self.timers[prev].next = next;
+        }
+        if next != NONE {
+            self.timers[next].prev = prev;
+        }
+    }
+
+    fn free(&mut self, index: usize) {
+        let timer = &mut self.timers[index];
+        timer.callback = None;
+        timer.generation = timer.generation.wrapping_add(1);
+    }
+}
+
+impl Timespec {
+    /// Returns the time in microseconds, rounded up.
+    /// Returns `Err(EINVAL)` if the time is negative or out of range.
+    pub fn to_micros(&self) -> Result<usize, Errno> {
+        if self.tv_sec < 0 || !(0..1_000_000_000).contains(&self.tv_nsec) {
+            return Err(Errno::EINVAL);
+        }
+        let sec = usize::try_from(self.tv_sec).map_err(|_| Errno::EINVAL)?;
+        let micros = (self.tv_nsec as usize + 999) / 1000;
+        sec.checked_mul(1_000_000)
+            .and_then(|t| t.checked_add(micros))
+            .ok_or(Errno::EINVAL)
+    }
+
+    pub fn from_micros(micros: usize) -> Self {
+        Self {
+            tv_sec: (micros / 1_000_000) as i64,
+            tv_nsec: (micros % 1_000_000 * 1000) as i64,
+        }
+    }
+}
+
+/// Returns the real time in seconds since the Unix epoch, or 0 if the device
+/// has no real-time clock.
+pub fn realtime() -> u32 {
+    (TargetArch::realtime_as_micro().unwrap_or(0) / 1_000_000) as u32
+}
+
+/// Wakes up the `WaitChannel` at `arg`.
+fn wake_sleeper(kernel: KernelRef<'_, '_>, arg: usize) {
+    // SAFETY: `KernelCtx::sleep_until` cancels the timer before the channel goes away.
+    let waitchannel = unsafe { &*(arg as *const WaitChannel) };
+    waitchannel.wakeup(kernel);
+}
+
+impl KernelCtx<'_, '_> {
+    /// Sleeps until the uptime reaches `deadline` microseconds.
+    /// Returns `Err(EINTR)` if the process is killed before, or `Err(EAGAIN)` if all
+    /// timers are in use.
+    pub fn sleep_until(&self, deadline: usize) -> Result<(), Errno> {
+        self.sleep_timer(deadline, true)
+    }
+
+    /// Sleeps until the uptime reaches `deadline` microseconds, even if the process
+    /// is killed. A kernel process, which never handles signals, uses it.
+    /// Returns `Err(EAGAIN)` if all timers are in use.
+    pub fn sleep_until_unkillable(&self, deadline: usize) -> Result<(), Errno> {
+        self.sleep_timer(deadline, false)
+    }
+
+    fn sleep_timer(&self, deadline: usize, killable: bool) -> Result<(), Errno> {
+        let waitchannel = WaitChannel::new();
+        let mut timers = self.kernel().timers().lock();
+        let id = timers
+            .add(
+                deadline,
+                wake_sleeper,
+                &waitchannel as *const WaitChannel as usize,
+            )
+            .map_err(|_| Errno::EAGAIN)?;
+        while timers.pending(id) {
+            if killable && self.proc().killed() {
+                let _ = timers.cancel(id);
+                return Err(Errno::EINTR);
+            }
+            waitchannel.sleep(&mut timers, self);
+        }
+        Ok(())
+    }
+}
@@ -1,12 +1,14 @@
 use core::fmt;
 
 use crate::{
-    arch::interface::{ProcManager, TrapFrameManager, TrapManager},
+    arch::interface::{ProcManager, TimeManager, TrapFrameManager, TrapManager},
     arch::TargetArch,
     hal::hal,
     kernel::{kernel_ref, KernelRef},
     ok_or,
     proc::{kernel_ctx, KernelCtx, Procstate},
+    signal::SIGSEGV,
+    timer::TICK_MICROS,
 };
 
 /// In ARM.v8 architecture, interrupts are part
@@ -21,13 +23,27 @@ use crate::{
 pub enum TrapTypes {
     Irq(IrqTypes),
     Syscall,
+    /// A page fault caused by the given type of access at the given virtual address.
+    PageFault(PageFaultTypes, usize),
     BadTrap,
     TimerInterrupt,
+    /// An interrupt from another CPU that has changed the user page table
+    /// that this CPU runs with.
+    TlbShootdown,
+}
+
+/// Types of memory accesses that cause page faults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFaultTypes {
+    Load,
+    Store,
+    Instruction,
 }
 
 #[derive(Debug)]
 pub enum IrqTypes {
-    Virtio,
+    /// An interrupt from the `i`th virtio disk.
+    Virtio(usize),
     Uart,
     Others(IrqNum),
     Unknown(IrqNum),
@@ -60,6 +76,7 @@ impl KernelCtx<'_, '_> {
             TargetArch::is_user_trap(),
             "usertrap: not from user mode(EL0)"
         );
+        hal().cpus().leave_user();
 
         // Send interrupts and exceptions to kerneltrap(),
         // since we're now in the kernel.
@@ -78,6 +95,7 @@ impl KernelCtx<'_, '_> {
             TargetArch::before_handling_trap(&trap_type, Some(self.proc_mut().trap_frame_mut()));
         }
 
+        let mut ticked = false;
         match &trap_type {
             TrapTypes::Syscall => {
                 // system call
@@ -90,25 +108,27 @@ impl KernelCtx<'_, '_> {
                 // SAFETY: Interrupt handlers has been configured properly
                 unsafe { TargetArch::intr_on() };
                 let syscall_no = self.proc_mut().trap_frame_mut().get_param_reg(7.into()) as i32;
+                // A failed system call returns the negated errno.
                 *self.proc_mut().trap_frame_mut().param_reg_mut(0.into()) =
-                    ok_or!(self.syscall(syscall_no), usize::MAX);
+                    match self.syscall(syscall_no) {
+                        Ok(ret) => ret,
+                        Err(errno) => errno.to_ret(),
+                    };
             }
             TrapTypes::Irq(irq_type) => unsafe {
                 self.kernel().handle_irq(irq_type);
             },
-            TrapTypes::BadTrap => {
-                self.kernel().as_ref().write_str("usertrap(): ");
-
-                TargetArch::print_trap_status(|arg: fmt::Arguments<'_>| {
-                    self.kernel().as_ref().write_fmt(arg);
-                });
-                self.proc().kill();
-                self.kernel().procs().exit_current(-1, &mut self);
+            TrapTypes::PageFault(typ, addr) => {
+                if self.handle_page_fault(*typ, *addr).is_err() {
+                    self.bad_trap();
+                }
             }
+            TrapTypes::BadTrap => self.bad_trap(),
             TrapTypes::TimerInterrupt => {
-                if TargetArch::cpu_id() == 0 {
-                    self.kernel().clock_intr();
-                }
+                ticked = self.kernel().clock_intr();
+            }
+            TrapTypes::TlbShootdown => {
+                // The TLB is flushed on the way back to user space.
             }
         }
 
@@ -122,29 +142,75 @@ impl KernelCtx<'_, '_> {
             self.kernel().procs().exit_current(-1, &mut self);
         }
 
-        // Give up the CPU if this is a timer interrupt.
-        if let TrapTypes::TimerInterrupt = trap_type {
-            self.yield_cpu();
+        // Give up the CPU if a clock tick has passed and the scheduler says so.
+        if ticked {
+            self.tick();
         }
 
         unsafe { self.user_trap_ret() }
     }
 
+    /// Handle a page fault from user space. Allocates a page that has not
+    /// been accessed yet, or copies a copy-on-write page on a store.
+    /// Returns Ok(()) if the fault has been resolved, Err(()) otherwise.
+    fn handle_page_fault(&mut self, typ: PageFaultTypes, addr: usize) -> Result<(), ()> {
+        let allocator = hal().kmem();
+        let mut memory = self.proc_mut().memory_mut();
+        memory
+            .alloc_on_demand(addr.into(), allocator)
+            .or_else(|_| match typ {
+                PageFaultTypes::Store => memory.copy_on_write(addr.into(), allocator),
+                PageFaultTypes::Load | PageFaultTypes::Instruction => Err(()),
+            })
+    }
+
+    /// Send SIGSEGV to the current process. If the process does not catch it,
+    /// print the trap status and kill the process.
+    fn bad_trap(&mut self) {
+        if !self.proc().info.lock().signals.force(SIGSEGV) {
+            return;
+        }
+        self.kernel().as_ref().write_str("usertrap(): ");
+
+        TargetArch::print_trap_status(|arg: fmt::Arguments<'_>| {
+            self.kernel().as_ref().write_fmt(arg);
+        });
+        self.proc().kill();
+    }
+
     /// Return to user space.
     ///
     /// # Safety
     ///
     /// It must be called only by `user_trap`.
     pub unsafe fn user_trap_ret(mut self) -> ! {
+        // Run the handlers of pending signals.
+        self.handle_signals();
+
         // Tell trampoline.S the user page table to switch to.
         let user_table = self.proc().memory().page_table_addr();
 
         let kstack = self.proc_mut().deref_mut_data().kstack;
 
+        let trap_frame_va = self.proc().trap_frame_va();
+
         let trapframe = self.proc_mut().trap_frame_mut();
 
+        // Stay on this CPU until it is in user space, where the other CPUs
+        // may find it to flush its TLB.
+        TargetArch::intr_off();
+        hal().cpus().enter_user(user_table);
+
         // SAFETY: It is called by `user_trap_ret`, after handling the user trap.
-        unsafe { TargetArch::user_trap_ret(user_table, trapframe, kstack, usertrap as usize) };
+        unsafe {
+            TargetArch::user_trap_ret(
+                user_table,
+                trapframe,
+                trap_frame_va,
+                kstack,
+                usertrap as usize,
+            )
+        };
     }
 }
 
@@ -166,6 +232,7 @@ impl KernelRef<'_, '_> {
         unsafe {
             TargetArch::before_handling_trap(&trap_type, None);
         }
+        let mut ticked = false;
         match &trap_type {
             TrapTypes::Syscall => {
                 // kernel trap cannot be a syscall.
@@ -174,7 +241,7 @@ impl KernelRef<'_, '_> {
             TrapTypes::Irq(irq_type) => unsafe {
                 self.handle_irq(irq_type);
             },
-            TrapTypes::BadTrap => {
+            TrapTypes::PageFault(..) | TrapTypes::BadTrap => {
                 self.as_ref().write_str("kerneltrap(): ");
 
                 TargetArch::print_trap_status(|arg: fmt::Arguments<'_>| {
@@ -183,9 +250,10 @@ impl KernelRef<'_, '_> {
                 panic!("kerneltrap");
             }
             TrapTypes::TimerInterrupt => {
-                if TargetArch::cpu_id() == 0 {
-                    self.clock_intr();
-                }
+                ticked = self.clock_intr();
+            }
+            TrapTypes::TlbShootdown => {
+                // This CPU runs with no user page table in the kernel.
             }
         }
 
@@ -195,15 +263,15 @@ impl KernelRef<'_, '_> {
             TargetArch::after_handling_trap(&trap_type);
         }
 
-        // Give up the CPU if this is a timer interrupt.
-        if let TrapTypes::TimerInterrupt = trap_type {
+        // Give up the CPU if a clock tick has passed and the scheduler says so.
+        if ticked {
             // TODO(https://github.com/kaist-cp/rv6/issues/517): safety?
             if let Some(ctx) = unsafe { self.get_ctx() } {
                 // SAFETY:
-                // Reading state without lock is safe because `proc_yield` and `sched`
+                // Reading state without lock is safe because `tick` and `sched`
                 // is called after we check if current process is `RUNNING`.
                 if unsafe { (*ctx.proc().info.get_mut_raw()).state } == Procstate::RUNNING {
-                    ctx.yield_cpu();
+                    ctx.tick();
                 }
             }
         }
@@ -228,8 +296,8 @@ impl KernelRef<'_, '_> {
                 // SAFETY: it's unsafe only when ctrl+p is pressed.
                 unsafe { hal().console().intr(self) };
             }
-            IrqTypes::Virtio => {
-                hal().disk().pinned_lock().get_pin_mut().intr(self);
+            IrqTypes::Virtio(i) => {
+                hal().disks().intr(*i, self);
             }
             IrqTypes::Unknown(irq_num) => {
                 // Use `panic!` instead of `println` to prevent stack overflow.
@@ -242,9 +310,18 @@ impl KernelRef<'_, '_> {
         }
     }
 
-    fn clock_intr(self) {
-        let mut ticks = self.ticks().lock();
-        *ticks = ticks.wrapping_add(1);
-        ticks.wakeup(self);
+    /// Runs the expired timers. On CPU 0, also counts the clock ticks since boot.
+    /// Returns true if a clock tick has passed since this CPU's last one, so
+    /// that early interrupts for the timers do not shorten the time slice.
+    fn clock_intr(self) -> bool {
+        let now = ok_or!(TargetArch::uptime_as_micro(), return false);
+        self.timers().lock().run(now, self);
+        let ticks = (now - self.boot_time()) / TICK_MICROS;
+        if TargetArch::cpu_id() == 0 {
+            *self.ticks().lock() = ticks as u32;
+        }
+        // SAFETY: interrupts are disabled while handling a trap.
+        let cpu = unsafe { hal().cpus().current_unchecked() };
+        cpu.count_ticks(ticks)
     }
 }
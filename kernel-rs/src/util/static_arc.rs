@@ -49,6 +49,14 @@ impl<T> StaticArc<T> {
         self.rc().load(Ordering::Acquire) > 0
     }
 
+    /// Returns the number of `Ref`s and `RefMut`s that refer to `self`.
+    pub fn borrows(self: StrongPinMut<'_, Self>) -> usize {
+        match self.rc().load(Ordering::Acquire) {
+            BORROWED_MUT => 1,
+            r => r,
+        }
+    }
+
     #[allow(clippy::needless_lifetimes)]
     pub fn get_mut<'s>(mut self: StrongPinMut<'s, Self>) -> Option<&'s mut T> {
         if self.as_mut().is_borrowed() {
@@ -123,6 +131,23 @@ impl<T> Ref<T> {
         core::mem::forget(self);
         Ok(RefMut(ptr))
     }
+
+    /// Consumes the `Ref` and returns its pointer. The reference count is not
+    /// decreased, so the pointer must be given back to `Ref::from_raw` later.
+    pub fn into_raw(self) -> NonNull<StaticArc<T>> {
+        let ptr = self.0;
+        core::mem::forget(self);
+        ptr
+    }
+
+    /// Makes a `Ref` out of a pointer returned by `Ref::into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` is returned by `Ref::into_raw`, and no other `Ref` has been made out of it.
+    pub unsafe fn from_raw(ptr: NonNull<StaticArc<T>>) -> Self {
+        Self(ptr)
+    }
 }
 
 impl<T> Deref for Ref<T> {
@@ -9,20 +9,17 @@
 //! the virtio spec:
 //! https:///docs.oasis-open.org/virtio/virtio/v1.1/virtio-v1.1.pdf
 
-// virtio mmio control registers, mapped starting at 0x10001000.
+// virtio mmio control registers of each disk, mapped starting at
+// MemLayout::VIRTIO0 or MemLayout::VIRTIO1.
 // from qemu virtio_mmio.h
 
 use core::ptr;
 
 use bitflags::bitflags;
-use cfg_if::cfg_if;
-
-use crate::arch::interface::MemLayout;
-use crate::arch::TargetArch;
 
 mod virtio_disk;
 
-pub use virtio_disk::VirtioDisk;
+pub use virtio_disk::VirtioDisks;
 
 /// Memory mapped IO registers.
 /// The kernel and virtio driver communicates to each other using these registers.
@@ -66,15 +63,16 @@ enum MmioRegs {
 }
 
 impl MmioRegs {
-    fn read(self) -> u32 {
+    /// Reads the register of the disk whose registers start at `base`.
+    fn read(self, base: usize) -> u32 {
         // SAFETY:
-        // * `src` is valid, as the kernel can access [VIRTIO0..VIRTIO0+PGSIZE).
+        // * `src` is valid, as the kernel can access the registers of each disk.
         // * `src` is properly aligned, as self % 4 == 0.
         // * `src` points to a properly initialized value, as u32 does not have
         //   any internal structure to be initialized.
         // * volatile concurrent accesses are safe.
         //   (https://github.com/kaist-cp/rv6/issues/188#issuecomment-683548362)
-        unsafe { ptr::read_volatile((TargetArch::VIRTIO0 as *mut u8).add(self as _) as _) }
+        unsafe { ptr::read_volatile((base as *mut u8).add(self as _) as _) }
     }
 
     /// # Safety
@@ -82,47 +80,42 @@ impl MmioRegs {
     /// Writing at memory mapped registers may cause hardware side effects.
     /// For example, after writing at `QueueNotify`, the virtio driver reads/writes the address given by the kernel.
     /// If a wrong address was given, this could lead to undefined behavior.
-    unsafe fn write(self, dst: u32) {
+    unsafe fn write(self, base: usize, dst: u32) {
         // SAFETY:
-        // * `dst` is valid, as the kernel can access [VIRTIO0..VIRTIO0+PGSIZE).
+        // * `dst` is valid, as the kernel can access the registers of each disk.
         // * `dst` is properly aligned, as self % 4 == 0.
         // * volatile concurrent accesses are safe.
         //   (https://github.com/kaist-cp/rv6/issues/188#issuecomment-683548362)
-        unsafe { ptr::write_volatile((TargetArch::VIRTIO0 as *mut u8).add(self as _) as _, dst) }
+        unsafe { ptr::write_volatile((base as *mut u8).add(self as _) as _, dst) }
     }
 
     /// Checks the virtio disk's properties.
-    fn check_virtio_disk() {
-        assert!(
-            MmioRegs::MagicValue.read() == 0x74726976,
-            "could not find virtio disk"
-        );
-        assert!(MmioRegs::Version.read() == 1, "could not find virtio disk");
-        assert!(MmioRegs::DeviceId.read() == 2, "could not find virtio disk");
-        assert!(
-            MmioRegs::VendorId.read() == 0x554d4551,
-            "could not find virtio disk"
-        );
+    /// Returns `false` if there is no disk attached at `base`.
+    fn check_virtio_disk(base: usize) -> bool {
+        MmioRegs::MagicValue.read(base) == 0x74726976
+            && MmioRegs::Version.read(base) == 1
+            && MmioRegs::DeviceId.read(base) == 2
+            && MmioRegs::VendorId.read(base) == 0x554d4551
     }
 
     /// Sets the virtio status.
-    fn set_status(status: &VirtIOStatus) {
+    fn set_status(base: usize, status: &VirtIOStatus) {
         // SAFETY: simply setting status bits does not cause side effects.
         unsafe {
-            MmioRegs::Status.write(status.bits());
+            MmioRegs::Status.write(base, status.bits());
         }
     }
 
     /// Returns the device's virtio features.
-    fn get_features() -> VirtIOFeatures {
-        VirtIOFeatures::from_bits_truncate(MmioRegs::DeviceFeatures.read())
+    fn get_features(base: usize) -> VirtIOFeatures {
+        VirtIOFeatures::from_bits_truncate(MmioRegs::DeviceFeatures.read(base))
     }
 
     /// Sets the device's virtio features.
-    fn set_features(features: &VirtIOFeatures) {
+    fn set_features(base: usize, features: &VirtIOFeatures) {
         // SAFETY: simply setting features bits does not cause side effects.
         unsafe {
-            MmioRegs::DriverFeatures.write(features.bits());
+            MmioRegs::DriverFeatures.write(base, features.bits());
         }
     }
 
@@ -132,10 +125,10 @@ impl MmioRegs {
     ///
     /// The virtio driver will uses this info to calculate addresses.
     /// Hence, the caller must give the correct page size. Otherwise, the driver may read/write at wrong addresses.
-    unsafe fn set_pg_size(size: u32) {
+    unsafe fn set_pg_size(base: usize, size: u32) {
         // SAFETY: simply telling the page size does not cause side effects.
         unsafe {
-            MmioRegs::GuestPageSize.write(size);
+            MmioRegs::GuestPageSize.write(base, size);
         }
     }
 
@@ -145,18 +138,23 @@ impl MmioRegs {
     ///
     /// The virtio driver will later use this info to read/write descriptors.
     /// Hence, the caller must give correct info.
-    unsafe fn select_and_init_queue(queue_num: u32, queue_size: u32, queue_pg_num: u32) {
+    unsafe fn select_and_init_queue(
+        base: usize,
+        queue_num: u32,
+        queue_size: u32,
+        queue_pg_num: u32,
+    ) {
         // SAFETY: simply selecting and initializing the queue does not cause side effects.
         unsafe {
-            MmioRegs::QueueSel.write(queue_num);
+            MmioRegs::QueueSel.write(base, queue_num);
         }
-        let max = MmioRegs::QueueNumMax.read();
+        let max = MmioRegs::QueueNumMax.read(base);
         assert!(max != 0, "virtio disk has no queue {}", queue_num);
         assert!(max >= NUM as u32, "virtio disk max queue too short");
 
         unsafe {
-            MmioRegs::QueueNum.write(queue_size);
-            MmioRegs::QueuePfn.write(queue_pg_num);
+            MmioRegs::QueueNum.write(base, queue_size);
+            MmioRegs::QueuePfn.write(base, queue_pg_num);
         }
     }
 
@@ -166,18 +164,18 @@ impl MmioRegs {
     ///
     /// After notifying the queue, the driver will try to access the queue and read/write at the addresses given through descriptors.
     /// This may cause undefined behavior if the descriptors were not well set or contains wrong addresses.
-    unsafe fn notify_queue(num: u32) {
+    unsafe fn notify_queue(base: usize, num: u32) {
         unsafe {
-            MmioRegs::QueueNotify.write(num);
+            MmioRegs::QueueNotify.write(base, num);
         }
     }
 
     /// Acknowledges all interrupts.
-    fn intr_ack_all() {
-        let intr_status = MmioRegs::InterruptStatus.read() & 0x3;
+    fn intr_ack_all(base: usize) {
+        let intr_status = MmioRegs::InterruptStatus.read(base) & 0x3;
         // SAFETY: simply acknowledging interrupts does not cause undefined behavior.
         unsafe {
-            MmioRegs::InterruptAck.write(intr_status);
+            MmioRegs::InterruptAck.write(base, intr_status);
         }
     }
 }
@@ -223,13 +221,7 @@ bitflags! {
 }
 
 // This many virtio descriptors. It must be a power of two.
-cfg_if! {
-    if #[cfg(feature = "lfs")] {
-        const NUM: usize = 1 << 5;  // LFS requires additional descriptors.
-    } else {
-        const NUM: usize = 1 << 3;
-    }
-}
+const NUM: usize = 1 << 5; // LFS requires additional descriptors.
 
 /// A single descriptor, from the spec.
 /// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-320005
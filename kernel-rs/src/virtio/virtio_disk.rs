@@ -3,6 +3,7 @@
 /// qemu presents a "legacy" virtio interface.
 ///
 /// qemu ... -drive file=fs.img,if=none,format=raw,id=x0 -device virtio-blk-device,drive=x0,bus=virtio-mmio-bus.0
+///           -drive file=fs2.img,if=none,format=raw,id=x1 -device virtio-blk-device,drive=x1,bus=virtio-mmio-bus.1
 use core::marker::PhantomPinned;
 use core::mem;
 use core::pin::Pin;
@@ -11,7 +12,6 @@ use core::sync::atomic::{fence, Ordering};
 
 use arrayvec::ArrayVec;
 use bitmaps::Bitmap;
-use cfg_if::cfg_if;
 use const_zero::const_zero;
 use pin_project::pin_project;
 
@@ -21,23 +21,31 @@ use super::{
 };
 use crate::{
     addr::{PGSHIFT, PGSIZE},
+    arch::interface::MemLayout,
     bio::Buf,
     kernel::KernelRef,
-    lock::{SleepableLock, SleepableLockGuard},
-    param::BSIZE,
+    lock::{SleepLock, SleepableLock, SleepableLockGuard},
+    param::{BSIZE, MAXREADBLOCKS, NDISK, ROOTDEV, SEGSIZE},
     proc::KernelCtx,
+    some_or,
 };
 
-cfg_if! {
-    if #[cfg(feature = "lfs")] {
-        use crate::param::SEGSIZE;
-        // Sequential write in a unit of one segment.
-        const MAX_SEQ_WRITE: usize = SEGSIZE;
-    } else {
-        // Do not support sequential write.
-        // Thus, `SleepableLock<VirtioDisk>::write_sequential` is the same as a normal write.
-        const MAX_SEQ_WRITE: usize = 0;
-    }
+// Sequential write in a unit of one segment.
+const MAX_SEQ_WRITE: usize = SEGSIZE;
+
+// Sequential read of a few blocks ahead.
+const MAX_SEQ_READ: usize = MAXREADBLOCKS;
+
+/// The virtio disks. The device number of `disks[i]` is `ROOTDEV + i`.
+pub struct VirtioDisks {
+    disks: [SleepableLock<VirtioDisk>; NDISK],
+
+    /// Is a disk attached to `disks[i]`?
+    present: [bool; NDISK],
+
+    /// Lets one sequential read hold its `Buf`s at a time, so that the reads
+    /// do not use up the buffer cache.
+    seq_read: SleepLock<()>,
 }
 
 // It must be page-aligned.
@@ -62,6 +70,9 @@ pub struct VirtioDisk {
     /// NUM used ring entries.
     used: VirtqUsed,
 
+    /// The address of the MMIO registers of the disk.
+    base: usize,
+
     #[pin]
     info: DiskInfo,
 
@@ -120,15 +131,110 @@ struct VirtIOBlockOutHeader {
     sector: usize,
 }
 
+impl VirtioDisks {
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `VirtioDisks::init`.
+    pub const unsafe fn new<A: MemLayout>() -> Self {
+        Self {
+            disks: [
+                SleepableLock::new("DISK", unsafe { VirtioDisk::new(A::VIRTIO0) }),
+                SleepableLock::new("DISK", unsafe { VirtioDisk::new(A::VIRTIO1) }),
+            ],
+            present: [false; NDISK],
+            seq_read: SleepLock::new("seq_read", ()),
+        }
+    }
+
+    /// Initializes the disks that are attached. Panics if the root disk is absent.
+    pub fn init(self: Pin<&mut Self>) {
+        // SAFETY: we do not move the disks.
+        let this = unsafe { self.get_unchecked_mut() };
+        for (disk, present) in this.disks.iter_mut().zip(this.present.iter_mut()) {
+            // SAFETY: `disk` is pinned as `self` is.
+            let disk = unsafe { Pin::new_unchecked(disk) };
+            *present = disk.get_pin_mut().as_ref().init();
+        }
+        assert!(this.present[0], "could not find virtio disk");
+    }
+
+    /// Returns the disk whose device number is `dev`, if it is attached.
+    fn disk(self: Pin<&Self>, dev: u32) -> Option<Pin<&SleepableLock<VirtioDisk>>> {
+        let i = dev.checked_sub(ROOTDEV)? as usize;
+        if !*self.present.get(i)? {
+            return None;
+        }
+        // SAFETY: `self` is pinned, and only shared references are exposed.
+        Some(unsafe { Pin::new_unchecked(&self.get_ref().disks[i]) })
+    }
+
+    /// Returns `true` if a disk whose device number is `dev` is attached.
+    pub fn is_present(self: Pin<&Self>, dev: u32) -> bool {
+        self.disk(dev).is_some()
+    }
+
+    /// Return a locked Buf with the `latest` contents of the indicated block.
+    pub fn read(self: Pin<&Self>, dev: u32, blockno: u32, ctx: &KernelCtx<'_, '_>) -> Buf {
+        self.disk(dev)
+            .expect("no such disk")
+            .read(dev, blockno, ctx)
+    }
+
+    pub fn write(self: Pin<&Self>, b: &mut Buf, ctx: &KernelCtx<'_, '_>) {
+        self.disk(b.dev).expect("no such disk").write(b, ctx);
+        b.mark_clean(ctx);
+    }
+
+    /// Reads the `n` blocks from `blockno` into the buffer cache through a
+    /// single disk read request, so that the following `read`s of the blocks
+    /// need not wait for the disk. Reads up to `MAX_SEQ_READ` blocks, and stops
+    /// at the first block that is already in the cache.
+    pub fn read_sequential(
+        self: Pin<&Self>,
+        dev: u32,
+        blockno: u32,
+        n: usize,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        let disk = self.disk(dev).expect("no such disk");
+        let guard = self.get_ref().seq_read.lock(ctx);
+        disk.read_sequential(dev, blockno, n, ctx);
+        guard.free(ctx);
+    }
+
+    /// Writes the `Buf`s, which must belong to the same disk, sequentially.
+    pub fn write_sequential(
+        self: Pin<&Self>,
+        barray: &mut ArrayVec<Buf, MAX_SEQ_WRITE>,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        let dev = some_or!(barray.first(), return).dev;
+        self.disk(dev)
+            .expect("no such disk")
+            .write_sequential(barray, ctx);
+        for b in barray {
+            b.mark_clean(ctx);
+        }
+    }
+
+    /// Handles an interrupt from `disks[i]`.
+    pub fn intr(self: Pin<&Self>, i: usize, kernel: KernelRef<'_, '_>) {
+        // SAFETY: `self` is pinned.
+        let disk = unsafe { Pin::new_unchecked(&self.get_ref().disks[i]) };
+        disk.pinned_lock().get_pin_mut().intr(kernel);
+    }
+}
+
 impl VirtioDisk {
     /// # Safety
     ///
     /// It must be used only after initializing it with `VirtioDisk::init`.
-    pub const unsafe fn new() -> Self {
+    const unsafe fn new(base: usize) -> Self {
         Self {
             desc: [VirtqDesc::new(); NUM],
             avail: VirtqAvail::new(),
             used: VirtqUsed::new(),
+            base,
             info: DiskInfo::new(),
             darray: ArrayVec::new_const(),
             write_buf: [0; BSIZE * MAX_SEQ_WRITE],
@@ -207,8 +313,9 @@ impl Drop for Descriptor {
 impl SleepableLock<VirtioDisk> {
     /// Return a locked Buf with the `latest` contents of the indicated block.
     // If buf.valid is true, we don't need to access Disk.
-    pub fn read(self: Pin<&Self>, dev: u32, blockno: u32, ctx: &KernelCtx<'_, '_>) -> Buf {
+    fn read(self: Pin<&Self>, dev: u32, blockno: u32, ctx: &KernelCtx<'_, '_>) -> Buf {
         let mut buf = ctx.kernel().bcache().get_buf(dev, blockno).lock(ctx);
+        ctx.kernel().bcache_stats().record(buf.is_initialized());
         if !buf.is_initialized() {
             VirtioDisk::rw(&mut self.pinned_lock(), &mut buf, false, ctx);
             buf.mark_initialized();
@@ -216,11 +323,34 @@ impl SleepableLock<VirtioDisk> {
         buf
     }
 
-    pub fn write(self: Pin<&Self>, b: &mut Buf, ctx: &KernelCtx<'_, '_>) {
+    fn write(self: Pin<&Self>, b: &mut Buf, ctx: &KernelCtx<'_, '_>) {
         VirtioDisk::rw(&mut self.pinned_lock(), b, true, ctx)
     }
 
-    pub fn write_sequential(
+    fn read_sequential(
+        self: Pin<&Self>,
+        dev: u32,
+        blockno: u32,
+        n: usize,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        let mut barray = ArrayVec::<Buf, MAX_SEQ_READ>::new();
+        for b in blockno..blockno + n.min(MAX_SEQ_READ) as u32 {
+            let buf = ctx.kernel().bcache().get_buf(dev, b).lock(ctx);
+            if buf.is_initialized() {
+                buf.free(ctx);
+                break;
+            }
+            barray.push(buf);
+        }
+        VirtioDisk::read_seq(&mut self.pinned_lock(), &mut barray, ctx);
+        for mut buf in barray {
+            buf.mark_initialized();
+            buf.free(ctx);
+        }
+    }
+
+    fn write_sequential(
         self: Pin<&Self>,
         barray: &mut ArrayVec<Buf, MAX_SEQ_WRITE>,
         ctx: &KernelCtx<'_, '_>,
@@ -230,19 +360,23 @@ impl SleepableLock<VirtioDisk> {
 }
 
 impl VirtioDisk {
-    pub fn init(self: Pin<&Self>) {
+    /// Initializes the disk. Returns `false` if no disk is attached.
+    fn init(self: Pin<&Self>) -> bool {
+        let base = self.base;
         let mut status: VirtIOStatus = VirtIOStatus::empty();
 
         // MMIO registers are located below KERNBASE, while kernel text and data
         // are located above KERNBASE, so we can safely read/write MMIO registers.
-        MmioRegs::check_virtio_disk();
+        if !MmioRegs::check_virtio_disk(base) {
+            return false;
+        }
         status.insert(VirtIOStatus::ACKNOWLEDGE);
-        MmioRegs::set_status(&status);
+        MmioRegs::set_status(base, &status);
         status.insert(VirtIOStatus::DRIVER);
-        MmioRegs::set_status(&status);
+        MmioRegs::set_status(base, &status);
 
         // Negotiate features
-        let features = MmioRegs::get_features()
+        let features = MmioRegs::get_features(base)
             - (VirtIOFeatures::BLK_F_RO
                 | VirtIOFeatures::BLK_F_SCSI
                 | VirtIOFeatures::BLK_F_CONFIG_WCE
@@ -251,30 +385,32 @@ impl VirtioDisk {
                 | VirtIOFeatures::RING_F_EVENT_IDX
                 | VirtIOFeatures::RING_F_INDIRECT_DESC);
 
-        MmioRegs::set_features(&features);
+        MmioRegs::set_features(base, &features);
 
         // Tell device that feature negotiation is complete.
         status.insert(VirtIOStatus::FEATURES_OK);
-        MmioRegs::set_status(&status);
+        MmioRegs::set_status(base, &status);
 
         // Tell device we're completely ready.
         status.insert(VirtIOStatus::DRIVER_OK);
-        MmioRegs::set_status(&status);
+        MmioRegs::set_status(base, &status);
         // SAFETY: page size is `PGSIZE`.
         unsafe {
-            MmioRegs::set_pg_size(PGSIZE as _);
+            MmioRegs::set_pg_size(base, PGSIZE as _);
         }
 
         // Initialize queue 0.
         unsafe {
             MmioRegs::select_and_init_queue(
+                base,
                 0,
                 NUM as _,
                 (self.desc.as_ptr() as usize >> PGSHIFT) as _,
             );
         }
 
-        // plic.rs and trap.rs arrange for interrupts from VIRTIO0_IRQ.
+        // plic.rs and trap.rs arrange for interrupts from VIRTIO0_IRQ and VIRTIO1_IRQ.
+        true
     }
 
     /// Reads or writes a disk block, where the disk block number is given through `b`.
@@ -361,6 +497,39 @@ impl VirtioDisk {
         VirtioDisk::notify_and_sleep(guard, desc, &mut barray[0], ctx);
     }
 
+    /// Reads the consecutive disk blocks of the `Buf`s in the given `barray`
+    /// through a single disk read request. The `Buf`s must be ordered in block
+    /// number order.
+    ///
+    /// Unlike `VirtioDisk::write_seq`, the device reads the blocks directly
+    /// into the `Buf`s, with a descriptor for each of them.
+    fn read_seq(
+        guard: &mut SleepableLockGuard<'_, Self>,
+        barray: &mut [Buf],
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        if barray.is_empty() {
+            return;
+        }
+
+        // Allocate a descriptor for each `Buf`, and two for the header and
+        // the status.
+        let desc = loop {
+            match guard
+                .get_pin_mut()
+                .alloc_descriptors::<{ MAX_SEQ_READ + 2 }>(barray.len() + 2)
+            {
+                Some(idx) => break idx,
+                None => guard.sleep(ctx),
+            }
+        };
+
+        guard.get_pin_mut().set_read_descriptors(&desc, barray);
+
+        // Notify the device for a new request and sleep until its done.
+        VirtioDisk::notify_and_sleep(guard, desc, &mut barray[0], ctx);
+    }
+
     pub fn intr(self: Pin<&mut Self>, kernel: KernelRef<'_, '_>) {
         // The device won't raise another interrupt until we tell it
         // we've seen this interrupt, which the following line does.
@@ -368,7 +537,7 @@ impl VirtioDisk {
         // the "used" ring, in which case we may process the new
         // completion entries in this interrupt, and have nothing to do
         // in the next interrupt, which is harmless.
-        MmioRegs::intr_ack_all();
+        MmioRegs::intr_ack_all(self.base);
 
         fence(Ordering::SeqCst);
 
@@ -378,7 +547,6 @@ impl VirtioDisk {
         let this = self.project();
         let info = this.info.project();
 
-        // TODO: make intr wake only one Buf
         while *info.used_idx != this.used.id {
             fence(Ordering::SeqCst);
             let id = this.used.ring[(*info.used_idx as usize) % NUM].id as usize;
@@ -391,7 +559,7 @@ impl VirtioDisk {
 
             // disk is done with buf
             *buf.disk_mut() = false;
-            buf.vdisk_request_waitchannel.wakeup(kernel);
+            buf.vdisk_request_waitchannel.wakeup_one(kernel);
 
             *info.used_idx = info.used_idx.wrapping_add(1);
         }
@@ -406,11 +574,20 @@ impl VirtioDisk {
     }
 
     /// Allocate three descriptors (they need not be contiguous).
-    /// Disk transfers always use three descriptors.
-    fn alloc_three_descriptors(mut self: Pin<&mut Self>) -> Option<[Descriptor; 3]> {
-        let mut descs = ArrayVec::<_, 3>::new();
+    /// Disk transfers always use three descriptors, except sequential reads.
+    fn alloc_three_descriptors(self: Pin<&mut Self>) -> Option<[Descriptor; 3]> {
+        self.alloc_descriptors::<3>(3)?.into_inner().ok()
+    }
+
+    /// Allocate `n` descriptors (they need not be contiguous), where `n` is at
+    /// most `N`.
+    fn alloc_descriptors<const N: usize>(
+        mut self: Pin<&mut Self>,
+        n: usize,
+    ) -> Option<ArrayVec<Descriptor, N>> {
+        let mut descs = ArrayVec::new();
 
-        for _ in 0..3 {
+        for _ in 0..n {
             if let Some(desc) = self.as_mut().alloc() {
                 descs.push(desc);
             } else {
@@ -421,7 +598,7 @@ impl VirtioDisk {
             }
         }
 
-        descs.into_inner().ok()
+        Some(descs)
     }
 
     /// Sets the given `descs` according to the specs of the legacy interface of block operations.
@@ -493,18 +670,71 @@ impl VirtioDisk {
         info.inflight[descs[0].idx].b = buf;
     }
 
+    /// Sets the given `descs` for a read of the consecutive disk blocks of the
+    /// `Buf`s in `barray`: the header, a descriptor for the data of each `Buf`,
+    /// and the status. We will wait on the waitchannel of the first `Buf`.
+    fn set_read_descriptors(self: Pin<&mut Self>, descs: &[Descriptor], barray: &mut [Buf]) {
+        let sector: usize = barray[0].blockno as usize * (BSIZE / 512);
+        let n = barray.len();
+
+        let this = self.project();
+        let mut info = this.info.project();
+
+        // 1. Set the header.
+        let buf0 = &mut info.ops[descs[0].idx];
+        *buf0 = VirtIOBlockOutHeader::new(false, sector);
+
+        this.desc[descs[0].idx] = VirtqDesc {
+            addr: buf0 as *const _ as _,
+            len: mem::size_of::<VirtIOBlockOutHeader>() as _,
+            flags: VirtqDescFlags::NEXT,
+            next: descs[1].idx as _,
+        };
+
+        // 2. Set the data of the `Buf`s, in order.
+        // Device writes b->data
+        for (i, b) in barray.iter().enumerate() {
+            this.desc[descs[i + 1].idx] = VirtqDesc {
+                addr: b.data().as_ptr() as _,
+                len: BSIZE as _,
+                flags: VirtqDescFlags::NEXT | VirtqDescFlags::WRITE,
+                next: descs[i + 2].idx as _,
+            };
+        }
+
+        // 3. Set the status.
+        // device writes 0 on success
+        info.inflight[descs[0].idx].status = true;
+
+        this.desc[descs[n + 1].idx] = VirtqDesc {
+            addr: &info.inflight[descs[0].idx].status as *const _ as _,
+            len: 1,
+            flags: VirtqDescFlags::WRITE,
+            next: 0,
+        };
+
+        // Record struct Buf for virtio_disk_intr().
+        let buf = &mut barray[0];
+        *buf.disk_mut() = true;
+        // It does not break the invariant because buf is &mut Buf, which refers
+        // to a valid Buf.
+        info.inflight[descs[0].idx].b = buf;
+    }
+
     /// Notifiy the device that we have a new request, which is described by `desc`,
     /// and sleep on `b`'s waitchannel to wait until its done.
-    fn notify_and_sleep(
+    fn notify_and_sleep<D: AsRef<[Descriptor]> + IntoIterator<Item = Descriptor>>(
         guard: &mut SleepableLockGuard<'_, Self>,
-        desc: [Descriptor; 3],
+        desc: D,
         b: &mut Buf,
         ctx: &KernelCtx<'_, '_>,
     ) {
+        let head = desc.as_ref()[0].idx;
+        let base = guard.base;
         let mut this = guard.get_pin_mut().project();
         // Tell the device the first index in our chain of descriptors.
         let ring_idx = this.avail.idx as usize % NUM;
-        this.avail.ring[ring_idx] = desc[0].idx as _;
+        this.avail.ring[ring_idx] = head as _;
 
         fence(Ordering::SeqCst);
 
@@ -513,10 +743,10 @@ impl VirtioDisk {
 
         fence(Ordering::SeqCst);
 
-        // SAFETY: the all three descriptors' fields are well set.
+        // SAFETY: the all descriptors' fields are well set.
         // Value is queue number.
         unsafe {
-            MmioRegs::notify_queue(0);
+            MmioRegs::notify_queue(base, 0);
         }
 
         // Wait for virtio_disk_intr() to say request has finished.
@@ -524,7 +754,7 @@ impl VirtioDisk {
 
         // As it assigns null, the invariant of inflight is maintained even if
         // b: &mut Buf becomes invalid after this method returns.
-        guard.get_pin_mut().project().info.project().inflight[desc[0].idx].b = ptr::null_mut();
+        guard.get_pin_mut().project().info.project().inflight[head].b = ptr::null_mut();
         desc.into_iter()
             .for_each(|desc| guard.get_pin_mut().free(desc));
         guard.wakeup(ctx.kernel());
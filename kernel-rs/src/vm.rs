@@ -1,4 +1,11 @@
-use core::{cmp, marker::PhantomData, mem, ops::DerefMut, pin::Pin, slice};
+use core::{
+    cmp,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    slice,
+};
 
 use bitflags::bitflags;
 use zerocopy::{AsBytes, FromBytes};
@@ -7,14 +14,20 @@ use crate::{
     addr::{pgrounddown, pgroundup, Addr, KVAddr, PAddr, UVAddr, VAddr, MAXVA, PGSIZE},
     arch::interface::{Arch, IPageTableEntry, PageTableManager},
     arch::TargetArch,
-    fs::{DefaultFs, InodeGuard},
+    arena::{Arena, ArenaObject, ArenaRc, ArrayArena},
+    errno::Errno,
+    fs::RcVnode,
+    hal::hal,
     kalloc::Kmem,
-    lock::SpinLock,
-    memlayout::{kstack, PHYSTOP, TRAMPOLINE, TRAPFRAME},
+    lock::{SpinLock, SpinLockGuard},
+    memlayout::{
+        kstack, MMAPTOP, PHYSTOP, TRAMPOLINE, TRAPFRAME, TRAPFRAMES, USTACKBASE, USTACKTOP,
+    },
     page::Page,
-    param::NPROC,
+    param::{NPROC, NVMA},
     proc::KernelCtx,
-    util::memmove,
+    some_or,
+    util::{memmove, strong_pin::StrongPin},
 };
 
 type PageTableEntry = <TargetArch as PageTableManager>::PageTableEntry;
@@ -49,6 +62,38 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Memory protection of a mapping, as given to mmap.
+    pub struct ProtFlags: i32 {
+        const PROT_READ = 0x1;
+        const PROT_WRITE = 0x2;
+        const PROT_EXEC = 0x4;
+    }
+}
+
+bitflags! {
+    /// Kind of a mapping, as given to mmap.
+    pub struct MapFlags: i32 {
+        const MAP_SHARED = 0x1;
+        const MAP_PRIVATE = 0x2;
+        const MAP_FIXED = 0x10;
+        const MAP_ANONYMOUS = 0x20;
+    }
+}
+
+impl From<ProtFlags> for AccessFlags {
+    fn from(prot: ProtFlags) -> Self {
+        let mut flags = AccessFlags::RU;
+        if prot.contains(ProtFlags::PROT_WRITE) {
+            flags |= AccessFlags::W;
+        }
+        if prot.contains(ProtFlags::PROT_EXEC) {
+            flags |= AccessFlags::X;
+        }
+        flags
+    }
+}
+
 const PTE_PER_PT: usize = PGSIZE / mem::size_of::<PageTableEntry>();
 
 /// # Safety
@@ -205,6 +250,9 @@ impl<A: VAddr> PageTable<A> {
 
     fn remove(&mut self, va: A) -> Option<PAddr> {
         let pte = self.get_mut(va, None)?;
+        if !pte.is_valid() {
+            return None;
+        }
         assert!(pte.is_data(), "PageTable::remove");
         let pa = pte.get_pa();
         pte.invalidate();
@@ -231,8 +279,8 @@ impl<A: VAddr> Drop for PageTable<A> {
 }
 
 /// UserMemory manages the page table and allocated pages of a process. Its
-/// invariant guarantees that every PAddr mapped to VAddr except TRAMPOLINE and
-/// TRAPFRAME is from Page. This property is crucial for safety of methods that
+/// invariant guarantees that every PAddr mapped to VAddr below TRAPFRAMES is
+/// from Page. This property is crucial for safety of methods that
 /// read or write on memory, such as copy_in. Also, it is essential for safety
 /// of freeing a page created from each PAddr as well.
 ///
@@ -241,18 +289,34 @@ impl<A: VAddr> Drop for PageTable<A> {
 /// For brevity, pt := page_table, and we treat pt as a function from va to pa.
 /// - If va ∈ dom(pt), va mod PGSIZE = 0 ∧ pt(va) mod PGSIZE = 0.
 /// - pt(TRAMPOLINE) = trampoline.
-/// - TRAPFRAME ∈ dom(pt).
-/// - If va ∈ dom(pt) ∧ va ∉ { TRAMPOLINE, TRAPFRAME },
-///   then Page::from_usize(pt(va)) succeeds without breaking the invariant of Page.
-/// - If va ∈ dom(pt) where va ∉ { 0, TRAMPOLINE, TRAPFRAME },
-///   then va - PGSIZE ∈ dom(pt).
-/// - pgroundup(size) ∉ dom(pt).
-/// - If size > 0, then pgroundup(size) - PGSIZE ∈ dom(pt).
+/// - If va ∈ dom(pt) ∧ TRAPFRAMES <= va < TRAMPOLINE, then pt(va) is the trap
+///   frame of a thread using this memory. UserMemory does not own it.
+/// - If va ∈ dom(pt) ∧ va < TRAPFRAMES,
+///   then Page::from_usize(pt(va)) succeeds without breaking the invariant of Page,
+///   or pt(va) is a copy-on-write page whose reference is counted by Kmem.
+/// - If pt(va) is shared with other memories, the PTE of va is read-only, and
+///   it is marked copy-on-write if the page should be writable.
+/// - If va ∈ dom(pt) where va < TRAPFRAMES, then va < size,
+///   USTACKBASE <= va < USTACKTOP, or va lies in a mapping of vmas.
+///   Such pages are not necessarily in dom(pt); they are allocated lazily on
+///   the first access by `UserMemory::alloc_on_demand`.
+/// - Mappings of vmas are page-aligned, disjoint, and lie in
+///   [pgroundup(size), MMAPTOP).
 pub struct UserMemory {
     /// Page table of process.
     page_table: PageTable<UVAddr>,
     /// Size of process memory (bytes).
     size: usize,
+    /// Memory mappings created by mmap.
+    vmas: [Option<Vma>; NVMA],
+}
+
+/// A memory mapping of a process, covering [start, end).
+#[derive(Clone, Copy)]
+struct Vma {
+    start: usize,
+    end: usize,
+    perm: PteFlags,
 }
 
 impl UserMemory {
@@ -300,6 +364,7 @@ impl UserMemory {
         let mut memory = Self {
             page_table: scopeguard::ScopeGuard::into_inner(page_table),
             size: 0,
+            vmas: [None; NVMA],
         };
 
         if let Some(src) = src_opt {
@@ -318,36 +383,89 @@ impl UserMemory {
         Some(memory)
     }
 
-    /// Makes a new memory by copying a given memory. Copies both the page
-    /// table and the physical memory. Returns Some(memory) on success, None on
-    /// failure. Frees any allocated pages on failure.
+    /// Makes a new memory that shares the physical memory of a given memory.
+    /// Copies the page table and the mappings, and makes every writable page
+    /// read-only and copy-on-write in both memories. The shared pages are
+    /// copied lazily by `UserMemory::copy_on_write` on the first write.
+    /// Returns Some(memory) on success, None on failure. Frees any allocated
+    /// pages on failure.
     pub fn clone(&mut self, trap_frame: PAddr, allocator: Pin<&SpinLock<Kmem>>) -> Option<Self> {
         let new = Self::new(trap_frame, None, allocator)?;
-        let mut new = scopeguard::guard(new, |mut new| {
-            let _ = new.dealloc(0, allocator);
-        });
-        for i in num_iter::range_step(0, self.size, PGSIZE) {
-            let pte = self
-                .page_table
-                .get_mut(i.into(), None)
-                .expect("clone_into: pte not found");
-            assert!(pte.is_valid(), "clone_into: invalid page");
+        let mut new = scopeguard::guard(new, |new| new.free(allocator));
+        new.size = self.size;
+        new.vmas = self.vmas;
 
+        let heap = num_iter::range_step(0, self.size, PGSIZE);
+        let stack = num_iter::range_step(USTACKBASE, USTACKTOP, PGSIZE);
+        let mappings = self
+            .vmas
+            .iter()
+            .flatten()
+            .flat_map(|vma| num_iter::range_step(vma.start, vma.end, PGSIZE));
+        for i in heap.chain(stack).chain(mappings) {
+            let pte = some_or!(self.page_table.get_mut(i.into(), None), continue);
+            if !pte.is_valid() {
+                // The page has not been touched yet.
+                continue;
+            }
+
+            if pte.is_writable() {
+                pte.set_cow();
+            }
             let pa = pte.get_pa();
             let flags = pte.get_flags();
-            let mut page = allocator.alloc(None)?;
-            // SAFETY: pa is an address in page_table,
-            // and thus it is the address of a page by the invariant.
-            let src = unsafe { slice::from_raw_parts(pa.into_usize() as *const u8, PGSIZE) };
-            memmove(page.deref_mut().deref_mut(), src);
+            // SAFETY: pa is an address in page_table, and thus it is the
+            // address of a page by the invariant. It is now read-only in both
+            // memories.
+            let page = unsafe { allocator.dup(pa) };
 
-            new.push_page(page, flags, allocator)
+            new.insert_page(i.into(), page, flags, allocator)
                 .map_err(|page| allocator.free(page))
                 .ok()?;
         }
-        let mut new = scopeguard::ScopeGuard::into_inner(new);
-        new.size = self.size;
-        Some(new)
+        // The other threads may still write to the shared pages through the
+        // writable mappings in their TLBs. On failure, the pages are not
+        // shared, and stay consistent even if written.
+        self.flush_tlbs();
+        Some(scopeguard::ScopeGuard::into_inner(new))
+    }
+
+    /// Resolve a write to the copy-on-write page at va. If the page is still
+    /// shared, copy it to a new page. Otherwise, make it writable again.
+    /// Returns Ok(()) on success, Err(()) if va is not a copy-on-write page or
+    /// the allocation has failed.
+    pub fn copy_on_write(&mut self, va: UVAddr, allocator: Pin<&SpinLock<Kmem>>) -> Result<(), ()> {
+        if va.into_usize() >= TRAPFRAMES {
+            return Err(());
+        }
+        let pte = self
+            .page_table
+            .get_mut(pgrounddown(va.into_usize()).into(), None)
+            .ok_or(())?;
+        if !pte.is_cow() {
+            return Err(());
+        }
+
+        let pa = pte.get_pa();
+        if allocator.refcnt(pa) == 1 {
+            // The other owners have already copied or freed the page.
+            pte.clear_cow(pa);
+            return Ok(());
+        }
+
+        let mut page = allocator.alloc(None).ok_or(())?;
+        // SAFETY: pa is an address in page_table,
+        // and thus it is the address of a page by the invariant.
+        let src = unsafe { slice::from_raw_parts(pa.into_usize() as *const u8, PGSIZE) };
+        memmove(page.deref_mut().deref_mut(), src);
+        pte.clear_cow(page.into_usize().into());
+        self.flush_tlbs();
+
+        // Drop the reference to the shared page.
+        // SAFETY: pa is the address of a page by the invariant, and it is not
+        // mapped in this memory anymore.
+        allocator.free(unsafe { Page::from_usize(pa.into_usize()) });
+        Ok(())
     }
 
     /// Get the size of this memory.
@@ -362,7 +480,7 @@ impl UserMemory {
     pub fn load_file(
         &mut self,
         va: UVAddr,
-        ip: &mut InodeGuard<'_, DefaultFs>,
+        ip: &RcVnode,
         offset: u32,
         sz: u32,
         ctx: &KernelCtx<'_, '_>,
@@ -381,12 +499,21 @@ impl UserMemory {
         Ok(())
     }
 
-    /// Allocate PTEs and physical memory to grow process to newsz, which need
-    /// not be page aligned. Returns Ok(new size) or Err(()) on error.
-    pub fn alloc(&mut self, newsz: usize, allocator: Pin<&SpinLock<Kmem>>) -> Result<usize, ()> {
+    /// Allocate PTEs and physical memory with given permissions to grow process
+    /// to newsz, which need not be page aligned. Returns Ok(new size) or
+    /// Err(()) on error.
+    pub fn alloc(
+        &mut self,
+        newsz: usize,
+        perm: AccessFlags,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<usize, ()> {
         if newsz <= self.size {
             return Ok(self.size);
         }
+        if newsz > self.mmap_base() {
+            return Err(());
+        }
 
         let oldsz = self.size;
         let mut this = scopeguard::guard(self, |this| {
@@ -394,18 +521,254 @@ impl UserMemory {
         });
         while pgroundup(this.size) < pgroundup(newsz) {
             let page = allocator.alloc(Some(0)).ok_or(())?;
-            this.push_page(
-                page,
-                (AccessFlags::R | AccessFlags::W | AccessFlags::X | AccessFlags::U).into(),
-                allocator,
-            )
-            .map_err(|page| allocator.free(page))?;
+            this.push_page(page, perm.into(), allocator)
+                .map_err(|page| allocator.free(page))?;
         }
         let this = scopeguard::ScopeGuard::into_inner(this);
         this.size = newsz;
         Ok(this.size)
     }
 
+    /// Grow process to newsz, which need not be page aligned, without
+    /// allocating physical memory. The new pages are allocated on the first
+    /// access by `UserMemory::alloc_on_demand`.
+    /// Returns Ok(new size) or Err(()) if newsz would overlap the mappings.
+    pub fn reserve(&mut self, newsz: usize) -> Result<usize, ()> {
+        if newsz > self.mmap_base() {
+            return Err(());
+        }
+        if newsz > self.size {
+            self.size = newsz;
+        }
+        Ok(self.size)
+    }
+
+    /// Allocate a zeroed page for va, which is below the size of this memory,
+    /// in the stack, or in a mapping but has not been accessed yet.
+    /// Returns Ok(()) on success, Err(()) if va is out of this memory, va is
+    /// already mapped, or the allocation has failed.
+    pub fn alloc_on_demand(
+        &mut self,
+        va: UVAddr,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), ()> {
+        let addr = va.into_usize();
+        let perm = if addr < self.size || (USTACKBASE..USTACKTOP).contains(&addr) {
+            AccessFlags::RWU.into()
+        } else {
+            self.find_vma(va).ok_or(())?.perm
+        };
+        let va = pgrounddown(addr).into();
+        if self.is_mapped(va) {
+            return Err(());
+        }
+        let page = allocator.alloc(Some(0)).ok_or(())?;
+        self.insert_page(va, page, perm, allocator)
+            .map_err(|page| allocator.free(page))
+    }
+
+    /// Create a new mapping of len bytes with given permissions, below
+    /// MMAPTOP and above the heap. No physical memory is allocated; the pages
+    /// are allocated by `UserMemory::alloc_on_demand` or filled by
+    /// `UserMemory::map_page`.
+    /// Returns Ok(start address of the mapping) on success, Err(()) if there
+    /// is no room for the mapping.
+    pub fn map(&mut self, len: usize, perm: AccessFlags) -> Result<UVAddr, ()> {
+        if len == 0 || len > MMAPTOP {
+            return Err(());
+        }
+        let len = pgroundup(len);
+        let slot = self.vmas.iter().position(|vma| vma.is_none()).ok_or(())?;
+
+        // Place the mapping just below the lowest mapping it does not fit above.
+        let mut end = MMAPTOP;
+        loop {
+            if end < pgroundup(self.size) + len {
+                return Err(());
+            }
+            let start = end - len;
+            match self
+                .vmas
+                .iter()
+                .flatten()
+                .find(|vma| vma.start < end && start < vma.end)
+            {
+                Some(vma) => end = vma.start,
+                None => break,
+            }
+        }
+
+        self.vmas[slot] = Some(Vma {
+            start: end - len,
+            end,
+            perm: perm.into(),
+        });
+        Ok((end - len).into())
+    }
+
+    /// Map a given page at va, which must be a page-aligned address in a
+    /// mapping that has not been accessed yet.
+    /// Ok(()) on success, Err(given page) on failure.
+    pub fn map_page(
+        &mut self,
+        va: UVAddr,
+        page: Page,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), Page> {
+        let vma = some_or!(self.find_vma(va), return Err(page));
+        if !va.is_page_aligned() || self.is_mapped(va) {
+            return Err(page);
+        }
+        self.insert_page(va, page, vma.perm, allocator)
+    }
+
+    /// Remove the mappings in [va, va + len), and free their pages. A mapping
+    /// that partially overlaps the range is shrunk or split.
+    /// Returns Ok(()) on success, Err(()) if va is not page-aligned, the range
+    /// is out of the mapping area, or a mapping cannot be split.
+    pub fn unmap(
+        &mut self,
+        va: UVAddr,
+        len: usize,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), ()> {
+        let start = va.into_usize();
+        if !va.is_page_aligned() || len == 0 || start >= MMAPTOP || len > MMAPTOP - start {
+            return Err(());
+        }
+        let end = pgroundup(start + len);
+
+        // Splitting a mapping takes a free slot, so check it before changing
+        // anything.
+        let free = self.vmas.iter().position(|vma| vma.is_none());
+        let split = self
+            .vmas
+            .iter()
+            .flatten()
+            .any(|vma| vma.start < start && end < vma.end);
+        if split && free.is_none() {
+            return Err(());
+        }
+
+        for i in 0..NVMA {
+            let vma = some_or!(self.vmas[i], continue);
+            if end <= vma.start || vma.end <= start {
+                continue;
+            }
+            if vma.start < start && end < vma.end {
+                // The range is in the middle of the mapping, which is the only
+                // mapping in the range.
+                let slot = free.ok_or(())?;
+                self.vmas[slot] = Some(Vma { start: end, ..vma });
+                self.vmas[i] = Some(Vma { end: start, ..vma });
+            } else if vma.start < start {
+                self.vmas[i] = Some(Vma { end: start, ..vma });
+            } else if end < vma.end {
+                self.vmas[i] = Some(Vma { start: end, ..vma });
+            } else {
+                self.vmas[i] = None;
+            }
+
+            let (from, to) = (cmp::max(start, vma.start), cmp::min(end, vma.end));
+            self.free_range(from, to, allocator);
+        }
+        Ok(())
+    }
+
+    /// Change the permissions of the pages in [va, va + len) to perm. A mapping
+    /// that partially overlaps the range is split, and its pages that have not
+    /// been accessed yet get perm when they are allocated. The other pages
+    /// have no permissions to record, so they are allocated first. A page
+    /// shared with other memories stays copy-on-write if perm is writable.
+    /// Returns Ok(()) on success, Err(()) if va is not page-aligned, a page in
+    /// the range is out of this memory, a mapping cannot be split, or the
+    /// allocation has failed. On failure, no permission is changed.
+    pub fn protect(
+        &mut self,
+        va: UVAddr,
+        len: usize,
+        perm: AccessFlags,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), ()> {
+        let start = va.into_usize();
+        if !va.is_page_aligned() || start >= TRAPFRAMES || len > TRAPFRAMES - start {
+            return Err(());
+        }
+        let end = pgroundup(start + len);
+
+        // Check the whole range, and the free slots that splitting the
+        // mappings takes, before changing anything.
+        for a in num_iter::range_step(start, end, PGSIZE) {
+            if a >= self.size
+                && !(USTACKBASE..USTACKTOP).contains(&a)
+                && self.find_vma(a.into()).is_none()
+            {
+                return Err(());
+            }
+            if let Some(pte) = self.page_table.get_mut(a.into(), None) {
+                if pte.is_valid() && !pte.is_user() {
+                    return Err(());
+                }
+            }
+        }
+        let splits = self
+            .vmas
+            .iter()
+            .flatten()
+            .map(|vma| {
+                (vma.start < start && start < vma.end) as usize
+                    + (vma.start < end && end < vma.end) as usize
+            })
+            .sum::<usize>();
+        if splits > self.vmas.iter().filter(|vma| vma.is_none()).count() {
+            return Err(());
+        }
+
+        for a in num_iter::range_step(start, end, PGSIZE) {
+            if !self.is_mapped(a.into()) && self.find_vma(a.into()).is_none() {
+                self.alloc_on_demand(a.into(), allocator)?;
+            }
+        }
+
+        let flags: PteFlags = perm.into();
+        for i in 0..NVMA {
+            let vma = some_or!(self.vmas[i], continue);
+            if end <= vma.start || vma.end <= start {
+                continue;
+            }
+            let (from, to) = (cmp::max(start, vma.start), cmp::min(end, vma.end));
+            self.vmas[i] = Some(Vma {
+                start: from,
+                end: to,
+                perm: flags,
+            });
+            if vma.start < from {
+                let slot = self.vmas.iter().position(|vma| vma.is_none()).ok_or(())?;
+                self.vmas[slot] = Some(Vma { end: from, ..vma });
+            }
+            if to < vma.end {
+                let slot = self.vmas.iter().position(|vma| vma.is_none()).ok_or(())?;
+                self.vmas[slot] = Some(Vma { start: to, ..vma });
+            }
+        }
+
+        // The pages that have not been accessed yet are left to
+        // `UserMemory::alloc_on_demand`.
+        for a in num_iter::range_step(start, end, PGSIZE) {
+            let pte = some_or!(self.page_table.get_mut(a.into(), None), continue);
+            if !pte.is_valid() {
+                continue;
+            }
+            let pa = pte.get_pa();
+            pte.set_entry(pa, flags);
+            if perm.contains(AccessFlags::W) && allocator.refcnt(pa) > 1 {
+                pte.set_cow();
+            }
+        }
+        self.flush_tlbs();
+        Ok(())
+    }
+
     /// Deallocate user pages to bring the process size to newsz, which need
     /// not be page-aligned. Returns the new process size.
     pub fn dealloc(&mut self, newsz: usize, allocator: Pin<&SpinLock<Kmem>>) -> usize {
@@ -413,11 +776,7 @@ impl UserMemory {
             return self.size;
         }
 
-        while pgroundup(newsz) < pgroundup(self.size) {
-            if let Some(page) = self.pop_page() {
-                allocator.free(page);
-            }
-        }
+        self.free_range(pgroundup(newsz), pgroundup(self.size), allocator);
         self.size = newsz;
         newsz
     }
@@ -429,7 +788,7 @@ impl UserMemory {
         match n.cmp(&0) {
             cmp::Ordering::Equal => (),
             cmp::Ordering::Greater => {
-                let _ = self.alloc(size + n as usize, allocator)?;
+                let _ = self.reserve(size + n as usize)?;
             }
             cmp::Ordering::Less => {
                 let _ = self.dealloc(size - (-n as usize), allocator);
@@ -438,26 +797,17 @@ impl UserMemory {
         Ok(size)
     }
 
-    /// Mark a PTE invalid for user access.
-    /// Used by exec for the user stack guard page.
-    pub fn clear(&mut self, va: UVAddr) {
-        self.page_table
-            .get_mut(va, None)
-            .expect("clear")
-            .clear_user();
-    }
-
     /// Copy from kernel to user.
     /// Copy len bytes from src to virtual address dstva in a given page table.
-    /// Return Ok(()) on success, Err(()) on error.
-    pub fn copy_out_bytes(&mut self, dstva: UVAddr, src: &[u8]) -> Result<(), ()> {
+    /// Return Ok(()) on success, Err(EFAULT) if the user memory is not accessible.
+    pub fn copy_out_bytes(&mut self, dstva: UVAddr, src: &[u8]) -> Result<(), Errno> {
         let mut dst = dstva.into_usize();
         let mut len = src.len();
         let mut offset = 0;
         while len > 0 {
             let va = pgrounddown(dst);
             let poffset = dst - va;
-            let page = self.get_slice(va.into()).ok_or(())?;
+            let page = self.get_slice_mut(va.into()).ok_or(Errno::EFAULT)?;
             let n = cmp::min(PGSIZE - poffset, len);
             memmove(&mut page[poffset..poffset + n], &src[offset..offset + n]);
             len -= n;
@@ -469,22 +819,22 @@ impl UserMemory {
 
     /// Copy from kernel to user.
     /// Copy from src to virtual address dstva in a given page table.
-    /// Return Ok(()) on success, Err(()) on error.
-    pub fn copy_out<T: AsBytes>(&mut self, dstva: UVAddr, src: &T) -> Result<(), ()> {
+    /// Return Ok(()) on success, Err(EFAULT) if the user memory is not accessible.
+    pub fn copy_out<T: AsBytes>(&mut self, dstva: UVAddr, src: &T) -> Result<(), Errno> {
         self.copy_out_bytes(dstva, src.as_bytes())
     }
 
     /// Copy from user to kernel.
     /// Copy len bytes to dst from virtual address srcva in a given page table.
-    /// Return Ok(()) on success, Err(()) on error.
-    pub fn copy_in_bytes(&mut self, dst: &mut [u8], srcva: UVAddr) -> Result<(), ()> {
+    /// Return Ok(()) on success, Err(EFAULT) if the user memory is not accessible.
+    pub fn copy_in_bytes(&mut self, dst: &mut [u8], srcva: UVAddr) -> Result<(), Errno> {
         let mut src = srcva.into_usize();
         let mut len = dst.len();
         let mut offset = 0;
         while len > 0 {
             let va = pgrounddown(src);
             let poffset = src - va;
-            let page = self.get_slice(va.into()).ok_or(())?;
+            let page = self.get_slice(va.into()).ok_or(Errno::EFAULT)?;
             let n = cmp::min(PGSIZE - poffset, len);
             memmove(&mut dst[offset..offset + n], &page[poffset..poffset + n]);
             len -= n;
@@ -496,27 +846,28 @@ impl UserMemory {
 
     /// Copy from user to kernel.
     /// Copy to dst from virtual address srcva in a given page table.
-    /// Return Ok(()) on success, Err(()) on error.
+    /// Return Ok(()) on success, Err(EFAULT) if the user memory is not accessible.
     pub unsafe fn copy_in<T: AsBytes + FromBytes>(
         &mut self,
         dst: &mut T,
         srcva: UVAddr,
-    ) -> Result<(), ()> {
+    ) -> Result<(), Errno> {
         self.copy_in_bytes(dst.as_bytes_mut(), srcva)
     }
 
     /// Copy a null-terminated string from user to kernel.
     /// Copy bytes to dst from virtual address srcva in a given page table,
     /// until a '\0', or max.
-    /// Return OK(()) on success, Err(()) on error.
-    pub fn copy_in_str(&mut self, dst: &mut [u8], srcva: UVAddr) -> Result<(), ()> {
+    /// Return OK(()) on success, Err(EFAULT) if the user memory is not accessible,
+    /// or Err(ENAMETOOLONG) if there is no '\0' in the first max bytes.
+    pub fn copy_in_str(&mut self, dst: &mut [u8], srcva: UVAddr) -> Result<(), Errno> {
         let mut src = srcva.into_usize();
         let mut offset = 0;
         let mut max = dst.len();
         while max > 0 {
             let va = pgrounddown(src);
             let poffset = src - va;
-            let page = self.get_slice(va.into()).ok_or(())?;
+            let page = self.get_slice(va.into()).ok_or(Errno::EFAULT)?;
             let n = cmp::min(PGSIZE - poffset, max);
 
             let from = &page[poffset..poffset + n];
@@ -533,7 +884,7 @@ impl UserMemory {
                 }
             }
         }
-        Err(())
+        Err(Errno::ENAMETOOLONG)
     }
 
     /// Return the address of the page table
@@ -541,57 +892,160 @@ impl UserMemory {
         self.page_table.as_usize()
     }
 
-    /// Return a page at va as a slice. Some(page) on success, None on failure.
+    /// Make the other CPUs drop the stale mappings of this memory from their
+    /// TLBs, after the page table has changed.
+    fn flush_tlbs(&self) {
+        hal().cpus().flush_tlbs(self.page_table_addr());
+    }
+
+    /// Map the trap frame of a new thread at the highest free page in
+    /// [TRAPFRAMES, TRAPFRAME). The trap frame stays owned by the thread.
+    /// Returns Ok(va of the trap frame) on success, Err(()) if every page is
+    /// in use or the allocation has failed.
+    pub fn map_trap_frame(
+        &mut self,
+        trap_frame: PAddr,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<usize, ()> {
+        let va = num_iter::range_step(TRAPFRAMES, TRAPFRAME, PGSIZE)
+            .rev()
+            .find(|&va| !self.is_mapped(va.into()))
+            .ok_or(())?;
+        self.page_table.insert(
+            va.into(),
+            trap_frame,
+            (AccessFlags::R | AccessFlags::W).into(),
+            allocator,
+        )?;
+        Ok(va)
+    }
+
+    /// Unmap the trap frame at va, mapped by `UserMemory::new` or
+    /// `UserMemory::map_trap_frame`, without freeing it.
+    pub fn unmap_trap_frame(&mut self, va: usize) {
+        assert!(
+            (TRAPFRAMES..TRAMPOLINE).contains(&va),
+            "unmap_trap_frame: not a trap frame"
+        );
+        let _ = self.page_table.remove(va.into());
+    }
+
+    /// Remove and free the pages in [start, end), which must be page-aligned
+    /// and must lie below TRAPFRAMES.
+    fn free_range(&mut self, start: usize, end: usize, allocator: Pin<&SpinLock<Kmem>>) {
+        // Take the pages from the user first, and free them only after the
+        // other CPUs have dropped them from their TLBs.
+        for a in num_iter::range_step(start, end, PGSIZE) {
+            if let Some(pte) = self.page_table.get_mut(a.into(), None) {
+                if pte.is_valid() {
+                    pte.clear_user();
+                }
+            }
+        }
+        self.flush_tlbs();
+
+        for a in num_iter::range_step(start, end, PGSIZE) {
+            if let Some(pa) = self.page_table.remove(a.into()) {
+                // SAFETY: pa is an address in page_table,
+                // and, thus, it is the address of a page by the invariant.
+                allocator.free(unsafe { Page::from_usize(pa.into_usize()) });
+            }
+        }
+    }
+
+    /// Return the lowest address of the mappings, or MMAPTOP if there is none.
+    fn mmap_base(&self) -> usize {
+        self.vmas
+            .iter()
+            .flatten()
+            .map(|vma| vma.start)
+            .min()
+            .unwrap_or(MMAPTOP)
+    }
+
+    /// Return the mapping that contains va.
+    fn find_vma(&self, va: UVAddr) -> Option<Vma> {
+        let va = va.into_usize();
+        self.vmas
+            .iter()
+            .flatten()
+            .find(|vma| vma.start <= va && va < vma.end)
+            .copied()
+    }
+
+    /// Return whether va is mapped to a page.
+    fn is_mapped(&mut self, va: UVAddr) -> bool {
+        self.page_table
+            .get_mut(va, None)
+            .map_or(false, |pte| pte.is_valid())
+    }
+
+    /// Return a page at va as a slice. A page that has not been accessed yet
+    /// is allocated first. Some(page) on success, None on failure.
     fn get_slice(&mut self, va: UVAddr) -> Option<&mut [u8]> {
-        if va.into_usize() >= TRAPFRAME {
+        if va.into_usize() >= TRAPFRAMES {
             return None;
         }
+        if !self.is_mapped(va) {
+            self.alloc_on_demand(va, hal().kmem()).ok()?;
+        }
         let pte = self.page_table.get_mut(va, None)?;
         if !pte.is_user() {
             return None;
         }
-        // SAFETY: va < TRAPFRAME, so pte.get_pa() is the address of a page.
+        // SAFETY: va < TRAPFRAMES, so pte.get_pa() is the address of a page.
         Some(unsafe { slice::from_raw_parts_mut(pte.get_pa().into_usize() as _, PGSIZE) })
     }
 
-    /// Increase the size by appending a given page with given flags.
-    /// Ok(()) on success, Err(given page) on failure.
-    fn push_page(
+    /// Return a page at va as a slice to write to. A copy-on-write page is
+    /// copied first. Some(page) on success, None on failure or if the page is
+    /// not writable by the user.
+    fn get_slice_mut(&mut self, va: UVAddr) -> Option<&mut [u8]> {
+        // Allocate the page first if it has not been accessed yet.
+        let _ = self.get_slice(va)?;
+        let pte = self.page_table.get_mut(va, None)?;
+        if pte.is_cow() {
+            self.copy_on_write(va, hal().kmem()).ok()?;
+        } else if !pte.is_writable() {
+            return None;
+        }
+        self.get_slice(va)
+    }
+
+    /// Map a given page at va, which must be page-aligned, with given flags. Ok(()) on success, Err(given page) on failure.
+    fn insert_page(
         &mut self,
+        va: UVAddr,
         page: Page,
         perm: PteFlags,
         allocator: Pin<&SpinLock<Kmem>>,
     ) -> Result<(), Page> {
         let pa = page.into_usize();
         // The invariant is maintained because page.addr() is the address of a page.
-        let size = pgroundup(self.size);
         self.page_table
-            .insert(size.into(), pa.into(), perm, allocator)
+            .insert(va, pa.into(), perm, allocator)
             // SAFETY: pa is the address of a given page.
-            .map_err(|_| unsafe { Page::from_usize(pa) })?;
-        self.size = size + PGSIZE;
-        Ok(())
+            .map_err(|_| unsafe { Page::from_usize(pa) })
     }
 
-    /// Decrease the size by removing the most recently appended page.
-    /// Some(page) if size > 0, None if size = 0.
-    fn pop_page(&mut self) -> Option<Page> {
-        if self.size == 0 {
-            return None;
-        }
-        self.size = pgroundup(self.size) - PGSIZE;
-        let pa = self
-            .page_table
-            .remove(self.size.into())
-            .expect("pop_page")
-            .into_usize();
-        // SAFETY: pa is an address in page_table,
-        // and, thus, it is the address of a page by the invariant.
-        Some(unsafe { Page::from_usize(pa) })
+    /// Increase the size by appending a given page with given flags.
+    /// Ok(()) on success, Err(given page) on failure.
+    fn push_page(
+        &mut self,
+        page: Page,
+        perm: PteFlags,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), Page> {
+        let size = pgroundup(self.size);
+        self.insert_page(size.into(), page, perm, allocator)?;
+        self.size = size + PGSIZE;
+        Ok(())
     }
 
     pub fn free(mut self, allocator: Pin<&SpinLock<Kmem>>) {
         let _ = self.dealloc(0, allocator);
+        let _ = self.unmap(UVAddr::from(0), MMAPTOP, allocator);
+        self.free_range(USTACKBASE, USTACKTOP, allocator);
         // SAFETY: self will be dropped.
         unsafe { self.page_table.free(allocator) };
         mem::forget(self);
@@ -606,6 +1060,90 @@ impl Drop for UserMemory {
     }
 }
 
+// `UserMemory` is `Send` because its pages are accessed only through its
+// methods, which take `&mut self`, and it does not point to thread-local data.
+unsafe impl Send for UserMemory {}
+
+/// A user memory shared by the threads of a process.
+///
+/// # Safety
+///
+/// `memory` is `Some` while an `RcAddressSpace` refers to this.
+pub struct AddressSpace {
+    memory: SpinLock<Option<UserMemory>>,
+}
+
+pub type AddressSpaceTable = ArrayArena<AddressSpace, NPROC>;
+
+/// A reference counted smart pointer to an `AddressSpace`.
+pub type RcAddressSpace = ArenaRc<AddressSpaceTable>;
+
+/// The locked user memory of an `AddressSpace`.
+pub struct UserMemoryGuard<'s>(SpinLockGuard<'s, Option<UserMemory>>);
+
+impl const Default for AddressSpace {
+    fn default() -> Self {
+        Self {
+            memory: SpinLock::new("address_space", None),
+        }
+    }
+}
+
+impl ArenaObject for AddressSpace {
+    type Ctx<'a, 'id: 'a> = ();
+
+    fn finalize<'a, 'id: 'a>(&mut self, _: Self::Ctx<'a, 'id>) {
+        if let Some(memory) = self.memory.get_mut().take() {
+            memory.free(hal().kmem());
+        }
+    }
+}
+
+impl AddressSpaceTable {
+    pub const fn new_table() -> Self {
+        ArrayArena::<AddressSpace, NPROC>::new("ASTABLE")
+    }
+
+    /// Allocate an address space that holds `memory`.
+    /// Frees `memory` on failure.
+    pub fn alloc_space(
+        self: StrongPin<'_, Self>,
+        memory: UserMemory,
+    ) -> Result<RcAddressSpace, ()> {
+        let mut memory = Some(memory);
+        let space = self.alloc(|| {
+            AddressSpace {
+                memory: SpinLock::new("address_space", memory.take()),
+            }
+        });
+        if let Some(memory) = memory {
+            memory.free(hal().kmem());
+        }
+        space.ok_or(())
+    }
+}
+
+impl AddressSpace {
+    /// Lock the user memory.
+    pub fn lock(&self) -> UserMemoryGuard<'_> {
+        UserMemoryGuard(self.memory.lock())
+    }
+}
+
+impl Deref for UserMemoryGuard<'_> {
+    type Target = UserMemory;
+
+    fn deref(&self) -> &UserMemory {
+        self.0.as_ref().expect("UserMemoryGuard: no memory")
+    }
+}
+
+impl DerefMut for UserMemoryGuard<'_> {
+    fn deref_mut(&mut self) -> &mut UserMemory {
+        self.0.as_mut().expect("UserMemoryGuard: no memory")
+    }
+}
+
 /// KernelMemory manages the page table and allocated pages of the kernel.
 /// Every PAddr in KernelMemory is not originated from a page. KernelMemory
 /// neither provides memory read/write methods nor decreases memory. Therefore,
@@ -663,6 +1201,19 @@ impl<A: Arch> KernelMemory<A> {
             )
             .ok()?;
 
+        // The second disk shares the page of the first one on some machines.
+        if pgrounddown(A::VIRTIO1) != pgrounddown(A::VIRTIO0) {
+            page_table
+                .insert_range(
+                    A::VIRTIO1.into(),
+                    PGSIZE,
+                    A::VIRTIO1.into(),
+                    (AccessFlags::R | AccessFlags::W).into(),
+                    allocator,
+                )
+                .ok()?;
+        }
+
         // Map the trampoline for trap entry/exit to
         // the highest virtual address in the kernel.
         page_table
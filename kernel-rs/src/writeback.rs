@@ -0,0 +1,85 @@
+//! Write-back of the file systems.
+//!
+//! A transaction does not write the blocks it changes to the disk at its end.
+//! They stay dirty in the buffer cache until the file system commits them:
+//!
+//! * The ufs commits the transactions that end within the commit latency
+//!   (`commit_ticks`) at once, and the lfs commits its segment when it fills up.
+//! * The flusher, a kernel process, syncs a mounted file system when its oldest
+//!   change that is not on the disk, such as the first block of an open ufs
+//!   transaction, has waited for the expire time (`expire_ticks`). It also syncs
+//!   all of them once in the expire time, for the file systems that do not keep
+//!   that time, such as the lfs with its segment and checkpoint.
+//! * `fsync` and `sync` commit at once.
+//!
+//! Both times are in clock ticks, and the `writeback` system call sets them.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{
+    arch::{interface::TimeManager, TargetArch},
+    param::{COMMIT_TICKS, DIRTY_EXPIRE_TICKS},
+    proc::KernelCtx,
+    timer::TICK_MICROS,
+};
+
+/// The times that control the write-back.
+pub struct Writeback {
+    /// How long the ufs waits for more transactions before it commits.
+    commit_ticks: AtomicU32,
+
+    /// How long a buffer may stay dirty.
+    expire_ticks: AtomicU32,
+}
+
+impl Writeback {
+    pub const fn new() -> Self {
+        Self {
+            commit_ticks: AtomicU32::new(COMMIT_TICKS),
+            expire_ticks: AtomicU32::new(DIRTY_EXPIRE_TICKS),
+        }
+    }
+
+    pub fn commit_ticks(&self) -> u32 {
+        self.commit_ticks.load(Ordering::Relaxed)
+    }
+
+    pub fn expire_ticks(&self) -> u32 {
+        self.expire_ticks.load(Ordering::Relaxed)
+    }
+
+    /// Sets the times that are `Some`.
+    pub fn set(&self, commit_ticks: Option<u32>, expire_ticks: Option<u32>) {
+        if let Some(ticks) = commit_ticks {
+            self.commit_ticks.store(ticks, Ordering::Relaxed);
+        }
+        if let Some(ticks) = expire_ticks {
+            self.expire_ticks.store(ticks, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The body of the flusher. It checks the mounted file systems once every tick.
+pub fn flusher(ctx: KernelCtx<'_, '_>) -> ! {
+    let kernel = ctx.kernel();
+    let mut last_sync = *kernel.ticks().lock();
+    loop {
+        // Without a clock or a free timer, just give up the CPU for a while.
+        let slept = match TargetArch::uptime_as_micro() {
+            Ok(now) => ctx.sleep_until_unkillable(now + TICK_MICROS).is_ok(),
+            Err(()) => false,
+        };
+        if !slept {
+            ctx.yield_cpu();
+        }
+
+        let now = *kernel.ticks().lock();
+        let expire_ticks = kernel.writeback().expire_ticks();
+        if now.wrapping_sub(last_sync) >= expire_ticks {
+            kernel.mounts().sync(&ctx);
+            last_sync = now;
+        } else {
+            kernel.mounts().sync_expired(now, expire_ticks, &ctx);
+        }
+    }
+}
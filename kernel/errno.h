@@ -0,0 +1,33 @@
+// Error numbers of system calls. A system call that fails returns the
+// negated error number; the C library stores it in errno and returns -1.
+// These match kernel-rs/src/errno.rs.
+#define EPERM         1   // Operation not permitted
+#define ENOENT        2   // No such file or directory
+#define ESRCH         3   // No such process
+#define EINTR         4   // Interrupted system call
+#define EIO           5   // I/O error
+#define E2BIG         7   // Argument list too long
+#define ENOEXEC       8   // Exec format error
+#define EBADF         9   // Bad file descriptor
+#define ECHILD       10   // No child processes
+#define EAGAIN       11   // Try again
+#define ENOMEM       12   // Out of memory
+#define EACCES       13   // Permission denied
+#define EFAULT       14   // Bad address
+#define EBUSY        16   // Device or resource busy
+#define EEXIST       17   // File exists
+#define EXDEV        18   // Cross-device link
+#define ENODEV       19   // No such device
+#define ENOTDIR      20   // Not a directory
+#define EISDIR       21   // Is a directory
+#define EINVAL       22   // Invalid argument
+#define ENFILE       23   // File table overflow
+#define EMFILE       24   // Too many open files
+#define EFBIG        27   // File too large
+#define ESPIPE       29   // Illegal seek
+#define EROFS        30   // Read-only file system
+#define EPIPE        32   // Broken pipe
+#define ENAMETOOLONG 36   // File name too long
+#define ENOSYS       38   // Function not implemented
+#define ENOTEMPTY    39   // Directory not empty
+#define ELOOP        40   // Too many symbolic links encountered
@@ -20,13 +20,46 @@ struct superblock {
   uint logstart;     // Block number of first log block
   uint inodestart;   // Block number of first inode block
   uint bmapstart;    // Block number of first free map block
+  uint features;     // Optional features (FS_*), zero in an old image
+  uint version;      // Must be FSVERSION
 };
 
 #define FSMAGIC 0x10203040
 
-#define NDIRECT 12
+// Version of the disk format. Version 1 added the permission bits, owners,
+// and times to struct dinode. An older image has version 0.
+#define FSVERSION 1
+
+#define FS_LONGNAMES 0x1  // directories have struct ldirent entries
+#define FS_BIGFILES  0x2  // inodes have doubly- and triply-indirect blocks
+#define FS_EXTENTS   0x4  // inodes have extent trees instead of indirect blocks
+
+// With FS_BIGFILES, addrs[] of an inode has NDIRECT direct blocks, an
+// indirect block, a doubly-indirect block, and a triply-indirect block.
+// Without it, as in an old image, addrs[] has 12 direct blocks and an
+// indirect block.
+#define NDIRECT 10
+#define NADDR (NDIRECT+3)
 #define NINDIRECT (BSIZE / sizeof(uint))
-#define MAXFILE (NDIRECT + NINDIRECT)
+#define MAXFILE (NDIRECT + NINDIRECT + NINDIRECT*NINDIRECT + NINDIRECT*NINDIRECT*NINDIRECT)
+
+// With FS_EXTENTS, addrs[] of an inode is the root of an extent tree instead.
+// A node of the tree is a struct extent_header followed by struct extents.
+// The root in addrs[] has room for NEXTENT extents, and the other nodes are
+// in blocks. In a leaf (depth 0), an extent says that the next len blocks of
+// the file are the disk blocks from start. In a node of depth d > 0, start
+// is the block of a node of depth d-1 that maps the next len blocks.
+struct extent_header {
+  ushort nentries;
+  ushort depth;
+};
+
+struct extent {
+  uint start;
+  uint len;
+};
+
+#define NEXTENT ((NADDR*sizeof(uint) - sizeof(struct extent_header)) / sizeof(struct extent))
 
 // On-disk inode structure
 struct dinode {
@@ -35,7 +68,15 @@ struct dinode {
   ushort minor;         // Minor device number (T_DEVICE only)
   short nlink;          // Number of links to inode in file system
   uint size;            // Size of file (bytes)
-  uint addrs[NDIRECT+1];   // Data block addresses
+  uint addrs[NADDR];    // Data block addresses
+  ushort mode;          // Permission bits
+  ushort pad;
+  uint uid;             // Owner
+  uint gid;             // Group
+  uint atime;           // Time of last access
+  uint mtime;           // Time of last modification
+  uint ctime;           // Time of last status change
+  uint reserved[10];    // Unused, so that IPB is a whole number
 };
 
 // Inodes per block.
@@ -58,3 +99,19 @@ struct dirent {
   char name[DIRSIZ];
 };
 
+// With FS_LONGNAMES, a directory is a sequence of variable-length entries
+// instead. An entry is a struct ldirent followed by the name without a NUL,
+// padded to 4 bytes. Entries do not cross blocks, and the entries of a
+// block cover the whole block: an entry may have free space after its name,
+// and a free entry has inum 0.
+#define LDIRSIZ 255
+
+struct ldirent {
+  uint inum;
+  ushort reclen;   // length of the entry, including the free space after it
+  ushort namelen;
+};
+
+// Length of an entry whose name has n bytes.
+#define LDIRENT_LEN(n) ((sizeof(struct ldirent) + (n) + 3) & ~3)
+
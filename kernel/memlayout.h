@@ -59,9 +59,17 @@
 // Address zero first:
 //   text
 //   original data and bss
-//   fixed-size stack
 //   expandable heap
 //   ...
-//   TRAPFRAME (p->trapframe, used by the trampoline)
+//   memory mappings (mmap), placed downward from MMAPTOP
+//   ...
+//   guard gap, never mapped
+//   stack, growing downward from USTACKTOP up to USTACKSIZE bytes
+//   trap frames of the other threads, downward from TRAPFRAME to TRAPFRAMES
+//   TRAPFRAME (p->trapframe of the first thread, used by the trampoline)
 //   TRAMPOLINE (the same page as in the kernel)
 #define TRAPFRAME (TRAMPOLINE - PGSIZE)
+#define TRAPFRAMES (TRAPFRAME - (NTHREAD-1)*PGSIZE)
+#define MMAPTOP (TRAPFRAME - (1L << 30))
+#define USTACKTOP TRAPFRAMES
+#define USTACKBASE (USTACKTOP - USTACKSIZE)
@@ -5,9 +5,14 @@
 #define NINODE       50  // maximum number of active i-nodes
 #define NDEV         10  // maximum major device number
 #define ROOTDEV       1  // device number of file system root disk
+#define TMPDEV        3  // device number of the tmpfs
+#define PROCDEV       4  // device number of the procfs
+#define DEVDEV        5  // device number of the devfs
 #define MAXARG       32  // max exec arguments
+#define USTACKSIZE   (1024*1024)  // maximum size of the user stack
+#define NTHREAD      16  // maximum number of threads sharing a user memory
 #define MAXOPBLOCKS  10  // max # of blocks any FS op writes
 #define LOGSIZE      (MAXOPBLOCKS*3)  // max data blocks in on-disk log
 #define NBUF         (MAXOPBLOCKS*3)  // size of disk block cache
-#define FSSIZE       5000  // size of file system in blocks
-#define MAXPATH      128   // maximum file path name
+#define FSSIZE       20000  // size of file system in blocks
+#define MAXPATH      256   // maximum file path name
@@ -1,6 +1,7 @@
 #define T_DIR     1   // Directory
 #define T_FILE    2   // File
 #define T_DEVICE  3   // Device
+#define T_SYMLINK 4   // Symbolic link
 
 struct stat {
   int dev;     // File system's disk device
@@ -8,4 +9,27 @@ struct stat {
   short type;  // Type of file
   short nlink; // Number of links to file
   uint64 size; // Size of file in bytes
+  uint mode;   // Permission bits
+  uint uid;    // Owner
+  uint gid;    // Group
+  uint atime;  // Time of last access, in seconds since the Unix epoch
+  uint mtime;  // Time of last modification
+  uint ctime;  // Time of last status change
+};
+
+// The argument of utime.
+struct utimbuf {
+  uint actime;  // Access time
+  uint modtime; // Modification time
+};
+
+// An entry of a directory, as getdents returns it. The record is padded so
+// that reclen is a multiple of 4.
+struct dirent64 {
+  uint ino;       // Inode number
+  uint off;       // Offset of the next entry, for lseek
+  ushort reclen;  // Length of this record
+  uchar type;     // Type of file
+  uchar namelen;  // Length of name
+  char name[];    // NUL-terminated name
 };
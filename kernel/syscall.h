@@ -28,3 +28,35 @@
 #define SYS_lseek 27
 #define SYS_uptime_as_micro 28
 #define SYS_clock  29
+#define SYS_mmap   30
+#define SYS_munmap 31
+#define SYS_mprotect 32
+#define SYS_sigaction 33
+#define SYS_sigprocmask 34
+#define SYS_sigreturn 35
+#define SYS_clone  36
+#define SYS_futex  37
+#define SYS_nice   38
+#define SYS_getpriority 39
+#define SYS_setpriority 40
+#define SYS_sched_setaffinity 41
+#define SYS_sched_getaffinity 42
+#define SYS_nanosleep 43
+#define SYS_clock_nanosleep 44
+#define SYS_mount  45
+#define SYS_umount 46
+#define SYS_symlink 47
+#define SYS_readlink 48
+#define SYS_rename 49
+#define SYS_chmod  50
+#define SYS_chown  51
+#define SYS_utime  52
+#define SYS_getuid 53
+#define SYS_setuid 54
+#define SYS_getgid 55
+#define SYS_setgid 56
+#define SYS_getdents 57
+#define SYS_fsync 58
+#define SYS_fdatasync 59
+#define SYS_sync 60
+#define SYS_writeback 61
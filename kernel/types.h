@@ -1,4 +1,5 @@
 #include <bits/types/struct_timeval.h>
+#include <bits/types/struct_timespec.h>
 #include <bits/types.h>
 #include <bits/types/sigset_t.h>
 
@@ -86,11 +87,34 @@ enum __itimer_which
   };
 
 
+#define	SIGHUP	1	/* hangup */
+#define	SIGINT	2	/* interrupt */
+#define	SIGQUIT	3	/* quit */
+#define	SIGILL	4	/* illegal instr. (not reset when caught) */
+#define	SIGTRAP	5	/* trace trap (not reset when caught) */
+#define	SIGABRT	6	/* abort() */
+#define	SIGFPE	8	/* floating point exception */
 #define	SIGKILL	9	/* kill (cannot be caught or ignored) */
+#define	SIGBUS	10	/* bus error */
+#define	SIGSEGV	11	/* segmentation violation */
+#define	SIGSYS	12	/* bad argument to system call */
+#define	SIGPIPE	13	/* write on a pipe with no one to read it */
 #define	SIGALRM	14	/* alarm clock */
 #define	SIGTERM	15	/* software termination signal from kill */
+#define	SIGURG	16	/* urgent condition on IO channel */
+#define	SIGCONT	19	/* continue a stopped process */
 #define	SIGCHLD	20	/* to parent on child stop or exit */
+#define	SIGWINCH 28	/* window size changes */
 #define SIGUSR1 30	/* user defined signal 1 */
+#define SIGUSR2 31	/* user defined signal 2 */
+#define	NSIG	32	/* number of signals, including 0 */
+
+#define	SA_RESETHAND	0x0004	/* reset to SIG_DFL when taking signal */
+#define	SA_NODEFER	0x0010	/* don't mask the signal we're delivering */
+
+#define	SIG_BLOCK	1	/* block specified signal set */
+#define	SIG_UNBLOCK	2	/* unblock specified signal set */
+#define	SIG_SETMASK	3	/* set specified signal set */
 
 typedef void (*sighandler_t)(int);
 #define	SIG_ERR	 ((sighandler_t) -1)	/* Error return.  */
@@ -110,10 +134,31 @@ struct sigaction
     void (*sa_restorer) (void);
   };
 
+// struct sigaction as the kernel sees it.
+// A mask has bit n set for signal n.
+struct ksigaction
+  {
+    __sighandler_t handler;
+    uint mask;
+    uint flags;
+    void (*restorer) (void);
+  };
+
 
 #ifndef __pid_t_defined
 typedef uint pid_t;
 #define __pid_t_defined
 #endif
 
+#define	FUTEX_WAIT	0	/* sleep while the word holds a value */
+#define	FUTEX_WAKE	1	/* wake the sleepers */
+
+#define	PRIO_PROCESS	0	/* getpriority and setpriority name a process */
+
+#ifndef CLOCK_REALTIME
+#define	CLOCK_REALTIME	0	/* counts from the Unix epoch */
+#define	CLOCK_MONOTONIC	1	/* counts from power-on */
+#define	TIMER_ABSTIME	1	/* clock_nanosleep sleeps until the given time */
+#endif
+
 #endif
@@ -8,6 +8,7 @@
 #include "kernel/stat.h"
 #include "user/user.h"
 #include "kernel/fcntl.h"
+#include "kernel/mman.h"
 // #include "rand.h"
 
 // #ifdef WIN32
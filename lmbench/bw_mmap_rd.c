@@ -84,7 +84,7 @@ main(int ac, char **av)
 	nbytes = state.nbytes = bytes(av[optind]);
 	strcpy(state.filename,av[optind+2]);
 	CHK(stat(state.filename, &sbuf));
-	if ((S_ISREG(sbuf.st_mode) && nbytes > sbuf.st_size) 
+	if ((sbuf.type == T_FILE && nbytes > sbuf.size) 
 	    || (nbytes < MINSZ)) {
 		fprintf(stderr,"<size> out of range!\n");
 		exit(1);
@@ -30,7 +30,7 @@
 char           *id = "$Id$\n";
 
 #include "bench.h"
-#include <sched.h>
+/* rv6 has no <sched.h> and no real-time scheduling class */
 
 typedef     enum {USLEEP, NANOSLEEP, SELECT, PSELECT, ITIMER} timer_e;
 
@@ -152,12 +152,16 @@ bench_itimer(iter_t iterations, void *cookie)
 int
 set_realtime()
 {
+#ifdef SCHED_RR
     struct sched_param sp;
 
     sp.sched_priority = sched_get_priority_max(SCHED_RR);
     if (sched_setscheduler(0, SCHED_RR, &sp) >= 0) return TRUE;
     perror("sched_setscheduler");
     return FALSE;
+#else
+    return 0;
+#endif
 }
 
 int
@@ -21,9 +21,8 @@
 #include <sys/procset.h>
 #endif
 
-#if defined(HAVE_SCHED_SETAFFINITY)
-#include <sched.h>
-#endif
+/* rv6 declares sched_setaffinity and sched_getaffinity in user/user.h */
+#define HAVE_SCHED_SETAFFINITY
 
 extern int custom(char* str, int cpu);
 extern int reverse_bits(int cpu);
@@ -164,6 +163,16 @@ sched_ncpus()
 #elif defined(_SC_NPROCESSORS_ONLN)
 	/* AIX, Solaris, and Linux interface */
 	return sysconf(_SC_NPROCESSORS_ONLN);
+#elif defined(HAVE_SCHED_SETAFFINITY)
+	/* rv6 interface: count the CPUs we may run on */
+	unsigned long mask;
+	int n = 0;
+
+	if (sched_getaffinity(0, sizeof(mask), &mask) < 0)
+		return 1;
+	for (; mask; mask &= mask - 1)
+		n++;
+	return n;
 #else
 	return 1;
 #endif
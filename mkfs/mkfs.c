@@ -4,6 +4,7 @@
 #include <string.h>
 #include <fcntl.h>
 #include <assert.h>
+#include <time.h>
 
 #define stat xv6_stat  // avoid clash with host struct stat
 #include "kernel/types.h"
@@ -31,6 +32,10 @@ struct superblock sb;
 char zeroes[BSIZE];
 uint freeinode = 1;
 uint freeblock;
+int extents;         // make inodes with extent trees (-e)
+char dirbuf[BSIZE];  // the last block of the root directory
+int dirlen;          // bytes of the entries in dirbuf
+int dirlast;         // offset of the last entry in dirbuf
 
 
 void balloc(int);
@@ -38,8 +43,12 @@ void wsect(uint, void*);
 void winode(uint, struct dinode*);
 void rinode(uint inum, struct dinode *ip);
 void rsect(uint sec, void *buf);
-uint ialloc(ushort type);
+uint ialloc(ushort type, ushort mode);
 void iappend(uint inum, void *p, int n);
+uint bmap(struct dinode *din, uint fbn);
+uint emap(struct dinode *din, uint fbn);
+void dappend(uint inum, char *name);
+void dflush(void);
 
 // convert to intel byte order
 ushort
@@ -68,21 +77,24 @@ int
 main(int argc, char *argv[])
 {
   int i, cc, fd;
-  uint rootino, inum, off;
-  struct dirent de;
+  uint rootino, inum;
   char buf[BSIZE];
-  struct dinode din;
 
 
   static_assert(sizeof(int) == 4, "Integers must be 4 bytes!");
 
+  if(argc > 1 && strcmp(argv[1], "-e") == 0){
+    extents = 1;
+    argc--;
+    argv++;
+  }
+
   if(argc < 2){
-    fprintf(stderr, "Usage: mkfs fs.img files...\n");
+    fprintf(stderr, "Usage: mkfs [-e] fs.img files...\n");
     exit(1);
   }
 
   assert((BSIZE % sizeof(struct dinode)) == 0);
-  assert((BSIZE % sizeof(struct dirent)) == 0);
 
   fsfd = open(argv[1], O_RDWR|O_CREAT|O_TRUNC, 0666);
   if(fsfd < 0){
@@ -102,6 +114,8 @@ main(int argc, char *argv[])
   sb.logstart = xint(2);
   sb.inodestart = xint(2+nlog);
   sb.bmapstart = xint(2+nlog+ninodeblocks);
+  sb.features = xint(FS_LONGNAMES | (extents ? FS_EXTENTS : FS_BIGFILES));
+  sb.version = xint(FSVERSION);
 
   printf("nmeta %d (boot, super, log blocks %u inode blocks %u, bitmap blocks %u) blocks %d total %d\n",
          nmeta, nlog, ninodeblocks, nbitmap, nblocks, FSSIZE);
@@ -115,18 +129,11 @@ main(int argc, char *argv[])
   memmove(buf, &sb, sizeof(sb));
   wsect(1, buf);
 
-  rootino = ialloc(T_DIR);
+  rootino = ialloc(T_DIR, 0755);
   assert(rootino == ROOTINO);
 
-  bzero(&de, sizeof(de));
-  de.inum = xshort(rootino);
-  strcpy(de.name, ".");
-  iappend(rootino, &de, sizeof(de));
-
-  bzero(&de, sizeof(de));
-  de.inum = xshort(rootino);
-  strcpy(de.name, "..");
-  iappend(rootino, &de, sizeof(de));
+  dappend(rootino, ".");
+  dappend(rootino, "..");
 
   for(i = 2; i < argc; i++){
     // get rid of "user/"
@@ -137,6 +144,7 @@ main(int argc, char *argv[])
       shortname = argv[i];
     
     assert(index(shortname, '/') == 0);
+    assert(strlen(shortname) <= LDIRSIZ);
 
     if((fd = open(argv[i], 0)) < 0){
       perror(argv[i]);
@@ -147,15 +155,15 @@ main(int argc, char *argv[])
     // The binaries are named _rm, _cat, etc. to keep the
     // build operating system from trying to execute them
     // in place of system binaries like rm and cat.
-    if(shortname[0] == '_')
+    // Only the binaries are executable, and passwd runs as root to
+    // rewrite /etc/passwd.
+    if(shortname[0] == '_'){
       shortname += 1;
-
-    inum = ialloc(T_FILE);
-
-    bzero(&de, sizeof(de));
-    de.inum = xshort(inum);
-    strncpy(de.name, shortname, DIRSIZ);
-    iappend(rootino, &de, sizeof(de));
+      inum = ialloc(T_FILE, strcmp(shortname, "passwd") == 0 ? 04755 : 0755);
+    } else {
+      inum = ialloc(T_FILE, 0644);
+    }
+    dappend(inum, shortname);
 
     while((cc = read(fd, buf, sizeof(buf))) > 0)
       iappend(inum, buf, cc);
@@ -163,12 +171,7 @@ main(int argc, char *argv[])
     close(fd);
   }
 
-  // fix size of root inode dir
-  rinode(rootino, &din);
-  off = xint(din.size);
-  off = ((off/BSIZE) + 1) * BSIZE;
-  din.size = xint(off);
-  winode(rootino, &din);
+  dflush();
 
   balloc(freeblock);
 
@@ -228,8 +231,9 @@ rsect(uint sec, void *buf)
   }
 }
 
+// Allocate an inode owned by root.
 uint
-ialloc(ushort type)
+ialloc(ushort type, ushort mode)
 {
   uint inum = freeinode++;
   struct dinode din;
@@ -238,6 +242,8 @@ ialloc(ushort type)
   din.type = xshort(type);
   din.nlink = xshort(1);
   din.size = xint(0);
+  din.mode = xshort(mode);
+  din.atime = din.mtime = din.ctime = xint(time(0));
   winode(inum, &din);
   return inum;
 }
@@ -260,6 +266,73 @@ balloc(int used)
 
 #define min(a, b) ((a) < (b) ? (a) : (b))
 
+// Return the address of block fbn of the inode din, allocating the block
+// and the indirect blocks to it if needed.
+uint
+bmap(struct dinode *din, uint fbn)
+{
+  uint indirect[NINDIRECT];
+  uint i, level, span, addr;
+
+  if(fbn < NDIRECT){
+    if(xint(din->addrs[fbn]) == 0)
+      din->addrs[fbn] = xint(freeblock++);
+    return xint(din->addrs[fbn]);
+  }
+
+  // Find the indirect block that leads to the block.
+  fbn -= NDIRECT;
+  span = NINDIRECT;
+  for(i = NDIRECT, level = 1; fbn >= span; i++, level++){
+    fbn -= span;
+    span *= NINDIRECT;
+  }
+  assert(i < NADDR);
+  if(xint(din->addrs[i]) == 0)
+    din->addrs[i] = xint(freeblock++);
+  addr = xint(din->addrs[i]);
+
+  // Walk down the indirect blocks.
+  for(; level > 0; level--){
+    span /= NINDIRECT;
+    rsect(addr, (char*)indirect);
+    if(indirect[fbn / span] == 0){
+      indirect[fbn / span] = xint(freeblock++);
+      wsect(addr, (char*)indirect);
+    }
+    addr = xint(indirect[fbn / span]);
+    fbn %= span;
+  }
+  return addr;
+}
+
+// Return the address of block fbn of the inode din with FS_EXTENTS,
+// allocating the block at the end of the file if needed. mkfs allocates
+// blocks in order, so the extents fit in the root of the tree in addrs[].
+uint
+emap(struct dinode *din, uint fbn)
+{
+  struct extent_header *eh = (struct extent_header*)din->addrs;
+  struct extent *ex = (struct extent*)(eh + 1);
+  uint i, n = xshort(eh->nentries);
+
+  for(i = 0; i < n; i++){
+    if(fbn < xint(ex[i].len))
+      return xint(ex[i].start) + fbn;
+    fbn -= xint(ex[i].len);
+  }
+  assert(fbn == 0);
+  if(n > 0 && xint(ex[n-1].start) + xint(ex[n-1].len) == freeblock){
+    ex[n-1].len = xint(xint(ex[n-1].len) + 1);
+  } else {
+    assert(n < NEXTENT);
+    ex[n].start = xint(freeblock);
+    ex[n].len = xint(1);
+    eh->nentries = xshort(n + 1);
+  }
+  return freeblock++;
+}
+
 void
 iappend(uint inum, void *xp, int n)
 {
@@ -267,7 +340,6 @@ iappend(uint inum, void *xp, int n)
   uint fbn, off, n1;
   struct dinode din;
   char buf[BSIZE];
-  uint indirect[NINDIRECT];
   uint x;
 
   rinode(inum, &din);
@@ -275,22 +347,11 @@ iappend(uint inum, void *xp, int n)
   // printf("append inum %d at off %d sz %d\n", inum, off, n);
   while(n > 0){
     fbn = off / BSIZE;
-    assert(fbn < MAXFILE);
-    if(fbn < NDIRECT){
-      if(xint(din.addrs[fbn]) == 0){
-        din.addrs[fbn] = xint(freeblock++);
-      }
-      x = xint(din.addrs[fbn]);
+    if(extents){
+      x = emap(&din, fbn);
     } else {
-      if(xint(din.addrs[NDIRECT]) == 0){
-        din.addrs[NDIRECT] = xint(freeblock++);
-      }
-      rsect(xint(din.addrs[NDIRECT]), (char*)indirect);
-      if(indirect[fbn - NDIRECT] == 0){
-        indirect[fbn - NDIRECT] = xint(freeblock++);
-        wsect(xint(din.addrs[NDIRECT]), (char*)indirect);
-      }
-      x = xint(indirect[fbn-NDIRECT]);
+      assert(fbn < MAXFILE);
+      x = bmap(&din, fbn);
     }
     n1 = min(n, (fbn + 1) * BSIZE - off);
     rsect(x, buf);
@@ -303,3 +364,38 @@ iappend(uint inum, void *xp, int n)
   din.size = xint(off);
   winode(inum, &din);
 }
+
+// Append the entry (inum, name) to the root directory.
+void
+dappend(uint inum, char *name)
+{
+  struct ldirent *de;
+  int n = strlen(name);
+  int len = LDIRENT_LEN(n);
+
+  if(dirlen + len > BSIZE)
+    dflush();
+  de = (struct ldirent*)(dirbuf + dirlen);
+  de->inum = xint(inum);
+  de->reclen = xshort(len);
+  de->namelen = xshort(n);
+  memmove(de + 1, name, n);
+  dirlast = dirlen;
+  dirlen += len;
+}
+
+// Write the entries in dirbuf as the next block of the root directory.
+// The last entry takes the rest of the block.
+void
+dflush(void)
+{
+  struct ldirent *de;
+
+  if(dirlen == 0)
+    return;
+  de = (struct ldirent*)(dirbuf + dirlast);
+  de->reclen = xshort(BSIZE - dirlast);
+  iappend(ROOTINO, dirbuf, BSIZE);
+  memset(dirbuf, 0, BSIZE);
+  dirlen = 0;
+}
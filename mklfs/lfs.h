@@ -21,6 +21,7 @@ struct superblock {
   uint checkpoint1;  // Block number of first checkpoint block
   uint checkpoint2;  // Block number of second checkpoint block
   uint segstart;     // Block number of first segment
+  uint version;      // Must be FSVERSION
 };
 
 /// Block types. Used in segment summary entries.
@@ -46,7 +47,11 @@ struct dimap {
   uint addr[NENTRY];
 };
 
-#define FSMAGIC 0x10203040
+#define FSMAGIC 0x10203041
+
+// Version of the disk format. Version 1 added the permission bits, owners,
+// and times to struct dinode. An older image has version 0.
+#define FSVERSION 1
 #define SEGSUM_MAGIC 0x10305070
 
 #define NDIRECT 12
@@ -61,6 +66,14 @@ struct dinode {
   short nlink;          // Number of links to inode in file system
   uint size;            // Size of file (bytes)
   uint addrs[NDIRECT+1];   // Data block addresses
+  ushort mode;          // Permission bits
+  ushort pad;
+  uint uid;             // Owner
+  uint gid;             // Group
+  uint atime;           // Time of last access
+  uint mtime;           // Time of last modification
+  uint ctime;           // Time of last status change
+  uint reserved[10];    // Unused, so that the size divides BSIZE
 };
 
 // Block containing inode i
@@ -4,6 +4,7 @@
 #include <string.h>
 #include <fcntl.h>
 #include <assert.h>
+#include <time.h>
 
 #define stat xv6_stat  // avoid clash with host struct stat
 #include "kernel/types.h"
@@ -66,7 +67,7 @@ void winode(uint, struct dinode*);
 void rinode(uint inum, struct dinode *ip);
 void wsect(uint, void*);
 void rsect(uint sec, void *buf);
-uint ialloc(ushort type);
+uint ialloc(ushort type, ushort mode);
 void wimap();
 void wchkpt(int chkpt_no);
 void iappend(uint inum, void *p, int n);
@@ -130,6 +131,7 @@ main(int argc, char *argv[])
   sb.checkpoint1 = xint(2);
   sb.checkpoint2 = xint(3);
   sb.segstart = xint(NMETA);
+  sb.version = xint(FSVERSION);
 
   printf("nmeta %d (boot, super, checkpoint1, checkpoint2) blocks %d total %d\n",
          NMETA, nblocks, FSSIZE);
@@ -145,7 +147,7 @@ main(int argc, char *argv[])
   memmove(buf, &sb, sizeof(sb));
   wsect(1, buf);
 
-  rootino = ialloc(T_DIR);
+  rootino = ialloc(T_DIR, 0755);
   assert(rootino == ROOTINO);
 
   bzero(&de, sizeof(de));
@@ -177,10 +179,14 @@ main(int argc, char *argv[])
     // The binaries are named _rm, _cat, etc. to keep the
     // build operating system from trying to execute them
     // in place of system binaries like rm and cat.
-    if(shortname[0] == '_')
+    // Only the binaries are executable, and passwd runs as root to
+    // rewrite /etc/passwd.
+    if(shortname[0] == '_'){
       shortname += 1;
-
-    inum = ialloc(T_FILE);
+      inum = ialloc(T_FILE, strcmp(shortname, "passwd") == 0 ? 04755 : 0755);
+    } else {
+      inum = ialloc(T_FILE, 0644);
+    }
 
     bzero(&de, sizeof(de));
     de.inum = xshort(inum);
@@ -289,8 +295,9 @@ rsect(uint sec, void *buf)
   }
 }
 
+// Allocate an inode owned by root.
 uint
-ialloc(ushort type)
+ialloc(ushort type, ushort mode)
 {
   uint inum = freeinode++;
   struct dinode din;
@@ -299,6 +306,8 @@ ialloc(ushort type)
   din.type = xshort(type);
   din.nlink = xshort(1);
   din.size = xint(0);
+  din.mode = xshort(mode);
+  din.atime = din.mtime = din.ctime = xint(time(0));
   imp[inum] = balloc(SEGSUM_INODE, inum, 0);
   winode(inum, &din);
   return inum;
@@ -8,27 +8,49 @@
 #include "kernel/file.h"
 #include "user/user.h"
 #include "kernel/fcntl.h"
+#include "kernel/param.h"
 
 #ifdef USERTEST
 char *argv[] = { "usertests", 0 };
 #else
-char *argv[] = { "sh", 0 };
+char *argv[] = { "login", 0 };
 #endif
 
+// The accounts that a new file system starts with.
+char *passwd = "root::0:0:/:/sh\n";
+
 int
 main(void)
 {
   // https://github.com/kaist-cp/rv6/commit/d12c1db8d9d7a7e5632e51ae712123d868087fe4
   // Add xstate to immediately run usertests and poweroff.
-  int pid, wpid, xstate;
+  int fd, pid, wpid, xstate;
 
-  if(open("console", O_RDWR) < 0){
+  // Mount the devfs, which has the console and the other devices.
+  mkdir("/dev");
+  mount(DEVDEV, "/dev", "devfs");
+  if(open("/dev/console", O_RDWR) < 0){
     mknod("console", CONSOLE, 0);
     open("console", O_RDWR);
   }
   dup(0);  // stdout
   dup(0);  // stderr
 
+  // Mount the procfs, which ps and top read.
+  mkdir("/proc");
+  if(mount(PROCDEV, "/proc", "procfs") < 0)
+    printf("init: cannot mount /proc\n");
+
+  // Create the password file that login reads, readable only by root.
+  mkdir("/etc");
+  if((fd = open(PASSWD_PATH, O_RDONLY)) < 0){
+    if((fd = open(PASSWD_PATH, O_CREATE|O_WRONLY)) < 0 ||
+       chmod(PASSWD_PATH, 0600) < 0 ||
+       write(fd, passwd, strlen(passwd)) != strlen(passwd))
+      printf("init: cannot create %s\n", PASSWD_PATH);
+  }
+  close(fd);
+
   for(;;){
     printf("init: starting %s\n", argv[0]);
     pid = fork();
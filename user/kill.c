@@ -5,13 +5,20 @@
 int
 main(int argc, char **argv)
 {
-  int i;
+  int i, sig;
 
   if(argc < 2){
-    fprintf(2, "usage: kill pid...\n");
+    fprintf(2, "usage: kill [-signum] pid...\n");
     exit(1);
   }
-  for(i=1; i<argc; i++)
-    kill(atoi(argv[i]));
+  sig = SIGKILL;
+  i = 1;
+  if(argv[1][0] == '-'){
+    sig = atoi(argv[1] + 1);
+    i++;
+  }
+  for(; i<argc; i++)
+    if(kill(atoi(argv[i]), sig) < 0)
+      fprintf(2, "kill: %s failed\n", argv[i]);
   exit(0);
 }
@@ -0,0 +1,60 @@
+// login: ask for a user name and a password, and start the user's
+// shell in the user's home directory with the user's ids.
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+// Read a line from the console without the newline.
+// Returns -1 at the end of the input.
+int
+getline(char *buf, int max)
+{
+  gets(buf, max);
+  if(buf[0] == 0)
+    return -1;
+  buf[strlen(buf) - 1] = 0;
+  return 0;
+}
+
+int
+main(void)
+{
+  char name[32], pass[32];
+  char *argv[2];
+  struct passwd *pw;
+
+  for(;;){
+    printf("login: ");
+    if(getline(name, sizeof(name)) < 0)
+      exit(1);
+    if(name[0] == 0)
+      continue;
+    pass[0] = 0;
+    pw = getpwnam(name);
+    if(pw == 0 || pw->pw_passwd[0] != 0){
+      // Ask even for an unknown user, so as not to tell who exists.
+      printf("Password: ");
+      if(getline(pass, sizeof(pass)) < 0)
+        exit(1);
+    }
+    if(pw != 0 && strcmp(pass, pw->pw_passwd) == 0)
+      break;
+    printf("Login incorrect\n");
+  }
+
+  // Drop root last, as only root may change the ids.
+  if(setgid(pw->pw_gid) < 0 || setuid(pw->pw_uid) < 0){
+    fprintf(2, "login: cannot set the ids of %s\n", pw->pw_name);
+    exit(1);
+  }
+  if(chdir(pw->pw_dir) < 0){
+    fprintf(2, "login: no home directory %s, using /\n", pw->pw_dir);
+    chdir("/");
+  }
+  argv[0] = pw->pw_shell;
+  argv[1] = 0;
+  exec(argv[0], argv);
+  fprintf(2, "login: exec %s failed\n", argv[0]);
+  exit(1);
+}
@@ -2,6 +2,7 @@
 #include "kernel/stat.h"
 #include "user/user.h"
 #include "kernel/fs.h"
+#include "kernel/param.h"
 
 char*
 fmtname(char *path)
@@ -22,13 +23,78 @@ fmtname(char *path)
   return buf;
 }
 
+int
+isleap(int year)
+{
+  return (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+}
+
+// Format t, in seconds since the Unix epoch, as "YYYY-MM-DD hh:mm".
+char*
+fmttime(uint t)
+{
+  static int mdays[] = {31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31};
+  static char buf[32];
+  uint days, n;
+  int year, mon;
+
+  days = t / 86400;
+  for(year = 1970; days >= (n = isleap(year) ? 366 : 365); year++)
+    days -= n;
+  for(mon = 0; days >= (n = mdays[mon] + (mon == 1 && isleap(year))); mon++)
+    days -= n;
+  sprintf(buf, "%d-%02d-%02d %02d:%02d", year, mon + 1, days + 1,
+          t / 3600 % 24, t / 60 % 60);
+  return buf;
+}
+
+// Format the type and the permission bits as "drwxr-xr-x", with an 's'
+// in place of the execute bit of the owner or the group for the
+// set-user-id or the set-group-id bit.
+char*
+fmtmode(int type, uint mode)
+{
+  static char buf[11];
+  int i;
+
+  switch(type){
+  case T_DIR:     buf[0] = 'd'; break;
+  case T_DEVICE:  buf[0] = 'c'; break;
+  case T_SYMLINK: buf[0] = 'l'; break;
+  default:        buf[0] = '-'; break;
+  }
+  for(i = 0; i < 9; i++)
+    buf[i+1] = mode & (0400 >> i) ? "rwx"[i % 3] : '-';
+  if(mode & 04000)
+    buf[3] = buf[3] == 'x' ? 's' : 'S';
+  if(mode & 02000)
+    buf[6] = buf[6] == 'x' ? 's' : 'S';
+  buf[10] = 0;
+  return buf;
+}
+
+// Print the entry of the file path in the long format.
+// A symbolic link has no stat of its own, so only its target is shown.
+void
+pr_long(char *path, struct stat *st, char *target)
+{
+  if(target){
+    printf("%s ? ? ? %d ? %s -> %s\n", fmtmode(T_SYMLINK, 0777), strlen(target),
+           fmtname(path), target);
+    return;
+  }
+  printf("%s %d %d %d %d %s %s\n", fmtmode(st->type, st->mode), st->nlink,
+         st->uid, st->gid, (int)st->size, fmttime(st->mtime), fmtname(path));
+}
+
 void
-ls(char *path)
+ls(char *path, int lflag)
 {
-  char buf[512], *p;
-  int fd;
-  struct dirent de;
+  char buf[512], target[MAXPATH+1], *p;
+  int fd, n;
   struct stat st;
+  struct dirent64 *de;
+  DIR *dir;
 
   if((fd = open(path, 0)) < 0){
     fprintf(2, "ls: cannot open %s\n", path);
@@ -43,28 +109,46 @@ ls(char *path)
 
   switch(st.type){
   case T_FILE:
-    printf("%s %d %d %l\n", fmtname(path), st.type, st.ino, st.size);
+  case T_DEVICE:
+    if(lflag)
+      pr_long(path, &st, 0);
+    else
+      printf("%s %d %d %l\n", fmtname(path), st.type, st.ino, st.size);
     break;
 
   case T_DIR:
-    if(strlen(path) + 1 + DIRSIZ + 1 > sizeof buf){
+    if(strlen(path) + 1 + LDIRSIZ + 1 > sizeof buf){
       printf("ls: path too long\n");
       break;
     }
+    if((dir = opendir(path)) == 0){
+      printf("ls: cannot open %s\n", path);
+      break;
+    }
     strcpy(buf, path);
     p = buf+strlen(buf);
     *p++ = '/';
-    while(read(fd, &de, sizeof(de)) == sizeof(de)){
-      if(de.inum == 0)
+    while((de = readdir(dir)) != 0){
+      strcpy(p, de->name);
+      // Show a symbolic link itself, as stat() shows its target.
+      if(de->type == T_SYMLINK && (n = readlink(buf, target, MAXPATH)) >= 0){
+        target[n] = 0;
+        if(lflag)
+          pr_long(buf, 0, target);
+        else
+          printf("%s %d %d %d -> %s\n", fmtname(buf), T_SYMLINK, de->ino, n, target);
         continue;
-      memmove(p, de.name, DIRSIZ);
-      p[DIRSIZ] = 0;
+      }
       if(stat(buf, &st) < 0){
         printf("ls: cannot stat %s\n", buf);
         continue;
       }
-      printf("%s %d %d %d\n", fmtname(buf), st.type, st.ino, st.size);
+      if(lflag)
+        pr_long(buf, &st, 0);
+      else
+        printf("%s %d %d %d\n", fmtname(buf), st.type, st.ino, st.size);
     }
+    closedir(dir);
     break;
   }
   close(fd);
@@ -73,13 +157,14 @@ ls(char *path)
 int
 main(int argc, char *argv[])
 {
-  int i;
+  int i, lflag;
 
-  if(argc < 2){
-    ls(".");
+  lflag = argc > 1 && strcmp(argv[1], "-l") == 0;
+  if(argc < 2 + lflag){
+    ls(".", lflag);
     exit(0);
   }
-  for(i=1; i<argc; i++)
-    ls(argv[i]);
+  for(i=1+lflag; i<argc; i++)
+    ls(argv[i], lflag);
   exit(0);
 }
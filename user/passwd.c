@@ -0,0 +1,89 @@
+// passwd: change the password of a user.
+// It runs set-user-id root, so that it may rewrite /etc/passwd, but
+// only root may change the password of another user.
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/fcntl.h"
+#include "user/user.h"
+
+#define TMP_PATH "/etc/passwd.tmp"
+
+// Ask for a line from the console, without the newline.
+void
+ask(char *prompt, char *buf, int max)
+{
+  printf("%s", prompt);
+  gets(buf, max);
+  if(buf[0] == 0)
+    exit(1);
+  buf[strlen(buf) - 1] = 0;
+}
+
+int
+main(int argc, char *argv[])
+{
+  char name[32], pass[32], again[32];
+  struct passwd *pw;
+  int uid, in, out;
+
+  if(argc > 2){
+    fprintf(2, "Usage: passwd [user]\n");
+    exit(1);
+  }
+  uid = getuid();
+  pw = argc == 2 ? getpwnam(argv[1]) : getpwuid(uid);
+  if(pw == 0){
+    fprintf(2, "passwd: no such user\n");
+    exit(1);
+  }
+  if(uid != 0 && pw->pw_uid != uid){
+    fprintf(2, "passwd: permission denied\n");
+    exit(1);
+  }
+  strcpy(name, pw->pw_name);
+  strcpy(pass, pw->pw_passwd);
+
+  if(uid != 0 && pass[0] != 0){
+    ask("Current password: ", again, sizeof(again));
+    if(strcmp(again, pass) != 0){
+      fprintf(2, "passwd: wrong password\n");
+      exit(1);
+    }
+  }
+  ask("New password: ", pass, sizeof(pass));
+  ask("Retype new password: ", again, sizeof(again));
+  if(strcmp(pass, again) != 0){
+    fprintf(2, "passwd: passwords do not match\n");
+    exit(1);
+  }
+  if(strchr(pass, ':') != 0){
+    fprintf(2, "passwd: a password may not contain ':'\n");
+    exit(1);
+  }
+
+  // Write the new file aside, and move it over the old one, so that a
+  // crash leaves either of them.
+  if((in = open(PASSWD_PATH, O_RDONLY)) < 0){
+    fprintf(2, "passwd: cannot open %s\n", PASSWD_PATH);
+    exit(1);
+  }
+  if((out = open(TMP_PATH, O_CREATE|O_WRONLY|O_TRUNC)) < 0 || chmod(TMP_PATH, 0600) < 0){
+    fprintf(2, "passwd: cannot create %s\n", TMP_PATH);
+    exit(1);
+  }
+  while((pw = fgetpwent(in)) != 0){
+    if(strcmp(pw->pw_name, name) == 0)
+      pw->pw_passwd = pass;
+    fprintf(out, "%s:%s:%d:%d:%s:%s\n", pw->pw_name, pw->pw_passwd,
+            pw->pw_uid, pw->pw_gid, pw->pw_dir, pw->pw_shell);
+  }
+  close(in);
+  close(out);
+  if(rename(TMP_PATH, PASSWD_PATH) < 0){
+    fprintf(2, "passwd: cannot update %s\n", PASSWD_PATH);
+    unlink(TMP_PATH);
+    exit(1);
+  }
+  exit(0);
+}
@@ -1160,8 +1160,10 @@ sscanf(const char *str, const char *fmt, ...)
 void
 perror(const char *s)
 {
-  // TODO: print corresponding errno message 
-  fprintf(2, "%s: error occured\n", s);
+  if(s && *s)
+    fprintf(2, "%s: %s\n", s, strerror(errno));
+  else
+    fprintf(2, "%s\n", strerror(errno));
 }
 
 void
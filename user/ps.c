@@ -0,0 +1,54 @@
+// ps: list the processes, as /proc shows them.
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/fcntl.h"
+#include "user/user.h"
+
+// Read /proc/<pid>/<file> into buf without the trailing newline.
+// Returns -1 if the file cannot be read, e.g. because the process is gone.
+int
+readproc(char *pid, char *file, char *buf, int n)
+{
+  char path[32];
+  int fd, m;
+
+  sprintf(path, "/proc/%s/%s", pid, file);
+  if((fd = open(path, O_RDONLY)) < 0)
+    return -1;
+  m = read(fd, buf, n-1);
+  close(fd);
+  if(m < 0)
+    return -1;
+  if(m > 0 && buf[m-1] == '\n')
+    m--;
+  buf[m] = 0;
+  return 0;
+}
+
+int
+main(int argc, char *argv[])
+{
+  char *pid, ppid[16], state[16], size[24], name[32];
+  struct dirent64 *de;
+  DIR *dir;
+
+  if((dir = opendir("/proc")) == 0){
+    fprintf(2, "ps: cannot open /proc\n");
+    exit(1);
+  }
+  printf("%5s %5s %-7s %9s %s\n", "PID", "PPID", "STATE", "SIZE", "NAME");
+  while((de = readdir(dir)) != 0){
+    if(de->name[0] < '0' || de->name[0] > '9')
+      continue;
+    pid = de->name;
+    if(readproc(pid, "parent", ppid, sizeof(ppid)) < 0 ||
+       readproc(pid, "state", state, sizeof(state)) < 0 ||
+       readproc(pid, "size", size, sizeof(size)) < 0 ||
+       readproc(pid, "name", name, sizeof(name)) < 0)
+      continue;
+    printf("%5s %5s %-7s %9s %s\n", pid, ppid, state, size, name);
+  }
+  closedir(dir);
+  exit(0);
+}
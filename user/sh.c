@@ -151,8 +151,10 @@ runstring(char *buf)
       fprintf(2, "cannot cd %s\n", buf+3);
     return;
   }
-  if(fork1() == 0)
+  if(fork1() == 0){
+    signal(SIGINT, SIG_DFL);
     runcmd(parsecmd(buf));
+  }
   wait(0);
 }
 
@@ -163,7 +165,7 @@ main(int argc, char *argv[])
   int fd;
 
   // Ensure that three file descriptors are open.
-  while((fd = open("console", O_RDWR)) >= 0){
+  while((fd = open("/dev/console", O_RDWR)) >= 0){
     if(fd >= 3){
       close(fd);
       break;
@@ -175,6 +177,9 @@ main(int argc, char *argv[])
     exit(0);
   }
 
+  // ^C interrupts the command, not the shell.
+  signal(SIGINT, SIG_IGN);
+
   // Read and run input commands.
   while(getcmd(buf, sizeof(buf)) >= 0)
     runstring(buf);
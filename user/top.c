@@ -0,0 +1,132 @@
+// top: show the memory, the buffer cache, the CPUs, and the processes
+// every second, as /proc shows them.
+// Usage: top [count], where count is the number of updates (default: forever).
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/fcntl.h"
+#include "user/user.h"
+
+// Read the file path into buf. Returns -1 if it cannot be read.
+int
+readfile(char *path, char *buf, int n)
+{
+  int fd, m;
+
+  if((fd = open(path, O_RDONLY)) < 0)
+    return -1;
+  m = read(fd, buf, n-1);
+  close(fd);
+  if(m < 0)
+    return -1;
+  buf[m] = 0;
+  return 0;
+}
+
+// Returns the number on the line "key <number>" of buf, or -1 if none.
+int
+field(char *buf, char *key)
+{
+  int n = strlen(key);
+  char *p;
+
+  for(p = buf; *p; p = strchr(p, '\n') + 1){
+    if(memcmp(p, key, n) == 0 && p[n] == ' ')
+      return atoi(p + n + 1);
+    if(strchr(p, '\n') == 0)
+      break;
+  }
+  return -1;
+}
+
+// Read /proc/<pid>/<file> into buf without the trailing newline.
+int
+readproc(char *pid, char *file, char *buf, int n)
+{
+  char path[32];
+  int m;
+
+  sprintf(path, "/proc/%s/%s", pid, file);
+  if(readfile(path, buf, n) < 0)
+    return -1;
+  m = strlen(buf);
+  if(m > 0 && buf[m-1] == '\n')
+    buf[m-1] = 0;
+  return 0;
+}
+
+void
+summary(void)
+{
+  char buf[256];
+  int ticks, free, total, hits, misses, dirty;
+
+  ticks = readfile("/proc/uptime", buf, sizeof(buf)) < 0 ? -1 : field(buf, "ticks");
+  printf("up %d ticks\n", ticks);
+
+  if(readfile("/proc/meminfo", buf, sizeof(buf)) == 0){
+    free = field(buf, "free");
+    total = field(buf, "total");
+    printf("mem: %d of %d pages free\n", free, total);
+  }
+
+  if(readfile("/proc/bcache", buf, sizeof(buf)) == 0){
+    hits = field(buf, "hits");
+    misses = field(buf, "misses");
+    dirty = field(buf, "dirty");
+    printf("bcache: %d hits, %d misses", hits, misses);
+    if(hits + misses > 0)
+      printf(" (%d%% hit)", hits * 100 / (hits + misses));
+    printf(", %d dirty\n", dirty);
+  }
+
+  if(readfile("/proc/cpus", buf, sizeof(buf)) == 0)
+    printf("%s", buf);
+}
+
+void
+processes(void)
+{
+  char *pid, state[16], size[24], name[32];
+  struct dirent64 *de;
+  DIR *dir;
+
+  if((dir = opendir("/proc")) == 0)
+    return;
+  printf("\n%5s %-7s %9s %s\n", "PID", "STATE", "SIZE", "NAME");
+  while((de = readdir(dir)) != 0){
+    if(de->name[0] < '0' || de->name[0] > '9')
+      continue;
+    pid = de->name;
+    if(readproc(pid, "state", state, sizeof(state)) < 0 ||
+       readproc(pid, "size", size, sizeof(size)) < 0 ||
+       readproc(pid, "name", name, sizeof(name)) < 0)
+      continue;
+    printf("%5s %-7s %9s %s\n", pid, state, size, name);
+  }
+  closedir(dir);
+}
+
+int
+main(int argc, char *argv[])
+{
+  int count = argc > 1 ? atoi(argv[1]) : -1;
+  int fd;
+
+  if((fd = open("/proc", O_RDONLY)) < 0){
+    fprintf(2, "top: cannot open /proc\n");
+    exit(1);
+  }
+  close(fd);
+  while(count != 0){
+    // Clear the screen, and move the cursor to the top.
+    printf("\033[2J\033[H");
+    summary();
+    processes();
+    if(count > 0)
+      count--;
+    if(count != 0)
+      usleep(1000000);
+  }
+  exit(0);
+}
@@ -1,6 +1,8 @@
 #include "kernel/types.h"
 #include "kernel/stat.h"
+#include "kernel/fs.h"
 #include "kernel/fcntl.h"
+#include "kernel/errno.h"
 #include "user/user.h"
 
 #define MICROSECS_PER_TICK 100000
@@ -151,10 +153,16 @@ bcopy(const void *src, void *dest, size_t n)
 
 
 
-// ignore all signal function calls
 void (*signal(int sig, void (*func)(int)))(int)
 {
-  return 0;
+  struct sigaction act, oldact;
+
+  act.sa_handler = func;
+  sigemptyset(&act.sa_mask);
+  act.sa_flags = 0;
+  if(sigaction(sig, &act, &oldact) < 0)
+    return SIG_ERR;
+  return oldact.sa_handler;
 };
 
 int
@@ -252,12 +260,6 @@ double atof(const char *s)
   return a;
 }
 
-// nothing to be done
-int fsync(int fildes)
-{
-  return 0;
-}
-
 // bool_t
 // pmap_set (ulong program, ulong version, int protocol, ushort port)
 // {
@@ -285,23 +287,27 @@ int gettimeofday(struct timeval *__restrict__ tp,
   return 0;
 }
 
+// Shared by the threads of a process, which have no thread-local storage.
+static int errno_value;
+
 int * __errno_location(void){
-  return 0;
+  return &errno_value;
 }
 
-// void *mmap(void *addr, size_t length, int prot, int flags,
-//           int fd, off_t offset)
-// {
-//   return 0;
-// }
-
-// int munmap(void *addr, size_t length)
-// {
-//   return 0;
-// }
+// The system call stubs jump here when the kernel returns a negated errno.
+long
+__syscall_error(long ret)
+{
+  errno = -ret;
+  return -1;
+}
 
 void usleep(unsigned long useconds) {
-  sleep(useconds / MICROSECS_PER_TICK);
+  struct timespec ts;
+
+  ts.tv_sec = useconds / 1000000;
+  ts.tv_nsec = useconds % 1000000 * 1000;
+  nanosleep(&ts, 0);
 }
 
 int creat(const char *path, mode_t mode){
@@ -317,6 +323,11 @@ int posix_select(int nfds, fd_set *restrict readfds,
             struct timeval* timeout)
 {
   // doesn't support writefds and exceptfds now.
+  if(nfds == 0 && timeout) {
+    // Nothing to wait for but the timeout.
+    usleep(timeout->tv_sec * 1000000 + timeout->tv_usec);
+    return 0;
+  }
   long ticks = (timeout->tv_sec * 1000000 + timeout->tv_usec) / MICROSECS_PER_TICK;
   if(writefds) {
     FD_ZERO(writefds);
@@ -562,23 +573,225 @@ setitimer(int which, const struct itimerval *new_value,
   return 0;
 }
 
+// The kernel only looks at the first word of a sigset_t,
+// in which bit n stands for signal n.
 int
 sigaction(int signum, const struct sigaction *restrict act,
                      struct sigaction *restrict oldact)
 {
+  struct ksigaction kact, koldact;
+
+  if(act){
+    kact.handler = act->sa_handler;
+    kact.mask = act->sa_mask.__val[0];
+    kact.flags = act->sa_flags;
+    kact.restorer = sigreturn;
+  }
+  if(__sigaction(signum, act ? &kact : 0, oldact ? &koldact : 0) < 0)
+    return -1;
+  if(oldact){
+    oldact->sa_handler = koldact.handler;
+    sigemptyset(&oldact->sa_mask);
+    oldact->sa_mask.__val[0] = koldact.mask;
+    oldact->sa_flags = koldact.flags;
+    oldact->sa_restorer = koldact.restorer;
+  }
+  return 0;
+}
+
+int
+sigprocmask(int how, const sigset_t *restrict set,
+                     sigset_t *restrict oldset)
+{
+  uint kset, koldset;
+
+  if(set)
+    kset = set->__val[0];
+  if(__sigprocmask(how, set ? &kset : 0, oldset ? &koldset : 0) < 0)
+    return -1;
+  if(oldset){
+    sigemptyset(oldset);
+    oldset->__val[0] = koldset;
+  }
   return 0;
 }
 
 int
 sigemptyset(sigset_t *set)
 {
+  memset(set, 0, sizeof(*set));
+  return 0;
+}
+
+int
+sigfillset(sigset_t *set)
+{
+  sigemptyset(set);
+  set->__val[0] = ~1UL;
+  return 0;
+}
+
+int
+sigaddset(sigset_t *set, int signum)
+{
+  if(signum <= 0 || signum >= NSIG)
+    return -1;
+  set->__val[0] |= 1UL << signum;
+  return 0;
+}
+
+int
+sigdelset(sigset_t *set, int signum)
+{
+  if(signum <= 0 || signum >= NSIG)
+    return -1;
+  set->__val[0] &= ~(1UL << signum);
+  return 0;
+}
+
+int
+sigismember(const sigset_t *set, int signum)
+{
+  if(signum <= 0 || signum >= NSIG)
+    return -1;
+  return (set->__val[0] >> signum) & 1;
+}
+
+#define PTHREAD_STACK_SIZE 8192
+
+static void
+pthread_start(void *arg)
+{
+  pthread_t t = arg;
+
+  t->ret = t->start(t->arg);
+  exit(0);
+}
+
+// The attributes are ignored.
+int
+pthread_create(pthread_t *thread, const void *attr,
+               void *(*start)(void *), void *arg)
+{
+  char *stack;
+  pthread_t t;
+
+  if((stack = malloc(PTHREAD_STACK_SIZE)) == 0)
+    return -1;
+  t = (pthread_t)(stack + PTHREAD_STACK_SIZE) - 1;
+  t->start = start;
+  t->arg = arg;
+  t->ret = 0;
+  t->stack = stack;
+  // The kernel stores the thread id in t->tid before the thread starts.
+  if(__clone(pthread_start, t, (void*)((uint64)t & ~0xfUL), &t->tid) < 0){
+    free(stack);
+    return -1;
+  }
+  *thread = t;
+  return 0;
+}
+
+int
+pthread_join(pthread_t thread, void **retval)
+{
+  int tid;
+
+  while((tid = thread->tid) != 0)
+    futex(&thread->tid, FUTEX_WAIT, tid);
+  if(retval)
+    *retval = thread->ret;
+  free(thread->stack);
+  return 0;
+}
+
+// The attributes are ignored.
+int
+pthread_mutex_init(pthread_mutex_t *mutex, const void *attr)
+{
+  mutex->state = 0;
+  return 0;
+}
+
+int
+pthread_mutex_lock(pthread_mutex_t *mutex)
+{
+  int c = 0;
+
+  if(__atomic_compare_exchange_n(&mutex->state, &c, 1, 0,
+                                 __ATOMIC_ACQUIRE, __ATOMIC_RELAXED))
+    return 0;
+  // Mark the mutex contended, so that the owner wakes us on unlock.
+  if(c != 2)
+    c = __atomic_exchange_n(&mutex->state, 2, __ATOMIC_ACQUIRE);
+  while(c != 0){
+    futex(&mutex->state, FUTEX_WAIT, 2);
+    c = __atomic_exchange_n(&mutex->state, 2, __ATOMIC_ACQUIRE);
+  }
   return 0;
 }
 
+int
+pthread_mutex_unlock(pthread_mutex_t *mutex)
+{
+  if(__atomic_exchange_n(&mutex->state, 0, __ATOMIC_RELEASE) == 2)
+    futex(&mutex->state, FUTEX_WAKE, 1);
+  return 0;
+}
+
+int
+nice(int inc)
+{
+  int ret = __nice(inc);
+  return ret < 0 ? ret : 20 - ret;
+}
+
+int
+getpriority(int which, int who)
+{
+  int ret = __getpriority(which, who);
+  return ret < 0 ? ret : 20 - ret;
+}
+
+static const char *errmsgs[] = {
+  [EPERM]        "Operation not permitted",
+  [ENOENT]       "No such file or directory",
+  [ESRCH]        "No such process",
+  [EINTR]        "Interrupted system call",
+  [EIO]          "I/O error",
+  [E2BIG]        "Argument list too long",
+  [ENOEXEC]      "Exec format error",
+  [EBADF]        "Bad file descriptor",
+  [ECHILD]       "No child processes",
+  [EAGAIN]       "Try again",
+  [ENOMEM]       "Out of memory",
+  [EACCES]       "Permission denied",
+  [EFAULT]       "Bad address",
+  [EBUSY]        "Device or resource busy",
+  [EEXIST]       "File exists",
+  [EXDEV]        "Cross-device link",
+  [ENODEV]       "No such device",
+  [ENOTDIR]      "Not a directory",
+  [EISDIR]       "Is a directory",
+  [EINVAL]       "Invalid argument",
+  [ENFILE]       "File table overflow",
+  [EMFILE]       "Too many open files",
+  [EFBIG]        "File too large",
+  [ESPIPE]       "Illegal seek",
+  [EROFS]        "Read-only file system",
+  [EPIPE]        "Broken pipe",
+  [ENAMETOOLONG] "File name too long",
+  [ENOSYS]       "Function not implemented",
+  [ENOTEMPTY]    "Directory not empty",
+  [ELOOP]        "Too many symbolic links encountered",
+};
+
 char*
-strerror(int errno)
+strerror(int errnum)
 {
-  return NULL;  
+  if(errnum > 0 && errnum < sizeof(errmsgs)/sizeof(errmsgs[0]) && errmsgs[errnum])
+    return (char*)errmsgs[errnum];
+  return "Unknown error";
 }
 
 int
@@ -591,7 +804,7 @@ execvp(const char * file, char * const argv[])
 int
 posix_kill(pid_t pid, int sig)
 {
-  return kill(pid);
+  return kill(pid, sig);
 }
 
 int
@@ -610,5 +823,119 @@ posix_exit(int i)
 int
 posix_mkdir(const char *pathname, mode_t mode)
 {
-  return mkdir(pathname);
+  if(mkdir(pathname) < 0)
+    return -1;
+  return chmod(pathname, mode);
+}
+
+// Read the next entry of the passwd file open at fd.
+// Returns 0 at the end of the file. The entry is overwritten by the next call.
+struct passwd*
+fgetpwent(int fd)
+{
+  static char line[128];
+  static struct passwd pw;
+  char *fields[6], *p;
+  int i, n;
+
+  for(;;){
+    for(n = 0; n + 1 < sizeof(line) && read(fd, &line[n], 1) == 1; n++)
+      if(line[n] == '\n')
+        break;
+    if(n == 0)
+      return 0;
+    line[n] = 0;
+
+    // Split the line at the colons.
+    p = line;
+    for(i = 0; i < 6; i++){
+      fields[i] = p;
+      while(*p && *p != ':')
+        p++;
+      if(i < 5 && *p == 0)
+        break;
+      *p++ = 0;
+    }
+    if(i < 6)
+      continue;  // a malformed line
+    pw.pw_name = fields[0];
+    pw.pw_passwd = fields[1];
+    pw.pw_uid = atoi(fields[2]);
+    pw.pw_gid = atoi(fields[3]);
+    pw.pw_dir = fields[4];
+    pw.pw_shell = fields[5];
+    return &pw;
+  }
+}
+
+struct passwd*
+getpwnam(const char *name)
+{
+  struct passwd *pw;
+  int fd;
+
+  if((fd = open(PASSWD_PATH, O_RDONLY)) < 0)
+    return 0;
+  while((pw = fgetpwent(fd)) != 0 && strcmp(pw->pw_name, name) != 0)
+    ;
+  close(fd);
+  return pw;
+}
+
+struct passwd*
+getpwuid(int uid)
+{
+  struct passwd *pw;
+  int fd;
+
+  if((fd = open(PASSWD_PATH, O_RDONLY)) < 0)
+    return 0;
+  while((pw = fgetpwent(fd)) != 0 && pw->pw_uid != uid)
+    ;
+  close(fd);
+  return pw;
+}
+
+DIR*
+opendir(const char *path)
+{
+  DIR *dir;
+  int fd;
+
+  if((fd = open(path, O_RDONLY)) < 0)
+    return 0;
+  if((dir = malloc(sizeof(*dir))) == 0){
+    close(fd);
+    return 0;
+  }
+  dir->fd = fd;
+  dir->pos = dir->len = 0;
+  return dir;
+}
+
+// Returns the next entry of dir, or 0 at the end of the directory.
+// The entry is overwritten by later calls.
+struct dirent64*
+readdir(DIR *dir)
+{
+  struct dirent64 *de;
+
+  if(dir->pos >= dir->len){
+    if((dir->len = getdents(dir->fd, (struct dirent64*)dir->buf, sizeof(dir->buf))) <= 0)
+      return 0;
+    dir->pos = 0;
+  }
+  de = (struct dirent64*)(dir->buf + dir->pos);
+  dir->pos += de->reclen;
+  return de;
+}
+
+int
+closedir(DIR *dir)
+{
+  int r;
+
+  r = close(dir->fd);
+  free(dir);
+  return r;
 }
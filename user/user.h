@@ -2,6 +2,8 @@
 
 struct stat;
 struct rtcdate;
+struct utimbuf;
+struct dirent64;
 
 // system calls
 int fork(void);
@@ -11,7 +13,7 @@ int pipe(int*);
 int write(int, const void*, int);
 int read(int, void*, int);
 int close(int);
-int kill(int);
+int kill(int, int);
 int exec(char*, char**);
 int open(const char*, int);
 int mknod(const char*, short, short);
@@ -25,7 +27,7 @@ int getpid(void);
 char* sbrk(int);
 int sleep(int);
 int uptime(void);
-int poweroff(int) __attribute__((noreturn));
+int poweroff(int);
 
 // newly added system calls
 int select(int nfds, fd_set *restrict readfds,
@@ -39,6 +41,38 @@ int uptime_as_micro();
 int gettimeofday(struct timeval *__restrict__ tp,
                 struct timezone *__restrict__ tzp);
 int clock(unsigned long*);
+void *mmap(void*, size_t, int, int, int, off_t);
+int munmap(void*, size_t);
+int mprotect(void*, size_t, int);
+int __sigaction(int, const struct ksigaction*, struct ksigaction*);
+int __sigprocmask(int, const uint*, uint*);
+void sigreturn(void) __attribute__((noreturn));
+int __clone(void (*)(void*), void*, void*, volatile int*);
+int futex(volatile int*, int, int);
+int __nice(int);
+int __getpriority(int, int);
+int setpriority(int, int, int);
+int sched_setaffinity(int, uint, const void*);
+int sched_getaffinity(int, uint, void*);
+int nanosleep(const struct timespec*, struct timespec*);
+int clock_nanosleep(int, int, const struct timespec*, struct timespec*);
+int mount(int, const char*, const char*);
+int umount(const char*);
+int symlink(const char*, const char*);
+int readlink(const char*, char*, int);
+int rename(const char*, const char*);
+int chmod(const char*, int);
+int chown(const char*, int, int);
+int utime(const char*, const struct utimbuf*);
+int getuid(void);
+int setuid(int);
+int getgid(void);
+int setgid(int);
+int getdents(int, struct dirent64*, int);
+int fsync(int);
+int fdatasync(int);
+int sync(void);
+int writeback(int, int);
 
 // ulib.c
 int stat(const char*, struct stat*);
@@ -73,9 +107,15 @@ sighandler_t signal(int signum, sighandler_t handler);
 unsigned int alarm(unsigned int seconds);
 
 // <unistd.h>
-int fsync(int fildes);
 char* getenv(const char *varname);
 
+// <errno.h>
+int *__errno_location(void);
+#define errno (*__errno_location ())
+
+// <string.h>
+char* strerror(int errnum);
+
 // <stdio.h>
 void perror(const char *s);
 int sscanf(const char *buffer, const char *format, ...);
@@ -126,7 +166,74 @@ int setitimer(int which, const struct itimerval *new_value,
 // <signal.h>
 int sigaction(int signum, const struct sigaction *restrict act,
                      struct sigaction *restrict oldact);
+int sigprocmask(int how, const sigset_t *restrict set,
+                     sigset_t *restrict oldset);
 int sigemptyset(sigset_t *set);
+int sigfillset(sigset_t *set);
+int sigaddset(sigset_t *set, int signum);
+int sigdelset(sigset_t *set, int signum);
+int sigismember(const sigset_t *set, int signum);
+
+// <sys/resource.h>
+int nice(int inc);
+int getpriority(int which, int who);
+
+// <pwd.h>
+// /etc/passwd has a line "name:password:uid:gid:home:shell" for each user.
+// The passwords are in plain text, so only root may read the file.
+#define PASSWD_PATH "/etc/passwd"
+
+struct passwd {
+  char *pw_name;
+  char *pw_passwd;
+  int pw_uid;
+  int pw_gid;
+  char *pw_dir;
+  char *pw_shell;
+};
+
+struct passwd *fgetpwent(int fd);
+struct passwd *getpwnam(const char *name);
+struct passwd *getpwuid(int uid);
+
+// <dirent.h>
+// An open directory, whose entries readdir reads a buffer at a time.
+typedef struct {
+  int fd;
+  int pos;  // offset of the next entry in buf
+  int len;  // number of bytes in buf
+  char buf[512];
+} DIR;
+
+DIR *opendir(const char *path);
+struct dirent64 *readdir(DIR *dir);
+int closedir(DIR *dir);
+
+// <pthread.h>
+// A thread created by pthread_create. It sits at the top of the
+// thread's stack, and the kernel clears tid when the thread exits.
+struct __pthread
+  {
+    volatile int tid;
+    void *(*start) (void *);
+    void *arg;
+    void *ret;
+    void *stack;
+  };
+typedef struct __pthread *pthread_t;
+
+// 0 if unlocked, 1 if locked, and 2 if locked and maybe contended.
+typedef struct
+  {
+    volatile int state;
+  } pthread_mutex_t;
+
+int pthread_create(pthread_t *thread, const void *attr,
+                   void *(*start)(void *), void *arg);
+int pthread_join(pthread_t thread, void **retval);
+int pthread_mutex_init(pthread_mutex_t *mutex, const void *attr);
+int pthread_mutex_lock(pthread_mutex_t *mutex);
+int pthread_mutex_unlock(pthread_mutex_t *mutex);
 
 
 
@@ -4,6 +4,8 @@
 #include "user/user.h"
 #include "kernel/fs.h"
 #include "kernel/fcntl.h"
+#include "kernel/mman.h"
+#include "kernel/errno.h"
 #include "kernel/syscall.h"
 #include "kernel/memlayout.h"
 #include "kernel/arch.h"
@@ -576,10 +578,15 @@ writetest(char *s)
   }
 }
 
+// the largest file, in blocks, of an lfs or of a ufs without FS_BIGFILES.
+#define SMALLMAXFILE (12 + NINDIRECT)
+
 void
 writebig(char *s)
 {
-  int i, fd, n;
+  // into the doubly-indirect block, if the file system has one.
+  enum { N = SMALLMAXFILE + 10 };
+  int i, fd, n, nblocks;
 
   fd = open("big", O_CREATE|O_RDWR);
   if(fd < 0){
@@ -587,9 +594,15 @@ writebig(char *s)
     exit(1);
   }
 
-  for(i = 0; i < MAXFILE; i++){
+  nblocks = N;
+  for(i = 0; i < N; i++){
     ((int*)buf)[0] = i;
     if(write(fd, buf, BSIZE) != BSIZE){
+      if(i == SMALLMAXFILE && errno == EFBIG){
+        // the file system has no bigger files.
+        nblocks = i;
+        break;
+      }
       printf("%s: error: write big file failed\n", s, i);
       exit(1);
     }
@@ -607,7 +620,7 @@ writebig(char *s)
   for(;;){
     i = read(fd, buf, BSIZE);
     if(i == 0){
-      if(n == MAXFILE - 1){
+      if(n != nblocks){
         printf("%s: read only %d blocks from big", s, n);
         exit(1);
       }
@@ -630,6 +643,138 @@ writebig(char *s)
   }
 }
 
+// a file of several megabytes, which reaches the doubly-indirect
+// block. it is written several times, more than the disk holds, so
+// truncation must free all of its blocks. skipped if the file system
+// has no files that big.
+void
+hugefile(char *s)
+{
+  enum { N = 6*1024, ROUNDS = 3 };
+  int fd, i, j, round;
+
+  for(round = 0; round < ROUNDS; round++){
+    fd = open("huge", O_CREATE|O_RDWR);
+    if(fd < 0){
+      printf("%s: create huge failed\n", s);
+      exit(1);
+    }
+    for(i = 0; i < N; i++){
+      for(j = 0; j < BSIZE/sizeof(int); j++)
+        ((int*)buf)[j] = i + j + round;
+      if(write(fd, buf, BSIZE) != BSIZE){
+        if(i == SMALLMAXFILE && errno == EFBIG){
+          close(fd);
+          unlink("huge");
+          return;
+        }
+        printf("%s: write of block %d of huge failed\n", s, i);
+        exit(1);
+      }
+    }
+    close(fd);
+
+    fd = open("huge", O_RDONLY);
+    if(fd < 0){
+      printf("%s: open huge failed\n", s);
+      exit(1);
+    }
+    for(i = 0; i < N; i++){
+      if(read(fd, buf, BSIZE) != BSIZE){
+        printf("%s: read of block %d of huge failed\n", s, i);
+        exit(1);
+      }
+      for(j = 0; j < BSIZE/sizeof(int); j++){
+        if(((int*)buf)[j] != i + j + round){
+          printf("%s: block %d of huge is wrong\n", s, i);
+          exit(1);
+        }
+      }
+    }
+    if(read(fd, buf, BSIZE) != 0){
+      printf("%s: huge is too long\n", s);
+      exit(1);
+    }
+    close(fd);
+    if(unlink("huge") < 0){
+      printf("%s: unlink huge failed\n", s);
+      exit(1);
+    }
+  }
+}
+
+// two files written a block at a time in turn, so that their blocks
+// interleave on the disk. with extent trees, each block of them is an
+// extent, and the trees grow deeper than the root in the inode.
+// skipped if the file system has no files that big.
+void
+fragfile(char *s)
+{
+  enum { N = 1000, M = 4 };
+  char *names[2] = { "frag0", "frag1" };
+  int fds[2];
+  int i, j, k, f, n;
+
+  for(f = 0; f < 2; f++){
+    fds[f] = open(names[f], O_CREATE|O_RDWR);
+    if(fds[f] < 0){
+      printf("%s: create %s failed\n", s, names[f]);
+      exit(1);
+    }
+  }
+  for(i = 0; i < N; i++){
+    for(f = 0; f < 2; f++){
+      for(j = 0; j < BSIZE/sizeof(int); j++)
+        ((int*)buf)[j] = i * 2 + f + j;
+      if(write(fds[f], buf, BSIZE) != BSIZE){
+        if(i == SMALLMAXFILE && errno == EFBIG){
+          for(f = 0; f < 2; f++){
+            close(fds[f]);
+            unlink(names[f]);
+          }
+          return;
+        }
+        printf("%s: write of block %d of %s failed\n", s, i, names[f]);
+        exit(1);
+      }
+    }
+  }
+
+  // read M blocks at a time, which cross extents.
+  for(f = 0; f < 2; f++){
+    close(fds[f]);
+    fds[f] = open(names[f], O_RDONLY);
+    if(fds[f] < 0){
+      printf("%s: open %s failed\n", s, names[f]);
+      exit(1);
+    }
+    for(i = 0; i < N; i += M){
+      n = read(fds[f], buf, M*BSIZE);
+      if(n != M*BSIZE){
+        printf("%s: read of block %d of %s returned %d\n", s, i, names[f], n);
+        exit(1);
+      }
+      for(k = 0; k < M; k++){
+        for(j = 0; j < BSIZE/sizeof(int); j++){
+          if(((int*)buf)[k*BSIZE/sizeof(int) + j] != (i + k) * 2 + f + j){
+            printf("%s: block %d of %s is wrong\n", s, i + k, names[f]);
+            exit(1);
+          }
+        }
+      }
+    }
+    if(read(fds[f], buf, BSIZE) != 0){
+      printf("%s: %s is too long\n", s, names[f]);
+      exit(1);
+    }
+    close(fds[f]);
+    if(unlink(names[f]) < 0){
+      printf("%s: unlink %s failed\n", s, names[f]);
+      exit(1);
+    }
+  }
+}
+
 // many creates, followed by unlink test
 void
 createtest(char *s)
@@ -806,7 +951,7 @@ killstatus(char *s)
       exit(0);
     }
     sleep(1);
-    kill(pid1);
+    kill(pid1, SIGKILL);
     wait(&xst);
     if(xst != -1) {
        printf("%s: status should be -1\n", s);
@@ -863,9 +1008,9 @@ preempt(char *s)
   }
   close(pfds[0]);
   printf("kill... ");
-  kill(pid1);
-  kill(pid2);
-  kill(pid3);
+  kill(pid1, SIGKILL);
+  kill(pid2, SIGKILL);
+  kill(pid3, SIGKILL);
   printf("wait... ");
   wait(0);
   wait(0);
@@ -921,7 +1066,7 @@ reparent(char *s)
     } else {
       int pid2 = fork();
       if(pid2 < 0){
-        kill(master_pid);
+        kill(master_pid, SIGKILL);
         exit(1);
       }
       exit(0);
@@ -1397,10 +1542,9 @@ concreate(char *s)
   char file[3];
   int i, pid, n, fd;
   char fa[N];
-  struct {
-    ushort inum;
-    char name[DIRSIZ];
-  } de;
+  char *name;
+  struct dirent64 *de;
+  DIR *dir;
 
   file[0] = 'C';
   file[2] = '\0';
@@ -1431,26 +1575,25 @@ concreate(char *s)
   }
 
   memset(fa, 0, sizeof(fa));
-  fd = open(".", 0);
+  dir = opendir(".");
   n = 0;
-  while(read(fd, &de, sizeof(de)) > 0){
-    if(de.inum == 0)
-      continue;
-    if(de.name[0] == 'C' && de.name[2] == '\0'){
-      i = de.name[1] - '0';
+  while((de = readdir(dir)) != 0){
+    name = de->name;
+    if(name[0] == 'C' && name[2] == '\0'){
+      i = name[1] - '0';
       if(i < 0 || i >= sizeof(fa)){
-        printf("%s: concreate weird file %s\n", s, de.name);
+        printf("%s: concreate weird file %s\n", s, name);
         exit(1);
       }
       if(fa[i]){
-        printf("%s: concreate duplicate file %s\n", s, de.name);
+        printf("%s: concreate duplicate file %s\n", s, name);
         exit(1);
       }
       fa[i] = 1;
       n++;
     }
   }
-  close(fd);
+  closedir(dir);
 
   if(n != N){
     printf("%s: concreate not enough files in directory listing\n", s);
@@ -1872,12 +2015,33 @@ bigfile(char *s)
   unlink("bigfile.dat");
 }
 
+// returns 1 if the file system of the current directory keeps names
+// longer than DIRSIZ instead of truncating them.
+int
+longnamesfs(void)
+{
+  int fd;
+
+  if((fd = open("123456789012345", O_CREATE|O_RDWR)) < 0){
+    printf("open 123456789012345 failed\n");
+    exit(1);
+  }
+  close(fd);
+  fd = open("12345678901234", O_RDONLY);
+  if(fd >= 0)
+    close(fd);
+  unlink("123456789012345");
+  return fd < 0;
+}
+
 void
 fourteen(char *s)
 {
   int fd;
 
-  // DIRSIZ is 14.
+  // DIRSIZ is 14, unless the file system has long names.
+  if(longnamesfs())
+    return;
 
   if(mkdir("12345678901234") != 0){
     printf("%s: mkdir 12345678901234 failed\n", s);
@@ -1918,6 +2082,133 @@ fourteen(char *s)
   unlink("12345678901234");
 }
 
+// count the entries of the directory path whose names start with prefix.
+int
+countdir(char *s, char *path, char *prefix)
+{
+  struct dirent64 *de;
+  DIR *dir;
+  int n;
+
+  if((dir = opendir(path)) == 0){
+    printf("%s: open %s failed\n", s, path);
+    exit(1);
+  }
+  n = 0;
+  while((de = readdir(dir)) != 0)
+    if(memcmp(de->name, prefix, strlen(prefix)) == 0)
+      n++;
+  closedir(dir);
+  return n;
+}
+
+void
+longnames(char *s)
+{
+  enum { N = 40 };
+  char name[LDIRSIZ+1], file[64];
+  int fd, i;
+
+  if(!longnamesfs())
+    return;
+
+  // names are not truncated to 14 bytes.
+  if(mkdir("12345678901234") != 0 || mkdir("123456789012345") != 0){
+    printf("%s: mkdir 12345678901234 and 123456789012345 failed\n", s);
+    exit(1);
+  }
+  if(unlink("123456789012345") != 0 || unlink("12345678901234") != 0){
+    printf("%s: unlink 12345678901234 and 123456789012345 failed\n", s);
+    exit(1);
+  }
+
+  // a name of LDIRSIZ bytes.
+  memset(name, 'n', LDIRSIZ);
+  name[LDIRSIZ] = 0;
+  if((fd = open(name, O_CREATE|O_RDWR)) < 0){
+    printf("%s: create of a long name failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  name[DIRSIZ] = 0;
+  if(open(name, O_RDONLY) >= 0){
+    printf("%s: open of a truncated name succeeded\n", s);
+    exit(1);
+  }
+  name[DIRSIZ] = 'n';
+  if(unlink(name) != 0){
+    printf("%s: unlink of a long name failed\n", s);
+    exit(1);
+  }
+
+  // entries over several blocks, freed and reused.
+  if(mkdir("lndir") != 0){
+    printf("%s: mkdir lndir failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < N; i++){
+    sprintf(file, "lndir/a_file_with_a_name_that_does_not_fit_in_a_dirent_%d", i);
+    if((fd = open(file, O_CREATE|O_RDWR)) < 0){
+      printf("%s: create %s failed\n", s, file);
+      exit(1);
+    }
+    close(fd);
+  }
+  if(countdir(s, "lndir", "a_file") != N){
+    printf("%s: lndir does not have %d entries\n", s, N);
+    exit(1);
+  }
+  for(i = 0; i < N; i += 2){
+    sprintf(file, "lndir/a_file_with_a_name_that_does_not_fit_in_a_dirent_%d", i);
+    if(unlink(file) != 0){
+      printf("%s: unlink %s failed\n", s, file);
+      exit(1);
+    }
+  }
+  if(countdir(s, "lndir", "a_file") != N/2){
+    printf("%s: lndir does not have %d entries\n", s, N/2);
+    exit(1);
+  }
+  for(i = 1; i < N; i += 2){
+    sprintf(file, "lndir/a_file_with_a_name_that_does_not_fit_in_a_dirent_%d", i);
+    if((fd = open(file, O_RDONLY)) < 0){
+      printf("%s: open %s failed\n", s, file);
+      exit(1);
+    }
+    close(fd);
+  }
+  for(i = 0; i < N; i += 2){
+    sprintf(file, "lndir/another_file_%d", i);
+    if((fd = open(file, O_CREATE|O_RDWR)) < 0){
+      printf("%s: create %s failed\n", s, file);
+      exit(1);
+    }
+    close(fd);
+  }
+  if(countdir(s, "lndir", "a") != N){
+    printf("%s: lndir does not have %d entries\n", s, N);
+    exit(1);
+  }
+  if(unlink("lndir") == 0){
+    printf("%s: unlink of a non-empty directory succeeded\n", s);
+    exit(1);
+  }
+  for(i = 0; i < N; i++){
+    if(i % 2)
+      sprintf(file, "lndir/a_file_with_a_name_that_does_not_fit_in_a_dirent_%d", i);
+    else
+      sprintf(file, "lndir/another_file_%d", i);
+    if(unlink(file) != 0){
+      printf("%s: unlink %s failed\n", s, file);
+      exit(1);
+    }
+  }
+  if(unlink("lndir") != 0){
+    printf("%s: unlink lndir failed\n", s);
+    exit(1);
+  }
+}
+
 void
 rmdot(char *s)
 {
@@ -2270,7 +2561,7 @@ sbrkfail(char *s)
   for(i = 0; i < sizeof(pids)/sizeof(pids[0]); i++){
     if(pids[i] == -1)
       continue;
-    kill(pids[i]);
+    kill(pids[i], SIGKILL);
     wait(0);
   }
   if(c == (char*)0xffffffffffffffffL){
@@ -2474,7 +2765,8 @@ rand()
 }
 
 // check that there's an invalid page beneath
-// the user stack, to catch stack overflow.
+// the lowest address the user stack can grow to,
+// to catch stack overflow.
 void
 stacktest(char *s)
 {
@@ -2484,7 +2776,11 @@ stacktest(char *s)
   pid = fork();
   if(pid == 0) {
     char *sp = (char *) r_sp();
-    sp -= PGSIZE;
+    if((uint64)sp < USTACKBASE || (uint64)sp >= USTACKTOP){
+      printf("%s: stacktest: stack %p is out of the stack region\n", s, sp);
+      exit(1);
+    }
+    sp = (char *) USTACKBASE - PGSIZE;
     // the *sp should cause a trap.
     printf("%s: stacktest: read below stack %p\n", s, *sp);
     exit(1);
@@ -2727,6 +3023,2007 @@ countfree()
   return n;
 }
 
+// fork a process that uses most of the free memory. with
+// copy-on-write fork, parent and child share the pages, so
+// the fork succeeds and the child's writes stay private.
+void
+cowfork(char *s)
+{
+  int free0 = countfree();
+  uint64 amt = (uint64)(free0 * 2 / 3) * 4096;
+
+  char *a = sbrk(amt);
+  if(a == (char*)0xffffffffffffffffL){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+  for(uint64 i = 0; i < amt; i += 4096)
+    *(int*)(a + i) = i / 4096;
+
+  int pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed; pages were copied?\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    for(uint64 i = 0; i < amt; i += 4096){
+      if(*(int*)(a + i) != i / 4096){
+        printf("%s: child sees wrong content\n", s);
+        exit(1);
+      }
+    }
+    // the child's free memory must not have shrunk by
+    // the size of the parent.
+    int free1 = countfree();
+    if(free1 < free0 - free0 * 2 / 3 - 256){
+      printf("%s: fork used %d pages\n", s, free0 - free1);
+      exit(1);
+    }
+    for(uint64 i = 0; i < 64 * 4096; i += 4096)
+      *(int*)(a + i) = -1;
+    exit(0);
+  }
+
+  int xstatus;
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(xstatus);
+  for(uint64 i = 0; i < amt; i += 4096){
+    if(*(int*)(a + i) != i / 4096){
+      printf("%s: child's write is visible to the parent\n", s);
+      exit(1);
+    }
+  }
+  sbrk(-amt);
+}
+
+// the kernel must copy a copy-on-write page before
+// writing to it on behalf of a process, e.g. in read().
+void
+cowcopyout(char *s)
+{
+  static char cbuf[4096];
+  int fds[2];
+
+  cbuf[0] = 'p';
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  int pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(fds[1]);
+    if(read(fds[0], cbuf, 1) != 1 || cbuf[0] != 'c'){
+      printf("%s: read into shared page failed\n", s);
+      exit(1);
+    }
+    exit(0);
+  }
+  close(fds[0]);
+  if(write(fds[1], "c", 1) != 1){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  close(fds[1]);
+
+  int xstatus;
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(xstatus);
+  if(cbuf[0] != 'p'){
+    printf("%s: child's read is visible to the parent\n", s);
+    exit(1);
+  }
+}
+
+// sbrk only reserves address space. pages are allocated
+// and zeroed on first touch, so growing far beyond physical
+// memory succeeds as long as little of it is used.
+void
+sbrklazy(char *s)
+{
+  enum { BIG=512*1024*1024, STRIDE=8*1024*1024 };
+  int free0 = countfree();
+
+  char *a = sbrk(BIG);
+  if(a == (char*)0xffffffffffffffffL){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+  int free1 = countfree();
+  if(free1 < free0 - 64){
+    printf("%s: sbrk allocated %d pages eagerly\n", s, free0 - free1);
+    exit(1);
+  }
+
+  for(uint64 i = 0; i < BIG; i += STRIDE){
+    if(a[i] != 0){
+      printf("%s: untouched page is not zeroed\n", s);
+      exit(1);
+    }
+    a[i] = 1;
+  }
+  int free2 = countfree();
+  if(free2 < free0 - 64 - 2 * (BIG / STRIDE)){
+    printf("%s: touching %d pages used %d pages\n", s, BIG / STRIDE, free0 - free2);
+    exit(1);
+  }
+
+  sbrk(-BIG);
+  if(countfree() < free0 - 64){
+    printf("%s: shrinking did not free pages\n", s);
+    exit(1);
+  }
+}
+
+// anonymous mappings are zero-filled, private across fork,
+// and inaccessible after munmap.
+void
+mmapanon(char *s)
+{
+  enum { SZ = 16*4096 };
+  char *p = mmap(0, SZ, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0);
+  if(p == MAP_FAILED){
+    printf("%s: mmap failed\n", s);
+    exit(1);
+  }
+  for(int i = 0; i < SZ; i += 4096){
+    if(p[i] != 0){
+      printf("%s: mapping is not zeroed\n", s);
+      exit(1);
+    }
+    p[i] = 'a';
+  }
+
+  int pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    p[0] = 'b';
+    exit(0);
+  }
+  int xstatus;
+  wait(&xstatus);
+  if(xstatus != 0 || p[0] != 'a'){
+    printf("%s: child write is visible to parent\n", s);
+    exit(1);
+  }
+
+  // unmapping the middle splits the mapping.
+  if(munmap(p + 4096, 4096) < 0 || p[0] != 'a' || p[2*4096] != 'a'){
+    printf("%s: munmap failed\n", s);
+    exit(1);
+  }
+  if(munmap(p, SZ) < 0){
+    printf("%s: munmap failed\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    p[0] = 'c';
+    printf("%s: access after munmap did not fault\n", s);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != -1)
+    exit(1);
+}
+
+// file mappings hold the file contents, and writes to
+// them are not written back.
+void
+mmapfile(char *s)
+{
+  enum { SZ = 3*4096 + 100 };
+  static char buf[SZ];
+  char *name = "mmapfile";
+
+  unlink(name);
+  int fd = open(name, O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: open failed\n", s);
+    exit(1);
+  }
+  for(int i = 0; i < SZ; i++)
+    buf[i] = 'a' + i % 26;
+  if(write(fd, buf, SZ) != SZ){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  fd = open(name, O_RDONLY);
+  char *p = mmap(0, 4*4096, PROT_READ|PROT_WRITE, MAP_PRIVATE, fd, 0);
+  close(fd);
+  if(p == MAP_FAILED){
+    printf("%s: mmap failed\n", s);
+    exit(1);
+  }
+  for(int i = 0; i < 4*4096; i++){
+    if(p[i] != (i < SZ ? buf[i] : 0)){
+      printf("%s: wrong content at %d\n", s, i);
+      exit(1);
+    }
+  }
+  p[0] = 'Z';
+  if(munmap(p, 4*4096) < 0){
+    printf("%s: munmap failed\n", s);
+    exit(1);
+  }
+
+  fd = open(name, O_RDONLY);
+  if(read(fd, buf, 1) != 1 || buf[0] != 'a'){
+    printf("%s: write to mapping reached the file\n", s);
+    exit(1);
+  }
+
+  // writes would never reach the file, so shared file mappings are refused.
+  if(mmap(0, 4096, PROT_READ, MAP_SHARED, fd, 0) != MAP_FAILED){
+    printf("%s: mapped a file shared\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // only readable files can be mapped.
+  fd = open(name, O_WRONLY);
+  if(mmap(0, 4096, PROT_READ, MAP_PRIVATE, fd, 0) != MAP_FAILED){
+    printf("%s: mapped a write-only file\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink(name);
+}
+
+// text is mapped read+execute, so writing to it must kill the process.
+void
+textwrite(char *s)
+{
+  int pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    volatile char *p = (char *)textwrite;
+    *p = 0;
+    printf("%s: write to text did not fault\n", s);
+    exit(0);
+  }
+  int xstatus;
+  wait(&xstatus);
+  if(xstatus != -1)  // did kernel kill child?
+    exit(1);
+
+  // nor may the kernel write to text on behalf of the process.
+  int fd = open("README", O_RDONLY);
+  if(fd < 0){
+    printf("%s: open README failed\n", s);
+    exit(1);
+  }
+  if(read(fd, (char *)textwrite, 1) != -1 || errno != EFAULT){
+    printf("%s: read into text did not fail with EFAULT\n", s);
+    exit(1);
+  }
+  close(fd);
+}
+
+// the stack is not executable, so jumping into it must kill the process.
+void
+stackexec(char *s)
+{
+  int pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    // a return instruction, so that the child exits normally if the stack
+    // is executable.
+#if defined __riscv
+    uint32 code[4] = { 0x00008067, 0x00008067, 0x00008067, 0x00008067 };
+#else
+    uint32 code[4] = { 0xd65f03c0, 0xd65f03c0, 0xd65f03c0, 0xd65f03c0 };
+#endif
+    void (*f)(void) = (void (*)(void))(uint64)code;
+    f();
+    exit(0);
+  }
+  int xstatus;
+  wait(&xstatus);
+  if(xstatus != -1){  // did kernel kill child?
+    printf("%s: jump into stack did not fault\n", s);
+    exit(1);
+  }
+}
+
+// a page made read-only by mprotect can be read but not written,
+// and becomes writable again.
+void
+mprotecttest(char *s)
+{
+  char *p = sbrk(2*4096);
+  if(p == (char*)0xffffffffffffffffL){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+  p = (char *)(((uint64)p + 4095) & ~4095L);
+  p[0] = 'a';
+  if(mprotect(p, 4096, PROT_READ) < 0){
+    printf("%s: mprotect failed\n", s);
+    exit(1);
+  }
+
+  int pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(p[0] != 'a')
+      exit(1);
+    p[0] = 'b';
+    printf("%s: write to read-only page did not fault\n", s);
+    exit(0);
+  }
+  int xstatus;
+  wait(&xstatus);
+  if(xstatus != -1)
+    exit(1);
+
+  if(mprotect(p, 4096, PROT_READ|PROT_WRITE) < 0){
+    printf("%s: mprotect failed\n", s);
+    exit(1);
+  }
+  p[0] = 'c';
+  if(p[0] != 'c'){
+    printf("%s: page is not writable again\n", s);
+    exit(1);
+  }
+
+  if(mprotect(p + 1, 4096, PROT_READ) == 0){
+    printf("%s: mprotect accepted an unaligned address\n", s);
+    exit(1);
+  }
+
+  // the middle page of a mapping, not accessed yet, stays read-only
+  // when it is allocated, and the rest of the mapping stays writable.
+  char *m = mmap(0, 3*4096, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0);
+  if(m == MAP_FAILED){
+    printf("%s: mmap failed\n", s);
+    exit(1);
+  }
+  if(mprotect(m + 4096, 4096, PROT_READ) < 0){
+    printf("%s: mprotect failed\n", s);
+    exit(1);
+  }
+  m[0] = 'a';
+  m[2*4096] = 'a';
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(m[4096] != 0)
+      exit(1);
+    m[4096] = 'b';
+    printf("%s: write to read-only mapping did not fault\n", s);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != -1)
+    exit(1);
+  munmap(m, 3*4096);
+}
+
+static int
+stackgrow_rec(int depth)
+{
+  volatile int buf[256];
+
+  buf[0] = depth;
+  if(depth == 0)
+    return buf[0];
+  return stackgrow_rec(depth - 1) + buf[0];
+}
+
+// the user stack grows on demand well beyond one page,
+// and its pages are freed on exit.
+void
+stackgrow(char *s)
+{
+  int free0 = countfree();
+  int pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    // about 100 pages of stack.
+    if(stackgrow_rec(400) != 400*401/2)
+      exit(1);
+    exit(0);
+  }
+  int xstatus;
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: deep recursion failed\n", s);
+    exit(1);
+  }
+  if(countfree() < free0 - 16){
+    printf("%s: stack pages were not freed\n", s);
+    exit(1);
+  }
+}
+
+volatile int sigcount;
+volatile int siglast;
+
+static void
+sigcounter(int sig)
+{
+  sigcount++;
+  siglast = sig;
+}
+
+// a caught signal runs its handler, and the interrupted code
+// resumes with its registers intact.
+void
+sighandler(char *s)
+{
+  volatile int before = 1234;
+
+  sigcount = 0;
+  if(signal(SIGUSR1, sigcounter) == SIG_ERR){
+    printf("%s: signal failed\n", s);
+    exit(1);
+  }
+  if(kill(getpid(), SIGUSR1) != 0){
+    printf("%s: kill failed\n", s);
+    exit(1);
+  }
+  if(sigcount != 1 || siglast != SIGUSR1 || before != 1234){
+    printf("%s: handler did not run once\n", s);
+    exit(1);
+  }
+
+  // an ignored signal is discarded.
+  signal(SIGUSR1, SIG_IGN);
+  kill(getpid(), SIGUSR1);
+  if(sigcount != 1){
+    printf("%s: ignored signal ran the handler\n", s);
+    exit(1);
+  }
+
+  // SIGKILL cannot be caught, and bad signal numbers are rejected.
+  if(signal(SIGKILL, sigcounter) != SIG_ERR || kill(getpid(), NSIG) >= 0){
+    printf("%s: bad signal accepted\n", s);
+    exit(1);
+  }
+}
+
+// a blocked signal stays pending until it is unblocked.
+void
+sigmask(char *s)
+{
+  sigset_t set, old;
+
+  sigcount = 0;
+  signal(SIGUSR2, sigcounter);
+  sigemptyset(&set);
+  sigaddset(&set, SIGUSR2);
+  if(sigprocmask(SIG_BLOCK, &set, &old) < 0){
+    printf("%s: sigprocmask failed\n", s);
+    exit(1);
+  }
+  kill(getpid(), SIGUSR2);
+  kill(getpid(), SIGUSR2);
+  if(sigcount != 0){
+    printf("%s: blocked signal delivered\n", s);
+    exit(1);
+  }
+  sigprocmask(SIG_SETMASK, &old, 0);
+  if(sigcount != 1){
+    printf("%s: pending signal delivered %d times\n", s, sigcount);
+    exit(1);
+  }
+  sigprocmask(SIG_SETMASK, 0, &old);
+  if(sigismember(&old, SIGUSR2)){
+    printf("%s: mask not restored\n", s);
+    exit(1);
+  }
+}
+
+// the parent gets SIGCHLD when a child exits.
+void
+sigchld(char *s)
+{
+  int pid, xstatus;
+
+  sigcount = 0;
+  signal(SIGCHLD, sigcounter);
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0)
+    exit(0);
+  wait(&xstatus);
+  if(sigcount != 1 || siglast != SIGCHLD){
+    printf("%s: no SIGCHLD\n", s);
+    exit(1);
+  }
+}
+
+static void
+sigsegvexit(int sig)
+{
+  exit(sig == SIGSEGV ? 0 : 1);
+}
+
+// a bad memory access raises SIGSEGV, which can be caught.
+void
+sigsegv(char *s)
+{
+  int pid, xstatus;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    signal(SIGSEGV, sigsegvexit);
+    // the guard between the mappings and the stack is never mapped.
+    *(volatile char *)MMAPTOP = 1;
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: SIGSEGV was not caught\n", s);
+    exit(1);
+  }
+}
+
+#define NTHREADS 4
+#define NINCR 1000
+
+static pthread_mutex_t threadlock;
+static int threadcount;
+
+static void *
+threadincr(void *arg)
+{
+  for(int i = 0; i < NINCR; i++){
+    pthread_mutex_lock(&threadlock);
+    threadcount++;
+    pthread_mutex_unlock(&threadlock);
+  }
+  return (char*)arg + 1;
+}
+
+// threads share memory, and a mutex keeps their updates apart.
+void
+threadmutex(char *s)
+{
+  pthread_t threads[NTHREADS];
+  void *ret;
+
+  pthread_mutex_init(&threadlock, 0);
+  threadcount = 0;
+  for(int i = 0; i < NTHREADS; i++){
+    if(pthread_create(&threads[i], 0, threadincr, (void*)(uint64)i) < 0){
+      printf("%s: pthread_create failed\n", s);
+      exit(1);
+    }
+  }
+  for(int i = 0; i < NTHREADS; i++){
+    pthread_join(threads[i], &ret);
+    if(ret != (void*)(uint64)(i + 1)){
+      printf("%s: thread %d returned %p\n", s, i, ret);
+      exit(1);
+    }
+  }
+  if(threadcount != NTHREADS * NINCR){
+    printf("%s: count %d, expected %d\n", s, threadcount, NTHREADS * NINCR);
+    exit(1);
+  }
+}
+
+static void *
+threadwrite(void *arg)
+{
+  int fd = *(int*)arg;
+
+  if(write(fd, "x", 1) != 1)
+    return (void*)1;
+  return 0;
+}
+
+static void *
+threadspin(void *arg)
+{
+  while(*(volatile int*)arg == 0)
+    ;
+  return 0;
+}
+
+// threads share open files, and the exit of the first thread
+// terminates the others.
+void
+threadfiles(char *s)
+{
+  pthread_t thread;
+  int fds[2], pid, xstatus, stop = 0;
+  void *ret;
+  char c;
+
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  if(pthread_create(&thread, 0, threadwrite, &fds[1]) < 0){
+    printf("%s: pthread_create failed\n", s);
+    exit(1);
+  }
+  pthread_join(thread, &ret);
+  if(ret != 0 || read(fds[0], &c, 1) != 1 || c != 'x'){
+    printf("%s: thread did not write to the pipe\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(pthread_create(&thread, 0, threadspin, &stop) < 0)
+      exit(1);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: child failed\n", s);
+    exit(1);
+  }
+}
+
+static int appendfd;
+
+static void *
+threadappend(void *arg)
+{
+  for(;;)
+    write(appendfd, "x", 1);
+  return 0;
+}
+
+// the exit of the first thread waits until the other threads are gone,
+// so that none of them runs after the parent has waited.
+void
+threadexitwait(char *s)
+{
+  pthread_t thread;
+  struct stat st1, st2;
+  int pid, xstatus;
+
+  unlink("texitf");
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    appendfd = open("texitf", O_CREATE|O_WRONLY);
+    if(appendfd < 0 || pthread_create(&thread, 0, threadappend, 0) < 0)
+      exit(1);
+    sleep(1);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: child failed\n", s);
+    exit(1);
+  }
+  if(stat("texitf", &st1) < 0){
+    printf("%s: stat texitf failed\n", s);
+    exit(1);
+  }
+  sleep(2);
+  if(stat("texitf", &st2) < 0 || st2.size != st1.size){
+    printf("%s: a thread outlived the exit of the process\n", s);
+    exit(1);
+  }
+  unlink("texitf");
+}
+
+// nice values can be read and set, and are inherited by fork.
+void
+nicetest(char *s)
+{
+  int pid, xstatus;
+
+  if(setpriority(PRIO_PROCESS, 0, 5) < 0 || getpriority(PRIO_PROCESS, 0) != 5){
+    printf("%s: setpriority failed\n", s);
+    exit(1);
+  }
+  if(nice(3) != 8 || getpriority(PRIO_PROCESS, getpid()) != 8){
+    printf("%s: nice failed\n", s);
+    exit(1);
+  }
+  // values out of range are clamped.
+  if(nice(100) != 19 || setpriority(PRIO_PROCESS, 0, -100) < 0 ||
+     getpriority(PRIO_PROCESS, 0) != -20){
+    printf("%s: nice value not clamped\n", s);
+    exit(1);
+  }
+  if(getpriority(PRIO_PROCESS + 1, 0) >= 0){
+    printf("%s: bad which accepted\n", s);
+    exit(1);
+  }
+
+  setpriority(PRIO_PROCESS, 0, 7);
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0)
+    exit(getpriority(PRIO_PROCESS, 0) == 7 ? 0 : 1);
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: nice value not inherited\n", s);
+    exit(1);
+  }
+}
+
+// more than NCPU processes per group, so that the groups compete.
+#define NSHARE 9
+#define SHARETICKS 10
+
+// processes with a higher priority get more CPU time.
+void
+schedshares(char *s)
+{
+  int fds[2], pid, xstatus, end;
+  long msg[2], counts[2] = {0, 0};
+
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  end = uptime() + SHARETICKS;
+  for(int i = 0; i < 2 * NSHARE; i++){
+    pid = fork();
+    if(pid < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pid == 0){
+      // odd children have a lower priority.
+      msg[0] = i % 2;
+      msg[1] = 0;
+      nice(msg[0] ? 15 : 0);
+      while(uptime() < end)
+        msg[1]++;
+      write(fds[1], msg, sizeof(msg));
+      exit(0);
+    }
+  }
+  close(fds[1]);
+  for(int i = 0; i < 2 * NSHARE; i++){
+    if(read(fds[0], msg, sizeof(msg)) != sizeof(msg)){
+      printf("%s: read failed\n", s);
+      exit(1);
+    }
+    counts[msg[0]] += msg[1];
+  }
+  close(fds[0]);
+  for(int i = 0; i < 2 * NSHARE; i++){
+    wait(&xstatus);
+    if(xstatus != 0){
+      printf("%s: child failed\n", s);
+      exit(1);
+    }
+  }
+  if(counts[0] <= 2 * counts[1]){
+    printf("%s: nice 0 ran %d times, nice 15 ran %d times\n",
+           s, (int)counts[0], (int)counts[1]);
+    exit(1);
+  }
+}
+
+// processes pinned to a CPU by sched_setaffinity still run, and their
+// children inherit the affinity.
+void
+affinitytest(char *s)
+{
+  unsigned long all, mask;
+  int ncpu, pid, xstatus;
+
+  if(sched_getaffinity(0, sizeof(all), &all) < 0 || all == 0){
+    printf("%s: sched_getaffinity failed\n", s);
+    exit(1);
+  }
+  if(sched_getaffinity(0, sizeof(all) - 1, &mask) >= 0){
+    printf("%s: short mask accepted\n", s);
+    exit(1);
+  }
+  mask = 0;
+  if(sched_setaffinity(0, sizeof(mask), &mask) >= 0){
+    printf("%s: empty mask accepted\n", s);
+    exit(1);
+  }
+
+  ncpu = 0;
+  for(mask = all; mask; mask &= mask - 1)
+    ncpu++;
+  // pin two spinning children to each CPU.
+  for(int i = 0; i < 2 * ncpu; i++){
+    pid = fork();
+    if(pid < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pid == 0){
+      int cpu = i % ncpu;
+      mask = all;
+      for(int j = 0; j < cpu; j++)
+        mask &= mask - 1;
+      mask &= -mask;
+      if(sched_setaffinity(0, sizeof(mask), &mask) < 0)
+        exit(1);
+      for(volatile int j = 0; j < 1000000; j++)
+        ;
+      pid = fork();
+      if(pid < 0)
+        exit(1);
+      if(pid == 0){
+        unsigned long m;
+        if(sched_getaffinity(0, sizeof(m), &m) < 0 || m != mask)
+          exit(1);
+        exit(0);
+      }
+      wait(&xstatus);
+      exit(xstatus);
+    }
+  }
+  for(int i = 0; i < 2 * ncpu; i++){
+    wait(&xstatus);
+    if(xstatus != 0){
+      printf("%s: pinned child failed\n", s);
+      exit(1);
+    }
+  }
+}
+
+// sleep for less than a clock tick with nanosleep and clock_nanosleep.
+void
+nanosleeptest(char *s)
+{
+  struct timespec ts;
+  int t0, t1, pid, xstatus, fds[2];
+  char c;
+
+  ts.tv_sec = 0;
+  ts.tv_nsec = 1000000000;
+  if(nanosleep(&ts, 0) >= 0){
+    printf("%s: bad tv_nsec accepted\n", s);
+    exit(1);
+  }
+  ts.tv_nsec = 0;
+  if(clock_nanosleep(-1, 0, &ts, 0) >= 0){
+    printf("%s: bad clock accepted\n", s);
+    exit(1);
+  }
+
+  // ten 10ms sleeps should take far less than ten 100ms ticks.
+  t0 = uptime_as_micro();
+  for(int i = 0; i < 10; i++){
+    ts.tv_sec = 0;
+    ts.tv_nsec = 10000000;
+    t1 = uptime_as_micro();
+    if(nanosleep(&ts, 0) < 0){
+      printf("%s: nanosleep failed\n", s);
+      exit(1);
+    }
+    if(uptime_as_micro() - t1 < 10000){
+      printf("%s: woke up early\n", s);
+      exit(1);
+    }
+  }
+  if(uptime_as_micro() - t0 >= 500000){
+    printf("%s: sleeps rounded up to ticks\n", s);
+    exit(1);
+  }
+
+  t0 = uptime_as_micro() + 15000;
+  ts.tv_sec = t0 / 1000000;
+  ts.tv_nsec = t0 % 1000000 * 1000;
+  if(clock_nanosleep(CLOCK_MONOTONIC, TIMER_ABSTIME, &ts, 0) < 0 || uptime_as_micro() < t0){
+    printf("%s: clock_nanosleep failed\n", s);
+    exit(1);
+  }
+
+  // children that sleep concurrently wake up in order.
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  for(int i = 3; i >= 0; i--){
+    pid = fork();
+    if(pid < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pid == 0){
+      close(fds[0]);
+      ts.tv_sec = 0;
+      ts.tv_nsec = (i + 1) * 40000000;
+      if(nanosleep(&ts, 0) < 0)
+        exit(1);
+      c = '0' + i;
+      write(fds[1], &c, 1);
+      exit(0);
+    }
+  }
+  close(fds[1]);
+  for(int i = 0; i < 4; i++){
+    if(read(fds[0], &c, 1) != 1 || c != '0' + i){
+      printf("%s: sleepers woke up out of order\n", s);
+      exit(1);
+    }
+  }
+  close(fds[0]);
+  for(int i = 0; i < 4; i++){
+    wait(&xstatus);
+    if(xstatus != 0){
+      printf("%s: sleeping child failed\n", s);
+      exit(1);
+    }
+  }
+
+  // kill cuts a long sleep short.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    ts.tv_sec = 1000;
+    ts.tv_nsec = 0;
+    nanosleep(&ts, 0);
+    exit(0);
+  }
+  sleep(1);
+  t0 = uptime_as_micro();
+  kill(pid, SIGKILL);
+  wait(&xstatus);
+  if(uptime_as_micro() - t0 >= 1000000){
+    printf("%s: kill did not wake the sleeper\n", s);
+    exit(1);
+  }
+}
+
+// checks that a system call failed with errno err.
+static void
+checkerrno(char *s, int ret, int err, char *what)
+{
+  if(ret != -1 || errno != err){
+    printf("%s: %s returned %d with errno %d, not %d\n", s, what, ret, errno, err);
+    exit(1);
+  }
+}
+
+// failed system calls return -1 and set errno.
+void
+errnotest(char *s)
+{
+  int fd;
+
+  unlink("errnodir/file");
+  unlink("errnodir");
+  if(mkdir("errnodir") < 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  fd = open("errnodir/file", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  checkerrno(s, open("errnonone", O_RDONLY), ENOENT, "open of a missing file");
+  checkerrno(s, mkdir("errnodir"), EEXIST, "mkdir of an existing directory");
+  checkerrno(s, read(fd, &fd, 1), EBADF, "read of a closed fd");
+  checkerrno(s, unlink("errnodir"), ENOTEMPTY, "unlink of a non-empty directory");
+  checkerrno(s, open("errnodir", O_RDWR), EISDIR, "open of a directory for writing");
+  checkerrno(s, chdir("errnodir/file"), ENOTDIR, "chdir to a file");
+  checkerrno(s, wait(0), ECHILD, "wait without children");
+  checkerrno(s, kill(1000000, SIGKILL), ESRCH, "kill of a missing process");
+  if(strcmp(strerror(ENOENT), "No such file or directory") != 0){
+    printf("%s: wrong strerror message\n", s);
+    exit(1);
+  }
+
+  unlink("errnodir/file");
+  unlink("errnodir");
+}
+
+// mount the file system of the second disk on a directory,
+// use it, and unmount it.
+void
+mounttest(char *s)
+{
+  struct stat st1, st2;
+  int fd;
+
+  unlink("mnt");
+  if(mkdir("mnt") < 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+
+  checkerrno(s, mount(2, "mnt", "nofs"), ENODEV, "mount of an unknown file system");
+  checkerrno(s, mount(9, "mnt", "ufs"), ENODEV, "mount of a missing disk");
+  // the second disk holds a ufs or an lfs, which may be of the same
+  // type as the root file system.
+  if(mount(2, "mnt", "lfs") < 0 && mount(2, "mnt", "ufs") < 0){
+    printf("%s: mount failed\n", s);
+    exit(1);
+  }
+  checkerrno(s, mount(2, "mnt", "ufs"), EBUSY, "mount of a mounted disk");
+
+  if(stat("README", &st1) < 0 || stat("mnt/README", &st2) < 0){
+    printf("%s: stat failed\n", s);
+    exit(1);
+  }
+  if(st1.dev == st2.dev){
+    printf("%s: mnt/README is on the root disk\n", s);
+    exit(1);
+  }
+  if(stat("mnt/..", &st2) < 0 || stat(".", &st1) < 0){
+    printf("%s: stat of .. failed\n", s);
+    exit(1);
+  }
+  if(st1.dev != st2.dev || st1.ino != st2.ino){
+    printf("%s: mnt/.. is not the parent of mnt\n", s);
+    exit(1);
+  }
+  checkerrno(s, link("mnt/README", "mntlink"), EXDEV, "link across file systems");
+
+  fd = open("mnt/new", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create in mnt failed\n", s);
+    exit(1);
+  }
+  if(write(fd, "hello", 5) != 5){
+    printf("%s: write in mnt failed\n", s);
+    exit(1);
+  }
+  checkerrno(s, umount("mnt"), EBUSY, "umount of a file system in use");
+  close(fd);
+  if(unlink("mnt/new") < 0){
+    printf("%s: unlink in mnt failed\n", s);
+    exit(1);
+  }
+
+  checkerrno(s, umount("/"), EBUSY, "umount of the root file system");
+  if(umount("mnt") < 0){
+    printf("%s: umount failed\n", s);
+    exit(1);
+  }
+  checkerrno(s, umount("mnt"), EINVAL, "umount of a directory that is not mounted");
+  if(open("mnt/README", O_RDONLY) >= 0){
+    printf("%s: mnt/README exists after umount\n", s);
+    exit(1);
+  }
+  unlink("mnt");
+}
+
+// mount the in-memory file system at /tmp, use it, and mount it
+// over the root directory.
+void
+tmpfstest(char *s)
+{
+  struct stat st;
+  int fd, i;
+
+  mkdir("/tmp");
+  if(mount(TMPDEV, "/tmp", "tmpfs") < 0){
+    printf("%s: mount failed\n", s);
+    exit(1);
+  }
+  if(stat("/tmp", &st) < 0 || st.dev != TMPDEV || st.type != T_DIR){
+    printf("%s: /tmp is not the tmpfs\n", s);
+    exit(1);
+  }
+
+  // a file of a few pages.
+  fd = open("/tmp/big", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create in /tmp failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < BUFSZ; i++)
+    buf[i] = i % 251;
+  if(write(fd, buf, BUFSZ) != BUFSZ){
+    printf("%s: write in /tmp failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  memset(buf, 0, BUFSZ);
+  fd = open("/tmp/big", O_RDONLY);
+  if(fd < 0 || read(fd, buf, BUFSZ) != BUFSZ){
+    printf("%s: read in /tmp failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < BUFSZ; i++){
+    if(buf[i] != (char)(i % 251)){
+      printf("%s: wrong content at %d\n", s, i);
+      exit(1);
+    }
+  }
+  checkerrno(s, umount("/tmp"), EBUSY, "umount of a file system in use");
+  close(fd);
+
+  if(mkdir("/tmp/dir") < 0 || (fd = open("/tmp/dir/file", O_CREATE|O_RDWR)) < 0){
+    printf("%s: mkdir in /tmp failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  checkerrno(s, unlink("/tmp/dir"), ENOTEMPTY, "unlink of a non-empty directory");
+  if(link("/tmp/big", "/tmp/dir/big") < 0){
+    printf("%s: link in /tmp failed\n", s);
+    exit(1);
+  }
+  if(stat("/tmp/dir/big", &st) < 0 || st.nlink != 2 || st.size != BUFSZ){
+    printf("%s: wrong stat of a link\n", s);
+    exit(1);
+  }
+  checkerrno(s, link("/tmp/big", "tmplink"), EXDEV, "link across file systems");
+  if(unlink("/tmp/dir/file") < 0 || unlink("/tmp/dir/big") < 0 ||
+     unlink("/tmp/dir") < 0 || unlink("/tmp/big") < 0){
+    printf("%s: unlink in /tmp failed\n", s);
+    exit(1);
+  }
+  if(open("/tmp/big", O_RDONLY) >= 0){
+    printf("%s: /tmp/big exists after unlink\n", s);
+    exit(1);
+  }
+  if(umount("/tmp") < 0){
+    printf("%s: umount failed\n", s);
+    exit(1);
+  }
+
+  // the tmpfs as the root.
+  if(mount(TMPDEV, "/", "tmpfs") < 0){
+    printf("%s: mount on / failed\n", s);
+    exit(1);
+  }
+  if(stat("/", &st) < 0 || st.dev != TMPDEV || stat("/..", &st) < 0 || st.dev != TMPDEV){
+    printf("%s: / is not the tmpfs\n", s);
+    exit(1);
+  }
+  if(umount("/") < 0){
+    printf("%s: umount of / failed\n", s);
+    exit(1);
+  }
+  if(stat("/", &st) < 0 || st.dev != ROOTDEV){
+    printf("%s: / is not the root disk after umount\n", s);
+    exit(1);
+  }
+}
+
+// reads /proc/<pid>/<file> into buf, and exits on failure.
+static void
+readproc(char *s, int pid, char *file, char *buf, int n)
+{
+  char path[32];
+  int fd, m;
+
+  sprintf(path, "/proc/%d/%s", pid, file);
+  fd = open(path, O_RDONLY);
+  if(fd < 0 || (m = read(fd, buf, n-1)) <= 0){
+    printf("%s: cannot read %s\n", s, path);
+    exit(1);
+  }
+  buf[m] = 0;
+  close(fd);
+}
+
+// /proc shows the processes, and cannot be modified.
+void
+proctest(char *s)
+{
+  char buf[64], path[32];
+  struct stat st;
+  int pid, fds[2], i;
+
+  if(stat("/proc", &st) < 0 || st.dev != PROCDEV || st.type != T_DIR){
+    printf("%s: /proc is not the procfs\n", s);
+    exit(1);
+  }
+
+  readproc(s, getpid(), "name", buf, sizeof(buf));
+  if(strcmp(buf, "usertests\n") != 0){
+    printf("%s: wrong name %s", s, buf);
+    exit(1);
+  }
+  readproc(s, getpid(), "parent", buf, sizeof(buf));
+  if(atoi(buf) != getppid()){
+    printf("%s: wrong parent %s", s, buf);
+    exit(1);
+  }
+  readproc(s, getpid(), "state", buf, sizeof(buf));
+  if(strcmp(buf, "run\n") != 0){
+    printf("%s: wrong state %s", s, buf);
+    exit(1);
+  }
+  readproc(s, getpid(), "fds", buf, sizeof(buf));
+  if(memcmp(buf, "0 1 2", 5) != 0){
+    printf("%s: wrong fds %s", s, buf);
+    exit(1);
+  }
+
+  // a child blocked in read sleeps.
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(fds[1]);
+    read(fds[0], buf, 1);
+    exit(0);
+  }
+  close(fds[0]);
+  for(i = 0; i < 100; i++){
+    readproc(s, pid, "state", buf, sizeof(buf));
+    if(strcmp(buf, "sleep\n") == 0)
+      break;
+    sleep(1);
+  }
+  if(i == 100){
+    printf("%s: child does not sleep, but %s", s, buf);
+    exit(1);
+  }
+  readproc(s, pid, "parent", buf, sizeof(buf));
+  if(atoi(buf) != getpid()){
+    printf("%s: wrong parent of child %s", s, buf);
+    exit(1);
+  }
+  close(fds[1]);
+  wait(0);
+  sprintf(path, "/proc/%d", pid);
+  checkerrno(s, open(path, O_RDONLY), ENOENT, "open of an exited process");
+
+  checkerrno(s, open("/proc/file", O_CREATE|O_RDWR), EROFS, "create in /proc");
+  checkerrno(s, mkdir("/proc/dir"), EROFS, "mkdir in /proc");
+  checkerrno(s, unlink("/proc/meminfo"), EROFS, "unlink in /proc");
+}
+
+void
+devtest(char *s)
+{
+  char buf[64];
+  struct stat st;
+  int fd, i, n;
+
+  if(stat("/dev", &st) < 0 || st.dev != DEVDEV || st.type != T_DIR){
+    printf("%s: /dev is not the devfs\n", s);
+    exit(1);
+  }
+  if(stat("/dev/console", &st) < 0 || st.type != T_DEVICE){
+    printf("%s: /dev/console is not a device\n", s);
+    exit(1);
+  }
+
+  // null reads nothing, and swallows writes.
+  if((fd = open("/dev/null", O_RDWR)) < 0){
+    printf("%s: open /dev/null failed\n", s);
+    exit(1);
+  }
+  if(read(fd, buf, sizeof(buf)) != 0){
+    printf("%s: read of /dev/null is not empty\n", s);
+    exit(1);
+  }
+  if(write(fd, "xyz", 3) != 3){
+    printf("%s: write to /dev/null failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // zero reads zeros.
+  if((fd = open("/dev/zero", O_RDONLY)) < 0){
+    printf("%s: open /dev/zero failed\n", s);
+    exit(1);
+  }
+  memset(buf, 'x', sizeof(buf));
+  if(read(fd, buf, sizeof(buf)) != sizeof(buf)){
+    printf("%s: short read of /dev/zero\n", s);
+    exit(1);
+  }
+  for(i = 0; i < sizeof(buf); i++){
+    if(buf[i] != 0){
+      printf("%s: /dev/zero read %d\n", s, buf[i]);
+      exit(1);
+    }
+  }
+  close(fd);
+
+  // random does not read the same byte over and over.
+  if((fd = open("/dev/random", O_RDONLY)) < 0){
+    printf("%s: open /dev/random failed\n", s);
+    exit(1);
+  }
+  if(read(fd, buf, sizeof(buf)) != sizeof(buf)){
+    printf("%s: short read of /dev/random\n", s);
+    exit(1);
+  }
+  n = 0;
+  for(i = 1; i < sizeof(buf); i++)
+    if(buf[i] != buf[0])
+      n++;
+  if(n == 0){
+    printf("%s: /dev/random is constant\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  checkerrno(s, open("/dev/file", O_CREATE|O_RDWR), EROFS, "create in /dev");
+  checkerrno(s, unlink("/dev/null"), EROFS, "unlink in /dev");
+}
+
+// symbolic links are followed in the middle and at the end of a path,
+// relative to the directory of the link, and not forever.
+void
+symlinktest(char *s)
+{
+  char *paths[] = { "slfile", "sldir/file", "sldir/rel", "symdir/rel" };
+  char buf[MAXPATH];
+  struct stat st;
+  int fd, i, n;
+
+  unlink("sldir");
+  unlink("slfile");
+  unlink("slnull");
+  unlink("sldangle");
+  unlink("nonexistent");
+  unlink("slloop1");
+  unlink("slloop2");
+  unlink("symdir/rel");
+  unlink("symdir/file");
+  unlink("symdir");
+
+  if(mkdir("symdir") < 0){
+    printf("%s: mkdir symdir failed\n", s);
+    exit(1);
+  }
+  fd = open("symdir/file", O_CREATE|O_RDWR);
+  if(fd < 0 || write(fd, "abc", 3) != 3){
+    printf("%s: create symdir/file failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(symlink("symdir/file", "slfile") < 0 || symlink("symdir", "sldir") < 0 ||
+     symlink("file", "symdir/rel") < 0 || symlink("/dev/null", "slnull") < 0){
+    printf("%s: symlink failed\n", s);
+    exit(1);
+  }
+
+  // a link to a file, a link in the middle of a path, and a relative
+  // link in another directory all lead to the file.
+  for(i = 0; i < sizeof(paths)/sizeof(paths[0]); i++){
+    memset(buf, 0, sizeof(buf));
+    if((fd = open(paths[i], O_RDONLY)) < 0){
+      printf("%s: open %s failed\n", s, paths[i]);
+      exit(1);
+    }
+    if(read(fd, buf, sizeof(buf)) != 3 || strcmp(buf, "abc") != 0){
+      printf("%s: read %s returned the wrong data\n", s, paths[i]);
+      exit(1);
+    }
+    close(fd);
+  }
+  if(stat("sldir", &st) < 0 || st.type != T_DIR){
+    printf("%s: stat of a link to a directory is not a directory\n", s);
+    exit(1);
+  }
+
+  // an absolute link crosses into another file system.
+  if((fd = open("slnull", O_WRONLY)) < 0 || write(fd, "xyz", 3) != 3){
+    printf("%s: write through a link to /dev/null failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // readlink returns the link itself.
+  n = readlink("slfile", buf, sizeof(buf));
+  if(n != strlen("symdir/file") || memcmp(buf, "symdir/file", n) != 0){
+    printf("%s: readlink slfile returned %d\n", s, n);
+    exit(1);
+  }
+  checkerrno(s, readlink("symdir/file", buf, sizeof(buf)), EINVAL, "readlink of a file");
+
+  // O_NOFOLLOW refuses a link at the end of the path only.
+  checkerrno(s, open("slfile", O_RDONLY|O_NOFOLLOW), ELOOP, "open of a link with O_NOFOLLOW");
+  if((fd = open("sldir/file", O_RDONLY|O_NOFOLLOW)) < 0){
+    printf("%s: open sldir/file with O_NOFOLLOW failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // a dangling link, a loop, and an existing name.
+  if(symlink("nonexistent", "sldangle") < 0){
+    printf("%s: symlink sldangle failed\n", s);
+    exit(1);
+  }
+  checkerrno(s, open("sldangle", O_RDONLY), ENOENT, "open of a dangling link");
+  n = readlink("sldangle", buf, sizeof(buf));
+  if(n != strlen("nonexistent")){
+    printf("%s: readlink sldangle returned %d\n", s, n);
+    exit(1);
+  }
+  checkerrno(s, open("sldangle", O_CREATE|O_RDWR|O_NOFOLLOW), ELOOP,
+             "create through a link with O_NOFOLLOW");
+  // creating through a dangling link creates its target.
+  if((fd = open("sldangle", O_CREATE|O_RDWR)) < 0){
+    printf("%s: create through a dangling link failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(stat("nonexistent", &st) < 0){
+    printf("%s: create through a dangling link did not create its target\n", s);
+    exit(1);
+  }
+  if(symlink("slloop2", "slloop1") < 0 || symlink("slloop1", "slloop2") < 0){
+    printf("%s: symlink slloop failed\n", s);
+    exit(1);
+  }
+  checkerrno(s, open("slloop1", O_RDONLY), ELOOP, "open of a loop of links");
+  checkerrno(s, open("slloop1", O_CREATE|O_RDWR), ELOOP, "create through a loop of links");
+  checkerrno(s, symlink("symdir", "slfile"), EEXIST, "symlink to an existing name");
+  checkerrno(s, symlink("", "slempty"), ENOENT, "symlink to an empty path");
+
+  // unlink removes the link, not its target.
+  if(unlink("slfile") < 0 || stat("symdir/file", &st) < 0){
+    printf("%s: unlink of a link removed its target\n", s);
+    exit(1);
+  }
+
+  unlink("sldir");
+  unlink("slnull");
+  unlink("sldangle");
+  unlink("nonexistent");
+  unlink("slloop1");
+  unlink("slloop2");
+  unlink("symdir/rel");
+  unlink("symdir/file");
+  if(unlink("symdir") < 0){
+    printf("%s: unlink symdir failed\n", s);
+    exit(1);
+  }
+}
+
+// checks that the file path holds the data want.
+void
+checkdata(char *s, char *path, char *want)
+{
+  char buf[16];
+  int fd, n;
+
+  memset(buf, 0, sizeof(buf));
+  if((fd = open(path, O_RDONLY)) < 0){
+    printf("%s: open %s failed\n", s, path);
+    exit(1);
+  }
+  n = read(fd, buf, sizeof(buf) - 1);
+  close(fd);
+  if(n != strlen(want) || strcmp(buf, want) != 0){
+    printf("%s: %s holds the wrong data\n", s, path);
+    exit(1);
+  }
+}
+
+// rename moves files and directories, replaces the target, and
+// refuses the moves that would break the tree.
+void
+renametest(char *s)
+{
+  struct stat st;
+  int fd;
+
+  unlink("rnfile");
+  unlink("rnfile2");
+  unlink("rna/b/file");
+  unlink("rna/b");
+  unlink("rna/d");
+  unlink("rna");
+  unlink("rnb/b/file");
+  unlink("rnb/b");
+  unlink("rnb");
+
+  fd = open("rnfile", O_CREATE|O_RDWR);
+  if(fd < 0 || write(fd, "one", 3) != 3){
+    printf("%s: create rnfile failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // a file in the same directory.
+  if(rename("rnfile", "rnfile2") < 0){
+    printf("%s: rename rnfile rnfile2 failed\n", s);
+    exit(1);
+  }
+  checkerrno(s, open("rnfile", O_RDONLY), ENOENT, "open of the old name");
+  checkdata(s, "rnfile2", "one");
+
+  // replacing an existing file.
+  fd = open("rnfile", O_CREATE|O_RDWR);
+  if(fd < 0 || write(fd, "two", 3) != 3){
+    printf("%s: create rnfile failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(rename("rnfile", "rnfile2") < 0){
+    printf("%s: rename over rnfile2 failed\n", s);
+    exit(1);
+  }
+  checkdata(s, "rnfile2", "two");
+  if(stat("rnfile2", &st) < 0 || st.nlink != 1){
+    printf("%s: rename over rnfile2 left wrong nlink\n", s);
+    exit(1);
+  }
+
+  // a directory to another parent.
+  if(mkdir("rna") < 0 || mkdir("rna/b") < 0 || mkdir("rnb") < 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  if(rename("rnfile2", "rna/b/file") < 0){
+    printf("%s: rename rnfile2 rna/b/file failed\n", s);
+    exit(1);
+  }
+  if(rename("rna/b", "rnb/b") < 0){
+    printf("%s: rename rna/b rnb/b failed\n", s);
+    exit(1);
+  }
+  checkdata(s, "rnb/b/file", "two");
+  checkdata(s, "rnb/b/../b/file", "two");
+  if(stat("rna", &st) < 0 || st.nlink != 1 || stat("rnb", &st) < 0 || st.nlink != 2){
+    printf("%s: rename of a directory left wrong nlink\n", s);
+    exit(1);
+  }
+
+  // the moves that would break the tree.
+  checkerrno(s, rename("rnb", "rnb/b/c"), EINVAL, "rename into its own subtree");
+  checkerrno(s, rename("rnb/b/..", "rnc"), EINVAL, "rename of ..");
+  checkerrno(s, rename("rna", "rnb"), ENOTEMPTY, "rename over a non-empty directory");
+  checkerrno(s, rename("rnb/b/file", "rna"), EISDIR, "rename of a file over a directory");
+  if(mkdir("rna/d") < 0){
+    printf("%s: mkdir rna/d failed\n", s);
+    exit(1);
+  }
+  checkerrno(s, rename("rna/d", "rnb/b/file"), ENOTDIR, "rename of a directory over a file");
+  checkerrno(s, rename("rnb/b/file", "/dev/file"), EXDEV, "rename to another file system");
+  checkerrno(s, rename("rnnone", "rnfile"), ENOENT, "rename of a missing file");
+
+  // an empty directory can be replaced.
+  if(rename("rna/d", "rna/e") < 0 || mkdir("rna/d") < 0 || rename("rna/e", "rna/d") < 0){
+    printf("%s: rename over an empty directory failed\n", s);
+    exit(1);
+  }
+  if(stat("rna", &st) < 0 || st.nlink != 2){
+    printf("%s: rename over a directory left wrong nlink\n", s);
+    exit(1);
+  }
+
+  if(unlink("rnb/b/file") < 0 || unlink("rnb/b") < 0 || unlink("rnb") < 0 ||
+     unlink("rna/d") < 0 || unlink("rna") < 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+}
+
+// inodes have permission bits, owners, and times, which chmod, chown,
+// and utime change, and exec needs a regular file with an execute bit.
+void
+permtest(char *s)
+{
+  struct stat st;
+  struct utimbuf times;
+  char *args[] = { "permfile", 0 };
+  int fd, pid, xstatus;
+
+  unlink("permfile");
+  unlink("permdir/file");
+  unlink("permdir");
+
+  fd = open("permfile", O_CREATE|O_RDWR);
+  if(fd < 0 || write(fd, "data", 4) != 4){
+    printf("%s: create permfile failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(stat("permfile", &st) < 0 || st.mode != 0644 || st.uid != 0 || st.gid != 0){
+    printf("%s: new file has mode %x uid %d gid %d\n", s, st.mode, st.uid, st.gid);
+    exit(1);
+  }
+  if(mkdir("permdir") < 0 || stat("permdir", &st) < 0 || st.mode != 0755){
+    printf("%s: new directory has the wrong mode\n", s);
+    exit(1);
+  }
+
+  if(chmod("permfile", 0600) < 0 || stat("permfile", &st) < 0 || st.mode != 0600){
+    printf("%s: chmod failed\n", s);
+    exit(1);
+  }
+  checkerrno(s, chmod("permnonexistent", 0600), ENOENT, "chmod of a missing file");
+
+  if(chown("permfile", 5, -1) < 0 || stat("permfile", &st) < 0 || st.uid != 5 || st.gid != 0){
+    printf("%s: chown of the owner failed\n", s);
+    exit(1);
+  }
+  if(chown("permfile", -1, 7) < 0 || stat("permfile", &st) < 0 || st.uid != 5 || st.gid != 7){
+    printf("%s: chown of the group failed\n", s);
+    exit(1);
+  }
+
+  times.actime = 1000;
+  times.modtime = 2000;
+  if(utime("permfile", &times) < 0 || stat("permfile", &st) < 0 ||
+     st.atime != 1000 || st.mtime != 2000){
+    printf("%s: utime failed\n", s);
+    exit(1);
+  }
+  if(utime("permfile", 0) < 0 || stat("permfile", &st) < 0 || st.mtime == 2000){
+    printf("%s: utime to now failed\n", s);
+    exit(1);
+  }
+
+  // writing changes the modification time.
+  if(utime("permfile", &times) < 0){
+    printf("%s: utime failed\n", s);
+    exit(1);
+  }
+  fd = open("permfile", O_RDWR);
+  if(fd < 0 || write(fd, "more", 4) != 4){
+    printf("%s: write permfile failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(stat("permfile", &st) < 0 || st.mtime == 2000){
+    printf("%s: write did not change mtime\n", s);
+    exit(1);
+  }
+
+  // even root may not execute a file without an execute bit.
+  checkerrno(s, exec("permfile", args), EACCES, "exec of a file without x");
+  checkerrno(s, exec("permdir", args), EACCES, "exec of a directory");
+
+  // a file in a directory that may not be searched cannot be opened, even
+  // with O_CREATE when the file exists.
+  fd = open("permdir/file", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create permdir/file failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(chmod("permdir", 0700) < 0){
+    printf("%s: chmod permdir failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0){
+      printf("%s: setuid failed\n", s);
+      exit(1);
+    }
+    checkerrno(s, open("permdir/file", O_CREATE|O_RDONLY), EACCES,
+               "open with O_CREATE in a directory without x");
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(1);
+
+  if(unlink("permdir/file") < 0 || unlink("permfile") < 0 || unlink("permdir") < 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+}
+
+// processes have real and effective user and group ids, which fork
+// inherits, setuid and setgid change, and which decide the access to files,
+// to other processes, and to the system calls only root may make.
+void
+credtest(char *s)
+{
+  struct stat st;
+  int fd, pid, xstatus;
+
+  if(getuid() != 0 || getgid() != 0){
+    printf("%s: usertests does not run as root\n", s);
+    exit(1);
+  }
+
+  unlink("credfile");
+  unlink("creddir");
+  fd = open("credfile", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create credfile failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(mkdir("creddir") < 0 || chmod("creddir", 0700) < 0){
+    printf("%s: mkdir creddir failed\n", s);
+    exit(1);
+  }
+
+  // chmod sets the set-user-id bit, and chown clears it.
+  if(chmod("credfile", 04755) < 0 || stat("credfile", &st) < 0 || st.mode != 04755){
+    printf("%s: chmod of the set-user-id bit failed\n", s);
+    exit(1);
+  }
+  if(chown("credfile", 0, -1) < 0 || stat("credfile", &st) < 0 || st.mode != 0755){
+    printf("%s: chown kept the set-user-id bit\n", s);
+    exit(1);
+  }
+  if(chmod("credfile", 0600) < 0){
+    printf("%s: chmod failed\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setgid(10) < 0 || setuid(10) < 0 || getuid() != 10 || getgid() != 10){
+      printf("%s: setuid to a user failed\n", s);
+      exit(1);
+    }
+    checkerrno(s, setuid(0), EPERM, "setuid back to root");
+    checkerrno(s, setgid(0), EPERM, "setgid back to root");
+    checkerrno(s, open("credfile", O_RDONLY), EACCES, "open of a file of root");
+    checkerrno(s, open("creddir/file", O_CREATE|O_RDWR), EACCES, "create in a directory of root");
+    checkerrno(s, chmod("credfile", 0666), EPERM, "chmod of a file of root");
+    checkerrno(s, chown("credfile", 10, 10), EPERM, "chown by a user");
+    checkerrno(s, poweroff(0), EPERM, "poweroff by a user");
+    checkerrno(s, mknod("creddev", 1, 1), EPERM, "mknod by a user");
+    checkerrno(s, mount(TMPDEV, "creddir", "tmpfs"), EPERM, "mount by a user");
+    checkerrno(s, umount("/"), EPERM, "umount by a user");
+    checkerrno(s, kill(getppid(), 0), EPERM, "kill of a process of root");
+    checkerrno(s, setpriority(PRIO_PROCESS, getppid(), 0), EPERM,
+               "setpriority of a process of root");
+    if(kill(getpid(), 0) < 0 || setpriority(PRIO_PROCESS, getpid(), 0) < 0){
+      printf("%s: a user cannot signal or renice its own process\n", s);
+      exit(1);
+    }
+
+    // a child has the ids of its parent.
+    pid = fork();
+    if(pid == 0)
+      exit(getuid() == 10 && getgid() == 10 ? 0 : 1);
+    wait(&xstatus);
+    if(xstatus != 0){
+      printf("%s: a child did not inherit the ids\n", s);
+      exit(1);
+    }
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(1);
+  if(getuid() != 0){
+    printf("%s: setuid in a child changed the parent\n", s);
+    exit(1);
+  }
+
+  if(unlink("credfile") < 0 || unlink("creddir") < 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+}
+
+// readdir lists the entries of a directory with their types, and getdents
+// resumes where the last call or an lseek to the offset of an entry left.
+void
+readdirtest(char *s)
+{
+  char buf[64], pid[16];
+  struct dirent64 *de;
+  struct stat st;
+  DIR *dir;
+  int fd, n, seen, resume;
+
+  unlink("rddir/file");
+  unlink("rddir/link");
+  unlink("rddir/sub");
+  unlink("rddir");
+  if(mkdir("rddir") < 0 || mkdir("rddir/sub") < 0 ||
+     (fd = open("rddir/file", O_CREATE|O_RDWR)) < 0 ||
+     symlink("file", "rddir/link") < 0){
+    printf("%s: cannot make rddir\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(stat("rddir/file", &st) < 0){
+    printf("%s: stat rddir/file failed\n", s);
+    exit(1);
+  }
+
+  if((dir = opendir("rddir")) == 0){
+    printf("%s: opendir rddir failed\n", s);
+    exit(1);
+  }
+  seen = n = 0;
+  while((de = readdir(dir)) != 0){
+    n++;
+    if(strcmp(de->name, ".") == 0 && de->type == T_DIR)
+      seen |= 1;
+    else if(strcmp(de->name, "..") == 0 && de->type == T_DIR)
+      seen |= 2;
+    else if(strcmp(de->name, "file") == 0 && de->type == T_FILE && de->ino == st.ino)
+      seen |= 4;
+    else if(strcmp(de->name, "sub") == 0 && de->type == T_DIR)
+      seen |= 8;
+    else if(strcmp(de->name, "link") == 0 && de->type == T_SYMLINK)
+      seen |= 16;
+    else {
+      printf("%s: readdir returned %s of type %d\n", s, de->name, de->type);
+      exit(1);
+    }
+    if(de->namelen != strlen(de->name)){
+      printf("%s: wrong namelen of %s\n", s, de->name);
+      exit(1);
+    }
+  }
+  if(n != 5 || seen != 31){
+    printf("%s: readdir missed entries of rddir\n", s);
+    exit(1);
+  }
+
+  // Rewind, and resume after the first entry.
+  if(lseek(dir->fd, 0, SEEK_SET) != 0 || getdents(dir->fd, (struct dirent64*)buf, sizeof(buf)) <= 0){
+    printf("%s: getdents after rewinding failed\n", s);
+    exit(1);
+  }
+  de = (struct dirent64*)buf;
+  resume = de->off;
+  if(lseek(dir->fd, resume, SEEK_SET) != resume){
+    printf("%s: lseek to an entry failed\n", s);
+    exit(1);
+  }
+  for(n = 0; getdents(dir->fd, (struct dirent64*)buf, 24) > 0; n++)
+    ;
+  if(n != 4){
+    printf("%s: getdents read %d entries after the first, not 4\n", s, n);
+    exit(1);
+  }
+  lseek(dir->fd, 0, SEEK_SET);
+  checkerrno(s, getdents(dir->fd, (struct dirent64*)buf, 8), EINVAL, "getdents into a short buffer");
+  closedir(dir);
+
+  fd = open("rddir/file", O_RDONLY);
+  checkerrno(s, getdents(fd, (struct dirent64*)buf, sizeof(buf)), ENOTDIR, "getdents of a file");
+  close(fd);
+
+  // The procfs lists the running processes.
+  sprintf(pid, "%d", getpid());
+  if((dir = opendir("/proc")) == 0){
+    printf("%s: opendir /proc failed\n", s);
+    exit(1);
+  }
+  while((de = readdir(dir)) != 0 && strcmp(de->name, pid) != 0)
+    ;
+  if(de == 0 || de->type != T_DIR){
+    printf("%s: /proc does not list %s\n", s, pid);
+    exit(1);
+  }
+  closedir(dir);
+
+  if(unlink("rddir/file") < 0 || unlink("rddir/link") < 0 || unlink("rddir/sub") < 0 ||
+     unlink("rddir") < 0){
+    printf("%s: unlink rddir failed\n", s);
+    exit(1);
+  }
+}
+
+// fsync, fdatasync and sync write a file to the disk, and only
+// root may change the write-back times.
+void
+synctest(char *s)
+{
+  int fd, p[2], pid, xstatus;
+
+  unlink("syncf");
+  fd = open("syncf", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create syncf failed\n", s);
+    exit(1);
+  }
+  if(write(fd, "hello", 5) != 5){
+    printf("%s: write syncf failed\n", s);
+    exit(1);
+  }
+  if(fsync(fd) != 0 || fdatasync(fd) != 0 || sync() != 0){
+    printf("%s: sync of syncf failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  checkdata(s, "syncf", "hello");
+  checkerrno(s, fsync(fd), EBADF, "fsync of a closed fd");
+
+  if(pipe(p) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  checkerrno(s, fsync(p[0]), EINVAL, "fsync of a pipe");
+  close(p[0]);
+  close(p[1]);
+
+  fd = open(".", O_RDONLY);
+  if(fd < 0 || fsync(fd) != 0){
+    printf("%s: fsync of a directory failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // Negative times keep the current ones.
+  if(writeback(-1, -1) != 0){
+    printf("%s: writeback as root failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0){
+      printf("%s: setuid failed\n", s);
+      exit(1);
+    }
+    checkerrno(s, writeback(-1, -1), EPERM, "writeback as a user");
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(1);
+
+  if(unlink("syncf") < 0){
+    printf("%s: unlink syncf failed\n", s);
+    exit(1);
+  }
+}
+
 // run each test in its own process. run returns 1 if child's exit()
 // indicates success.
 int
@@ -2828,10 +5125,44 @@ main(int argc, char *argv[])
     {exitwait, "exitwait"},
     {rmdot, "rmdot"},
     {fourteen, "fourteen"},
+    {longnames, "longnames"},
     {bigfile, "bigfile"},
     {dirfile, "dirfile"},
     {iref, "iref"},
     {forktest, "forktest"},
+    {cowfork, "cowfork"},
+    {cowcopyout, "cowcopyout"},
+    {sbrklazy, "sbrklazy"},
+    {mmapanon, "mmapanon"},
+    {mmapfile, "mmapfile"},
+    {textwrite, "textwrite"},
+    {stackexec, "stackexec"},
+    {mprotecttest, "mprotecttest"},
+    {stackgrow, "stackgrow"},
+    {sighandler, "sighandler"},
+    {sigmask, "sigmask"},
+    {sigchld, "sigchld"},
+    {sigsegv, "sigsegv"},
+    {threadmutex, "threadmutex"},
+    {threadfiles, "threadfiles"},
+    {threadexitwait, "threadexitwait"},
+    {nicetest, "nicetest"},
+    {schedshares, "schedshares"},
+    {affinitytest, "affinitytest"},
+    {nanosleeptest, "nanosleeptest"},
+    {errnotest, "errnotest"},
+    {mounttest, "mounttest"},
+    {tmpfstest, "tmpfstest"},
+    {proctest, "proctest"},
+    {devtest, "devtest"},
+    {hugefile, "hugefile"},
+    {fragfile, "fragfile"},
+    {symlinktest, "symlinktest"},
+    {renametest, "renametest"},
+    {permtest, "permtest"},
+    {credtest, "credtest"},
+    {readdirtest, "readdirtest"},
+    {synctest, "synctest"},
     {bigdir, "bigdir"}, // slow
     { 0, 0},
   };
@@ -10,24 +10,50 @@ $target = $ENV{'TARGET'};
 
 if($target eq "riscv") {
     *entry = sub {
+        my ($name, $label) = @_;
+        $label = $name unless defined $label;
+        print ".global $label\n";
+        print "${label}:\n";
+        print " li a7, SYS_${name}\n";
+        print " ecall\n";
+        # A result in [-4095, -1] is a negated errno.
+        print " li t0, -4096\n";
+        print " bleu a0, t0, 1f\n";
+        print " tail __syscall_error\n";
+        print "1:\n";
+        print " ret\n";
+    };
+    *noreturn_entry = sub {
         my $name = shift;
         print ".global $name\n";
         print "${name}:\n";
         print " li a7, SYS_${name}\n";
         print " ecall\n";
-        print " ret\n";
     }
 }
 elsif($target eq "arm") {
     *entry = sub {
-        my $name = shift;
-        print ".global $name\n";
-        print "${name}:\n";
+        my ($name, $label) = @_;
+        $label = $name unless defined $label;
+        print ".global $label\n";
+        print "${label}:\n";
     	print " STR x7, [sp, #-0x08]!\n";
         print " MOV x7, #SYS_${name}\n";
         print " SVC 0x00\n";
         print " LDR x7, [sp], #0x08\n";
+        # A result in [-4095, -1] is a negated errno.
+        print " CMN x0, #4095\n";
+        print " B.CS __syscall_error\n";
         print " br x30;	//lr = x30\n";
+    };
+    # Leaves the stack alone, since sigreturn finds the signal frame
+    # at the stack pointer.
+    *noreturn_entry = sub {
+        my $name = shift;
+        print ".global $name\n";
+        print "${name}:\n";
+        print " MOV x7, #SYS_${name}\n";
+        print " SVC 0x00\n";
     }
 }
 else {
@@ -64,3 +90,39 @@ entry("getppid");
 entry("lseek");
 entry("uptime_as_micro");
 entry("clock");
+entry("mmap");
+entry("munmap");
+entry("mprotect");
+# The C library wraps these to convert struct sigaction and sigset_t.
+entry("sigaction", "__sigaction");
+entry("sigprocmask", "__sigprocmask");
+noreturn_entry("sigreturn");
+# The C library wraps this in pthread_create.
+entry("clone", "__clone");
+entry("futex");
+# The kernel returns 20 - nice, which is never negative, and the C library
+# converts it back.
+entry("nice", "__nice");
+entry("getpriority", "__getpriority");
+entry("setpriority");
+entry("sched_setaffinity");
+entry("sched_getaffinity");
+entry("nanosleep");
+entry("clock_nanosleep");
+entry("mount");
+entry("umount");
+entry("symlink");
+entry("readlink");
+entry("rename");
+entry("chmod");
+entry("chown");
+entry("utime");
+entry("getuid");
+entry("setuid");
+entry("getgid");
+entry("setgid");
+entry("getdents");
+entry("fsync");
+entry("fdatasync");
+entry("sync");
+entry("writeback");